@@ -24,6 +24,10 @@ fn from(value: CommonArchCli) -> Self {
 /// Download and restore packages needed for building the specified architectures.
 pub struct RestorePackagesCli {
     arch: Vec<CommonArchCli>,
+    /// Restore packages for building artifacts of this architecture on each
+    /// of the specified host architectures (defaults to the host architecture).
+    #[clap(long)]
+    target_arch: Option<CommonArchCli>,
 }
 
 impl IntoPipeline for RestorePackagesCli {
@@ -74,6 +78,7 @@ fn into_pipeline(self, backend_hint: PipelineBackendHint) -> anyhow::Result<Pipe
             job = job.dep_on(
                 |ctx| flowey_lib_hvlite::_jobs::local_restore_packages::Request {
                     arch: arch.into(),
+                    target_arch: self.target_arch.unwrap_or(arch).into(),
                     done: ctx.new_done_handle(),
                 },
             );
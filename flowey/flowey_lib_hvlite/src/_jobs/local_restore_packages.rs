@@ -7,10 +7,15 @@
 use crate::init_openvmm_magicpath_openhcl_sysroot::OpenvmmSysrootArch;
 use crate::run_cargo_build::common::CommonArch;
 use flowey::node::prelude::*;
+use std::collections::BTreeSet;
 
 flowey_request! {
     pub struct Request{
+        /// Architecture of the host the build runs on.
         pub arch: CommonArch,
+        /// Architecture of the artifacts being built. Selects the sysroot,
+        /// lxutil, UEFI and test kernel packages.
+        pub target_arch: CommonArch,
         pub done: WriteVar<SideEffect>,
     }
 }
@@ -31,61 +36,70 @@ fn imports(ctx: &mut ImportCtx<'_>) {
     fn emit(requests: Vec<Self::Request>, ctx: &mut NodeCtx<'_>) -> anyhow::Result<()> {
         let mut deps = vec![ctx.reqv(crate::init_openvmm_magicpath_protoc::Request)];
 
-        for req in &requests {
-            match req.arch {
-                CommonArch::X86_64 => {
-                    if matches!(ctx.platform(), FlowPlatform::Linux) {
-                        deps.extend_from_slice(&[ctx
-                            .reqv(|v| crate::init_openvmm_magicpath_openhcl_sysroot::Request {
-                                arch: OpenvmmSysrootArch::X64,
-                                path: v,
-                            })
-                            .into_side_effect()]);
-                    }
-                    deps.extend_from_slice(&[
-                        ctx.reqv(|done| crate::init_openvmm_magicpath_lxutil::Request {
-                            arch: LxutilArch::X86_64,
-                            done,
-                        }),
-                        ctx.reqv(|done| crate::init_openvmm_magicpath_uefi_mu_msvm::Request {
-                            arch: MuMsvmArch::X86_64,
-                            done,
-                        }),
-                        ctx.reqv(
-                            |done| crate::init_openvmm_magicpath_linux_test_kernel::Request {
-                                arch: OpenvmmLinuxTestKernelArch::X64,
-                                done,
-                            },
-                        ),
-                    ]);
-                }
-                CommonArch::Aarch64 => {
-                    if matches!(ctx.platform(), FlowPlatform::Linux) {
-                        deps.extend_from_slice(&[ctx
-                            .reqv(|v| crate::init_openvmm_magicpath_openhcl_sysroot::Request {
-                                arch: OpenvmmSysrootArch::Aarch64,
-                                path: v,
-                            })
-                            .into_side_effect()]);
+        // Multiple jobs may request the same packages, so only emit one set of
+        // download steps per (host, target) pair.
+        let arches = requests
+            .iter()
+            .map(|req| (req.arch, req.target_arch))
+            .collect::<BTreeSet<_>>();
+
+        for (arch, target_arch) in arches {
+            if arch != target_arch {
+                match (arch, target_arch) {
+                    (CommonArch::X86_64, CommonArch::Aarch64) => {
+                        // The cross toolchain only ships as part of the
+                        // OpenHCL sysroot, which is only available on Linux.
+                        if !matches!(ctx.platform(), FlowPlatform::Linux) {
+                            anyhow::bail!(
+                                "cross-building {target_arch:?} artifacts from an {arch:?} host is only supported on linux"
+                            );
+                        }
                     }
-                    deps.extend_from_slice(&[
-                        ctx.reqv(|done| crate::init_openvmm_magicpath_lxutil::Request {
-                            arch: LxutilArch::Aarch64,
-                            done,
-                        }),
-                        ctx.reqv(|done| crate::init_openvmm_magicpath_uefi_mu_msvm::Request {
-                            arch: MuMsvmArch::Aarch64,
-                            done,
-                        }),
-                        ctx.reqv(
-                            |done| crate::init_openvmm_magicpath_linux_test_kernel::Request {
-                                arch: OpenvmmLinuxTestKernelArch::Aarch64,
-                                done,
-                            },
-                        ),
-                    ]);
+                    _ => anyhow::bail!(
+                        "cross-building {target_arch:?} artifacts from an {arch:?} host is not supported"
+                    ),
                 }
             }
+
+            let (sysroot_arch, lxutil_arch, uefi_arch, test_kernel_arch) = match target_arch {
+                CommonArch::X86_64 => (
+                    OpenvmmSysrootArch::X64,
+                    LxutilArch::X86_64,
+                    MuMsvmArch::X86_64,
+                    OpenvmmLinuxTestKernelArch::X64,
+                ),
+                CommonArch::Aarch64 => (
+                    OpenvmmSysrootArch::Aarch64,
+                    LxutilArch::Aarch64,
+                    MuMsvmArch::Aarch64,
+                    OpenvmmLinuxTestKernelArch::Aarch64,
+                ),
+            };
+
+            if matches!(ctx.platform(), FlowPlatform::Linux) {
+                deps.extend_from_slice(&[ctx
+                    .reqv(|v| crate::init_openvmm_magicpath_openhcl_sysroot::Request {
+                        arch: sysroot_arch,
+                        path: v,
+                    })
+                    .into_side_effect()]);
+            }
+            deps.extend_from_slice(&[
+                ctx.reqv(|done| crate::init_openvmm_magicpath_lxutil::Request {
+                    arch: lxutil_arch,
+                    done,
+                }),
+                ctx.reqv(|done| crate::init_openvmm_magicpath_uefi_mu_msvm::Request {
+                    arch: uefi_arch,
+                    done,
+                }),
+                ctx.reqv(
+                    |done| crate::init_openvmm_magicpath_linux_test_kernel::Request {
+                        arch: test_kernel_arch,
+                        done,
+                    },
+                ),
+            ]);
         }
 
         ctx.emit_side_effect_step(deps, requests.into_iter().map(|x| x.done));
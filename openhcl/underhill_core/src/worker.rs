@@ -1657,6 +1657,8 @@ async fn new_underhill_vm(
         #[cfg(guest_arch = "x86_64")]
         cpuid,
         crash_notification_send,
+        #[cfg(guest_arch = "x86_64")]
+        guest_crash_notify: None,
         emulate_apic,
         vmtime: &vmtime_source,
         cvm_cpuid_info: runtime_params.cvm_cpuid_info(),
@@ -1809,6 +1811,8 @@ async fn new_underhill_vm(
                 with_psp: dps.general.psp_enabled,
                 pm_base: PM_BASE,
                 acpi_irq: SYSTEM_IRQ_ACPI,
+                ssdts: Vec::new(),
+                pci_root_buses: Vec::new(),
             };
 
             let config = firmware_pcat::config::PcatBiosConfig {
@@ -2251,9 +2255,14 @@ async fn new_underhill_vm(
                 })),
             });
 
-    let deps_winbond_super_io_and_floppy_stub = chipset
-        .with_winbond_super_io_and_floppy_stub
-        .then_some(dev::WinbondSuperIoAndFloppyStubDeps);
+    let deps_winbond_super_io_and_floppy_stub =
+        chipset.with_winbond_super_io_and_floppy_stub.then(|| {
+            dev::WinbondSuperIoAndFloppyStubDeps {
+                // COM ports are emulated as standalone devices.
+                serial_ports: [None, None],
+                parallel_port: None,
+            }
+        });
 
     #[cfg(not(guest_arch = "x86_64"))]
     let deps_piix4_cmos_rtc = None;
@@ -2781,6 +2790,8 @@ async fn new_underhill_vm(
             client_notify_send: halt_notify_send,
             vtl_guest_memory: [Some(gm.vtl0()), gm.vtl1(), None],
             debugger_rpc,
+            // VPs run on the thread pool, which already affinitizes its threads.
+            vp_affinity: Vec::new(),
         },
     )
     .context("failed to create partition unit")?;
@@ -3121,7 +3132,8 @@ async fn load_firmware(
     #[cfg(guest_arch = "aarch64")]
     let crate::loader::VpContext::Vbs(registers) = vtl0_vp_context;
 
-    let registers = initial_regs(&registers, caps, &processor_topology.vp_arch(VpIndex::BSP));
+    let registers = initial_regs(&registers, caps, &processor_topology.vp_arch(VpIndex::BSP))
+        .context("invalid initial registers")?;
     partition_unit
         .set_initial_regs(Vtl::Vtl0, registers)
         .instrument(tracing::info_span!("set_initial_regs"))
@@ -18,6 +18,10 @@
         pub use processor::tdx::shared_pages_required_per_cpu as tdx_shared_pages_required_per_cpu;
         pub use processor::tdx::TdxBacked;
         pub use crate::processor::mshv::x64::HypervisorBackedX86 as HypervisorBacked;
+        pub use crate::processor::mshv::x64::GuestCrash;
+        pub use crate::processor::mshv::x64::GuestCrashKind;
+        pub use crate::processor::mshv::x64::GuestCrashNotify;
+        pub use crate::processor::mshv::x64::GuestCrashRegisters;
         use devmsr::MsrDevice;
         use processor::snp::SnpBackedShared;
         use processor::tdx::TdxBackedShared;
@@ -183,12 +187,22 @@ struct UhPartitionInner {
     #[inspect(skip)]
     enter_modes_atomic: AtomicU8,
     cpuid: Mutex<CpuidLeafSet>,
+    /// The sorted CPUID functions that have results in `cpuid`. Results are
+    /// updated at runtime but functions are never added, so CPUID exits for
+    /// other functions can return the hypervisor's default result without
+    /// taking the `cpuid` lock.
+    #[cfg(guest_arch = "x86_64")]
+    #[inspect(skip)]
+    cpuid_functions: Vec<u32>,
     lower_vtl_memory_layout: MemoryLayout,
     gm: VtlArray<GuestMemory, 2>,
     untrusted_dma_memory: GuestMemory,
     #[cfg_attr(guest_arch = "aarch64", allow(dead_code))]
     #[inspect(skip)]
     crash_notification_send: mesh::Sender<VtlCrash>,
+    #[cfg(guest_arch = "x86_64")]
+    #[inspect(skip)]
+    guest_crash_notify: Option<Arc<dyn GuestCrashNotify>>,
     monitor_page: MonitorPage,
     software_devices: Option<ApicSoftwareDevices>,
     // TODO: move this into some per-backing state.
@@ -342,6 +356,9 @@ struct SoftwareCvmVtl1State {
     // TODO: inspect
     #[inspect(skip)]
     default_vtl_protections: Option<HvMapGpaFlags>,
+    /// The VsmPartitionConfig value last set by the guest.
+    #[inspect(with = "|x| inspect::AsHex(u64::from(*x))")]
+    vsm_partition_config: hvdef::HvRegisterVsmPartitionConfig,
 }
 
 #[cfg_attr(guest_arch = "aarch64", allow(dead_code))]
@@ -1065,6 +1082,10 @@ pub struct UhPartitionNewParams<'a> {
     /// The mesh sender to use for crash notifications.
     // FUTURE: remove mesh dependency from this layer.
     pub crash_notification_send: mesh::Sender<VtlCrash>,
+    /// An object to notify when a guest VP triple faults, on
+    /// hypervisor-backed partitions.
+    #[cfg(guest_arch = "x86_64")]
+    pub guest_crash_notify: Option<Arc<dyn GuestCrashNotify>>,
     /// Whether to emulate the APIC.
     pub emulate_apic: bool,
     /// The VM time source.
@@ -1309,6 +1330,14 @@ pub async fn new(
             isolation,
         );
 
+        #[cfg(guest_arch = "x86_64")]
+        let cpuid_functions = {
+            // The leaves are sorted by function.
+            let mut functions: Vec<_> = cpuid.leaves().iter().map(|leaf| leaf.function).collect();
+            functions.dedup();
+            functions
+        };
+
         #[cfg(guest_arch = "x86_64")]
         let cpuid = Mutex::new(cpuid);
 
@@ -1384,7 +1413,11 @@ pub async fn new(
             gm: params.gm,
             untrusted_dma_memory: params.untrusted_dma_memory,
             cpuid,
+            #[cfg(guest_arch = "x86_64")]
+            cpuid_functions,
             crash_notification_send: params.crash_notification_send,
+            #[cfg(guest_arch = "x86_64")]
+            guest_crash_notify: params.guest_crash_notify,
             monitor_page: MonitorPage::new(),
             software_devices,
             lower_vtl_memory_layout: params.lower_vtl_memory_layout.clone(),
@@ -309,6 +309,14 @@ pub enum SidecarRemoveExit {
         #[inspect(hex)]
         subleaf: u32,
     },
+    Eoi {
+        #[inspect(hex)]
+        vector: u32,
+    },
+    Exception {
+        #[inspect(hex)]
+        vector: u16,
+    },
     Hypervisor {
         #[inspect(debug)]
         message: hvdef::HvMessageType,
@@ -409,6 +417,8 @@ pub enum UhRunVpError {
     HypercallRetry(#[source] guestmem::GuestMemoryError),
     #[error("unexpected debug exception with dr6 value {0:#x}")]
     UnexpectedDebugException(u64),
+    #[error("unexpected exit message type {0:?}")]
+    UnexpectedExit(hvdef::HvMessageType),
 }
 
 /// Underhill processor run error
@@ -35,12 +35,12 @@
 
 #[derive(Inspect)]
 pub(super) struct UhApicState {
-    lapic: LocalApic,
+    pub(super) lapic: LocalApic,
     #[inspect(debug)]
     vtl: GuestVtl,
     pub(super) halted: bool,
     pub(super) startup_suspend: bool,
-    nmi_pending: bool,
+    pub(super) nmi_pending: bool,
 }
 
 impl UhApicState {
@@ -54,6 +54,15 @@ pub fn new(lapic: LocalApic, vtl: GuestVtl, vp_info: &VpInfo) -> Self {
         }
     }
 
+    /// Latches a newly requested NMI and returns whether an NMI is pending.
+    ///
+    /// The NMI stays pending, and is saved with the VP's activity, until it
+    /// is injected.
+    pub(super) fn latch_nmi(&mut self, nmi: bool) -> bool {
+        self.nmi_pending |= nmi;
+        self.nmi_pending
+    }
+
     pub fn base_address(&self) -> Option<u64> {
         self.lapic.base_address()
     }
@@ -276,8 +285,7 @@ pub(super) fn poll_apic(&mut self, vtl: GuestVtl, scan_irr: bool) -> Result<(),
             interrupt,
         } = lapic.lapic.scan(&mut self.vmtime, scan_irr);
 
-        if nmi || lapic.nmi_pending {
-            lapic.nmi_pending = true;
+        if lapic.latch_nmi(nmi) {
             lapic.handle_nmi(
                 &mut self.runner,
                 &mut self.backing.next_deliverability_notifications,
@@ -309,6 +317,7 @@ pub(super) fn poll_apic(&mut self, vtl: GuestVtl, scan_irr: bool) -> Result<(),
     }
 
     fn handle_init(&mut self, vtl: GuestVtl) -> Result<(), UhRunVpError> {
+        self.backing.crash_reporter.reset();
         let vp_info = self.inner.vp_info;
         let mut access = self.access_state(vtl.into());
         virt::x86::vp::x86_init(&mut access, &vp_info).map_err(UhRunVpError::State)
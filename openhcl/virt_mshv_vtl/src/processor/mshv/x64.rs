@@ -47,7 +47,12 @@
 use inspect::Inspect;
 use inspect::InspectMut;
 use inspect_counters::Counter;
+use inspect_counters::Histogram;
+use parking_lot::Mutex;
+use std::sync::atomic::AtomicU64;
 use std::sync::atomic::Ordering::Relaxed;
+use std::time::Duration;
+use std::time::Instant;
 use virt::io::CpuIo;
 use virt::state::HvRegisterState;
 use virt::state::StateElement;
@@ -55,6 +60,7 @@
 use virt::vp::AccessVpState;
 use virt::x86::MsrError;
 use virt::x86::MsrErrorExt;
+use virt::CpuidLeafSet;
 use virt::StopVp;
 use virt::VpHaltReason;
 use virt::VpIndex;
@@ -68,6 +74,7 @@
 use x86defs::xsave::XsaveHeader;
 use x86defs::xsave::XFEATURE_SSE;
 use x86defs::xsave::XFEATURE_X87;
+use x86defs::xsave::XSAVE_LEGACY_LEN;
 use zerocopy::AsBytes;
 use zerocopy::FromBytes;
 use zerocopy::FromZeroes;
@@ -87,10 +94,73 @@ pub struct HypervisorBackedX86 {
     /// Next set of deliverability notifications. See register definition for details.
     #[inspect(with = "|x| inspect::AsHex(u64::from(*x))")]
     pub(super) next_deliverability_notifications: HvDeliverabilityNotificationsRegister,
+    #[inspect(mut)]
     stats: ProcessorStatsX86,
+    /// The most recent exits, for diagnosing stuck VPs.
+    exit_history: ExitHistory<EXIT_HISTORY_LEN>,
+    #[inspect(skip)]
+    pub(super) crash_reporter: GuestCrashReporter,
+    platform_msrs: PlatformMsrs,
+    deliverability_tracker: DeliverabilityTracker,
 }
 
-#[derive(Inspect, Default)]
+/// The number of recent exits recorded for each VP.
+const EXIT_HISTORY_LEN: usize = 64;
+
+/// A ring buffer of the most recent exits.
+///
+/// This is only written from the VP's thread, which has exclusive access to
+/// it, so recording an exit needs no synchronization.
+struct ExitHistory<const N: usize> {
+    entries: [ExitRecord; N],
+    /// The total number of exits recorded.
+    count: u64,
+}
+
+#[derive(Copy, Clone, Inspect)]
+struct ExitRecord {
+    #[inspect(debug)]
+    typ: HvMessageType,
+    #[inspect(hex)]
+    rip: u64,
+    /// The host TSC when the exit was observed.
+    tsc: u64,
+}
+
+impl<const N: usize> ExitHistory<N> {
+    fn new() -> Self {
+        Self {
+            entries: [ExitRecord {
+                typ: HvMessageType::HvMessageTypeNone,
+                rip: 0,
+                tsc: 0,
+            }; N],
+            count: 0,
+        }
+    }
+
+    fn record(&mut self, typ: HvMessageType, rip: u64, tsc: u64) {
+        self.entries[(self.count % N as u64) as usize] = ExitRecord { typ, rip, tsc };
+        self.count += 1;
+    }
+
+    /// Returns the retained exits and their sequence numbers, oldest first.
+    fn iter(&self) -> impl Iterator<Item = (u64, &ExitRecord)> {
+        (self.count.saturating_sub(N as u64)..self.count)
+            .map(|seq| (seq, &self.entries[(seq % N as u64) as usize]))
+    }
+}
+
+impl<const N: usize> Inspect for ExitHistory<N> {
+    fn inspect(&self, req: inspect::Request<'_>) {
+        let mut resp = req.respond();
+        for (seq, record) in self.iter() {
+            resp.field(&seq.to_string(), record);
+        }
+    }
+}
+
+#[derive(InspectMut, Default)]
 struct ProcessorStatsX86 {
     io_port: Counter,
     mmio: Counter,
@@ -104,6 +174,85 @@ struct ProcessorStatsX86 {
     unrecoverable_exception: Counter,
     halt: Counter,
     exception_intercept: Counter,
+    /// Exits with a message type that is not handled.
+    unexpected_exit: Counter,
+    /// IO port exits where RAX in the CPU context did not match the message.
+    io_port_rax_mismatch: Counter,
+    /// Whether to measure the time spent handling each exit. Off by default to
+    /// keep clock reads off the exit path.
+    #[inspect(mut)]
+    measure_latency: bool,
+    latency: ExitLatencyX86,
+}
+
+/// Histograms of the time spent handling each exit reason, in microseconds.
+#[derive(Inspect, Default)]
+struct ExitLatencyX86 {
+    io_port: Histogram<16>,
+    mmio: Histogram<16>,
+    unaccepted_gpa: Histogram<16>,
+    hypercall: Histogram<16>,
+    synic_deliverable: Histogram<16>,
+    interrupt_deliverable: Histogram<16>,
+    cpuid: Histogram<16>,
+    msr: Histogram<16>,
+    eoi: Histogram<16>,
+    unrecoverable_exception: Histogram<16>,
+    halt: Histogram<16>,
+    exception_intercept: Histogram<16>,
+}
+
+impl ExitLatencyX86 {
+    fn record(&mut self, typ: HvMessageType, elapsed: Duration) {
+        let histogram = match typ {
+            HvMessageType::HvMessageTypeX64IoPortIntercept => &mut self.io_port,
+            HvMessageType::HvMessageTypeUnmappedGpa | HvMessageType::HvMessageTypeGpaIntercept => {
+                &mut self.mmio
+            }
+            HvMessageType::HvMessageTypeUnacceptedGpa => &mut self.unaccepted_gpa,
+            HvMessageType::HvMessageTypeHypercallIntercept => &mut self.hypercall,
+            HvMessageType::HvMessageTypeSynicSintDeliverable => &mut self.synic_deliverable,
+            HvMessageType::HvMessageTypeX64InterruptionDeliverable => {
+                &mut self.interrupt_deliverable
+            }
+            HvMessageType::HvMessageTypeX64CpuidIntercept => &mut self.cpuid,
+            HvMessageType::HvMessageTypeMsrIntercept => &mut self.msr,
+            HvMessageType::HvMessageTypeX64ApicEoi => &mut self.eoi,
+            HvMessageType::HvMessageTypeUnrecoverableException => &mut self.unrecoverable_exception,
+            HvMessageType::HvMessageTypeX64Halt => &mut self.halt,
+            HvMessageType::HvMessageTypeExceptionIntercept => &mut self.exception_intercept,
+            _ => return,
+        };
+        histogram.add_sample(elapsed.as_micros().try_into().unwrap_or(u64::MAX));
+    }
+}
+
+/// Handles an exit with a message type that the VP does not expect, which may
+/// come from a newer hypervisor, by failing the VP rather than panicking.
+fn unexpected_exit(
+    stats: &mut ProcessorStatsX86,
+    typ: HvMessageType,
+) -> VpHaltReason<UhRunVpError> {
+    tracelimit::error_ratelimited!(?typ, "unexpected exit message type");
+    stats.unexpected_exit.increment();
+    VpHaltReason::InvalidVmState(UhRunVpError::UnexpectedExit(typ))
+}
+
+/// Resyncs RAX in the CPU context with the value in an IO port intercept
+/// message.
+///
+/// The two should always match, but a mismatch is survivable since the message
+/// holds the guest's value, so it is logged rather than failing the VMM.
+fn sync_io_port_rax(stats: &mut ProcessorStatsX86, message_rax: u64, context_rax: &mut u64) {
+    if *context_rax != message_rax {
+        tracelimit::warn_ratelimited!(
+            message_rax,
+            context_rax = *context_rax,
+            "io port intercept rax does not match cpu context"
+        );
+        stats.io_port_rax_mismatch.increment();
+        *context_rax = message_rax;
+    }
 }
 
 impl BackingPrivate for HypervisorBackedX86 {
@@ -155,6 +304,10 @@ fn new(params: BackingParams<'_, '_, Self>) -> Result<Self, Error> {
             deliverability_notifications: Default::default(),
             next_deliverability_notifications: Default::default(),
             stats: Default::default(),
+            exit_history: ExitHistory::new(),
+            crash_reporter: GuestCrashReporter::default(),
+            platform_msrs: PlatformMsrs::new(),
+            deliverability_tracker: DeliverabilityTracker::default(),
         })
     }
 
@@ -175,6 +328,9 @@ async fn run_vp(
         dev: &impl CpuIo,
         stop: &mut StopVp<'_>,
     ) -> Result<(), VpHaltReason<UhRunVpError>> {
+        // Interrupt and SINT readiness requests only update
+        // `next_deliverability_notifications`, so all requests made since the
+        // last run are applied with a single register write here.
         if this.backing.deliverability_notifications
             != this.backing.next_deliverability_notifications
         {
@@ -216,7 +372,15 @@ async fn run_vp(
         };
 
         if intercepted {
-            let stat = match this.runner.exit_message().header.typ {
+            let message = this.runner.exit_message();
+            let typ = message.header.typ;
+            let rip = HvX64InterceptMessageHeader::ref_from_prefix(message.payload())
+                .map_or(0, |header| header.rip);
+            this.backing
+                .exit_history
+                .record(typ, rip, safe_x86_intrinsics::rdtsc());
+            let start = this.backing.stats.measure_latency.then(Instant::now);
+            let stat = match typ {
                 HvMessageType::HvMessageTypeX64IoPortIntercept => {
                     this.handle_io_port_exit(dev).await?;
                     &mut this.backing.stats.io_port
@@ -266,9 +430,12 @@ async fn run_vp(
                     this.handle_exception()?;
                     &mut this.backing.stats.exception_intercept
                 }
-                reason => unreachable!("unknown exit reason: {:#x?}", reason),
+                typ => return Err(unexpected_exit(&mut this.backing.stats, typ)),
             };
             stat.increment();
+            if let Some(start) = start {
+                this.backing.stats.latency.record(typ, start.elapsed());
+            }
 
             if this.runner.is_sidecar() && !this.partition.no_sidecar_hotplug.load(Relaxed) {
                 // We got and handled an exit and this is a sidecar VP. Cancel
@@ -279,10 +446,16 @@ async fn run_vp(
                 // in the sidecar kernel. But since we have received at least
                 // one exit, we can expect that we will receive more, and
                 // handling the exits remotely introduces jitter.
-                let message = this.runner.exit_message();
-                this.inner
-                    .set_sidecar_exit_reason(SidecarExitReason::Exit(parse_sidecar_exit(message)));
-                return Err(VpHaltReason::Cancel);
+                //
+                // EOIs are the exception: they are frequent for
+                // interrupt-heavy workloads but cheap to handle, so on their
+                // own they do not justify moving the VP.
+                let exit = parse_sidecar_exit(this.runner.exit_message());
+                if !matches!(exit, SidecarRemoveExit::Eoi { .. }) {
+                    this.inner
+                        .set_sidecar_exit_reason(SidecarExitReason::Exit(exit));
+                    return Err(VpHaltReason::Cancel);
+                }
             }
         }
         Ok(())
@@ -309,12 +482,18 @@ fn request_extint_readiness(this: &mut UhProcessor<'_, Self>) {
         this.backing
             .next_deliverability_notifications
             .set_interrupt_notification(true);
+        this.backing
+            .deliverability_tracker
+            .request(NotificationKind::Interrupt);
     }
 
     fn request_untrusted_sint_readiness(this: &mut UhProcessor<'_, Self>, sints: u16) {
         this.backing
             .next_deliverability_notifications
             .set_sints(this.backing.next_deliverability_notifications.sints() | sints);
+        this.backing
+            .deliverability_tracker
+            .request(NotificationKind::Sint);
     }
 
     // If there's no register page, assume only VTL0 is supported.
@@ -327,6 +506,16 @@ fn last_vtl(this: &UhProcessor<'_, Self>) -> GuestVtl {
     fn switch_vtl_state(_this: &mut UhProcessor<'_, Self>, _target_vtl: GuestVtl) {
         unreachable!("vtl switching should be managed by the hypervisor");
     }
+
+    fn inspect_extra(this: &mut UhProcessor<'_, Self>, resp: &mut inspect::Response<'_>) {
+        // The hypervisor owns the MTRRs, so read them through instead of
+        // keeping a copy in the backing that could go stale.
+        resp.field_with("mtrrs", || {
+            Self::access_vp_state(this, GuestVtl::Vtl0)
+                .cache_control()
+                .ok()
+        });
+    }
 }
 
 fn parse_sidecar_exit(message: &hvdef::HvMessage) -> SidecarRemoveExit {
@@ -378,6 +567,19 @@ fn parse_sidecar_exit(message: &hvdef::HvMessage) -> SidecarRemoveExit {
                     .then_some((message.rdx << 32) | message.rax as u32 as u64),
             }
         }
+        HvMessageType::HvMessageTypeX64ApicEoi => {
+            let message = hvdef::HvX64ApicEoiMessage::ref_from_prefix(message.payload()).unwrap();
+            SidecarRemoveExit::Eoi {
+                vector: message.interrupt_vector,
+            }
+        }
+        HvMessageType::HvMessageTypeExceptionIntercept => {
+            let message =
+                hvdef::HvX64ExceptionInterceptMessage::ref_from_prefix(message.payload()).unwrap();
+            SidecarRemoveExit::Exception {
+                vector: message.vector,
+            }
+        }
         typ => SidecarRemoveExit::Hypervisor { message: typ },
     }
 }
@@ -386,6 +588,120 @@ fn next_rip(value: &HvX64InterceptMessageHeader) -> u64 {
     value.rip.wrapping_add(value.instruction_len() as u64)
 }
 
+/// The kind of guest crash reported to a [`GuestCrashNotify`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GuestCrashKind {
+    /// The guest triple faulted, or otherwise hit an exception that the
+    /// processor could not deliver.
+    TripleFault,
+}
+
+/// The register context of a crashed VP.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct GuestCrashRegisters {
+    pub rsp: u64,
+    pub cr0: u64,
+    pub cr2: u64,
+    pub cr3: u64,
+    pub cr4: u64,
+    pub efer: u64,
+}
+
+/// A guest crash observed on a VP.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct GuestCrash {
+    /// The kind of crash.
+    pub kind: GuestCrashKind,
+    /// The VP that crashed.
+    pub vp_index: VpIndex,
+    /// The VTL that was running when the VP crashed.
+    pub vtl: GuestVtl,
+    /// The instruction pointer at the time of the crash.
+    pub rip: u64,
+    /// The flags register at the time of the crash.
+    pub rflags: u64,
+    /// The remaining register context, or `None` if it could not be read.
+    pub registers: Option<GuestCrashRegisters>,
+}
+
+/// Trait for receiving notifications of guest crashes.
+///
+/// The notification is sent from the crashed VP's thread, so implementations
+/// should not block.
+pub trait GuestCrashNotify: Send + Sync {
+    /// Called when a VP crashes.
+    fn notify(&self, crash: &GuestCrash);
+}
+
+/// Reports a VP's guest crashes, at most once until the VP is reset.
+///
+/// A crashed VP may exit repeatedly before the VM is torn down, so only the
+/// first crash is logged and sent to the sink.
+#[derive(Default)]
+pub(super) struct GuestCrashReporter {
+    reported: bool,
+}
+
+impl GuestCrashReporter {
+    /// Rearms the reporter after the VP has been reset by an INIT.
+    pub(super) fn reset(&mut self) {
+        self.reported = false;
+    }
+
+    fn report(&mut self, notify: Option<&dyn GuestCrashNotify>, crash: &GuestCrash) {
+        if std::mem::replace(&mut self.reported, true) {
+            return;
+        }
+        let GuestCrash {
+            kind,
+            vp_index,
+            vtl,
+            rip,
+            rflags,
+            registers,
+        } = crash;
+        let vp_index = vp_index.index();
+        match registers {
+            Some(GuestCrashRegisters {
+                rsp,
+                cr0,
+                cr2,
+                cr3,
+                cr4,
+                efer,
+            }) => {
+                tracing::error!(
+                    ?kind,
+                    vp_index,
+                    ?vtl,
+                    rip = %format_args!("{rip:#x}"),
+                    rflags = %format_args!("{rflags:#x}"),
+                    rsp = %format_args!("{rsp:#x}"),
+                    cr0 = %format_args!("{cr0:#x}"),
+                    cr2 = %format_args!("{cr2:#x}"),
+                    cr3 = %format_args!("{cr3:#x}"),
+                    cr4 = %format_args!("{cr4:#x}"),
+                    efer = %format_args!("{efer:#x}"),
+                    "guest crash"
+                );
+            }
+            None => {
+                tracing::error!(
+                    ?kind,
+                    vp_index,
+                    ?vtl,
+                    rip = %format_args!("{rip:#x}"),
+                    rflags = %format_args!("{rflags:#x}"),
+                    "guest crash, failed to get register context"
+                );
+            }
+        }
+        if let Some(notify) = notify {
+            notify.notify(crash);
+        }
+    }
+}
+
 impl UhProcessor<'_, HypervisorBackedX86> {
     fn set_rip(&mut self, rip: u64) -> Result<(), VpHaltReason<UhRunVpError>> {
         self.runner
@@ -417,6 +733,10 @@ fn handle_interrupt_deliverable_exit(
             .next_deliverability_notifications
             .set_interrupt_notification(false);
 
+        self.backing
+            .deliverability_tracker
+            .deliverable(NotificationKind::Interrupt);
+
         if let Some(vector) = bus.acknowledge_pic_interrupt() {
             let event = hvdef::HvX64PendingExtIntEvent::new()
                 .with_event_pending(true)
@@ -448,6 +768,9 @@ fn handle_synic_deliverable_exit(&mut self) {
 
         // This is updated by `deliver_synic_messages below`, so clear it here.
         self.backing.next_deliverability_notifications.set_sints(0);
+        self.backing
+            .deliverability_tracker
+            .deliverable(NotificationKind::Sint);
 
         // These messages are always VTL0, as VTL1 does not own any VMBUS channels.
         self.deliver_synic_messages(GuestVtl::Vtl0, message.deliverable_sints);
@@ -526,14 +849,19 @@ async fn handle_io_port_exit(
         &mut self,
         dev: &impl CpuIo,
     ) -> Result<(), VpHaltReason<UhRunVpError>> {
-        let message = hvdef::HvX64IoPortInterceptMessage::ref_from_prefix(
+        // Copy the message so that the CPU context can be updated below.
+        let message = hvdef::HvX64IoPortInterceptMessage::read_from_prefix(
             self.runner.exit_message().payload(),
         )
         .unwrap();
 
         tracing::trace!(msg = %format_args!("{:x?}", message), "io_port");
 
-        assert_eq!(message.rax, self.runner.cpu_context().gps[protocol::RAX]);
+        sync_io_port_rax(
+            &mut self.backing.stats,
+            message.rax,
+            &mut self.runner.cpu_context_mut().gps[protocol::RAX],
+        );
 
         let interruption_pending = message.header.execution_state.interruption_pending();
 
@@ -577,8 +905,11 @@ async fn handle_unaccepted_gpa_intercept(
                 UhRunVpError::UnacceptedMemoryAccess(gpa),
             ))
         } else {
-            // TODO SNP: for hardware isolation, if the intercept is due to a guest
-            // error, inject a machine check
+            // Accesses outside of lower VTL RAM are MMIO. Injecting a machine
+            // check for guest-caused accesses to unaccepted memory only
+            // applies to hardware-isolated partitions, which do not use this
+            // backing; their backings handle these faults in their own exit
+            // paths.
             self.handle_mmio_exit(dev).await?;
             Ok(())
         }
@@ -599,7 +930,9 @@ fn handle_cpuid_intercept(&mut self) -> Result<(), VpHaltReason<UhRunVpError>> {
 
         tracing::trace!(msg = %format_args!("{:x?}", message), "cpuid");
 
-        let [eax, ebx, ecx, edx] = self.partition.cpuid.lock().result(
+        let [eax, ebx, ecx, edx] = cpuid_result(
+            &self.partition.cpuid,
+            &self.partition.cpuid_functions,
             message.rax as u32,
             message.rcx as u32,
             &default_result,
@@ -637,7 +970,10 @@ fn handle_msr_intercept(&mut self, dev: &impl CpuIo) -> Result<(), VpHaltReason<
                 } else {
                     Err(MsrError::Unknown)
                 };
-                let r = r.or_else_if_unknown(|| self.read_msr(msr));
+                let r = r
+                    .or_else_if_unknown(|| self.read_msr(msr))
+                    .or_else_if_unknown(|| self.backing.platform_msrs.read(msr))
+                    .or_else_if_unknown(|| self.read_mtrr(msr));
 
                 let value = match r {
                     Ok(v) => v,
@@ -669,7 +1005,10 @@ fn handle_msr_intercept(&mut self, dev: &impl CpuIo) -> Result<(), VpHaltReason<
                 } else {
                     Err(MsrError::Unknown)
                 };
-                let r = r.or_else_if_unknown(|| self.write_msr(msr, value));
+                let r = r
+                    .or_else_if_unknown(|| self.write_msr(msr, value))
+                    .or_else_if_unknown(|| self.backing.platform_msrs.write(msr, value))
+                    .or_else_if_unknown(|| self.write_mtrr(msr, value));
                 match r {
                     Ok(()) => {}
                     Err(MsrError::Unknown) => {
@@ -688,6 +1027,20 @@ fn handle_msr_intercept(&mut self, dev: &impl CpuIo) -> Result<(), VpHaltReason<
         self.set_rip(rip)
     }
 
+    /// Reads an MTRR MSR from the hypervisor, which owns the MTRR state (see
+    /// [`vp::CacheControl`]).
+    fn read_mtrr(&mut self, msr: u32) -> Result<u64, MsrError> {
+        read_mtrr_msr(msr, |name| self.runner.get_vp_register(name))
+    }
+
+    /// Writes an MTRR MSR to the hypervisor. The hypervisor rejects writes to
+    /// read-only MSRs and values with reserved bits set.
+    fn write_mtrr(&mut self, msr: u32, value: u64) -> Result<(), MsrError> {
+        write_mtrr_msr(msr, value, |name, value| {
+            self.runner.set_vp_register(name, value)
+        })
+    }
+
     fn inject_gpf(&mut self) {
         let exception_event = hvdef::HvX64PendingExceptionEvent::new()
             .with_event_pending(true)
@@ -715,10 +1068,10 @@ fn handle_eoi(&self, dev: &impl CpuIo) -> Result<(), VpHaltReason<UhRunVpError>>
         Ok(())
     }
 
-    fn handle_unrecoverable_exception(&self) -> Result<(), VpHaltReason<UhRunVpError>> {
-        Err(VpHaltReason::TripleFault {
-            vtl: self.last_vtl().into(),
-        })
+    fn handle_unrecoverable_exception(&mut self) -> Result<(), VpHaltReason<UhRunVpError>> {
+        let vtl = self.last_vtl();
+        self.report_guest_crash(GuestCrashKind::TripleFault);
+        Err(VpHaltReason::TripleFault { vtl: vtl.into() })
     }
 
     fn handle_halt(&mut self) -> Result<(), VpHaltReason<UhRunVpError>> {
@@ -727,6 +1080,59 @@ fn handle_halt(&mut self) -> Result<(), VpHaltReason<UhRunVpError>> {
         Ok(())
     }
 
+    /// Logs a guest crash for the intercept being handled, and reports it to
+    /// the partition's crash notification sink, if there is one.
+    fn report_guest_crash(&mut self, kind: GuestCrashKind) {
+        let header =
+            HvX64InterceptMessageHeader::ref_from_prefix(self.runner.exit_message().payload())
+                .unwrap();
+        let (rip, rflags) = (header.rip, header.rflags);
+
+        // The register context is best effort, since the VP is halting either
+        // way.
+        const NAMES: &[HvX64RegisterName] = &[
+            HvX64RegisterName::Rsp,
+            HvX64RegisterName::Cr0,
+            HvX64RegisterName::Cr2,
+            HvX64RegisterName::Cr3,
+            HvX64RegisterName::Cr4,
+            HvX64RegisterName::Efer,
+        ];
+        let mut values = [HvRegisterValue::new_zeroed(); NAMES.len()];
+        let registers = match self.runner.get_vp_registers(NAMES, &mut values) {
+            Ok(()) => {
+                let [rsp, cr0, cr2, cr3, cr4, efer] = values.map(|v| v.as_u64());
+                Some(GuestCrashRegisters {
+                    rsp,
+                    cr0,
+                    cr2,
+                    cr3,
+                    cr4,
+                    efer,
+                })
+            }
+            Err(err) => {
+                tracing::warn!(
+                    error = &err as &dyn std::error::Error,
+                    "failed to get crash register context"
+                );
+                None
+            }
+        };
+
+        let crash = GuestCrash {
+            kind,
+            vp_index: self.vp_index(),
+            vtl: self.last_vtl(),
+            rip,
+            rflags,
+            registers,
+        };
+        self.backing
+            .crash_reporter
+            .report(self.partition.guest_crash_notify.as_deref(), &crash);
+    }
+
     fn handle_exception(&mut self) -> Result<(), VpHaltReason<UhRunVpError>> {
         let message = hvdef::HvX64ExceptionInterceptMessage::ref_from_prefix(
             self.runner.exit_message().payload(),
@@ -741,56 +1147,21 @@ fn handle_exception(&mut self) -> Result<(), VpHaltReason<UhRunVpError>> {
     }
 
     fn emulator_state(&mut self) -> x86emu::CpuState {
-        const NAMES: &[HvX64RegisterName] = &[
-            HvX64RegisterName::Rsp,
-            HvX64RegisterName::Es,
-            HvX64RegisterName::Ds,
-            HvX64RegisterName::Fs,
-            HvX64RegisterName::Gs,
-            HvX64RegisterName::Ss,
-            HvX64RegisterName::Cr0,
-            HvX64RegisterName::Efer,
-        ];
-        let mut values = [FromZeroes::new_zeroed(); NAMES.len()];
+        let mut values = [FromZeroes::new_zeroed(); EMULATOR_REGISTERS.len()];
         self.runner
-            .get_vp_registers(NAMES, &mut values)
+            .get_vp_registers(&EMULATOR_REGISTERS, &mut values)
             .expect("register query should not fail");
 
-        let [rsp, es, ds, fs, gs, ss, cr0, efer] = values;
-
-        let mut gps = self.runner.cpu_context().gps;
-        gps[x86emu::CpuState::RSP] = rsp.as_u64();
-
+        let gps = self.runner.cpu_context().gps;
         let message = self.runner.exit_message();
         let header = HvX64InterceptMessageHeader::ref_from_prefix(message.payload()).unwrap();
-
-        x86emu::CpuState {
-            gps,
-            segs: [
-                from_seg(es.into()),
-                from_seg(header.cs_segment),
-                from_seg(ss.into()),
-                from_seg(ds.into()),
-                from_seg(fs.into()),
-                from_seg(gs.into()),
-            ],
-            rip: header.rip,
-            rflags: header.rflags.into(),
-            cr0: cr0.as_u64(),
-            efer: efer.as_u64(),
-        }
+        emulator_cpu_state(values, gps, header)
     }
 
     fn set_emulator_state(&mut self, state: &x86emu::CpuState) {
         self.runner
-            .set_vp_registers([
-                (HvX64RegisterName::Rip, state.rip),
-                (HvX64RegisterName::Rflags, state.rflags.into()),
-                (HvX64RegisterName::Rsp, state.gps[x86emu::CpuState::RSP]),
-            ])
+            .set_vp_registers(emulator_register_writes(state))
             .unwrap();
-
-        self.runner.cpu_context_mut().gps = state.gps;
     }
 
     fn set_vsm_partition_config(
@@ -897,10 +1268,145 @@ fn set_vsm_partition_config(
 
         let hc_regs = [(HvX64RegisterName::VsmPartitionConfig, u64::from(value))];
         self.runner.set_vp_registers_hvcall(vtl.into(), hc_regs)?;
+        guest_vsm_inner.vsm_partition_config = value;
         guest_vsm.enable_vtl_protection = true;
 
         Ok(())
     }
+
+    fn get_vsm_partition_config(
+        &self,
+        vtl: GuestVtl,
+    ) -> Result<HvRegisterVsmPartitionConfig, HvError> {
+        if vtl != GuestVtl::Vtl1 {
+            return Err(HvError::InvalidParameter);
+        }
+
+        Ok(vsm_partition_config(&self.partition.guest_vsm.read()))
+    }
+}
+
+/// Returns the VsmPartitionConfig value last set by the guest, as recorded in
+/// the guest VSM state by `set_vsm_partition_config`.
+fn vsm_partition_config(guest_vsm: &GuestVsmState) -> HvRegisterVsmPartitionConfig {
+    let GuestVsmState::Enabled { vtl1 } = guest_vsm else {
+        return HvRegisterVsmPartitionConfig::new();
+    };
+    match vtl1.inner {
+        GuestVsmVtl1StateInner::SoftwareCvm { state } => state.vsm_partition_config,
+        GuestVsmVtl1StateInner::HardwareCvm { .. } => HvRegisterVsmPartitionConfig::new()
+            .with_enable_vtl_protection(vtl1.enable_vtl_protection),
+    }
+}
+
+/// Returns the xsave state for the legacy FP and SSE state in `fx_state`.
+///
+/// Only the legacy components are reported. The extended components (AVX,
+/// AVX-512, and so on) are shared between VTLs and live in the processor while
+/// VTL2 runs, and neither the HCL nor the hypervisor register interface
+/// exposes them, so this backing cannot access them.
+fn legacy_xsave(fx_state: &Fxsave, caps: &virt::x86::X86PartitionCapabilities) -> vp::Xsave {
+    #[repr(C)]
+    #[derive(AsBytes)]
+    struct XsaveStandard {
+        fxsave: Fxsave,
+        xsave_header: XsaveHeader,
+    }
+    let state = XsaveStandard {
+        fxsave: fx_state.clone(),
+        xsave_header: XsaveHeader {
+            xstate_bv: XFEATURE_X87 | XFEATURE_SSE,
+            ..FromZeroes::new_zeroed()
+        },
+    };
+    vp::Xsave::from_standard(state.as_bytes(), caps)
+}
+
+/// Returns the legacy FP and SSE state to restore from `value`.
+///
+/// Fails if `value` has features the partition does not support, or extended
+/// features that are not in their init state, since this backing cannot
+/// restore those (see [`legacy_xsave`]).
+fn legacy_fx_state(
+    value: &vp::Xsave,
+    caps: &virt::x86::X86PartitionCapabilities,
+) -> Result<Fxsave, vp_state::Error> {
+    let header = XsaveHeader::read_from_prefix(&value.compact()[XSAVE_LEGACY_LEN..]).unwrap();
+    let unsupported = header.xstate_bv & !(caps.xsave.features | caps.xsave.supervisor_features);
+    if unsupported != 0 {
+        return Err(vp_state::Error::UnsupportedXsaveFeatures(unsupported));
+    }
+
+    let extended = header.xstate_bv & !(XFEATURE_X87 | XFEATURE_SSE);
+    if extended != 0 {
+        return Err(vp_state::Error::ExtendedXsaveState(extended));
+    }
+
+    Ok(value.fxsave())
+}
+
+/// The registers read for the emulator, in addition to those in the intercept
+/// message and the CPU context.
+const EMULATOR_REGISTERS: [HvX64RegisterName; 8] = [
+    HvX64RegisterName::Rsp,
+    HvX64RegisterName::Es,
+    HvX64RegisterName::Ds,
+    HvX64RegisterName::Fs,
+    HvX64RegisterName::Gs,
+    HvX64RegisterName::Ss,
+    HvX64RegisterName::Cr0,
+    HvX64RegisterName::Efer,
+];
+
+/// Builds the emulator's CPU state from the values of [`EMULATOR_REGISTERS`],
+/// the general purpose registers from the CPU context, and the intercept
+/// message header.
+fn emulator_cpu_state(
+    values: [HvRegisterValue; EMULATOR_REGISTERS.len()],
+    mut gps: [u64; 16],
+    header: &HvX64InterceptMessageHeader,
+) -> x86emu::CpuState {
+    let [rsp, es, ds, fs, gs, ss, cr0, efer] = values;
+    gps[x86emu::CpuState::RSP] = rsp.as_u64();
+    x86emu::CpuState {
+        gps,
+        segs: [
+            from_seg(es.into()),
+            from_seg(header.cs_segment),
+            from_seg(ss.into()),
+            from_seg(ds.into()),
+            from_seg(fs.into()),
+            from_seg(gs.into()),
+        ],
+        rip: header.rip,
+        rflags: header.rflags.into(),
+        cr0: cr0.as_u64(),
+        efer: efer.as_u64(),
+    }
+}
+
+/// Returns the register writes to apply the emulator's updated `state`, as a
+/// single batch.
+///
+/// RFLAGS comes first, since setting it ends a batch of writes through the
+/// register page. The general purpose registers other than RSP land in the CPU
+/// context, where the RSP slot holds CR2 instead.
+fn emulator_register_writes(
+    state: &x86emu::CpuState,
+) -> impl '_ + Iterator<Item = (HvX64RegisterName, u64)> {
+    let gps = state
+        .gps
+        .iter()
+        .enumerate()
+        .filter(|&(i, _)| i != x86emu::CpuState::RSP)
+        .map(|(i, &v)| (HvX64RegisterName(HvX64RegisterName::Rax.0 + i as u32), v));
+    [
+        (HvX64RegisterName::Rflags, state.rflags.into()),
+        (HvX64RegisterName::Rip, state.rip),
+        (HvX64RegisterName::Rsp, state.gps[x86emu::CpuState::RSP]),
+    ]
+    .into_iter()
+    .chain(gps)
 }
 
 impl<T: CpuIo> EmulatorSupport for UhEmulationState<'_, '_, T, HypervisorBackedX86> {
@@ -1186,6 +1692,12 @@ fn lapic_write(&mut self, address: u64, data: &[u8]) {
 }
 
 impl<T: CpuIo> UhHypercallHandler<'_, '_, T, HypervisorBackedX86> {
+    // The TLB flush hypercalls (HvFlushVirtualAddressSpace/List and their Ex
+    // variants) are intentionally absent. Unlike on isolated partitions, the
+    // hypervisor owns the guest's address translation here and completes
+    // these hypercalls without forwarding them to VTL2. VTL2 also cannot
+    // reissue them on the guest's behalf, since a flush only affects the
+    // calling VTL.
     const MSHV_DISPATCHER: hv1_hypercall::Dispatcher<Self> = hv1_hypercall::dispatcher!(
         Self,
         [
@@ -1195,6 +1707,7 @@ impl<T: CpuIo> UhHypercallHandler<'_, '_, T, HypervisorBackedX86> {
             hv1_hypercall::HvX64StartVirtualProcessor,
             hv1_hypercall::HvGetVpIndexFromApicId,
             hv1_hypercall::HvSetVpRegisters,
+            hv1_hypercall::HvGetVpRegisters,
         ]
     );
 }
@@ -1271,6 +1784,318 @@ fn set_xmm(&mut self, n: usize, value: u128) {
     }
 }
 
+/// Returns the hypervisor register backing the MTRR MSR `msr`.
+fn mtrr_register(msr: u32) -> Option<HvX64RegisterName> {
+    let name = match msr {
+        x86defs::X86X_MSR_MTRR_CAP => HvX64RegisterName::MsrMtrrCap,
+        x86defs::X86X_MSR_MTRR_DEF_TYPE => HvX64RegisterName::MsrMtrrDefType,
+        x86defs::X86X_MSR_MTRR_FIX64K_00000 => HvX64RegisterName::MsrMtrrFix64k00000,
+        x86defs::X86X_MSR_MTRR_FIX16K_80000 => HvX64RegisterName::MsrMtrrFix16k80000,
+        x86defs::X86X_MSR_MTRR_FIX16K_A0000 => HvX64RegisterName::MsrMtrrFix16kA0000,
+        x86defs::X86X_MSR_MTRR_FIX4K_C0000 => HvX64RegisterName::MsrMtrrFix4kC0000,
+        x86defs::X86X_MSR_MTRR_FIX4K_C8000 => HvX64RegisterName::MsrMtrrFix4kC8000,
+        x86defs::X86X_MSR_MTRR_FIX4K_D0000 => HvX64RegisterName::MsrMtrrFix4kD0000,
+        x86defs::X86X_MSR_MTRR_FIX4K_D8000 => HvX64RegisterName::MsrMtrrFix4kD8000,
+        x86defs::X86X_MSR_MTRR_FIX4K_E0000 => HvX64RegisterName::MsrMtrrFix4kE0000,
+        x86defs::X86X_MSR_MTRR_FIX4K_E8000 => HvX64RegisterName::MsrMtrrFix4kE8000,
+        x86defs::X86X_MSR_MTRR_FIX4K_F0000 => HvX64RegisterName::MsrMtrrFix4kF0000,
+        x86defs::X86X_MSR_MTRR_FIX4K_F8000 => HvX64RegisterName::MsrMtrrFix4kF8000,
+        // Variable range MTRRs are interleaved base/mask pairs.
+        x86defs::X86X_MSR_MTRR_PHYSBASE0..=0x21f => {
+            let index = (msr - x86defs::X86X_MSR_MTRR_PHYSBASE0) / 2;
+            let first = if msr % 2 == 0 {
+                HvX64RegisterName::MsrMtrrPhysBase0
+            } else {
+                HvX64RegisterName::MsrMtrrPhysMask0
+            };
+            HvX64RegisterName(first.0 + index)
+        }
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// Emulated platform MSRs that guests commonly probe during boot.
+///
+/// The values are per VP, since each processor has its own copy of these
+/// MSRs.
+#[derive(Inspect)]
+struct PlatformMsrs {
+    #[inspect(hex)]
+    misc_enable: u64,
+    #[inspect(hex)]
+    feature_control: u64,
+}
+
+impl PlatformMsrs {
+    /// The bits of IA32_MISC_ENABLE that the guest may change. Writes to other
+    /// bits are ignored, since their meaning varies by processor model.
+    const MISC_ENABLE_WRITABLE: x86defs::MiscEnable = x86defs::MiscEnable::new()
+        .with_fast_string(true)
+        .with_enhanced_speedstep(true)
+        .with_limit_cpuid(true)
+        .with_xtpr_disable(true);
+
+    /// The lock bit of IA32_FEATURE_CONTROL. Once set, the MSR cannot be
+    /// written until reset.
+    const FEATURE_CONTROL_LOCK: u64 = x86defs::vmx::VMX_FEATURE_CONTROL_LOCKED;
+
+    /// The defined bits of IA32_FEATURE_CONTROL: the lock bit, the VMX enable
+    /// bits (inside and outside SMX), and the LMCE enable bit. Setting the
+    /// VMX bits does not expose VMX to the guest.
+    const FEATURE_CONTROL_VALID: u64 = Self::FEATURE_CONTROL_LOCK | 0b110 | 1 << 20;
+
+    fn new() -> Self {
+        Self {
+            misc_enable: hv1_emulator::x86::MISC_ENABLE.into(),
+            feature_control: 0,
+        }
+    }
+
+    fn read(&self, msr: u32) -> Result<u64, MsrError> {
+        match msr {
+            x86defs::X86X_IA32_MSR_MISC_ENABLE => Ok(self.misc_enable),
+            x86defs::X86X_IA32_MSR_FEATURE_CONTROL => Ok(self.feature_control),
+            // Report platform ID zero.
+            x86defs::X86X_IA32_MSR_PLATFORM_ID => Ok(0),
+            _ => Err(MsrError::Unknown),
+        }
+    }
+
+    fn write(&mut self, msr: u32, value: u64) -> Result<(), MsrError> {
+        match msr {
+            x86defs::X86X_IA32_MSR_MISC_ENABLE => {
+                let writable = u64::from(Self::MISC_ENABLE_WRITABLE);
+                self.misc_enable = (self.misc_enable & !writable) | (value & writable);
+            }
+            x86defs::X86X_IA32_MSR_FEATURE_CONTROL => {
+                if self.feature_control & Self::FEATURE_CONTROL_LOCK != 0
+                    || value & !Self::FEATURE_CONTROL_VALID != 0
+                {
+                    return Err(MsrError::InvalidAccess);
+                }
+                self.feature_control = value;
+            }
+            x86defs::X86X_IA32_MSR_PLATFORM_ID => return Err(MsrError::InvalidAccess),
+            _ => return Err(MsrError::Unknown),
+        }
+        Ok(())
+    }
+}
+
+/// The next deliverability notification sequence id. This is shared by all VPs
+/// so that the ids in a trace are unique.
+static NEXT_NOTIFICATION_SEQ: AtomicU64 = AtomicU64::new(1);
+
+/// A kind of deliverability notification.
+#[derive(Debug, Copy, Clone)]
+enum NotificationKind {
+    Sint,
+    Interrupt,
+}
+
+/// Tracks outstanding deliverability notification requests, to correlate each
+/// request with the exit that satisfies it.
+///
+/// Each request is assigned a sequence id and a tracing span that lasts until
+/// the notification is delivered, so that slow deliveries show up in traces.
+/// Repeated requests before delivery share the id of the first.
+#[derive(Inspect, Default)]
+struct DeliverabilityTracker {
+    /// The sequence id of the outstanding SINT notification request.
+    #[inspect(with = "|x| x.as_ref().map(|x| x.seq)")]
+    sint: Option<PendingNotification>,
+    /// The sequence id of the outstanding interrupt notification request.
+    #[inspect(with = "|x| x.as_ref().map(|x| x.seq)")]
+    interrupt: Option<PendingNotification>,
+}
+
+struct PendingNotification {
+    seq: u64,
+    span: tracing::Span,
+}
+
+impl DeliverabilityTracker {
+    fn pending(&mut self, kind: NotificationKind) -> &mut Option<PendingNotification> {
+        match kind {
+            NotificationKind::Sint => &mut self.sint,
+            NotificationKind::Interrupt => &mut self.interrupt,
+        }
+    }
+
+    /// Records a request for a notification, returning its sequence id.
+    fn request(&mut self, kind: NotificationKind) -> u64 {
+        self.pending(kind)
+            .get_or_insert_with(|| {
+                let seq = NEXT_NOTIFICATION_SEQ.fetch_add(1, Relaxed);
+                let span = tracing::trace_span!("deliverability_notification", ?kind, seq);
+                tracing::trace!(parent: &span, ?kind, seq, "notification requested");
+                PendingNotification { seq, span }
+            })
+            .seq
+    }
+
+    /// Records the delivery of a notification, returning the sequence id of
+    /// the request that it satisfies, if any. This closes the request's span.
+    fn deliverable(&mut self, kind: NotificationKind) -> Option<u64> {
+        let PendingNotification { seq, span } = self.pending(kind).take()?;
+        tracing::trace!(parent: &span, ?kind, seq, "notification deliverable");
+        Some(seq)
+    }
+}
+
+/// Reads the MTRR MSR `msr` through `get`, which reads a hypervisor register.
+fn read_mtrr_msr<E>(
+    msr: u32,
+    get: impl FnOnce(HvX64RegisterName) -> Result<HvRegisterValue, E>,
+) -> Result<u64, MsrError> {
+    let name = mtrr_register(msr).ok_or(MsrError::Unknown)?;
+    get(name)
+        .map(|value| value.as_u64())
+        .map_err(|_| MsrError::InvalidAccess)
+}
+
+/// Writes the MTRR MSR `msr` through `set`, which writes a hypervisor
+/// register.
+fn write_mtrr_msr<E>(
+    msr: u32,
+    value: u64,
+    set: impl FnOnce(HvX64RegisterName, HvRegisterValue) -> Result<(), E>,
+) -> Result<(), MsrError> {
+    let name = mtrr_register(msr).ok_or(MsrError::Unknown)?;
+    set(name, value.into()).map_err(|_| MsrError::InvalidAccess)
+}
+
+/// Returns the result of a CPUID instruction.
+///
+/// Only functions in the sorted `overridden` list take the `cpuid` lock; all
+/// others pass the hypervisor's `default` result through.
+fn cpuid_result(
+    cpuid: &Mutex<CpuidLeafSet>,
+    overridden: &[u32],
+    function: u32,
+    index: u32,
+    default: &[u32; 4],
+) -> [u32; 4] {
+    if overridden.binary_search(&function).is_ok() {
+        cpuid.lock().result(function, index, default)
+    } else {
+        *default
+    }
+}
+
+/// Saves the state of the emulated APIC for `vtl`.
+///
+/// The register interface does not expose the state of an APIC owned by the
+/// hypervisor, so this fails when the APIC is not emulated.
+fn save_emulated_apic(
+    lapics: &mut Option<VtlArray<apic::UhApicState, 2>>,
+    vtl: GuestVtl,
+) -> Result<vp::Apic, vp_state::Error> {
+    let lapics = lapics
+        .as_mut()
+        .ok_or(vp_state::Error::HypervisorOwnedApic)?;
+    Ok(lapics[vtl].lapic.save())
+}
+
+/// Restores the state of the emulated APIC for `vtl`.
+fn restore_emulated_apic(
+    lapics: &mut Option<VtlArray<apic::UhApicState, 2>>,
+    vtl: GuestVtl,
+    value: &vp::Apic,
+) -> Result<(), vp_state::Error> {
+    let lapics = lapics
+        .as_mut()
+        .ok_or(vp_state::Error::HypervisorOwnedApic)?;
+    lapics[vtl]
+        .lapic
+        .restore(value)
+        .map_err(vp_state::Error::InvalidApicBase)
+}
+
+/// Records the activity state kept by the emulated APIC for `vtl`.
+///
+/// When the hypervisor owns the APIC, an NMI that it has latched but not yet
+/// injected is not visible through any register, so this fails rather than
+/// reporting no NMI pending.
+fn save_apic_activity(
+    lapics: &Option<VtlArray<apic::UhApicState, 2>>,
+    vtl: GuestVtl,
+    activity: &mut vp::Activity,
+) -> Result<(), vp_state::Error> {
+    let lapics = lapics
+        .as_ref()
+        .ok_or(vp_state::Error::HypervisorOwnedApic)?;
+    activity.nmi_pending = lapics[vtl].nmi_pending;
+    Ok(())
+}
+
+/// Restores the activity state kept by the emulated APIC for `vtl`.
+fn restore_apic_activity(
+    lapics: &mut Option<VtlArray<apic::UhApicState, 2>>,
+    vtl: GuestVtl,
+    activity: &vp::Activity,
+) -> Result<(), vp_state::Error> {
+    let lapics = lapics
+        .as_mut()
+        .ok_or(vp_state::Error::HypervisorOwnedApic)?;
+    // The pending NMI is injected the next time the APIC is polled.
+    lapics[vtl].nmi_pending = activity.nmi_pending;
+    Ok(())
+}
+
+/// Builds the synthetic timer state from the values of [`STIMER_REGISTERS`].
+fn synic_timers_from_registers(values: &[HvRegisterValue; 8]) -> vp::SynicTimers {
+    // The register interface does not expose the adjustment or any
+    // undelivered expiration message. An expired one-shot timer whose message
+    // has not been delivered is still enabled with a count in the past, so it
+    // fires again as soon as it is restored.
+    let mut timers = [vp::SynicTimer::default(); 4];
+    for (timer, regs) in timers.iter_mut().zip(values.chunks_exact(2)) {
+        timer.config = regs[0].as_u64();
+        timer.count = regs[1].as_u64();
+    }
+    vp::SynicTimers { timers }
+}
+
+/// Returns the register writes that restore the synthetic timer state, in
+/// the order they must be applied.
+fn synic_timer_register_writes(
+    value: &vp::SynicTimers,
+) -> Vec<(HvX64RegisterName, HvRegisterValue)> {
+    // Disable each timer before writing its count, so that neither an
+    // auto-enable timer nor the previous configuration arms the timer with a
+    // stale count. The count is absolute reference time for one-shot timers
+    // and a period for periodic timers; both restore as-is.
+    let mut regs = Vec::with_capacity(12);
+    for (timer, names) in value.timers.iter().zip(STIMER_REGISTERS.chunks_exact(2)) {
+        let config = hvdef::HvSynicStimerConfig::from(timer.config);
+        regs.push((
+            names[0],
+            HvRegisterValue::from(u64::from(
+                config.with_enabled(false).with_auto_enable(false),
+            )),
+        ));
+        regs.push((names[1], HvRegisterValue::from(timer.count)));
+        if config.enabled() || config.auto_enable() {
+            regs.push((names[0], HvRegisterValue::from(timer.config)));
+        }
+    }
+    regs
+}
+
+/// Config and count registers for each synthetic timer, in order.
+const STIMER_REGISTERS: [HvX64RegisterName; 8] = [
+    HvX64RegisterName::Stimer0Config,
+    HvX64RegisterName::Stimer0Count,
+    HvX64RegisterName::Stimer1Config,
+    HvX64RegisterName::Stimer1Count,
+    HvX64RegisterName::Stimer2Config,
+    HvX64RegisterName::Stimer2Count,
+    HvX64RegisterName::Stimer3Config,
+    HvX64RegisterName::Stimer3Count,
+];
+
 trait ToVpRegisterName: 'static + Copy + std::fmt::Debug {
     fn to_vp_reg_name(self) -> VpRegisterName;
 }
@@ -1338,54 +2163,34 @@ fn set_registers(&mut self, value: &vp::Registers) -> Result<(), Self::Error> {
     }
 
     fn activity(&mut self) -> Result<vp::Activity, Self::Error> {
-        let activity: vp::Activity = self.get_register_state()?;
-
-        // TODO: Get the NMI pending bit from the APIC.
-        // let apic = self.vp.whp(self.vtl).get_apic()?;
-        // activity.nmi_pending = hv_apic_nmi_pending(&apic);
+        let mut activity: vp::Activity = self.get_register_state()?;
+        save_apic_activity(&self.vp.backing.lapics, self.vtl, &mut activity)?;
         Ok(activity)
     }
 
     fn set_activity(&mut self, value: &vp::Activity) -> Result<(), Self::Error> {
-        self.set_register_state(value)?;
-
-        // TODO: Set the NMI pending bit via the APIC.
-        // let mut apic = self.vp.whp(self.vtl).get_apic()?;
-        // set_hv_apic_nmi_pending(&mut apic, value.nmi_pending);
-        // self.vp.whp(self.vtl).set_apic(&apic)?;
-        Ok(())
+        restore_apic_activity(&mut self.vp.backing.lapics, self.vtl, value)?;
+        self.set_register_state(value)
     }
 
     fn xsave(&mut self) -> Result<vp::Xsave, Self::Error> {
-        // TODO: get the rest of the xsave state, not just the legacy FP state.
-        //
-        // This is just used for debugging, so this should not be a problem.
-        #[repr(C)]
-        #[derive(AsBytes)]
-        struct XsaveStandard {
-            fxsave: Fxsave,
-            xsave_header: XsaveHeader,
-        }
-        let state = XsaveStandard {
-            fxsave: self.vp.runner.cpu_context().fx_state.clone(),
-            xsave_header: XsaveHeader {
-                xstate_bv: XFEATURE_X87 | XFEATURE_SSE,
-                ..FromZeroes::new_zeroed()
-            },
-        };
-        Ok(vp::Xsave::from_standard(state.as_bytes(), self.caps()))
+        Ok(legacy_xsave(
+            &self.vp.runner.cpu_context().fx_state,
+            self.caps(),
+        ))
     }
 
-    fn set_xsave(&mut self, _value: &vp::Xsave) -> Result<(), Self::Error> {
-        Err(vp_state::Error::Unimplemented("xsave"))
+    fn set_xsave(&mut self, value: &vp::Xsave) -> Result<(), Self::Error> {
+        self.vp.runner.cpu_context_mut().fx_state = legacy_fx_state(value, self.caps())?;
+        Ok(())
     }
 
     fn apic(&mut self) -> Result<vp::Apic, Self::Error> {
-        Err(vp_state::Error::Unimplemented("apic"))
+        save_emulated_apic(&mut self.vp.backing.lapics, self.vtl)
     }
 
-    fn set_apic(&mut self, _value: &vp::Apic) -> Result<(), Self::Error> {
-        Err(vp_state::Error::Unimplemented("apic"))
+    fn set_apic(&mut self, value: &vp::Apic) -> Result<(), Self::Error> {
+        restore_emulated_apic(&mut self.vp.backing.lapics, self.vtl, value)
     }
 
     fn xcr(&mut self) -> Result<vp::Xcr0, Self::Error> {
@@ -1469,11 +2274,20 @@ fn set_synic_msrs(&mut self, value: &vp::SyntheticMsrs) -> Result<(), Self::Erro
     }
 
     fn synic_timers(&mut self) -> Result<vp::SynicTimers, Self::Error> {
-        Err(vp_state::Error::Unimplemented("synic_timers"))
+        let mut values = [HvRegisterValue::new_zeroed(); 8];
+        self.vp
+            .runner
+            .get_vp_registers(&STIMER_REGISTERS, &mut values)
+            .map_err(vp_state::Error::GetRegisters)?;
+        Ok(synic_timers_from_registers(&values))
     }
 
-    fn set_synic_timers(&mut self, _value: &vp::SynicTimers) -> Result<(), Self::Error> {
-        Err(vp_state::Error::Unimplemented("synic_timers"))
+    fn set_synic_timers(&mut self, value: &vp::SynicTimers) -> Result<(), Self::Error> {
+        self.vp
+            .runner
+            .set_vp_registers(synic_timer_register_writes(value))
+            .map_err(vp_state::Error::SetRegisters)?;
+        Ok(())
     }
 
     fn synic_message_queues(&mut self) -> Result<vp::SynicMessageQueues, Self::Error> {
@@ -1529,6 +2343,143 @@ fn retarget_interrupt(
     }
 }
 
+/// The VP state behind the `HvSetVpRegisters` and `HvGetVpRegisters`
+/// handlers, separated from [`UhHypercallHandler`] so that the handlers can be
+/// tested without a VP.
+trait VpRegisterAccess {
+    /// The index of the calling VP.
+    fn vp_index(&self) -> u32;
+
+    /// Returns the VTL targeted by the hypercall, which must be no higher than
+    /// the calling VTL.
+    fn target_vtl(&self, vtl: Option<Vtl>) -> Result<GuestVtl, HvError>;
+
+    fn set_vsm_partition_config(
+        &mut self,
+        value: HvRegisterVsmPartitionConfig,
+        vtl: GuestVtl,
+    ) -> Result<(), HvError>;
+
+    fn vsm_partition_config(&self, vtl: GuestVtl) -> Result<HvRegisterVsmPartitionConfig, HvError>;
+
+    /// Reads any other register from the VP.
+    fn get_vp_register(
+        &mut self,
+        name: HvX64RegisterName,
+        vtl: GuestVtl,
+    ) -> Result<HvRegisterValue, HvError>;
+}
+
+impl<T> VpRegisterAccess for UhHypercallHandler<'_, '_, T, HypervisorBackedX86> {
+    fn vp_index(&self) -> u32 {
+        self.vp.vp_index().index()
+    }
+
+    fn target_vtl(&self, vtl: Option<Vtl>) -> Result<GuestVtl, HvError> {
+        self.target_vtl_no_higher(vtl.unwrap_or(self.vp.last_vtl().into()))
+    }
+
+    fn set_vsm_partition_config(
+        &mut self,
+        value: HvRegisterVsmPartitionConfig,
+        vtl: GuestVtl,
+    ) -> Result<(), HvError> {
+        self.vp.set_vsm_partition_config(value, vtl)
+    }
+
+    fn vsm_partition_config(&self, vtl: GuestVtl) -> Result<HvRegisterVsmPartitionConfig, HvError> {
+        self.vp.get_vsm_partition_config(vtl)
+    }
+
+    fn get_vp_register(
+        &mut self,
+        name: HvX64RegisterName,
+        vtl: GuestVtl,
+    ) -> Result<HvRegisterValue, HvError> {
+        // The runner can only access VTL 0 registers. Those of higher VTLs
+        // are owned by the hypervisor.
+        if vtl != GuestVtl::Vtl0 {
+            return Err(HvError::AccessDenied);
+        }
+        self.vp.runner.get_vp_register(name).map_err(|err| {
+            // The ioctl path does not report the hypervisor status, so just
+            // log the failure.
+            tracelimit::warn_ratelimited!(
+                ?name,
+                error = &err as &dyn std::error::Error,
+                "failed to get vp register for guest"
+            );
+            HvError::InvalidParameter
+        })
+    }
+}
+
+fn set_vp_registers(
+    access: &mut impl VpRegisterAccess,
+    partition_id: u64,
+    vp_index: u32,
+    vtl: Option<Vtl>,
+    registers: &[hypercall::HvRegisterAssoc],
+) -> hvdef::HvRepResult {
+    if partition_id != hvdef::HV_PARTITION_ID_SELF {
+        return Err((HvError::AccessDenied, 0));
+    }
+
+    if vp_index != hvdef::HV_VP_INDEX_SELF && vp_index != access.vp_index() {
+        return Err((HvError::InvalidVpIndex, 0));
+    }
+
+    let target_vtl = access.target_vtl(vtl).map_err(|e| (e, 0))?;
+
+    for (i, reg) in registers.iter().enumerate() {
+        if reg.name == HvX64RegisterName::VsmPartitionConfig.into() {
+            let value = HvRegisterVsmPartitionConfig::from(reg.value.as_u64());
+            access
+                .set_vsm_partition_config(value, target_vtl)
+                .map_err(|e| (e, i))?;
+        } else {
+            return Err((HvError::InvalidParameter, i));
+        }
+    }
+
+    Ok(())
+}
+
+fn get_vp_registers(
+    access: &mut impl VpRegisterAccess,
+    partition_id: u64,
+    vp_index: u32,
+    vtl: Option<Vtl>,
+    registers: &[hvdef::HvRegisterName],
+    output: &mut [HvRegisterValue],
+) -> hvdef::HvRepResult {
+    if partition_id != hvdef::HV_PARTITION_ID_SELF {
+        return Err((HvError::AccessDenied, 0));
+    }
+
+    if vp_index != hvdef::HV_VP_INDEX_SELF && vp_index != access.vp_index() {
+        return Err((HvError::InvalidVpIndex, 0));
+    }
+
+    let target_vtl = access.target_vtl(vtl).map_err(|e| (e, 0))?;
+
+    for (i, (&name, output)) in registers.iter().zip(output).enumerate() {
+        let name = HvX64RegisterName::from(name);
+        *output = if name == HvX64RegisterName::VsmPartitionConfig {
+            let value = access
+                .vsm_partition_config(target_vtl)
+                .map_err(|e| (e, i))?;
+            u64::from(value).into()
+        } else {
+            access
+                .get_vp_register(name, target_vtl)
+                .map_err(|e| (e, i))?
+        };
+    }
+
+    Ok(())
+}
+
 impl<T> hv1_hypercall::SetVpRegisters for UhHypercallHandler<'_, '_, T, HypervisorBackedX86> {
     fn set_vp_registers(
         &mut self,
@@ -1537,30 +2488,20 @@ fn set_vp_registers(
         vtl: Option<Vtl>,
         registers: &[hypercall::HvRegisterAssoc],
     ) -> hvdef::HvRepResult {
-        if partition_id != hvdef::HV_PARTITION_ID_SELF {
-            return Err((HvError::AccessDenied, 0));
-        }
-
-        if vp_index != hvdef::HV_VP_INDEX_SELF && vp_index != self.vp.vp_index().index() {
-            return Err((HvError::InvalidVpIndex, 0));
-        }
-
-        let target_vtl = self
-            .target_vtl_no_higher(vtl.unwrap_or(self.vp.last_vtl().into()))
-            .map_err(|e| (e, 0))?;
-
-        for (i, reg) in registers.iter().enumerate() {
-            if reg.name == HvX64RegisterName::VsmPartitionConfig.into() {
-                let value = HvRegisterVsmPartitionConfig::from(reg.value.as_u64());
-                self.vp
-                    .set_vsm_partition_config(value, target_vtl)
-                    .map_err(|e| (e, i))?;
-            } else {
-                return Err((HvError::InvalidParameter, i));
-            }
-        }
+        set_vp_registers(self, partition_id, vp_index, vtl, registers)
+    }
+}
 
-        Ok(())
+impl<T> hv1_hypercall::GetVpRegisters for UhHypercallHandler<'_, '_, T, HypervisorBackedX86> {
+    fn get_vp_registers(
+        &mut self,
+        partition_id: u64,
+        vp_index: u32,
+        vtl: Option<Vtl>,
+        registers: &[hvdef::HvRegisterName],
+        output: &mut [HvRegisterValue],
+    ) -> hvdef::HvRepResult {
+        get_vp_registers(self, partition_id, vp_index, vtl, registers, output)
     }
 }
 
@@ -1850,3 +2791,829 @@ fn restore(&mut self, state: Self::SavedState) -> Result<(), RestoreError> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deliverability_sequence_ids() {
+        let mut tracker = DeliverabilityTracker::default();
+        assert_eq!(tracker.deliverable(NotificationKind::Sint), None);
+
+        // Repeated requests share a sequence id until delivery.
+        let sint = tracker.request(NotificationKind::Sint);
+        assert_eq!(tracker.request(NotificationKind::Sint), sint);
+        let interrupt = tracker.request(NotificationKind::Interrupt);
+        assert_ne!(interrupt, sint);
+
+        assert_eq!(tracker.deliverable(NotificationKind::Sint), Some(sint));
+        assert_eq!(tracker.deliverable(NotificationKind::Sint), None);
+        let next_sint = tracker.request(NotificationKind::Sint);
+        assert!(next_sint > interrupt);
+        assert_eq!(
+            tracker.deliverable(NotificationKind::Interrupt),
+            Some(interrupt)
+        );
+        assert_eq!(tracker.deliverable(NotificationKind::Sint), Some(next_sint));
+    }
+
+    #[test]
+    fn vsm_partition_config_readback() {
+        assert_eq!(
+            u64::from(vsm_partition_config(&GuestVsmState::NotGuestEnabled)),
+            0
+        );
+
+        // Record the value the way `set_vsm_partition_config` does, and read
+        // it back with all its fields.
+        let value = HvRegisterVsmPartitionConfig::new()
+            .with_enable_vtl_protection(true)
+            .with_default_vtl_protection_mask(0xf)
+            .with_zero_memory_on_reset(true)
+            .with_intercept_vp_startup(true);
+        let state = GuestVsmState::Enabled {
+            vtl1: GuestVsmVtl1State {
+                enable_vtl_protection: value.enable_vtl_protection(),
+                inner: GuestVsmVtl1StateInner::SoftwareCvm {
+                    state: crate::SoftwareCvmVtl1State {
+                        default_vtl_protections: Some(HvMapGpaFlags::from(
+                            value.default_vtl_protection_mask() as u32,
+                        )),
+                        vsm_partition_config: value,
+                    },
+                },
+            },
+        };
+        assert_eq!(u64::from(vsm_partition_config(&state)), u64::from(value));
+    }
+
+    /// A VP that handles the get and set register hypercalls without a
+    /// partition behind it.
+    struct TestVp {
+        vtl: GuestVtl,
+        vsm_partition_config: HvRegisterVsmPartitionConfig,
+        rip: u64,
+        gps: [u64; 16],
+    }
+
+    impl VpRegisterAccess for TestVp {
+        fn vp_index(&self) -> u32 {
+            0
+        }
+
+        fn target_vtl(&self, vtl: Option<Vtl>) -> Result<GuestVtl, HvError> {
+            let vtl = vtl.unwrap_or(self.vtl.into());
+            if Vtl::from(self.vtl) < vtl {
+                return Err(HvError::AccessDenied);
+            }
+            Ok(vtl.try_into().unwrap())
+        }
+
+        fn set_vsm_partition_config(
+            &mut self,
+            value: HvRegisterVsmPartitionConfig,
+            vtl: GuestVtl,
+        ) -> Result<(), HvError> {
+            if vtl != GuestVtl::Vtl1 {
+                return Err(HvError::InvalidParameter);
+            }
+            self.vsm_partition_config = value;
+            Ok(())
+        }
+
+        fn vsm_partition_config(
+            &self,
+            vtl: GuestVtl,
+        ) -> Result<HvRegisterVsmPartitionConfig, HvError> {
+            if vtl != GuestVtl::Vtl1 {
+                return Err(HvError::InvalidParameter);
+            }
+            Ok(self.vsm_partition_config)
+        }
+
+        fn get_vp_register(
+            &mut self,
+            name: HvX64RegisterName,
+            vtl: GuestVtl,
+        ) -> Result<HvRegisterValue, HvError> {
+            if vtl != GuestVtl::Vtl0 {
+                return Err(HvError::AccessDenied);
+            }
+            match name {
+                HvX64RegisterName::Rip => Ok(self.rip.into()),
+                _ => Err(HvError::InvalidParameter),
+            }
+        }
+    }
+
+    impl hv1_hypercall::SetVpRegisters for TestVp {
+        fn set_vp_registers(
+            &mut self,
+            partition_id: u64,
+            vp_index: u32,
+            vtl: Option<Vtl>,
+            registers: &[hypercall::HvRegisterAssoc],
+        ) -> hvdef::HvRepResult {
+            set_vp_registers(self, partition_id, vp_index, vtl, registers)
+        }
+    }
+
+    impl hv1_hypercall::GetVpRegisters for TestVp {
+        fn get_vp_registers(
+            &mut self,
+            partition_id: u64,
+            vp_index: u32,
+            vtl: Option<Vtl>,
+            registers: &[hvdef::HvRegisterName],
+            output: &mut [HvRegisterValue],
+        ) -> hvdef::HvRepResult {
+            get_vp_registers(self, partition_id, vp_index, vtl, registers, output)
+        }
+    }
+
+    impl hv1_hypercall::X64RegisterState for TestVp {
+        fn rip(&mut self) -> u64 {
+            self.rip
+        }
+
+        fn set_rip(&mut self, rip: u64) {
+            self.rip = rip;
+        }
+
+        fn gp(&mut self, n: hv1_hypercall::X64HypercallRegister) -> u64 {
+            self.gps[n as usize]
+        }
+
+        fn set_gp(&mut self, n: hv1_hypercall::X64HypercallRegister, value: u64) {
+            self.gps[n as usize] = value;
+        }
+
+        fn xmm(&mut self, _n: usize) -> u128 {
+            unreachable!("no fast hypercalls")
+        }
+
+        fn set_xmm(&mut self, _n: usize, _value: u128) {
+            unreachable!("no fast hypercalls")
+        }
+    }
+
+    const TEST_DISPATCHER: hv1_hypercall::Dispatcher<TestVp> = hv1_hypercall::dispatcher!(
+        TestVp,
+        [
+            hv1_hypercall::HvSetVpRegisters,
+            hv1_hypercall::HvGetVpRegisters
+        ],
+    );
+
+    const INPUT_GPA: u64 = 0;
+    const OUTPUT_GPA: u64 = HV_PAGE_SIZE;
+
+    /// Issues a rep hypercall from `vp` with `header` and `reps` as input,
+    /// returning the hypercall output.
+    fn call<T: AsBytes>(
+        vp: &mut TestVp,
+        gm: &guestmem::GuestMemory,
+        code: hvdef::HypercallCode,
+        header: hypercall::GetSetVpRegisters,
+        reps: &[T],
+    ) -> hypercall::HypercallOutput {
+        use hv1_hypercall::X64RegisterState;
+
+        gm.write_plain(INPUT_GPA, &header).unwrap();
+        gm.write_at(INPUT_GPA + size_of_val(&header) as u64, reps.as_bytes())
+            .unwrap();
+        let control = hypercall::Control::new()
+            .with_code(code.0)
+            .with_rep_count(reps.len());
+        vp.set_gp(hv1_hypercall::X64HypercallRegister::Rcx, control.into());
+        vp.set_gp(hv1_hypercall::X64HypercallRegister::Rdx, INPUT_GPA);
+        vp.set_gp(hv1_hypercall::X64HypercallRegister::R8, OUTPUT_GPA);
+        TEST_DISPATCHER.dispatch(gm, hv1_hypercall::X64RegisterIo::new(&mut *vp, true));
+        hypercall::HypercallOutput::from(vp.gp(hv1_hypercall::X64HypercallRegister::Rax))
+    }
+
+    #[test]
+    fn get_vp_registers_after_set() {
+        let gm = guestmem::GuestMemory::allocate(2 * HV_PAGE_SIZE as usize);
+        let mut vp = TestVp {
+            vtl: GuestVtl::Vtl1,
+            vsm_partition_config: HvRegisterVsmPartitionConfig::new(),
+            rip: 0x1000,
+            gps: [0; 16],
+        };
+        let header = |partition_id, target_vtl: Vtl| hypercall::GetSetVpRegisters {
+            partition_id,
+            vp_index: hvdef::HV_VP_INDEX_SELF,
+            target_vtl: target_vtl.into(),
+            rsvd: [0; 3],
+        };
+
+        let value = HvRegisterVsmPartitionConfig::new()
+            .with_enable_vtl_protection(true)
+            .with_default_vtl_protection_mask(0x3)
+            .with_deny_lower_vtl_startup(true);
+        let output = call(
+            &mut vp,
+            &gm,
+            hvdef::HypercallCode::HvCallSetVpRegisters,
+            header(hvdef::HV_PARTITION_ID_SELF, Vtl::Vtl1),
+            &[hypercall::HvRegisterAssoc::from((
+                HvX64RegisterName::VsmPartitionConfig,
+                u64::from(value),
+            ))],
+        );
+        assert_eq!(output.call_status(), 0);
+        assert_eq!(output.elements_processed(), 1);
+
+        let output = call(
+            &mut vp,
+            &gm,
+            hvdef::HypercallCode::HvCallGetVpRegisters,
+            header(hvdef::HV_PARTITION_ID_SELF, Vtl::Vtl1),
+            &[hvdef::HvRegisterName::from(
+                HvX64RegisterName::VsmPartitionConfig,
+            )],
+        );
+        assert_eq!(output.call_status(), 0);
+        assert_eq!(output.elements_processed(), 1);
+        let read: u64 = gm.read_plain(OUTPUT_GPA).unwrap();
+        assert_eq!(read, u64::from(value));
+
+        // Other registers are read from the VP.
+        let rip = vp.rip;
+        let output = call(
+            &mut vp,
+            &gm,
+            hvdef::HypercallCode::HvCallGetVpRegisters,
+            header(hvdef::HV_PARTITION_ID_SELF, Vtl::Vtl0),
+            &[hvdef::HvRegisterName::from(HvX64RegisterName::Rip)],
+        );
+        assert_eq!(output.call_status(), 0);
+        let read: u64 = gm.read_plain(OUTPUT_GPA).unwrap();
+        assert_eq!(read, rip);
+
+        // But not for higher VTLs, whose registers the hypervisor owns.
+        let output = call(
+            &mut vp,
+            &gm,
+            hvdef::HypercallCode::HvCallGetVpRegisters,
+            header(hvdef::HV_PARTITION_ID_SELF, Vtl::Vtl1),
+            &[hvdef::HvRegisterName::from(HvX64RegisterName::Rip)],
+        );
+        assert_eq!(output.call_status(), HvError::AccessDenied.0);
+        assert_eq!(output.elements_processed(), 0);
+
+        // Another partition's registers cannot be read.
+        let output = call(
+            &mut vp,
+            &gm,
+            hvdef::HypercallCode::HvCallGetVpRegisters,
+            header(1, Vtl::Vtl1),
+            &[hvdef::HvRegisterName::from(
+                HvX64RegisterName::VsmPartitionConfig,
+            )],
+        );
+        assert_eq!(output.call_status(), HvError::AccessDenied.0);
+        assert_eq!(output.elements_processed(), 0);
+
+        // Nor can a VTL higher than the caller's.
+        vp.vtl = GuestVtl::Vtl0;
+        for name in [
+            HvX64RegisterName::VsmPartitionConfig,
+            HvX64RegisterName::Rip,
+        ] {
+            let output = call(
+                &mut vp,
+                &gm,
+                hvdef::HypercallCode::HvCallGetVpRegisters,
+                header(hvdef::HV_PARTITION_ID_SELF, Vtl::Vtl1),
+                &[hvdef::HvRegisterName::from(name)],
+            );
+            assert_eq!(output.call_status(), HvError::AccessDenied.0);
+            assert_eq!(output.elements_processed(), 0);
+        }
+    }
+
+    #[test]
+    fn unknown_exit_message() {
+        let mut stats = ProcessorStatsX86::default();
+        let reason = unexpected_exit(&mut stats, HvMessageType(0x8000_ffff));
+        assert!(matches!(
+            reason,
+            VpHaltReason::InvalidVmState(UhRunVpError::UnexpectedExit(HvMessageType(0x8000_ffff)))
+        ));
+        assert_eq!(stats.unexpected_exit.get(), 1);
+    }
+
+    #[test]
+    fn legacy_xsave_round_trip() {
+        use x86defs::cpuid::CpuidFunction;
+        use x86defs::xsave::XFEATURE_YMM;
+        use x86defs::xsave::XSAVE_VARIABLE_OFFSET;
+
+        // A processor with x87, SSE, and AVX state.
+        const YMM_OFFSET: u32 = XSAVE_VARIABLE_OFFSET as u32;
+        const YMM_LEN: u32 = 256;
+        let topology = vm_topology::processor::TopologyBuilder::new_x86()
+            .x2apic(vm_topology::processor::x86::X2ApicState::Unsupported)
+            .build(1)
+            .unwrap();
+        let mut cpuid = |function: u32, index: u32| match CpuidFunction(function) {
+            CpuidFunction::VendorAndMaxFunction => {
+                [CpuidFunction::ExtendedStateEnumeration.0, 0, 0, 0]
+            }
+            CpuidFunction::VersionAndFeatures => [0, 0, 1 << 26, 0],
+            CpuidFunction::ExtendedStateEnumeration => match index {
+                0 => [
+                    (XFEATURE_X87 | XFEATURE_SSE | XFEATURE_YMM) as u32,
+                    0,
+                    YMM_OFFSET + YMM_LEN,
+                    0,
+                ],
+                2 => [YMM_LEN, YMM_OFFSET, 0, 0],
+                _ => [0; 4],
+            },
+            _ => [0; 4],
+        };
+        let caps = virt::x86::X86PartitionCapabilities::from_cpuid(&topology, &mut cpuid);
+
+        let mut fx_state = Fxsave::new_zeroed();
+        fx_state.fcw = 0x27f;
+        fx_state.mxcsr = 0x1f80 | 0x8000;
+        fx_state.xmm[3] = [0xab; 16];
+        let xsave = legacy_xsave(&fx_state, &caps);
+        assert_eq!(
+            legacy_fx_state(&xsave, &caps).unwrap().as_bytes(),
+            fx_state.as_bytes()
+        );
+
+        // Extended state cannot be restored yet.
+        let mut standard = vec![0u8; (YMM_OFFSET + YMM_LEN) as usize];
+        standard[..XSAVE_LEGACY_LEN].copy_from_slice(fx_state.as_bytes());
+        let header = XsaveHeader {
+            xstate_bv: XFEATURE_X87 | XFEATURE_SSE | XFEATURE_YMM,
+            ..FromZeroes::new_zeroed()
+        };
+        standard[XSAVE_LEGACY_LEN..XSAVE_VARIABLE_OFFSET].copy_from_slice(header.as_bytes());
+        standard[YMM_OFFSET as usize..].fill(0xcd);
+        let xsave = vp::Xsave::from_standard(&standard, &caps);
+        assert!(matches!(
+            legacy_fx_state(&xsave, &caps),
+            Err(vp_state::Error::ExtendedXsaveState(XFEATURE_YMM))
+        ));
+    }
+
+    #[test]
+    fn emulator_state_round_trip() {
+        let seg = |n: u64| HvRegisterValue::from(n as u128 | ((n as u128) << 64));
+        let values = [
+            0x1000u64.into(),
+            seg(1),
+            seg(2),
+            seg(3),
+            seg(4),
+            seg(5),
+            0x8000_0011u64.into(),
+            0xd01u64.into(),
+        ];
+        let gps = std::array::from_fn(|i| 0x100 + i as u64);
+        let header = HvX64InterceptMessageHeader {
+            rip: 0xfff0,
+            rflags: 0x202,
+            ..FromZeroes::new_zeroed()
+        };
+        let state = emulator_cpu_state(values, gps, &header);
+
+        // Apply the writes to a register file holding the values that were
+        // read, and check that reading the state back finds the same values.
+        let mut regs = EMULATOR_REGISTERS
+            .into_iter()
+            .zip(values.map(|v| v.as_u128()))
+            .collect::<Vec<_>>();
+        let mut context_gps = gps;
+        context_gps[x86emu::CpuState::RSP] = 0xc2;
+        for (name, value) in emulator_register_writes(&state) {
+            let index = name.0.wrapping_sub(HvX64RegisterName::Rax.0) as usize;
+            if name != HvX64RegisterName::Rsp && index < context_gps.len() {
+                context_gps[index] = value;
+            } else if let Some(reg) = regs.iter_mut().find(|(n, _)| *n == name) {
+                reg.1 = value.into();
+            } else {
+                regs.push((name, value.into()));
+            }
+        }
+        let reg = |name| regs.iter().find(|&&(n, _)| n == name).unwrap().1;
+
+        // RSP in the CPU context is untouched, since it is written as a register.
+        assert_eq!(context_gps[x86emu::CpuState::RSP], 0xc2);
+        assert_eq!(reg(HvX64RegisterName::Rip), 0xfff0);
+        assert_eq!(reg(HvX64RegisterName::Rflags), 0x202);
+        let values = EMULATOR_REGISTERS.map(|name| HvRegisterValue::from(reg(name)));
+        assert_eq!(emulator_cpu_state(values, context_gps, &header), state);
+    }
+
+    #[test]
+    fn io_port_rax_mismatch() {
+        let mut stats = ProcessorStatsX86::default();
+        let mut rax = 0x1234;
+        sync_io_port_rax(&mut stats, 0x1234, &mut rax);
+        assert_eq!(stats.io_port_rax_mismatch.get(), 0);
+
+        sync_io_port_rax(&mut stats, 0xabcd, &mut rax);
+        assert_eq!(rax, 0xabcd);
+        assert_eq!(stats.io_port_rax_mismatch.get(), 1);
+    }
+
+    #[test]
+    fn mtrr_register_mapping() {
+        assert_eq!(
+            mtrr_register(x86defs::X86X_MSR_MTRR_PHYSBASE0),
+            Some(HvX64RegisterName::MsrMtrrPhysBase0)
+        );
+        assert_eq!(
+            mtrr_register(x86defs::X86X_MSR_MTRR_PHYSBASE0 + 5),
+            Some(HvX64RegisterName::MsrMtrrPhysMask2)
+        );
+        assert_eq!(
+            mtrr_register(0x21e),
+            Some(HvX64RegisterName::MsrMtrrPhysBaseF)
+        );
+        assert_eq!(
+            mtrr_register(x86defs::X86X_MSR_MTRR_FIX4K_F8000),
+            Some(HvX64RegisterName::MsrMtrrFix4kF8000)
+        );
+        assert_eq!(mtrr_register(x86defs::X86X_MSR_CR_PAT), None);
+    }
+
+    /// Returns emulated APICs for both VTLs of the BSP, each in its own set.
+    fn new_lapics() -> (
+        [virt_support_apic::LocalApicSet; 2],
+        VtlArray<apic::UhApicState, 2>,
+    ) {
+        let vp_info = vm_topology::processor::x86::X86VpInfo {
+            base: virt::VpInfo {
+                vp_index: VpIndex::BSP,
+                vnode: 0,
+            },
+            apic_id: 0,
+        };
+        let sets = [(); 2].map(|()| virt_support_apic::LocalApicSet::builder().build());
+        let lapics = [
+            apic::UhApicState::new(sets[0].add_apic(&vp_info), GuestVtl::Vtl0, &vp_info.base),
+            apic::UhApicState::new(sets[1].add_apic(&vp_info), GuestVtl::Vtl1, &vp_info.base),
+        ]
+        .into();
+        (sets, lapics)
+    }
+
+    #[test]
+    fn pass_through_cpuid_skips_lock() {
+        use std::sync::mpsc;
+        use std::sync::Arc;
+
+        const OVERRIDDEN: u32 = 1;
+        const PASS_THROUGH: u32 = 7;
+        let default = [0x11, 0x22, 0x33, 0x44];
+        let cpuid = Arc::new(Mutex::new(CpuidLeafSet::new(vec![virt::CpuidLeaf::new(
+            OVERRIDDEN,
+            [1, 2, 3, 4],
+        )])));
+
+        let query = |function| {
+            let cpuid = cpuid.clone();
+            let (send, recv) = mpsc::channel();
+            std::thread::spawn(move || {
+                send.send(cpuid_result(&cpuid, &[OVERRIDDEN], function, 0, &default))
+                    .unwrap();
+            });
+            recv
+        };
+
+        let guard = cpuid.lock();
+
+        // A pass-through leaf completes while another thread holds the lock.
+        let pass_through = query(PASS_THROUGH);
+        assert_eq!(
+            pass_through.recv_timeout(Duration::from_secs(10)).unwrap(),
+            default
+        );
+
+        // A modified leaf still waits for the lock.
+        let overridden = query(OVERRIDDEN);
+        assert!(overridden.recv_timeout(Duration::from_millis(100)).is_err());
+        drop(guard);
+        assert_eq!(
+            overridden.recv_timeout(Duration::from_secs(10)).unwrap(),
+            [1, 2, 3, 4]
+        );
+    }
+
+    struct TestApicClient;
+
+    impl virt_support_apic::ApicClient for TestApicClient {
+        fn cr8(&mut self) -> u32 {
+            0
+        }
+
+        fn set_cr8(&mut self, _value: u32) {}
+
+        fn set_apic_base(&mut self, _value: u64) {}
+
+        fn wake(&mut self, _vp_index: VpIndex) {}
+
+        fn eoi(&mut self, _vector: u8) {}
+
+        fn now(&mut self) -> vmcore::vmtime::VmTime {
+            vmcore::vmtime::VmTime::from_100ns(0)
+        }
+
+        fn pull_offload(&mut self) -> ([u32; 8], [u32; 8]) {
+            unreachable!()
+        }
+    }
+
+    #[test]
+    fn emulated_apic_round_trip() {
+        // The hypervisor owns the APIC, so its state cannot be accessed.
+        assert!(matches!(
+            save_emulated_apic(&mut None, GuestVtl::Vtl0),
+            Err(vp_state::Error::HypervisorOwnedApic)
+        ));
+
+        // Software enable the APIC and program a periodic LVT timer entry.
+        const SVR: u64 = 0xf0;
+        const LVT_TIMER: u64 = 0x320;
+        let lvt_timer: u32 = 0x2_00ef;
+        let (_sets, lapics) = new_lapics();
+        let mut lapics = Some(lapics);
+        let lapic = &mut lapics.as_mut().unwrap()[GuestVtl::Vtl0].lapic;
+        for (offset, value) in [(SVR, 0x1ffu32), (LVT_TIMER, lvt_timer)] {
+            lapic
+                .access(&mut TestApicClient)
+                .mmio_write(0xfee0_0000 + offset, &value.to_ne_bytes());
+        }
+
+        let saved = save_emulated_apic(&mut lapics, GuestVtl::Vtl0).unwrap();
+        assert_eq!(saved.registers[(LVT_TIMER >> 4) as usize], lvt_timer);
+        assert!(matches!(
+            restore_emulated_apic(&mut None, GuestVtl::Vtl0, &saved),
+            Err(vp_state::Error::HypervisorOwnedApic)
+        ));
+
+        let (_sets, fresh) = new_lapics();
+        let mut fresh = Some(fresh);
+        restore_emulated_apic(&mut fresh, GuestVtl::Vtl0, &saved).unwrap();
+        assert_eq!(
+            save_emulated_apic(&mut fresh, GuestVtl::Vtl0).unwrap(),
+            saved
+        );
+    }
+
+    #[test]
+    fn pending_nmi_activity() {
+        let mut activity = vp::Activity::default();
+
+        // The hypervisor owns the APIC, so its pending NMI cannot be accessed.
+        assert!(matches!(
+            save_apic_activity(&None, GuestVtl::Vtl0, &mut activity),
+            Err(vp_state::Error::HypervisorOwnedApic)
+        ));
+        assert!(matches!(
+            restore_apic_activity(&mut None, GuestVtl::Vtl0, &activity),
+            Err(vp_state::Error::HypervisorOwnedApic)
+        ));
+
+        // Send an NMI to VTL 0 and latch it without injecting it.
+        let (sets, lapics) = new_lapics();
+        let mut lapics = Some(lapics);
+        sets[0].request_interrupt(0xfee0_0000, 0x400, |_| {});
+        let lapic = &mut lapics.as_mut().unwrap()[GuestVtl::Vtl0];
+        let nmi = lapic.lapic.flush().nmi;
+        assert!(nmi);
+        assert!(lapic.latch_nmi(nmi));
+
+        save_apic_activity(&lapics, GuestVtl::Vtl0, &mut activity).unwrap();
+        assert!(activity.nmi_pending);
+        save_apic_activity(&lapics, GuestVtl::Vtl1, &mut activity).unwrap();
+        assert!(!activity.nmi_pending);
+
+        // The NMI is still pending after a restore into fresh APICs.
+        activity.nmi_pending = true;
+        let (_sets, lapics) = new_lapics();
+        let mut lapics = Some(lapics);
+        restore_apic_activity(&mut lapics, GuestVtl::Vtl0, &activity).unwrap();
+        let lapic = &mut lapics.as_mut().unwrap()[GuestVtl::Vtl0];
+        let nmi = lapic.lapic.flush().nmi;
+        assert!(!nmi);
+        assert!(lapic.latch_nmi(nmi));
+    }
+
+    #[test]
+    fn synic_timers_round_trip() {
+        let periodic = hvdef::HvSynicStimerConfig::new()
+            .with_enabled(true)
+            .with_periodic(true)
+            .with_sint(2);
+        let one_shot = hvdef::HvSynicStimerConfig::new()
+            .with_enabled(true)
+            .with_sint(3);
+        let mut saved = vp::SynicTimers {
+            timers: [vp::SynicTimer::default(); 4],
+        };
+        // A periodic timer with a 1ms period.
+        saved.timers[0].config = periodic.into();
+        saved.timers[0].count = 10_000;
+        // A one-shot timer due in the future.
+        saved.timers[1].config = one_shot.into();
+        saved.timers[1].count = 50_000_000;
+        // A one-shot timer that expired before it was saved.
+        saved.timers[3].config = one_shot.with_auto_enable(true).into();
+        saved.timers[3].count = 1;
+
+        // Restore into a fresh set of registers, checking that no timer is
+        // enabled while its count is written.
+        let mut registers = std::collections::HashMap::new();
+        for (name, value) in synic_timer_register_writes(&saved) {
+            if let Some(index) = STIMER_REGISTERS.iter().position(|&n| n == name) {
+                if index % 2 == 1 {
+                    let config = hvdef::HvSynicStimerConfig::from(
+                        registers
+                            .get(&STIMER_REGISTERS[index - 1].0)
+                            .copied()
+                            .unwrap_or(0),
+                    );
+                    assert!(!config.enabled() && !config.auto_enable());
+                }
+            }
+            registers.insert(name.0, value.as_u64());
+        }
+
+        let values = STIMER_REGISTERS.map(|name| HvRegisterValue::from(registers[&name.0]));
+        assert_eq!(synic_timers_from_registers(&values), saved);
+    }
+
+    #[test]
+    fn variable_mtrr_readback() {
+        let mut registers = std::collections::HashMap::new();
+        let base = x86defs::X86X_MSR_MTRR_PHYSBASE0 + 2;
+        let mask = base + 1;
+        let mut write = |msr, value| {
+            write_mtrr_msr(msr, value, |name, value| {
+                registers.insert(name.0, value.as_u64());
+                Ok::<_, ()>(())
+            })
+        };
+        write(base, 0xc000_0006).unwrap();
+        write(mask, 0xf_ffc0_0800).unwrap();
+
+        let read = |msr| {
+            read_mtrr_msr(msr, |name| {
+                registers
+                    .get(&name.0)
+                    .map(|&value| HvRegisterValue::from(value))
+                    .ok_or(())
+            })
+        };
+        assert_eq!(read(base).unwrap(), 0xc000_0006);
+        assert_eq!(read(mask).unwrap(), 0xf_ffc0_0800);
+        assert_eq!(
+            registers[&HvX64RegisterName::MsrMtrrPhysBase1.0],
+            0xc000_0006
+        );
+        assert_eq!(
+            registers[&HvX64RegisterName::MsrMtrrPhysMask1.0],
+            0xf_ffc0_0800
+        );
+
+        // Registers the hypervisor rejects become #GPs, and non-MTRR MSRs are
+        // left to the caller.
+        assert!(matches!(
+            read(x86defs::X86X_MSR_MTRR_PHYSBASE0),
+            Err(MsrError::InvalidAccess)
+        ));
+        assert!(matches!(
+            read(x86defs::X86X_MSR_CR_PAT),
+            Err(MsrError::Unknown)
+        ));
+    }
+
+    #[test]
+    fn exit_history_wraps() {
+        let mut history = ExitHistory::<4>::new();
+        assert_eq!(history.iter().count(), 0);
+
+        for i in 0..3 {
+            history.record(HvMessageType::HvMessageTypeX64Halt, i, i * 10);
+        }
+        let seqs: Vec<_> = history.iter().map(|(seq, _)| seq).collect();
+        assert_eq!(seqs, [0, 1, 2]);
+
+        for i in 3..10 {
+            history.record(HvMessageType::HvMessageTypeX64CpuidIntercept, i, i * 10);
+        }
+        let records: Vec<_> = history
+            .iter()
+            .map(|(seq, record)| (seq, record.typ, record.rip, record.tsc))
+            .collect();
+        assert_eq!(
+            records,
+            (6..10)
+                .map(|i| (i, HvMessageType::HvMessageTypeX64CpuidIntercept, i, i * 10))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn crash_reported_once() {
+        use std::sync::atomic::AtomicUsize;
+
+        struct Sink(AtomicUsize);
+
+        impl GuestCrashNotify for Sink {
+            fn notify(&self, crash: &GuestCrash) {
+                assert_eq!(crash.kind, GuestCrashKind::TripleFault);
+                assert_eq!(crash.rip, 0xfff0);
+                self.0.fetch_add(1, Relaxed);
+            }
+        }
+
+        let sink = Sink(AtomicUsize::new(0));
+        let crash = GuestCrash {
+            kind: GuestCrashKind::TripleFault,
+            vp_index: VpIndex::BSP,
+            vtl: GuestVtl::Vtl0,
+            rip: 0xfff0,
+            rflags: 0x2,
+            registers: None,
+        };
+        let mut reporter = GuestCrashReporter::default();
+        reporter.report(Some(&sink), &crash);
+        reporter.report(Some(&sink), &crash);
+        assert_eq!(sink.0.load(Relaxed), 1);
+
+        // A crash after the VP is reset is reported again.
+        reporter.reset();
+        reporter.report(Some(&sink), &crash);
+        assert_eq!(sink.0.load(Relaxed), 2);
+
+        // Reporting without a sink just logs.
+        GuestCrashReporter::default().report(None, &crash);
+    }
+
+    #[test]
+    fn misc_enable_write() {
+        let mut msrs = PlatformMsrs::new();
+        let initial = msrs.read(x86defs::X86X_IA32_MSR_MISC_ENABLE).unwrap();
+        assert!(x86defs::MiscEnable::from(initial).fast_string());
+
+        // Clear fast strings and set an unsupported bit, which is ignored.
+        let value = u64::from(
+            x86defs::MiscEnable::from(initial)
+                .with_fast_string(false)
+                .with_limit_cpuid(true)
+                .with_tcc(true),
+        );
+        msrs.write(x86defs::X86X_IA32_MSR_MISC_ENABLE, value)
+            .unwrap();
+        let misc_enable =
+            x86defs::MiscEnable::from(msrs.read(x86defs::X86X_IA32_MSR_MISC_ENABLE).unwrap());
+        assert!(!misc_enable.fast_string());
+        assert!(misc_enable.limit_cpuid());
+        assert!(!misc_enable.tcc());
+        assert!(misc_enable.bts_unavailable());
+    }
+
+    #[test]
+    fn feature_control_lock() {
+        let mut msrs = PlatformMsrs::new();
+        let msr = x86defs::X86X_IA32_MSR_FEATURE_CONTROL;
+        assert_eq!(msrs.read(msr).unwrap(), 0);
+
+        // Reserved bits are rejected.
+        assert!(matches!(
+            msrs.write(msr, 1 << 3),
+            Err(MsrError::InvalidAccess)
+        ));
+
+        // Enable VMX outside SMX and lock the MSR.
+        msrs.write(msr, 0b100).unwrap();
+        msrs.write(msr, 0b101).unwrap();
+        assert_eq!(msrs.read(msr).unwrap(), 0b101);
+
+        // Further writes fail, even to clear the lock bit.
+        assert!(matches!(msrs.write(msr, 0), Err(MsrError::InvalidAccess)));
+        assert!(matches!(
+            msrs.write(msr, 0b101),
+            Err(MsrError::InvalidAccess)
+        ));
+        assert_eq!(msrs.read(msr).unwrap(), 0b101);
+
+        // Platform ID is read-only.
+        assert_eq!(msrs.read(x86defs::X86X_IA32_MSR_PLATFORM_ID).unwrap(), 0);
+        assert!(matches!(
+            msrs.write(x86defs::X86X_IA32_MSR_PLATFORM_ID, 0),
+            Err(MsrError::InvalidAccess)
+        ));
+    }
+}
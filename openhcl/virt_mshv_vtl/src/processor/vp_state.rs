@@ -30,4 +30,10 @@ pub enum Error {
     Unimplemented(&'static str),
     #[error("failed to set apic base MSR")]
     InvalidApicBase(#[source] virt_support_apic::InvalidApicBase),
+    #[error("xsave state contains features {0:#x} unsupported by the partition")]
+    UnsupportedXsaveFeatures(u64),
+    #[error("extended xsave features {0:#x} cannot be restored on this backing")]
+    ExtendedXsaveState(u64),
+    #[error("apic state is owned by the hypervisor")]
+    HypervisorOwnedApic,
 }
@@ -10,6 +10,7 @@
 use anyhow::Context;
 use cfg_if::cfg_if;
 use chipset_device_resources::IRQ_LINE_SET;
+use chipset_device_resources::PIRQ_LINE_SET;
 use debug_ptr::DebugPtr;
 use disk_backend::resolve::ResolveDiskParameters;
 use disk_backend::SimpleDisk;
@@ -39,6 +40,7 @@
 use hvlite_defs::config::VpciDeviceConfig;
 use hvlite_defs::config::Vtl2BaseAddressType;
 use hvlite_defs::config::Vtl2Config;
+use hvlite_defs::config::WallClockConfig;
 use hvlite_defs::config::X2ApicConfig;
 use hvlite_defs::config::X86TopologyConfig;
 use hvlite_defs::rpc::PulseSaveRestoreError;
@@ -52,6 +54,8 @@
 use input_core::InputData;
 use input_core::MultiplexedInputHandle;
 use inspect::Inspect;
+use local_clock::LocalClockDelta;
+use local_clock::LocalClockTime;
 use membacking::GuestMemoryBuilder;
 use membacking::GuestMemoryManager;
 use membacking::SharedMemoryBacking;
@@ -123,6 +127,10 @@
 use vmcore::vmtime::VmTimeSource;
 use vmgs_broker::resolver::VmgsFileResolver;
 use vmm_core::acpi_builder::AcpiTablesBuilder;
+use vmm_core::emuplat::wall_clock::FrozenWallClock;
+use vmm_core::emuplat::wall_clock::HostWallClock;
+use vmm_core::emuplat::wall_clock::OffsetWallClock;
+use vmm_core::emuplat::wall_clock::WallClockSource;
 use vmm_core::input_distributor::InputDistributor;
 use vmm_core::partition_unit::block_on_vp;
 use vmm_core::partition_unit::Halt;
@@ -191,6 +199,7 @@ fn from_config(config: Config) -> Self {
             vmbus_devices: config.vmbus_devices,
             chipset_devices: config.chipset_devices,
             generation_id_recv: config.generation_id_recv,
+            wall_clock: config.wall_clock,
         }
     }
 }
@@ -230,6 +239,7 @@ pub struct Manifest {
     vmbus_devices: Vec<(DeviceVtl, Resource<VmbusDeviceHandleKind>)>,
     chipset_devices: Vec<ChipsetDeviceHandle>,
     generation_id_recv: Option<mesh::Receiver<[u8; 16]>>,
+    wall_clock: WallClockConfig,
 }
 
 #[derive(Protobuf, SavedStateRoot)]
@@ -1020,6 +1030,18 @@ async fn load(
 
         let generation_id_recv = cfg.generation_id_recv.unwrap_or_else(|| mesh::channel().1);
 
+        let wall_clock: Box<dyn WallClockSource> = match cfg.wall_clock {
+            WallClockConfig::Host => Box::new(HostWallClock),
+            WallClockConfig::Offset { millis } => {
+                Box::new(OffsetWallClock::new(LocalClockDelta::from_millis(millis)))
+            }
+            WallClockConfig::Frozen {
+                millis_since_unix_epoch,
+            } => Box::new(FrozenWallClock::new(
+                LocalClockTime::from_millis_since_unix_epoch(millis_since_unix_epoch),
+            )),
+        };
+
         let logger = Box::new(emuplat::firmware::MeshLogger::new(
             cfg.firmware_event_send.clone(),
         ));
@@ -1100,7 +1122,7 @@ async fn load(
                     },
                     vsm_config: None,
                     // TODO: persist SystemTimeClock time across reboots.
-                    time_source: Box::new(local_clock::SystemTimeClock::new()),
+                    time_source: wall_clock.new_clock(),
                 })
             }
             #[cfg(guest_arch = "x86_64")]
@@ -1129,6 +1151,8 @@ async fn load(
                             with_psp: cfg.chipset.with_generic_psp,
                             pm_base: PM_BASE,
                             acpi_irq: SYSTEM_IRQ_ACPI,
+                            ssdts: Vec::new(),
+                            pci_root_buses: Vec::new(),
                         };
                         let srat = acpi_tables_builder.build_srat();
                         firmware_pcat::config::PcatBiosConfig {
@@ -1332,7 +1356,7 @@ async fn load(
         let deps_generic_cmos_rtc = (cfg.chipset.with_generic_cmos_rtc).then(|| {
             // TODO: persist SystemTimeClock time across reboots.
             // TODO: move to instantiate via a resource.
-            let time_source = Box::new(local_clock::SystemTimeClock::new());
+            let time_source = wall_clock.new_clock();
             dev::GenericCmosRtcDeps {
                 irq: 8,
                 time_source,
@@ -1421,6 +1445,9 @@ async fn load(
                 Some(dev::WinbondSuperIoAndFloppyFullDeps {
                     primary_disk_drive,
                     secondary_disk_drive,
+                    // COM ports are emulated as standalone devices.
+                    serial_ports: [None, None],
+                    parallel_port: None,
                 }),
             ),
             (false, false) => (None, None),
@@ -1485,7 +1512,7 @@ async fn load(
         let deps_piix4_cmos_rtc = (cfg.chipset.with_piix4_cmos_rtc).then(|| {
             // TODO: persist SystemTimeClock time across reboots.
             // TODO: move to instantiate via a resource.
-            let time_source = Box::new(local_clock::SystemTimeClock::new());
+            let time_source = wall_clock.new_clock();
             dev::Piix4CmosRtcDeps {
                 time_source,
                 initial_cmos: initial_rtc_cmos,
@@ -2046,12 +2073,17 @@ async fn add_virtio_vpci(
                         .with_pci_addr(0, device_number, 0)
                         .on_pci_bus(bus)
                         .try_add(|services| {
+                            // With the PIIX4 PCI-ISA bridge present, INTA# is
+                            // routed through PIRQA, whose default routing
+                            // matches the hard-wired IRQ reported above.
+                            let interrupt = if cfg.chipset.with_piix4_pci_isa_bridge {
+                                services.new_line(PIRQ_LINE_SET, "interrupt", 0)
+                            } else {
+                                services.new_line(IRQ_LINE_SET, "interrupt", pci_inta_line)
+                            };
                             VirtioPciDevice::new(
                                 device,
-                                PciInterruptModel::IntX(
-                                    PciInterruptPin::IntA,
-                                    services.new_line(IRQ_LINE_SET, "interrupt", pci_inta_line),
-                                ),
+                                PciInterruptModel::IntX(PciInterruptPin::IntA, interrupt),
                                 partition.clone().into_doorbell_registration(Vtl::Vtl0),
                                 &mut services.register_mmio(),
                                 Some(&mapper),
@@ -2181,6 +2213,7 @@ async fn add_virtio_vpci(
                     cfg.hypervisor.with_vtl2.is_some().then_some(&gm),
                 ],
                 debugger_rpc: cfg.debugger_rpc,
+                vp_affinity: Vec::new(),
             },
         )
         .context("failed to create partition unit")?;
@@ -2293,6 +2326,8 @@ async fn load_firmware(&mut self, vtl2_only: bool) -> anyhow::Result<()> {
             with_pit: self.chipset_cfg.with_generic_pit,
             pm_base: PM_BASE,
             acpi_irq: SYSTEM_IRQ_ACPI,
+            ssdts: Vec::new(),
+            pci_root_buses: Vec::new(),
         };
 
         if vtl2_only {
@@ -2466,7 +2501,8 @@ async fn load_firmware(&mut self, vtl2_only: bool) -> anyhow::Result<()> {
             &regs,
             self.partition.caps(),
             &self.processor_topology.vp_arch(VpIndex::BSP),
-        );
+        )
+        .context("invalid initial registers")?;
 
         tracing::debug!(?initial_regs, "initial_registers");
         self.partition_unit
@@ -1076,6 +1076,12 @@ enum ParameterAreaState {
                         | X86Register::R11(_)
                         | X86Register::R12(_)
                         | X86Register::Rflags(_)
+                        | X86Register::Dr0(_)
+                        | X86Register::Dr1(_)
+                        | X86Register::Dr2(_)
+                        | X86Register::Dr3(_)
+                        | X86Register::Dr6(_)
+                        | X86Register::Dr7(_)
                         | X86Register::Idtr(_)
                         | X86Register::MtrrDefType(_)
                         | X86Register::MtrrFix64k00000(_)
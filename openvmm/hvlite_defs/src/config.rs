@@ -51,6 +51,7 @@ pub struct Config {
     pub vmbus_devices: Vec<(DeviceVtl, Resource<VmbusDeviceHandleKind>)>,
     pub chipset_devices: Vec<ChipsetDeviceHandle>,
     pub generation_id_recv: Option<mesh::Receiver<[u8; 16]>>,
+    pub wall_clock: WallClockConfig,
 }
 
 // ARM64 needs a larger low gap.
@@ -295,6 +296,18 @@ pub struct SwitchPortId {
     PcatBootDevice::Floppy,
 ];
 
+/// The source of wall-clock time for the guest's real time clocks.
+#[derive(MeshPayload, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WallClockConfig {
+    /// Report the host's time.
+    #[default]
+    Host,
+    /// Report the host's time, skewed by `millis` milliseconds.
+    Offset { millis: i64 },
+    /// Report a fixed time that only changes when the guest sets it.
+    Frozen { millis_since_unix_epoch: i64 },
+}
+
 #[derive(MeshPayload, Debug, Clone, Copy, PartialEq)]
 pub enum PcatBootDevice {
     Floppy,
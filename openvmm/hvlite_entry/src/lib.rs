@@ -55,6 +55,7 @@
 use hvlite_defs::config::VpciDeviceConfig;
 use hvlite_defs::config::Vtl2BaseAddressType;
 use hvlite_defs::config::Vtl2Config;
+use hvlite_defs::config::WallClockConfig;
 use hvlite_defs::config::DEFAULT_MMIO_GAPS;
 use hvlite_defs::config::DEFAULT_MMIO_GAPS_WITH_VTL2;
 use hvlite_defs::config::DEFAULT_PCAT_BOOT_ORDER;
@@ -1226,6 +1227,7 @@ fn vm_config_from_command_line(
         firmware_event_send: None,
         debugger_rpc: None,
         generation_id_recv: None,
+        wall_clock: WallClockConfig::Host,
     };
 
     storage.build_config(&mut cfg, &mut resources, opt.scsi_sub_channels)?;
@@ -21,6 +21,7 @@
 use hvlite_defs::config::ProcessorTopologyConfig;
 use hvlite_defs::config::VirtioBus;
 use hvlite_defs::config::VmbusConfig;
+use hvlite_defs::config::WallClockConfig;
 use hvlite_defs::rpc::VmRpc;
 use hvlite_defs::worker::VmWorkerParameters;
 use hvlite_defs::worker::VM_WORKER;
@@ -512,6 +513,7 @@ async fn create_vm(&mut self, request: vmservice::CreateVmRequest) -> anyhow::Re
             debugger_rpc: None,
             chipset_devices: chipset.chipset_devices,
             generation_id_recv: None,
+            wall_clock: WallClockConfig::Host,
         };
 
         let mut scsi_rpc = None;
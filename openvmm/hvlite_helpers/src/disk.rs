@@ -56,7 +56,13 @@ pub fn open_disk_type(path: &Path, read_only: bool) -> anyhow::Result<Resource<D
                 .write(!read_only)
                 .open(path)?;
 
-            Resource::new(disk_backend_resources::FileDiskHandle(file))
+            Resource::new(disk_backend_resources::FileDiskHandle {
+                file,
+                direct: false,
+                sector_size: None,
+                physical_sector_size: None,
+                disk_id: None,
+            })
         }
     })
 }
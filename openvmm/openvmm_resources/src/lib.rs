@@ -42,6 +42,7 @@
     // Disks
     disk_ramdisk::resolver::RamDiskResolver,
     disk_file::FileDiskResolver,
+    disk_file::overlay::FileOverlayDiskResolver,
     disk_prwrap::DiskWithReservationsResolver,
     disk_vhd1::Vhd1Resolver,
     #[cfg(windows)]
@@ -41,6 +41,7 @@
 use hvlite_defs::config::VpciDeviceConfig;
 use hvlite_defs::config::Vtl2BaseAddressType;
 use hvlite_defs::config::Vtl2Config;
+use hvlite_defs::config::WallClockConfig;
 use hvlite_defs::config::DEFAULT_MMIO_GAPS;
 use hvlite_defs::config::DEFAULT_MMIO_GAPS_WITH_VTL2;
 use hvlite_defs::config::DEFAULT_PCAT_BOOT_ORDER;
@@ -340,6 +341,7 @@ pub fn new(
             secure_boot_enabled: false,
             debugger_rpc: None,
             generation_id_recv: None,
+            wall_clock: WallClockConfig::Host,
         };
 
         // Make the pipette connection listener.
@@ -157,7 +157,14 @@ pub async fn run(mut self) -> anyhow::Result<(PetriVm, PipetteClient)> {
                     device: SimpleScsiDiskHandle {
                         read_only: true,
                         parameters: Default::default(),
-                        disk: FileDiskHandle(agent_disk).into_resource(),
+                        disk: FileDiskHandle {
+                            file: agent_disk,
+                            direct: false,
+                            sector_size: None,
+                            physical_sector_size: None,
+                            disk_id: None,
+                        }
+                        .into_resource(),
                     }
                     .into_resource(),
                 }],
@@ -208,7 +215,14 @@ pub async fn run(mut self) -> anyhow::Result<(PetriVm, PipetteClient)> {
                         device: SimpleScsiDiskHandle {
                             read_only: true,
                             parameters: Default::default(),
-                            disk: FileDiskHandle(uh_agent_disk).into_resource(),
+                            disk: FileDiskHandle {
+                                file: uh_agent_disk,
+                                direct: false,
+                                sector_size: None,
+                                physical_sector_size: None,
+                                disk_id: None,
+                            }
+                            .into_resource(),
                         }
                         .into_resource(),
                     }],
@@ -66,6 +66,12 @@
 /// would have a `T: MeshPayload` constraint on the generated `MeshPayload`
 /// impl. However, by adding `#[mesh(bound = "T: MyTrait")]`, this can be
 /// replaced with the specified bound (which can be empty).
+///
+/// **`#[mesh(proto_enum)]`** encodes a field-less enum as a protobuf `enum`,
+/// that is, as a varint of the variant number, instead of as a message with a
+/// `oneof`. The enum must be `Copy`, and variant numbers must be non-zero.
+/// Zero is the protobuf default, so a missing field or an explicit zero
+/// decodes as the first variant.
 #[proc_macro_derive(MeshPayload, attributes(mesh))]
 pub fn derive_mesh_message(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
@@ -116,6 +122,7 @@ struct Modifiers {
     protobuf_mod: Path,
     package: Option<syn::LitStr>,
     rename: Option<syn::LitStr>,
+    proto_enum: Option<Span>,
 }
 
 impl Modifiers {
@@ -150,6 +157,7 @@ enum Attr {
     Transparent,
     Package(syn::LitStr),
     Rename(syn::LitStr),
+    ProtoEnum,
 }
 
 impl Parse for Attr {
@@ -177,6 +185,8 @@ fn parse(input: ParseStream<'_>) -> syn::Result<Self> {
             Ok(Self::Package(parse_string_attr(input)?))
         } else if ident == "rename" {
             Ok(Self::Rename(parse_string_attr(input)?))
+        } else if ident == "proto_enum" {
+            Ok(Self::ProtoEnum)
         } else {
             return Err(syn::Error::new(input.span(), "unknown attribute"));
         }
@@ -203,6 +213,7 @@ fn parse_attributes(
     let mut transparent = None;
     let mut package = None;
     let mut rename = None;
+    let mut proto_enum = None;
     for attr in attrs.iter().filter(|attr| attr.path().is_ident("mesh")) {
         for WithSpan(attr, span) in attr.parse_args_with(parse_attr_list)? {
             match attr {
@@ -215,6 +226,7 @@ fn parse_attributes(
                 Attr::Transparent => transparent = Some(span),
                 Attr::Package(val) => package = Some(val),
                 Attr::Rename(val) => rename = Some(val),
+                Attr::ProtoEnum => proto_enum = Some(span),
             }
         }
     }
@@ -229,6 +241,7 @@ fn parse_attributes(
         protobuf_mod: protobuf_mod.unwrap_or_else(|| syn::parse_str(default_protobuf_mod).unwrap()),
         package,
         rename,
+        proto_enum,
     })
 }
 
@@ -951,6 +964,10 @@ fn derive_enum(
         ));
     }
 
+    if modifiers.proto_enum.is_some() {
+        return derive_proto_enum(input, &modifiers, data);
+    }
+
     let type_ident = modifiers
         .impl_for_type
         .clone()
@@ -1311,6 +1328,147 @@ impl #message_impl_generics #protobuf_mod::DefaultEncoding for #this #message_wh
     })
 }
 
+/// Derives the encoding for a field-less enum with `#[mesh(proto_enum)]`, which
+/// is encoded as a varint of the variant number and described as a protobuf
+/// `enum` rather than as a message with a `oneof`.
+fn derive_proto_enum(
+    input: &DeriveInput,
+    modifiers: &Modifiers,
+    data: &DataEnum,
+) -> syn::Result<TokenStream> {
+    let protobuf_mod = &modifiers.protobuf_mod;
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new(
+            input.generics.span(),
+            "proto_enum not supported on generic enums",
+        ));
+    }
+
+    let type_ident = modifiers
+        .impl_for_type
+        .clone()
+        .unwrap_or_else(|| input.ident.clone().into());
+
+    let mut variant_numbers = BTreeSet::new();
+    let mut variant_idents = Vec::new();
+    let mut numbers = Vec::new();
+    let mut value_descriptors = Vec::new();
+    for (variant_index, variant) in data.variants.iter().enumerate() {
+        if !matches!(variant.fields, Fields::Unit) {
+            return Err(syn::Error::new(
+                variant.fields.span(),
+                "proto_enum variants cannot have fields",
+            ));
+        }
+
+        let mods = parse_item_attributes(&variant.attrs, true)?;
+        if let Some(transparent) = mods.transparent {
+            return Err(syn::Error::new(
+                transparent,
+                "transparent not supported on proto_enum variants",
+            ));
+        }
+        let number_span = mods
+            .field_number
+            .as_ref()
+            .map_or(variant.ident.span(), |n| n.span());
+        let number: u32 = mods
+            .field_number
+            .as_ref()
+            .map_or(Ok(variant_index as u32 + 1), |n| n.base10_parse())?;
+
+        // Zero is reserved for the implicit unspecified value.
+        if number == 0 {
+            return Err(syn::Error::new(
+                number_span,
+                "proto_enum variant numbers must be non-zero",
+            ));
+        }
+        if !variant_numbers.insert(number) {
+            return Err(syn::Error::new(number_span, "duplicate field number"));
+        }
+        if modifiers.package.is_some() && mods.field_number.is_none() {
+            return Err(syn::Error::new(
+                variant.ident.span(),
+                "all variants must have explicit numbers when package is set",
+            ));
+        }
+
+        let variant_name = variant.ident.to_string();
+        let variant_doc = doc_string(&variant.attrs);
+        value_descriptors.push(quote! {
+            #protobuf_mod::protofile::EnumValueDescriptor::new(#variant_doc, #variant_name, #number)
+        });
+        variant_idents.push(&variant.ident);
+        numbers.push(proc_macro2::Literal::u64_unsuffixed(number.into()));
+    }
+
+    // Zero, the unspecified value, is the protobuf default for a missing
+    // field, so decode it as the first variant.
+    let default_arm = variant_idents
+        .first()
+        .map(|ident| quote!(0 => Ok(Self::#ident),));
+
+    let describe = if let Some(package) = &modifiers.package {
+        let name = if let Some(name) = &modifiers.rename {
+            quote!(#name)
+        } else {
+            let name = input.ident.to_string();
+            quote!(#name)
+        };
+        let doc = doc_string(&input.attrs);
+        quote! {
+            impl #protobuf_mod::protofile::DescribeField<#type_ident> for #protobuf_mod::encoding::VarintField {
+                const FIELD_TYPE: #protobuf_mod::protofile::FieldType<'static> =
+                    #protobuf_mod::protofile::FieldType::enumeration(|| {
+                        const TLD: &#protobuf_mod::protofile::TopLevelDescriptor<'static> =
+                            &#protobuf_mod::protofile::TopLevelDescriptor::enumeration(
+                                #package,
+                                &#protobuf_mod::protofile::EnumDescriptor::new(#name, #doc, &[#(#value_descriptors,)*]),
+                            );
+                        TLD
+                    });
+            }
+        }
+    } else {
+        quote!()
+    };
+
+    Ok(quote! {
+        impl #protobuf_mod::encoding::ToNumber for #type_ident {
+            fn to_u64(self) -> u64 {
+                match self {
+                    #(Self::#variant_idents => #numbers,)*
+                }
+            }
+
+            fn to_i64(self) -> i64 {
+                <Self as #protobuf_mod::encoding::ToNumber>::to_u64(self) as i64
+            }
+        }
+
+        impl #protobuf_mod::encoding::FromNumber for #type_ident {
+            fn from_u64(v: u64) -> #protobuf_mod::Result<Self> {
+                match v {
+                    #default_arm
+                    #(#numbers => Ok(Self::#variant_idents),)*
+                    _ => Err(#protobuf_mod::Error::new(#protobuf_mod::encoding::UnknownEnumValue(v))),
+                }
+            }
+
+            fn from_i64(v: i64) -> #protobuf_mod::Result<Self> {
+                <Self as #protobuf_mod::encoding::FromNumber>::from_u64(v as u64)
+            }
+        }
+
+        impl #protobuf_mod::DefaultEncoding for #type_ident {
+            type Encoding = #protobuf_mod::encoding::VarintField;
+        }
+
+        #describe
+    })
+}
+
 fn is_standard_tuple(fields: &Fields, field_data: &[FieldData<'_>]) -> bool {
     // If the fields have names, generate a type.
     if !matches!(fields, Fields::Unnamed(_)) {
@@ -4,6 +4,7 @@
 //! Protobuf encodings for Rust types.
 
 pub use super::time::DurationEncoding;
+pub use super::time::SystemTimeEncoding;
 
 use super::inplace_some;
 use super::protobuf::decode_with;
@@ -56,6 +57,7 @@
 use std::num::NonZeroUsize;
 use std::sync::Arc;
 use std::time::Duration;
+use std::time::SystemTime;
 use thiserror::Error;
 
 /// An encoding derived by `mesh_derive` for `T`.
@@ -174,6 +176,12 @@ fn from_i64(v: i64) -> Result<Self> {
 #[error("value must be non-zero")]
 struct MustBeNonZero;
 
+/// The error returned when decoding a number that does not correspond to any
+/// variant of an enum encoded with `#[mesh(proto_enum)]`.
+#[derive(Debug, Error)]
+#[error("unknown enum value {0}")]
+pub struct UnknownEnumValue(pub u64);
+
 macro_rules! nonzero_number {
     ($($ty:ty)*) => {
         $(
@@ -1646,6 +1654,7 @@ impl $crate::Downcast<$ty> for $ty {}
     String: StringField,
 
     Duration: MessageEncoding<DurationEncoding>,
+    SystemTime: MessageEncoding<SystemTimeEncoding>,
 
     Infallible: ImpossibleField,
 }
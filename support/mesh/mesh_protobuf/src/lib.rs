@@ -493,6 +493,8 @@ enum DecodeError {
 
     #[error("duration out of range")]
     DurationRange,
+    #[error("timestamp out of range")]
+    TimestampRange,
 }
 
 impl Error {
@@ -567,6 +569,7 @@ mod tests {
     use crate::FieldDecode;
     use crate::FieldEncode;
     use crate::NoResources;
+    use crate::Timestamp;
     use mesh_derive::Protobuf;
     use std::borrow::Cow;
     use std::collections::BTreeMap;
@@ -575,6 +578,7 @@ mod tests {
     use std::error::Error;
     use std::num::NonZeroU32;
     use std::time::Duration;
+    use std::time::SystemTime;
 
     #[track_caller]
     fn assert_roundtrips<T>(t: T)
@@ -789,6 +793,58 @@ struct CanPackNest {
         }
     }
 
+    #[test]
+    fn test_proto_enum() {
+        #[derive(Protobuf, Debug, Copy, Clone, PartialEq, Eq)]
+        #[mesh(proto_enum)]
+        enum Color {
+            Red,
+            #[mesh(5)]
+            Green,
+        }
+
+        #[derive(Protobuf, Debug, Clone, PartialEq, Eq)]
+        struct Palette {
+            primary: Color,
+            others: Vec<Color>,
+        }
+
+        #[derive(Protobuf)]
+        struct Raw {
+            primary: u32,
+            others: Vec<u32>,
+        }
+
+        let palette = Palette {
+            primary: Color::Green,
+            others: vec![Color::Red, Color::Green],
+        };
+        let v = encode(palette.clone());
+        let raw = decode::<Raw>(&v).unwrap();
+        assert_eq!(raw.primary, 5);
+        assert_eq!(raw.others, [1, 5]);
+        assert_eq!(decode::<Palette>(&v).unwrap(), palette);
+
+        let v = encode(Raw {
+            primary: 3,
+            others: Vec::new(),
+        });
+        decode::<Palette>(&v).unwrap_err();
+
+        // A missing or zero value decodes as the first variant.
+        let v = encode(Raw {
+            primary: 0,
+            others: vec![0, 5],
+        });
+        assert_eq!(
+            decode::<Palette>(&v).unwrap(),
+            Palette {
+                primary: Color::Red,
+                others: vec![Color::Red, Color::Green],
+            }
+        );
+    }
+
     #[test]
     fn test_merge() {
         #[derive(Protobuf, Debug, Clone, PartialEq, Eq)]
@@ -1103,6 +1159,25 @@ fn test_duration() {
         );
     }
 
+    #[test]
+    fn test_system_time() {
+        assert_roundtrips(SystemTime::UNIX_EPOCH);
+        assert_roundtrips(SystemTime::UNIX_EPOCH + Duration::from_nanos(1_500_000_000));
+        assert_roundtrips(SystemTime::UNIX_EPOCH - Duration::from_nanos(1_500_000_000));
+        assert_eq!(
+            encode(SystemTime::UNIX_EPOCH + Duration::from_secs(1)),
+            encode(Timestamp {
+                seconds: 1,
+                nanos: 0
+            })
+        );
+        decode::<SystemTime>(&encode(Timestamp {
+            seconds: 0,
+            nanos: -1,
+        }))
+        .unwrap_err();
+    }
+
     #[test]
     fn test_failure_recovery() {
         let m = encode(("foo", 2, 3));
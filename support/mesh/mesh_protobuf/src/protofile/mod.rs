@@ -5,9 +5,14 @@
 //! to generate `.proto` files that are binary compatible with the associated
 //! Rust types.
 
+mod verify;
 mod writer;
 
 pub use writer::DescriptorWriter;
+pub use writer::FileLayout;
+pub use writer::IndentStyle;
+pub use writer::JsonNames;
+pub use writer::Syntax;
 
 use crate::DefaultEncoding;
 use std::fmt::Display;
@@ -83,13 +88,27 @@ fn eq(&self, other: &TypeUrl<'_>) -> bool {
     }
 }
 
+impl MessageDescription<'static> {
+    /// The well-known `google.protobuf.Timestamp` type.
+    pub const TIMESTAMP: Self = Self::External {
+        name: "google.protobuf.Timestamp",
+        import_path: "google/protobuf/timestamp.proto",
+    };
+
+    /// The well-known `google.protobuf.Duration` type.
+    pub const DURATION: Self = Self::External {
+        name: "google.protobuf.Duration",
+        import_path: "google/protobuf/duration.proto",
+    };
+}
+
 impl MessageDescription<'_> {
     /// Returns the type URL to use with `google.protobuf.Any`.
     pub const fn type_url(&self) -> TypeUrl<'_> {
         match *self {
             MessageDescription::Internal(tld) => TypeUrl {
                 package: tld.package,
-                name: tld.message.name,
+                name: tld.name(),
             },
             MessageDescription::External { name, .. } => TypeUrl { package: "", name },
         }
@@ -128,6 +147,7 @@ enum FieldKind<'a> {
         import_path: &'static str,
     },
     Message(fn() -> MessageDescription<'a>),
+    Enum(fn() -> &'a TopLevelDescriptor<'a>),
     Tuple(&'a [FieldType<'a>]),
     KeyValue(&'a [FieldType<'a>; 2]),
 }
@@ -201,6 +221,16 @@ pub const fn message(f: fn() -> MessageDescription<'a>) -> Self {
         }
     }
 
+    /// Returns a field type for an enum whose top-level descriptor is returned
+    /// by `f`.
+    pub const fn enumeration(f: fn() -> &'a TopLevelDescriptor<'a>) -> Self {
+        Self {
+            kind: FieldKind::Enum(f),
+            sequence_type: None,
+            annotation: "",
+        }
+    }
+
     /// Returns a field type for a local message type with `name`.
     pub const fn local(name: &'a str) -> Self {
         Self {
@@ -267,6 +297,18 @@ pub const fn external(name: &'a str, import_path: &'static str) -> Self {
         }
     }
 
+    /// Returns a field type for the well-known `google.protobuf.Timestamp`
+    /// type.
+    pub const fn timestamp() -> Self {
+        Self::message(|| MessageDescription::TIMESTAMP)
+    }
+
+    /// Returns a field type for the well-known `google.protobuf.Duration`
+    /// type.
+    pub const fn duration() -> Self {
+        Self::message(|| MessageDescription::DURATION)
+    }
+
     /// Returns true if this is a sequence type (optional or repeated).
     pub const fn is_sequence(&self) -> bool {
         self.sequence_type.is_some()
@@ -283,6 +325,7 @@ pub const fn can_pack(&self) -> bool {
                 v.as_bytes(),
                 b"double" | b"float" | b"int64" | b"uint64" | b"int32" | b"uint32" | b"bool"
             ),
+            FieldKind::Enum(_) => true,
             _ => false,
         }
     }
@@ -295,6 +338,7 @@ pub struct FieldDescriptor<'a> {
     field_number: u32,
     comment: &'a str,
     name: &'a str,
+    options: &'a [FieldOption<'a>],
 }
 
 impl<'a> FieldDescriptor<'a> {
@@ -310,8 +354,53 @@ pub const fn new(
             field_number,
             comment,
             name,
+            options: &[],
         }
     }
+
+    /// Sets the options to write for the field, such as `deprecated`.
+    pub const fn options(mut self, options: &'a [FieldOption<'a>]) -> Self {
+        self.options = options;
+        self
+    }
+}
+
+/// A field option, written as `name = value` in the field's option list.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct FieldOption<'a> {
+    name: &'a str,
+    value: OptionValue<'a>,
+}
+
+/// The value of a [`FieldOption`] or [`MessageOption`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum OptionValue<'a> {
+    /// A boolean.
+    Bool(bool),
+    /// An integer.
+    Int(i64),
+    /// A string, which is quoted when written.
+    String(&'a str),
+    /// An identifier, such as an enum value name, which is written as is.
+    Identifier(&'a str),
+}
+
+impl<'a> FieldOption<'a> {
+    /// Returns a new option. `name` is written as is, so custom options must
+    /// include the surrounding parentheses.
+    pub const fn new(name: &'a str, value: OptionValue<'a>) -> Self {
+        Self { name, value }
+    }
+
+    /// Returns the `deprecated = true` option.
+    pub const fn deprecated() -> Self {
+        Self::new("deprecated", OptionValue::Bool(true))
+    }
+
+    /// Returns the `json_name` option.
+    pub const fn json_name(name: &'a str) -> Self {
+        Self::new("json_name", OptionValue::String(name))
+    }
 }
 
 /// A description of a protobuf `oneof`.
@@ -336,6 +425,12 @@ pub struct MessageDescriptor<'a> {
     fields: &'a [FieldDescriptor<'a>],
     oneofs: &'a [OneofDescriptor<'a>],
     messages: &'a [MessageDescriptor<'a>],
+    reserved_numbers: &'a [(u32, u32)],
+    reserved_names: &'a [&'a str],
+    options: &'a [MessageOption<'a>],
+    /// Whether the writer synthesized the message, such as for a tuple field,
+    /// rather than it being defined by a type.
+    synthesized: bool,
 }
 
 impl<'a> MessageDescriptor<'a> {
@@ -353,21 +448,200 @@ pub const fn new(
             fields,
             oneofs,
             messages,
+            reserved_numbers: &[],
+            reserved_names: &[],
+            options: &[],
+            synthesized: false,
+        }
+    }
+
+    /// Reserves field numbers and names so that they cannot be reused, such
+    /// as for fields that have been removed.
+    ///
+    /// `numbers` is a list of inclusive ranges of field numbers.
+    pub const fn reserve(mut self, numbers: &'a [(u32, u32)], names: &'a [&'a str]) -> Self {
+        self.reserved_numbers = numbers;
+        self.reserved_names = names;
+        self
+    }
+
+    /// Sets the options to write for the message, such as `deprecated`.
+    pub const fn options(mut self, options: &'a [MessageOption<'a>]) -> Self {
+        self.options = options;
+        self
+    }
+}
+
+/// A message option, written as an `option name = value;` statement in the
+/// message body.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct MessageOption<'a> {
+    name: &'a str,
+    value: OptionValue<'a>,
+}
+
+impl<'a> MessageOption<'a> {
+    /// Returns a new option. `name` is written as is, so custom options must
+    /// include the surrounding parentheses.
+    pub const fn new(name: &'a str, value: OptionValue<'a>) -> Self {
+        Self { name, value }
+    }
+
+    /// Returns the `deprecated = true` option.
+    pub const fn deprecated() -> Self {
+        Self::new("deprecated", OptionValue::Bool(true))
+    }
+}
+
+/// A descriptor for an enum value.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct EnumValueDescriptor<'a> {
+    comment: &'a str,
+    name: &'a str,
+    number: u32,
+}
+
+impl<'a> EnumValueDescriptor<'a> {
+    /// Returns a new descriptor.
+    ///
+    /// `name` is the name of the value without the enum name prefix, which is
+    /// added when the value is written. `number` must be non-zero, since zero
+    /// is reserved for the implicit unspecified value.
+    pub const fn new(comment: &'a str, name: &'a str, number: u32) -> Self {
+        Self {
+            comment,
+            name,
+            number,
+        }
+    }
+}
+
+/// A descriptor for a protobuf `enum`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct EnumDescriptor<'a> {
+    comment: &'a str,
+    name: &'a str,
+    values: &'a [EnumValueDescriptor<'a>],
+}
+
+impl<'a> EnumDescriptor<'a> {
+    /// Returns a new descriptor.
+    pub const fn new(
+        name: &'a str,
+        comment: &'a str,
+        values: &'a [EnumValueDescriptor<'a>],
+    ) -> Self {
+        Self {
+            comment,
+            name,
+            values,
         }
     }
 }
 
-/// A message descriptor for a message rooted directly in a package (and not
+/// A descriptor for a message or enum rooted directly in a package (and not
 /// nested in another message type).
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub struct TopLevelDescriptor<'a> {
     package: &'a str,
-    message: &'a MessageDescriptor<'a>,
+    item: TopLevelItem<'a>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+enum TopLevelItem<'a> {
+    Message(&'a MessageDescriptor<'a>),
+    Enum(&'a EnumDescriptor<'a>),
 }
 
 impl<'a> TopLevelDescriptor<'a> {
-    /// Returns a new descriptor.
+    /// Returns a new descriptor for a message.
     pub const fn message(package: &'a str, message: &'a MessageDescriptor<'a>) -> Self {
-        Self { package, message }
+        Self {
+            package,
+            item: TopLevelItem::Message(message),
+        }
+    }
+
+    /// Returns a new descriptor for an enum.
+    pub const fn enumeration(package: &'a str, enumeration: &'a EnumDescriptor<'a>) -> Self {
+        Self {
+            package,
+            item: TopLevelItem::Enum(enumeration),
+        }
+    }
+
+    const fn name(&self) -> &'a str {
+        match self.item {
+            TopLevelItem::Message(message) => message.name,
+            TopLevelItem::Enum(enumeration) => enumeration.name,
+        }
+    }
+}
+
+/// A descriptor for a gRPC service.
+#[derive(Copy, Clone)]
+pub struct ServiceDescriptor<'a> {
+    package: &'a str,
+    name: &'a str,
+    comment: &'a str,
+    methods: &'a [MethodDescriptor<'a>],
+}
+
+impl<'a> ServiceDescriptor<'a> {
+    /// Returns a new descriptor for service `name` in `package`.
+    pub const fn new(
+        package: &'a str,
+        name: &'a str,
+        comment: &'a str,
+        methods: &'a [MethodDescriptor<'a>],
+    ) -> Self {
+        Self {
+            package,
+            name,
+            comment,
+            methods,
+        }
+    }
+}
+
+/// A descriptor for a method of a gRPC service.
+#[derive(Copy, Clone)]
+pub struct MethodDescriptor<'a> {
+    name: &'a str,
+    comment: &'a str,
+    request: MessageDescription<'a>,
+    response: MessageDescription<'a>,
+    client_streaming: bool,
+    server_streaming: bool,
+}
+
+impl<'a> MethodDescriptor<'a> {
+    /// Returns a new descriptor for a unary method.
+    pub const fn new(
+        name: &'a str,
+        comment: &'a str,
+        request: MessageDescription<'a>,
+        response: MessageDescription<'a>,
+    ) -> Self {
+        Self {
+            name,
+            comment,
+            request,
+            response,
+            client_streaming: false,
+            server_streaming: false,
+        }
+    }
+
+    /// Marks the method as taking a stream of requests.
+    pub const fn client_streaming(mut self) -> Self {
+        self.client_streaming = true;
+        self
+    }
+
+    /// Marks the method as returning a stream of responses.
+    pub const fn server_streaming(mut self) -> Self {
+        self.server_streaming = true;
+        self
     }
 }
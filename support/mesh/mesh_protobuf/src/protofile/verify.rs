@@ -0,0 +1,594 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A lightweight checker for written `.proto` files.
+//!
+//! This only understands the subset of the protobuf language that
+//! [`DescriptorWriter`](super::DescriptorWriter) emits. It is not a general
+//! purpose parser.
+
+use super::writer::Import;
+use std::io;
+
+/// Checks that `text`, the contents of the file `file` for package `package`,
+/// is well formed, and that its imports match the types that it refers to.
+///
+/// `imports` are the imports that the writer collected for the file, which
+/// determine the packages that each import path provides.
+pub(super) fn verify(
+    file: &str,
+    text: &str,
+    package: &str,
+    imports: &[Import<'_>],
+) -> io::Result<()> {
+    let tokens = tokenize(text).map_err(|err| err.into_io(file))?;
+    let mut parser = Parser {
+        tokens: &tokens,
+        pos: 0,
+        referenced: Vec::new(),
+        imported: Vec::new(),
+    };
+    parser.file(package).map_err(|err| err.into_io(file))?;
+
+    // The package of each referenced type. Packages may nest (e.g. `foo` and
+    // `foo.bar`), so this is the longest known package containing the type.
+    let owners = Vec::from_iter(parser.referenced.iter().map(|&(name, line)| {
+        let owner = std::iter::once(package)
+            .chain(imports.iter().map(|import| import.package))
+            .filter(|&package| in_package(name, package))
+            .max_by_key(|package| package.len());
+        (name, owner, line)
+    }));
+    let provides = |path: &str, owner: Option<&str>| {
+        imports
+            .iter()
+            .any(|import| import.path == path && Some(import.package) == owner)
+    };
+    for &(path, line) in &parser.imported {
+        if !imports.iter().any(|import| import.path == path) {
+            return Err(Error::new(line, format!("unexpected import \"{path}\"")).into_io(file));
+        }
+        if !owners.iter().any(|&(_, owner, _)| provides(path, owner)) {
+            return Err(Error::new(line, format!("unused import \"{path}\"")).into_io(file));
+        }
+    }
+    for &(name, owner, line) in &owners {
+        if owner != Some(package)
+            && !parser
+                .imported
+                .iter()
+                .any(|&(path, _)| provides(path, owner))
+        {
+            return Err(Error::new(line, format!("type {name} is not imported")).into_io(file));
+        }
+    }
+    Ok(())
+}
+
+/// Returns whether the fully-qualified type name `name` (with a leading dot)
+/// is in `package` or one of its subpackages.
+fn in_package(name: &str, package: &str) -> bool {
+    name.strip_prefix('.')
+        .and_then(|name| name.strip_prefix(package))
+        .is_some_and(|rest| rest.starts_with('.'))
+}
+
+struct Error {
+    line: usize,
+    message: String,
+}
+
+impl Error {
+    fn new(line: usize, message: String) -> Self {
+        Self { line, message }
+    }
+
+    fn into_io(self, file: &str) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "generated file {file} is malformed at line {}: {}",
+                self.line, self.message
+            ),
+        )
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Token<'a> {
+    /// An identifier, possibly qualified and possibly fully qualified with a
+    /// leading dot.
+    Ident(&'a str),
+    Int(&'a str),
+    /// A string literal, without the quotes.
+    Str(&'a str),
+    Symbol(char),
+    End,
+}
+
+impl std::fmt::Display for Token<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            Token::Ident(s) | Token::Int(s) => write!(f, "`{s}`"),
+            Token::Str(s) => write!(f, "\"{s}\""),
+            Token::Symbol(c) => write!(f, "`{c}`"),
+            Token::End => f.pad("end of file"),
+        }
+    }
+}
+
+/// Splits `text` into tokens, each with its line number, discarding
+/// whitespace and comments.
+fn tokenize(text: &str) -> Result<Vec<(Token<'_>, usize)>, Error> {
+    let mut tokens = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line_number = i + 1;
+        let mut rest = line;
+        loop {
+            rest = rest.trim_start();
+            let Some(c) = rest.chars().next() else {
+                break;
+            };
+            let len = if rest.starts_with("//") {
+                break;
+            } else if c.is_ascii_alphabetic() || c == '_' || c == '.' {
+                let len = rest
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.'))
+                    .unwrap_or(rest.len());
+                let ident = &rest[..len];
+                if ident
+                    .strip_prefix('.')
+                    .unwrap_or(ident)
+                    .split('.')
+                    .any(|part| part.is_empty() || part.starts_with(|c: char| c.is_ascii_digit()))
+                {
+                    return Err(Error::new(
+                        line_number,
+                        format!("malformed identifier `{ident}`"),
+                    ));
+                }
+                tokens.push((Token::Ident(ident), line_number));
+                len
+            } else if c.is_ascii_digit() || c == '-' {
+                let len = 1 + rest[1..]
+                    .find(|c: char| !c.is_ascii_digit())
+                    .unwrap_or(rest.len() - 1);
+                if len == 1 && c == '-' {
+                    return Err(Error::new(line_number, "malformed integer".into()));
+                }
+                tokens.push((Token::Int(&rest[..len]), line_number));
+                len
+            } else if c == '"' {
+                let mut escaped = false;
+                let end = rest[1..].find(|c| {
+                    let end = c == '"' && !escaped;
+                    escaped = c == '\\' && !escaped;
+                    end
+                });
+                let Some(end) = end else {
+                    return Err(Error::new(line_number, "unterminated string".into()));
+                };
+                tokens.push((Token::Str(&rest[1..end + 1]), line_number));
+                end + 2
+            } else if "{}()[]<>;=,".contains(c) {
+                tokens.push((Token::Symbol(c), line_number));
+                1
+            } else {
+                return Err(Error::new(
+                    line_number,
+                    format!("unexpected character {c:?}"),
+                ));
+            };
+            rest = &rest[len..];
+        }
+    }
+    tokens.push((Token::End, text.lines().count()));
+    Ok(tokens)
+}
+
+struct Parser<'a, 't> {
+    tokens: &'t [(Token<'a>, usize)],
+    pos: usize,
+    /// The fully-qualified type names referred to, with their line numbers.
+    referenced: Vec<(&'a str, usize)>,
+    /// The import paths, with their line numbers.
+    imported: Vec<(&'a str, usize)>,
+}
+
+impl<'a> Parser<'a, '_> {
+    fn peek(&self) -> Token<'a> {
+        self.tokens[self.pos].0
+    }
+
+    fn line(&self) -> usize {
+        self.tokens[self.pos].1
+    }
+
+    fn next(&mut self) {
+        if self.peek() != Token::End {
+            self.pos += 1;
+        }
+    }
+
+    fn unexpected(&self, expected: &str) -> Error {
+        Error::new(
+            self.line(),
+            format!("expected {expected}, found {}", self.peek()),
+        )
+    }
+
+    fn symbol(&mut self, c: char) -> Result<(), Error> {
+        if self.peek() != Token::Symbol(c) {
+            return Err(self.unexpected(&format!("`{c}`")));
+        }
+        self.next();
+        Ok(())
+    }
+
+    fn keyword(&mut self, keyword: &str) -> Result<(), Error> {
+        if self.peek() != Token::Ident(keyword) {
+            return Err(self.unexpected(&format!("`{keyword}`")));
+        }
+        self.next();
+        Ok(())
+    }
+
+    /// Consumes `keyword` if it is next.
+    fn optional_keyword(&mut self, keyword: &str) -> bool {
+        let found = self.peek() == Token::Ident(keyword);
+        if found {
+            self.next();
+        }
+        found
+    }
+
+    /// Parses an unqualified name.
+    fn name(&mut self) -> Result<&'a str, Error> {
+        match self.peek() {
+            Token::Ident(name) if !name.contains('.') => {
+                self.next();
+                Ok(name)
+            }
+            _ => Err(self.unexpected("a name")),
+        }
+    }
+
+    /// Parses a type name, recording it if it is fully qualified.
+    fn type_name(&mut self) -> Result<(), Error> {
+        match self.peek() {
+            Token::Ident(name) => {
+                if name.starts_with('.') {
+                    self.referenced.push((name, self.line()));
+                }
+                self.next();
+                Ok(())
+            }
+            _ => Err(self.unexpected("a type")),
+        }
+    }
+
+    fn int(&mut self) -> Result<(), Error> {
+        match self.peek() {
+            Token::Int(_) => {
+                self.next();
+                Ok(())
+            }
+            _ => Err(self.unexpected("an integer")),
+        }
+    }
+
+    fn string(&mut self) -> Result<&'a str, Error> {
+        match self.peek() {
+            Token::Str(s) => {
+                self.next();
+                Ok(s)
+            }
+            _ => Err(self.unexpected("a string")),
+        }
+    }
+
+    fn file(&mut self, package: &str) -> Result<(), Error> {
+        self.keyword("syntax")?;
+        self.symbol('=')?;
+        let line = self.line();
+        let syntax = self.string()?;
+        if syntax != "proto2" && syntax != "proto3" {
+            return Err(Error::new(line, format!("unknown syntax \"{syntax}\"")));
+        }
+        self.symbol(';')?;
+
+        self.keyword("package")?;
+        if self.peek() != Token::Ident(package) {
+            return Err(self.unexpected(&format!("package `{package}`")));
+        }
+        self.next();
+        self.symbol(';')?;
+
+        while self.optional_keyword("import") {
+            let line = self.line();
+            let path = self.string()?;
+            self.imported.push((path, line));
+            self.symbol(';')?;
+        }
+
+        loop {
+            match self.peek() {
+                Token::Ident("message") => self.message()?,
+                Token::Ident("enum") => self.enumeration()?,
+                Token::Ident("service") => self.service()?,
+                Token::End => break,
+                _ => return Err(self.unexpected("a message, enum, or service")),
+            }
+        }
+        Ok(())
+    }
+
+    fn message(&mut self) -> Result<(), Error> {
+        self.keyword("message")?;
+        self.name()?;
+        self.symbol('{')?;
+        loop {
+            match self.peek() {
+                Token::Symbol('}') => break,
+                Token::Ident("message") => self.message()?,
+                Token::Ident("enum") => self.enumeration()?,
+                Token::Ident("oneof") => self.oneof()?,
+                Token::Ident("reserved") => self.reserved()?,
+                Token::Ident("option") => self.option()?,
+                _ => self.field(true)?,
+            }
+        }
+        self.symbol('}')
+    }
+
+    fn option(&mut self) -> Result<(), Error> {
+        self.keyword("option")?;
+        self.name()?;
+        self.symbol('=')?;
+        self.option_value()?;
+        self.symbol(';')
+    }
+
+    fn option_value(&mut self) -> Result<(), Error> {
+        match self.peek() {
+            Token::Ident(_) | Token::Int(_) | Token::Str(_) => {
+                self.next();
+                Ok(())
+            }
+            _ => Err(self.unexpected("an option value")),
+        }
+    }
+
+    fn reserved(&mut self) -> Result<(), Error> {
+        self.keyword("reserved")?;
+        if matches!(self.peek(), Token::Str(_)) {
+            self.string()?;
+            while self.peek() == Token::Symbol(',') {
+                self.next();
+                self.string()?;
+            }
+        } else {
+            self.int()?;
+            if self.optional_keyword("to") {
+                self.int()?;
+            }
+            while self.peek() == Token::Symbol(',') {
+                self.next();
+                self.int()?;
+                if self.optional_keyword("to") {
+                    self.int()?;
+                }
+            }
+        }
+        self.symbol(';')
+    }
+
+    fn oneof(&mut self) -> Result<(), Error> {
+        self.keyword("oneof")?;
+        self.name()?;
+        self.symbol('{')?;
+        while self.peek() != Token::Symbol('}') {
+            self.field(false)?;
+        }
+        self.symbol('}')
+    }
+
+    /// Parses a field. `allow_label` is false for `oneof` variants.
+    fn field(&mut self, allow_label: bool) -> Result<(), Error> {
+        if allow_label {
+            if let Token::Ident("optional" | "repeated" | "required") = self.peek() {
+                self.next();
+            }
+        }
+        if self.optional_keyword("map") {
+            self.symbol('<')?;
+            self.name()?;
+            self.symbol(',')?;
+            self.type_name()?;
+            self.symbol('>')?;
+        } else {
+            self.type_name()?;
+        }
+        self.name()?;
+        self.symbol('=')?;
+        self.int()?;
+        if self.peek() == Token::Symbol('[') {
+            self.next();
+            loop {
+                self.name()?;
+                self.symbol('=')?;
+                self.option_value()?;
+                if self.peek() != Token::Symbol(',') {
+                    break;
+                }
+                self.next();
+            }
+            self.symbol(']')?;
+        }
+        self.symbol(';')
+    }
+
+    fn enumeration(&mut self) -> Result<(), Error> {
+        self.keyword("enum")?;
+        self.name()?;
+        self.symbol('{')?;
+        while self.peek() != Token::Symbol('}') {
+            self.name()?;
+            self.symbol('=')?;
+            self.int()?;
+            self.symbol(';')?;
+        }
+        self.symbol('}')
+    }
+
+    fn service(&mut self) -> Result<(), Error> {
+        self.keyword("service")?;
+        self.name()?;
+        self.symbol('{')?;
+        while self.peek() != Token::Symbol('}') {
+            self.keyword("rpc")?;
+            self.name()?;
+            self.symbol('(')?;
+            self.optional_keyword("stream");
+            self.type_name()?;
+            self.symbol(')')?;
+            self.keyword("returns")?;
+            self.symbol('(')?;
+            self.optional_keyword("stream");
+            self.type_name()?;
+            self.symbol(')')?;
+            self.symbol(';')?;
+        }
+        self.symbol('}')
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::verify;
+    use crate::protofile::writer::Import;
+
+    const FILE: &str = r#"// Autogenerated, do not edit.
+
+syntax = "proto3";
+package test;
+
+import "google/protobuf/timestamp.proto";
+
+import "other.proto";
+
+message Foo {
+  reserved 4, 6 to 8;
+  reserved "old";
+
+  message Pair {
+    uint32 key = 1;
+    .other.Bar value = 2;
+  }
+
+  oneof choice {
+    bool flag = 9;
+    Pair pair = 10;
+  }
+  optional uint32 x = 1 [deprecated = true, json_name = "ex"]; // annotation
+  repeated .google.protobuf.Timestamp times = 2;
+  map<string, .test.Foo> foos = 3;
+}
+
+enum Color {
+  COLOR_UNSPECIFIED = 0;
+  COLOR_RED = 1;
+}
+
+service Svc {
+  rpc Go (stream .test.Foo) returns (.other.Bar);
+}
+"#;
+
+    const IMPORTS: &[Import<'static>] = &[
+        Import {
+            path: std::borrow::Cow::Borrowed("google/protobuf/timestamp.proto"),
+            package: "google.protobuf",
+        },
+        Import {
+            path: std::borrow::Cow::Borrowed("other.proto"),
+            package: "other",
+        },
+    ];
+
+    fn check(text: &str) -> Result<(), String> {
+        verify("test.proto", text, "test", IMPORTS).map_err(|err| err.to_string())
+    }
+
+    #[test]
+    fn well_formed() {
+        check(FILE).unwrap();
+    }
+
+    #[test]
+    fn malformed() {
+        for (from, to, expected) in [
+            (
+                "x = 1 [",
+                "x = 1 ",
+                "line 23: expected `;`, found `deprecated`",
+            ),
+            (
+                "COLOR_RED = 1;",
+                "COLOR_RED = 1",
+                "line 31: expected `;`, found `}`",
+            ),
+            (
+                "  }\n  optional",
+                "  optional",
+                "line 22: expected `=`, found `x`",
+            ),
+            ("proto3", "proto4", "line 3: unknown syntax \"proto4\""),
+            (
+                "package test",
+                "package tset",
+                "line 4: expected package `test`",
+            ),
+            (
+                ".other.Bar value",
+                ".other..Bar value",
+                "line 16: malformed identifier",
+            ),
+            ("\"ex\"", "\"ex", "line 23: unterminated string"),
+            (
+                "times = 2",
+                "times = 2.5",
+                "line 24: malformed identifier `.5`",
+            ),
+        ] {
+            let text = FILE.replacen(from, to, 1);
+            assert_ne!(text, FILE, "{from}");
+            let err = check(&text).unwrap_err();
+            assert!(err.contains(expected), "{from}: {err}");
+        }
+    }
+
+    #[test]
+    fn imports() {
+        // An import that no type needs.
+        let text = FILE.replace(".google.protobuf.Timestamp", "uint64");
+        let err = check(&text).unwrap_err();
+        assert!(
+            err.contains("line 6: unused import \"google/protobuf/timestamp.proto\""),
+            "{err}"
+        );
+
+        // An import that the writer did not collect.
+        let text = FILE.replace("other.proto", "another.proto");
+        let err = check(&text).unwrap_err();
+        assert!(err.contains("line 8: unexpected import"), "{err}");
+
+        // A type from a package that is not imported.
+        let text = FILE.replace("import \"other.proto\";", "");
+        let err = check(&text).unwrap_err();
+        assert!(
+            err.contains("line 16: type .other.Bar is not imported"),
+            "{err}"
+        );
+    }
+}
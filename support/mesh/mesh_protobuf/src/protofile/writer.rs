@@ -3,16 +3,24 @@
 
 //! Code to write .proto files from descriptors.
 
+use super::EnumDescriptor;
 use super::FieldDescriptor;
+use super::FieldOption;
 use super::FieldType;
 use super::MessageDescriptor;
+use super::MethodDescriptor;
 use super::OneofDescriptor;
+use super::OptionValue;
+use super::ServiceDescriptor;
 use super::TopLevelDescriptor;
+use super::TopLevelItem;
 use crate::protofile::FieldKind;
 use crate::protofile::MessageDescription;
 use crate::protofile::SequenceType;
+use heck::ToShoutySnakeCase;
 use heck::ToUpperCamelCase;
 use std::borrow::Cow;
+use std::collections::HashMap;
 use std::collections::HashSet;
 use std::io;
 use std::io::Write;
@@ -22,7 +30,81 @@
 /// A type used to write protobuf descriptors to `.proto`-format files.
 pub struct DescriptorWriter<'a> {
     descriptors: Vec<&'a TopLevelDescriptor<'a>>,
+    services: Vec<&'a ServiceDescriptor<'a>>,
     file_heading: &'a str,
+    syntax: Syntax,
+    indent: IndentStyle,
+    layout: FileLayout,
+    file_name: Option<Box<dyn 'a + Fn(&str) -> String>>,
+    json_names: JsonNames,
+    mark_synthesized: bool,
+    verify: bool,
+}
+
+/// The protobuf language version to write.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Syntax {
+    /// `proto2`. Every singular field is written as `optional`, since mesh
+    /// omits default values from the encoding and so can never satisfy a
+    /// `required` field, and packable repeated fields are marked as packed.
+    Proto2,
+    /// `proto3`.
+    Proto3,
+}
+
+/// The indentation written for each level of nesting.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum IndentStyle {
+    /// Indent with the given number of spaces.
+    Spaces(usize),
+    /// Indent with a single tab.
+    Tab,
+}
+
+/// How `.proto` files are named after their packages.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FileLayout {
+    /// Each package is written to a single file in the root directory, named
+    /// after the full package name (e.g. `foo.bar` is written to
+    /// `foo.bar.proto`).
+    Flat,
+    /// Each component of a package but the last is a directory, following
+    /// protoc's conventional layout (e.g. `foo.bar` is written to
+    /// `foo/bar.proto`).
+    Nested,
+}
+
+/// How the JSON name of each field is written.
+///
+/// A field's own `json_name` option always takes precedence.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum JsonNames {
+    /// No `json_name` option is written, so protobuf derives each JSON name
+    /// from the field name in lowerCamelCase.
+    Implicit,
+    /// The lowerCamelCase JSON name is written explicitly for each field.
+    CamelCase,
+    /// The field name is written as the JSON name, preserving snake_case
+    /// names in the JSON mapping.
+    Preserve,
+}
+
+impl IndentStyle {
+    fn unit(&self) -> String {
+        match *self {
+            IndentStyle::Spaces(n) => " ".repeat(n),
+            IndentStyle::Tab => "\t".into(),
+        }
+    }
+}
+
+impl Syntax {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Syntax::Proto2 => "proto2",
+            Syntax::Proto3 => "proto3",
+        }
+    }
 }
 
 impl<'a> DescriptorWriter<'a> {
@@ -34,71 +116,258 @@ impl<'a> DescriptorWriter<'a> {
     /// `descriptors` will be found and written to `.proto` files as well.
     pub fn new(descriptors: impl IntoIterator<Item = &'a MessageDescription<'a>>) -> Self {
         // First find all the descriptors starting with the provided roots.
-        let mut descriptors = referenced_descriptors(descriptors);
-
-        // Sort the descriptors to get a consistent order from run to run and build to build.
-        descriptors.sort_by_key(|desc| (desc.package, desc.message.name));
-        // Deduplicate by package and name. TODO: ensure duplicates match.
-        descriptors.dedup_by_key(|desc| (desc.package, desc.message.name));
+        let descriptors = referenced_descriptors(descriptors.into_iter().copied());
 
         Self {
-            descriptors,
+            descriptors: sorted_descriptors(descriptors),
+            services: Vec::new(),
             file_heading: "",
+            syntax: Syntax::Proto3,
+            indent: IndentStyle::Spaces(2),
+            layout: FileLayout::Flat,
+            file_name: None,
+            json_names: JsonNames::Implicit,
+            mark_synthesized: false,
+            verify: false,
         }
     }
 
+    /// Sets the indentation of the written files. Defaults to two spaces.
+    pub fn indent(&mut self, indent: IndentStyle) -> &mut Self {
+        self.indent = indent;
+        self
+    }
+
+    /// Adds gRPC services to write.
+    ///
+    /// Each service is written to the `.proto` file for its package, after the
+    /// messages. The request and response messages of each method, and any
+    /// message types they refer to, are written as well.
+    pub fn services(
+        &mut self,
+        services: impl IntoIterator<Item = &'a ServiceDescriptor<'a>>,
+    ) -> &mut Self {
+        let start = self.services.len();
+        self.services.extend(services);
+        let roots = self.services[start..]
+            .iter()
+            .flat_map(|service| service.methods)
+            .flat_map(|method| [method.request, method.response])
+            .chain(
+                self.descriptors
+                    .iter()
+                    .map(|&tld| MessageDescription::Internal(tld)),
+            );
+        self.descriptors = sorted_descriptors(referenced_descriptors(roots));
+
+        // Sort the services to get a consistent order from run to run.
+        self.services
+            .sort_by_key(|service| (service.package, service.name));
+        self.services
+            .dedup_by_key(|service| (service.package, service.name));
+        self
+    }
+
     /// Sets the file heading written to each file.
     pub fn file_heading(&mut self, file_heading: &'a str) -> &mut Self {
         self.file_heading = file_heading;
         self
     }
 
+    /// Sets the syntax of the written files. Defaults to [`Syntax::Proto3`].
+    pub fn syntax(&mut self, syntax: Syntax) -> &mut Self {
+        self.syntax = syntax;
+        self
+    }
+
+    /// Sets how the written files are named after their packages, which also
+    /// determines the paths used to import them. Defaults to
+    /// [`FileLayout::Flat`].
+    pub fn layout(&mut self, layout: FileLayout) -> &mut Self {
+        self.layout = layout;
+        self
+    }
+
+    /// Sets the function used to name the file for each package, overriding
+    /// the file layout. The name is also used to import the package from the
+    /// files of other packages.
+    ///
+    /// Writing fails if two packages are given the same name.
+    pub fn file_name(&mut self, file_name: impl 'a + Fn(&str) -> String) -> &mut Self {
+        self.file_name = Some(Box::new(file_name));
+        self
+    }
+
+    /// Sets how the JSON name of each field is written. Defaults to
+    /// [`JsonNames::Implicit`].
+    pub fn json_names(&mut self, json_names: JsonNames) -> &mut Self {
+        self.json_names = json_names;
+        self
+    }
+
+    /// Sets whether to mark the messages that the writer synthesizes, such as
+    /// for tuple fields and map entries, rather than writing them from a
+    /// type's descriptor. Defaults to false.
+    ///
+    /// When set, each such message begins with the comment
+    /// `// option (mesh.synthesized) = true;`. This is a comment rather than
+    /// an option so that the files do not depend on a definition of the
+    /// custom option.
+    pub fn mark_synthesized(&mut self, mark_synthesized: bool) -> &mut Self {
+        self.mark_synthesized = mark_synthesized;
+        self
+    }
+
+    /// Sets whether to check the generated files before writing them.
+    /// Defaults to false.
+    ///
+    /// When set, each file is parsed back to check that it is syntactically
+    /// well formed, that each import is needed by a type that the file refers
+    /// to, and that each type from another package is imported. This guards
+    /// against bugs in the writer, so it is mostly useful in tests and build
+    /// tools.
+    pub fn verify(&mut self, verify: bool) -> &mut Self {
+        self.verify = verify;
+        self
+    }
+
     /// Writes the `.proto` files to writers returned by `f`.
+    ///
+    /// Fails without writing anything if any message has duplicate field
+    /// numbers, uses a field number reserved by protobuf, has a field name
+    /// that is not a valid protobuf identifier, or has two fields with the
+    /// same JSON name, if any enum has duplicate or zero values, or if two
+    /// packages have the same file name. If verification is enabled, also
+    /// fails without writing anything if any generated file fails
+    /// verification.
     pub fn write<W: Write>(&self, mut f: impl FnMut(&str) -> io::Result<W>) -> io::Result<()> {
-        let mut descriptors = self.descriptors.iter().copied().peekable();
-        while let Some(&first) = descriptors.peek() {
-            let file = f(&package_proto_file(first.package))?;
-            let mut writer = PackageWriter::new(first.package, Box::new(file));
-            write!(
-                writer,
-                "{file_heading}// Autogenerated, do not edit.\n\nsyntax = \"proto3\";\npackage {proto_package};\n",
-                file_heading = self.file_heading,
-                proto_package = first.package,
-            )?;
-            writer.nl_next();
+        for desc in &self.descriptors {
+            desc.validate(self.json_names)?;
+        }
 
-            // Collect imports.
-            let mut imports = Vec::new();
-            let n = {
-                let mut descriptors = descriptors.clone();
-                let mut n = 0;
-                while descriptors
-                    .peek()
-                    .map_or(false, |d| d.package == first.package)
-                {
-                    let desc = descriptors.next().unwrap();
-                    desc.message.collect_imports(&mut writer, &mut imports)?;
-                    n += 1;
-                }
-                n
-            };
+        let mut packages = Vec::from_iter(
+            self.descriptors
+                .iter()
+                .map(|desc| desc.package)
+                .chain(self.services.iter().map(|service| service.package)),
+        );
+        packages.sort();
+        packages.dedup();
 
-            imports.sort();
-            imports.dedup();
-            for import in imports {
-                writeln!(writer, "import \"{import}\";")?;
+        let mut names = HashMap::new();
+        for &package in &packages {
+            let name = self.package_file_name(package);
+            if let Some(other) = names.insert(name.clone(), package) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("packages {other} and {package} have the same file name {name}"),
+                ));
             }
+        }
 
-            writer.nl_next();
-
-            // Collect messages.
-            for desc in (&mut descriptors).take(n) {
-                desc.message.fmt(&mut writer)?;
+        if !self.verify {
+            for package in packages {
+                let file = f(&self.package_file_name(package))?;
+                self.write_package(package, file)?;
             }
+            return Ok(());
+        }
+
+        let mut files = Vec::new();
+        for package in packages {
+            let name = self.package_file_name(package);
+            let mut text = Vec::new();
+            let imports = self.write_package(package, &mut text)?;
+            let text = String::from_utf8(text)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            super::verify::verify(&name, &text, package, &imports)?;
+            files.push((name, text));
+        }
+        for (name, text) in files {
+            f(&name)?.write_all(text.as_bytes())?;
         }
         Ok(())
     }
 
+    /// Returns the name of the `.proto` file for `package`.
+    fn package_file_name(&self, package: &str) -> String {
+        match &self.file_name {
+            Some(file_name) => file_name(package),
+            None => package_proto_file(package, self.layout),
+        }
+    }
+
+    /// Writes the `.proto` file for `package` to `file`, returning the
+    /// imports that it needs.
+    fn write_package(&self, package: &'a str, file: impl Write) -> io::Result<Vec<Import<'a>>> {
+        let descriptors = self
+            .descriptors
+            .iter()
+            .filter(|desc| desc.package == package);
+        let services = self
+            .services
+            .iter()
+            .filter(|service| service.package == package);
+
+        let file_name = |package: &str| self.package_file_name(package);
+        let mut writer = PackageWriter::new(
+            package,
+            self.syntax,
+            self.indent,
+            &file_name,
+            self.json_names,
+            self.mark_synthesized,
+            Box::new(file),
+        );
+        write!(
+            writer,
+            "{file_heading}// Autogenerated, do not edit.\n\nsyntax = \"{syntax}\";\npackage {proto_package};\n",
+            file_heading = self.file_heading,
+            syntax = self.syntax.as_str(),
+            proto_package = package,
+        )?;
+        writer.nl_next();
+
+        // Collect imports.
+        let mut imports = Vec::new();
+        for desc in descriptors.clone() {
+            desc.collect_imports(&mut writer, &mut imports)?;
+        }
+        for service in services.clone() {
+            service.collect_imports(&mut writer, &mut imports)?;
+        }
+
+        // Write the well-known imports first, separated from the imports of
+        // other packages.
+        imports.sort();
+        imports.dedup();
+        let mut paths = Vec::from_iter(imports.iter().map(|import| &*import.path));
+        paths.dedup();
+        let (well_known, local): (Vec<_>, Vec<_>) = paths
+            .into_iter()
+            .partition(|path| path.starts_with("google/protobuf/"));
+        for path in &well_known {
+            writeln!(writer, "import \"{path}\";")?;
+        }
+        if !well_known.is_empty() {
+            writer.nl_next();
+        }
+        for path in &local {
+            writeln!(writer, "import \"{path}\";")?;
+        }
+
+        writer.nl_next();
+
+        // Collect messages, then services.
+        for desc in descriptors {
+            desc.fmt(&mut writer)?;
+        }
+        for service in services {
+            service.fmt(&mut writer)?;
+        }
+        Ok(imports)
+    }
+
     /// Writes the `.proto` files to disk, rooted at `path`.
     ///
     /// Returns the paths of written files.
@@ -122,26 +391,64 @@ struct PackageWriter<'a, 'w> {
     needs_nl: bool,
     needs_indent: bool,
     indent: String,
+    indent_unit: String,
     package: &'a str,
+    syntax: Syntax,
+    file_name: &'w dyn Fn(&str) -> String,
+    json_names: JsonNames,
+    mark_synthesized: bool,
+}
+
+/// An import of a `.proto` file.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub(super) struct Import<'a> {
+    /// The path of the imported file.
+    pub path: Cow<'a, str>,
+    /// The package of a type that the import provides.
+    pub package: &'a str,
+}
+
+impl<'a> Import<'a> {
+    /// Returns the import for the externally-defined type `name`.
+    fn external(name: &'a str, import_path: &'a str) -> Self {
+        Self {
+            path: import_path.into(),
+            package: name.rsplit_once('.').map_or("", |(package, _)| package),
+        }
+    }
 }
 
 impl<'a, 'w> PackageWriter<'a, 'w> {
-    fn new(package: &'a str, writer: Box<dyn 'w + Write>) -> Self {
+    fn new(
+        package: &'a str,
+        syntax: Syntax,
+        indent: IndentStyle,
+        file_name: &'w dyn Fn(&str) -> String,
+        json_names: JsonNames,
+        mark_synthesized: bool,
+        writer: Box<dyn 'w + Write>,
+    ) -> Self {
         Self {
             writer,
             needs_nl: false,
             needs_indent: false,
             indent: String::new(),
+            indent_unit: indent.unit(),
             package,
+            syntax,
+            file_name,
+            json_names,
+            mark_synthesized,
         }
     }
 
     fn indent(&mut self) {
-        self.indent += "  ";
+        self.indent += &self.indent_unit;
     }
 
     fn unindent(&mut self) {
-        self.indent.truncate(self.indent.len() - 2);
+        self.indent
+            .truncate(self.indent.len() - self.indent_unit.len());
         self.needs_nl = false;
     }
 
@@ -180,13 +487,12 @@ fn flush(&mut self) -> io::Result<()> {
 
 /// Computes the referenced descriptors from a set of descriptors.
 fn referenced_descriptors<'a>(
-    descriptors: impl IntoIterator<Item = &'a MessageDescription<'a>>,
+    descriptors: impl IntoIterator<Item = MessageDescription<'a>>,
 ) -> Vec<&'a TopLevelDescriptor<'a>> {
-    let mut descriptors =
-        Vec::from_iter(descriptors.into_iter().copied().filter_map(|d| match d {
-            MessageDescription::Internal(tld) => Some(tld),
-            MessageDescription::External { .. } => None,
-        }));
+    let mut descriptors = Vec::from_iter(descriptors.into_iter().filter_map(|d| match d {
+        MessageDescription::Internal(tld) => Some(tld),
+        MessageDescription::External { .. } => None,
+    }));
     let mut inserted = HashSet::from_iter(descriptors.iter().copied());
 
     fn process_field_type<'a>(
@@ -202,6 +508,11 @@ fn process_field_type<'a>(
                     }
                 }
             }
+            FieldKind::Enum(tld) => {
+                if inserted.insert(tld()) {
+                    descriptors.push(tld());
+                }
+            }
             FieldKind::Tuple(tys) => {
                 for ty in tys {
                     process_field_type(ty, descriptors, inserted);
@@ -235,22 +546,230 @@ fn process_message<'a>(
 
     let mut i = 0;
     while let Some(&tld) = descriptors.get(i) {
-        process_message(tld.message, &mut descriptors, &mut inserted);
+        match tld.item {
+            TopLevelItem::Message(message) => {
+                process_message(message, &mut descriptors, &mut inserted)
+            }
+            TopLevelItem::Enum(_) => {}
+        }
         i += 1;
     }
 
     descriptors
 }
 
-fn package_proto_file(package: &str) -> String {
-    format!("{}.proto", package)
+/// Sorts and deduplicates descriptors to get a consistent order from run to
+/// run and build to build.
+fn sorted_descriptors<'a>(
+    mut descriptors: Vec<&'a TopLevelDescriptor<'a>>,
+) -> Vec<&'a TopLevelDescriptor<'a>> {
+    descriptors.sort_by_key(|desc| (desc.package, desc.name()));
+    // Deduplicate by package and name. TODO: ensure duplicates match.
+    descriptors.dedup_by_key(|desc| (desc.package, desc.name()));
+    descriptors
+}
+
+/// Field numbers reserved for the protobuf implementation.
+const RESERVED_FIELD_NUMBERS: std::ops::RangeInclusive<u32> = 19000..=19999;
+
+fn package_proto_file(package: &str, layout: FileLayout) -> String {
+    match layout {
+        FileLayout::Flat => format!("{}.proto", package),
+        FileLayout::Nested => format!("{}.proto", package.replace('.', "/")),
+    }
+}
+
+/// Returns whether `name` is a valid protobuf identifier.
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Returns the JSON name that protobuf derives from the field name `name`,
+/// following protoc: each underscore is removed and the letter after it is
+/// capitalized.
+fn default_json_name(name: &str) -> String {
+    let mut json_name = String::with_capacity(name.len());
+    let mut capitalize = false;
+    for c in name.chars() {
+        if c == '_' {
+            capitalize = true;
+        } else if capitalize {
+            json_name.push(c.to_ascii_uppercase());
+            capitalize = false;
+        } else {
+            json_name.push(c);
+        }
+    }
+    json_name
+}
+
+impl<'a> TopLevelDescriptor<'a> {
+    fn validate(&self, json_names: JsonNames) -> io::Result<()> {
+        match self.item {
+            TopLevelItem::Message(message) => message.validate(self.package, json_names),
+            TopLevelItem::Enum(enumeration) => enumeration.validate(self.package),
+        }
+    }
+
+    fn collect_imports(
+        &self,
+        w: &mut PackageWriter<'a, '_>,
+        imports: &mut Vec<Import<'a>>,
+    ) -> io::Result<()> {
+        match self.item {
+            TopLevelItem::Message(message) => message.collect_imports(w, imports),
+            TopLevelItem::Enum(_) => Ok(()),
+        }
+    }
+
+    fn fmt(&self, w: &mut PackageWriter<'_, '_>) -> io::Result<()> {
+        match self.item {
+            TopLevelItem::Message(message) => message.fmt(w),
+            TopLevelItem::Enum(enumeration) => enumeration.fmt_enum(w),
+        }
+    }
+}
+
+impl EnumDescriptor<'_> {
+    /// Validates the values of this enum. `scope` is the package containing
+    /// this enum.
+    fn validate(&self, scope: &str) -> io::Result<()> {
+        let mut numbers = HashSet::new();
+        for value in self.values {
+            let number = value.number;
+            if number == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "enum {scope}.{} uses reserved value 0 for {}",
+                        self.name, value.name
+                    ),
+                ));
+            }
+            if !numbers.insert(number) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("enum {scope}.{} has duplicate value {number}", self.name),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the enum.
+    ///
+    /// Value names are prefixed with the enum name, since protobuf enum values
+    /// share a namespace with the enum's siblings. proto3 requires the first
+    /// value to be zero, so an unspecified value is written for it.
+    fn fmt_enum(&self, w: &mut PackageWriter<'_, '_>) -> io::Result<()> {
+        if !self.comment.is_empty() {
+            for line in self.comment.split('\n') {
+                writeln!(w, "//{line}")?;
+            }
+        }
+        let prefix = self.name.to_shouty_snake_case();
+        writeln!(w, "enum {} {{", self.name)?;
+        w.indent();
+        if let Some(first) = self.values.first() {
+            writeln!(
+                w,
+                "// Decoded as {prefix}_{}.",
+                first.name.to_shouty_snake_case()
+            )?;
+        }
+        writeln!(w, "{prefix}_UNSPECIFIED = 0;")?;
+        for value in self.values {
+            if !value.comment.is_empty() {
+                for line in value.comment.split('\n') {
+                    writeln!(w, "//{}", line.trim_end())?;
+                }
+            }
+            writeln!(
+                w,
+                "{prefix}_{} = {};",
+                value.name.to_shouty_snake_case(),
+                value.number
+            )?;
+        }
+        w.unindent();
+        writeln!(w, "}}")?;
+        w.nl_next();
+        Ok(())
+    }
 }
 
 impl<'a> MessageDescriptor<'a> {
+    /// Validates the field numbers and names of this message and its nested
+    /// messages. `scope` is the package or message containing this message.
+    fn validate(&self, scope: &str, json_names: JsonNames) -> io::Result<()> {
+        let name = format!("{scope}.{}", self.name);
+        let mut numbers = HashSet::new();
+        let mut json_fields = HashMap::new();
+        for field in self
+            .fields
+            .iter()
+            .chain(self.oneofs.iter().flat_map(|oneof| oneof.variants))
+        {
+            let number = field.field_number;
+            if RESERVED_FIELD_NUMBERS.contains(&number) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("message {name} uses reserved field number {number}"),
+                ));
+            }
+            if self
+                .reserved_numbers
+                .iter()
+                .any(|&(start, end)| (start..=end).contains(&number))
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("message {name} reuses reserved field number {number}"),
+                ));
+            }
+            if self.reserved_names.contains(&field.name) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("message {name} reuses reserved field name {}", field.name),
+                ));
+            }
+            if !numbers.insert(number) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("message {name} has duplicate field number {number}"),
+                ));
+            }
+            if !is_identifier(field.name) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("message {name} has invalid field name {:?}", field.name),
+                ));
+            }
+            let json_name = field.json_name(json_names);
+            if let Some(other) = json_fields.insert(json_name.clone(), field.name) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "message {name} has fields {other} and {} with the same JSON name {json_name}",
+                        field.name
+                    ),
+                ));
+            }
+        }
+        for message in self.messages {
+            message.validate(&name, json_names)?;
+        }
+        Ok(())
+    }
+
     fn collect_imports(
         &self,
         w: &mut PackageWriter<'a, '_>,
-        imports: &mut Vec<Cow<'a, str>>,
+        imports: &mut Vec<Import<'a>>,
     ) -> io::Result<()> {
         for message in self.messages {
             message.collect_imports(w, imports)?;
@@ -274,6 +793,45 @@ fn fmt(&self, w: &mut PackageWriter<'_, '_>) -> io::Result<()> {
         }
         writeln!(w, "message {} {{", self.name)?;
         w.indent();
+        for option in self.options {
+            write!(w, "option {} = ", option.name)?;
+            option.value.fmt(w)?;
+            writeln!(w, ";")?;
+        }
+        let mark_synthesized = self.synthesized && w.mark_synthesized;
+        if mark_synthesized {
+            writeln!(w, "// option (mesh.synthesized) = true;")?;
+        }
+        if !self.options.is_empty() || mark_synthesized {
+            w.nl_next();
+        }
+        if !self.reserved_numbers.is_empty() {
+            write!(w, "reserved ")?;
+            for (i, &(start, end)) in self.reserved_numbers.iter().enumerate() {
+                if i > 0 {
+                    write!(w, ", ")?;
+                }
+                if start == end {
+                    write!(w, "{start}")?;
+                } else {
+                    write!(w, "{start} to {end}")?;
+                }
+            }
+            writeln!(w, ";")?;
+        }
+        if !self.reserved_names.is_empty() {
+            write!(w, "reserved ")?;
+            for (i, name) in self.reserved_names.iter().enumerate() {
+                if i > 0 {
+                    write!(w, ", ")?;
+                }
+                write!(w, "\"{name}\"")?;
+            }
+            writeln!(w, ";")?;
+        }
+        if !self.reserved_numbers.is_empty() || !self.reserved_names.is_empty() {
+            w.nl_next();
+        }
         for message in self.messages {
             message.fmt(w)?;
         }
@@ -287,7 +845,67 @@ fn fmt(&self, w: &mut PackageWriter<'_, '_>) -> io::Result<()> {
             oneof.fmt(w)?;
         }
         for field in self.fields {
-            field.fmt(w)?;
+            field.fmt(w, false)?;
+        }
+        w.unindent();
+        writeln!(w, "}}")?;
+        w.nl_next();
+        Ok(())
+    }
+}
+
+impl<'a> MessageDescription<'a> {
+    fn collect_imports(&self, w: &PackageWriter<'a, '_>, imports: &mut Vec<Import<'a>>) {
+        match *self {
+            MessageDescription::Internal(tld) => {
+                if w.package != tld.package {
+                    imports.push(Import {
+                        path: (w.file_name)(tld.package).into(),
+                        package: tld.package,
+                    });
+                }
+            }
+            MessageDescription::External { name, import_path } => {
+                imports.push(Import::external(name, import_path));
+            }
+        }
+    }
+
+    /// Writes the fully-qualified name of the message type.
+    fn fmt_name(&self, w: &mut PackageWriter<'_, '_>) -> io::Result<()> {
+        match *self {
+            MessageDescription::Internal(tld) => write!(w, ".{}.{}", tld.package, tld.name()),
+            MessageDescription::External {
+                name,
+                import_path: _,
+            } => write!(w, ".{name}"),
+        }
+    }
+}
+
+impl<'a> ServiceDescriptor<'a> {
+    fn collect_imports(
+        &self,
+        w: &mut PackageWriter<'a, '_>,
+        imports: &mut Vec<Import<'a>>,
+    ) -> io::Result<()> {
+        for method in self.methods {
+            method.request.collect_imports(w, imports);
+            method.response.collect_imports(w, imports);
+        }
+        Ok(())
+    }
+
+    fn fmt(&self, w: &mut PackageWriter<'_, '_>) -> io::Result<()> {
+        if !self.comment.is_empty() {
+            for line in self.comment.split('\n') {
+                writeln!(w, "//{}", line.trim_end())?;
+            }
+        }
+        writeln!(w, "service {} {{", self.name)?;
+        w.indent();
+        for method in self.methods {
+            method.fmt(w)?;
         }
         w.unindent();
         writeln!(w, "}}")?;
@@ -296,30 +914,49 @@ fn fmt(&self, w: &mut PackageWriter<'_, '_>) -> io::Result<()> {
     }
 }
 
+impl MethodDescriptor<'_> {
+    fn fmt(&self, w: &mut PackageWriter<'_, '_>) -> io::Result<()> {
+        if !self.comment.is_empty() {
+            for line in self.comment.split('\n') {
+                writeln!(w, "//{}", line.trim_end())?;
+            }
+        }
+        write!(w, "rpc {} (", self.name)?;
+        if self.client_streaming {
+            write!(w, "stream ")?;
+        }
+        self.request.fmt_name(w)?;
+        write!(w, ") returns (")?;
+        if self.server_streaming {
+            write!(w, "stream ")?;
+        }
+        self.response.fmt_name(w)?;
+        writeln!(w, ");")?;
+        Ok(())
+    }
+}
+
 impl<'a> FieldType<'a> {
     fn collect_imports(
         &self,
         w: &mut PackageWriter<'a, '_>,
-        imports: &mut Vec<Cow<'a, str>>,
+        imports: &mut Vec<Import<'a>>,
     ) -> io::Result<()> {
         match self.kind {
             FieldKind::Builtin(_) | FieldKind::Local(_) => {}
-            FieldKind::External { import_path, .. } => {
-                imports.push(import_path.into());
+            FieldKind::External { name, import_path } => {
+                imports.push(Import::external(name, import_path));
             }
-            FieldKind::Message(f) => match f() {
-                MessageDescription::Internal(tld) => {
-                    if w.package != tld.package {
-                        imports.push(package_proto_file(tld.package).into());
-                    }
+            FieldKind::Enum(f) => {
+                let tld = f();
+                if w.package != tld.package {
+                    imports.push(Import {
+                        path: (w.file_name)(tld.package).into(),
+                        package: tld.package,
+                    });
                 }
-                MessageDescription::External {
-                    name: _,
-                    import_path,
-                } => {
-                    imports.push(import_path.into());
-                }
-            },
+            }
+            FieldKind::Message(f) => f().collect_imports(w, imports),
             FieldKind::Tuple(field_types) => {
                 for field_type in field_types {
                     field_type.collect_imports(w, imports)?;
@@ -351,7 +988,8 @@ fn fmt_nested_message(&self, w: &mut PackageWriter<'_, '_>) -> io::Result<()> {
             FieldKind::Builtin(_)
             | FieldKind::Local(_)
             | FieldKind::External { .. }
-            | FieldKind::Message(_) => {}
+            | FieldKind::Message(_)
+            | FieldKind::Enum(_) => {}
         }
         Ok(())
     }
@@ -372,11 +1010,36 @@ fn fmt_tuple_message(
             .iter()
             .map(|(&ty, number, name)| FieldDescriptor::new("", ty, name.as_ref(), *number))
             .collect::<Vec<_>>();
-        MessageDescriptor::new(&self.name.to_upper_camel_case(), "", &fields, &[], &[]).fmt(w)?;
+        MessageDescriptor {
+            synthesized: true,
+            ..MessageDescriptor::new(&self.name.to_upper_camel_case(), "", &fields, &[], &[])
+        }
+        .fmt(w)?;
         Ok(())
     }
 
-    fn fmt(&self, w: &mut PackageWriter<'_, '_>) -> io::Result<()> {
+    /// Returns the field's `json_name` option, if it has one.
+    fn json_name_option(&self) -> Option<&str> {
+        self.options.iter().find_map(|option| match option.value {
+            OptionValue::String(v) if option.name == "json_name" => Some(v),
+            _ => None,
+        })
+    }
+
+    /// Returns the name of the field in the JSON mapping.
+    fn json_name(&self, json_names: JsonNames) -> Cow<'_, str> {
+        if let Some(json_name) = self.json_name_option() {
+            return json_name.into();
+        }
+        match json_names {
+            JsonNames::Implicit | JsonNames::CamelCase => default_json_name(self.name).into(),
+            JsonNames::Preserve => self.name.into(),
+        }
+    }
+
+    /// Writes the field. `in_oneof` should be set for `oneof` variants, which
+    /// never have a label.
+    fn fmt(&self, w: &mut PackageWriter<'_, '_>, in_oneof: bool) -> io::Result<()> {
         if !self.comment.is_empty() {
             for line in self.comment.split('\n') {
                 writeln!(w, "//{}", line.trim_end())?;
@@ -384,7 +1047,7 @@ fn fmt(&self, w: &mut PackageWriter<'_, '_>) -> io::Result<()> {
         }
 
         let is_message = match self.field_type.kind {
-            FieldKind::Builtin(_) => false,
+            FieldKind::Builtin(_) | FieldKind::Enum(_) => false,
             FieldKind::Local(_)
             | FieldKind::External { .. }
             | FieldKind::Message(_)
@@ -392,27 +1055,26 @@ fn fmt(&self, w: &mut PackageWriter<'_, '_>) -> io::Result<()> {
             | FieldKind::KeyValue { .. } => true,
         };
 
-        match self.field_type.sequence_type {
+        match (w.syntax, self.field_type.sequence_type) {
+            (_, None | Some(SequenceType::Optional)) if in_oneof => {}
             // Message fields are implicitly optional.
-            Some(SequenceType::Optional) if !is_message => write!(w, "optional ")?,
-            None | Some(SequenceType::Optional) => {}
-            Some(SequenceType::Repeated) => write!(w, "repeated ")?,
-            Some(SequenceType::Map(key)) => write!(w, "map<{key}, ")?,
+            (Syntax::Proto3, Some(SequenceType::Optional)) if !is_message => {
+                write!(w, "optional ")?
+            }
+            (Syntax::Proto3, None | Some(SequenceType::Optional)) => {}
+            // proto2 singular fields always need a label.
+            (Syntax::Proto2, None | Some(SequenceType::Optional)) => write!(w, "optional ")?,
+            (_, Some(SequenceType::Repeated)) => write!(w, "repeated ")?,
+            (_, Some(SequenceType::Map(key))) => write!(w, "map<{key}, ")?,
         };
         match self.field_type.kind {
             FieldKind::Builtin(name) | FieldKind::Local(name) => write!(w, "{}", name)?,
             FieldKind::External { name, .. } => write!(w, ".{}", name)?,
-            FieldKind::Message(tld) => match tld() {
-                MessageDescription::Internal(tld) => {
-                    write!(w, ".{}.{}", tld.package, tld.message.name)?;
-                }
-                MessageDescription::External {
-                    name,
-                    import_path: _,
-                } => {
-                    write!(w, ".{name}")?;
-                }
-            },
+            FieldKind::Message(f) => f().fmt_name(w)?,
+            FieldKind::Enum(tld) => {
+                let tld = tld();
+                write!(w, ".{}.{}", tld.package, tld.name())?;
+            }
             FieldKind::Tuple(_) | FieldKind::KeyValue(_) => {
                 write!(w, "{}", self.name.to_upper_camel_case())?
             }
@@ -420,7 +1082,44 @@ fn fmt(&self, w: &mut PackageWriter<'_, '_>) -> io::Result<()> {
         if matches!(self.field_type.sequence_type, Some(SequenceType::Map(_))) {
             write!(w, ">")?;
         }
-        write!(w, " {} = {};", self.name, self.field_number)?;
+        write!(w, " {} = {}", self.name, self.field_number)?;
+        // proto3 packs packable repeated fields by default, but proto2 does
+        // not.
+        let packed = w.syntax == Syntax::Proto2
+            && self.field_type.sequence_type == Some(SequenceType::Repeated)
+            && FieldType {
+                sequence_type: None,
+                ..self.field_type
+            }
+            .can_pack();
+        let json_name = match w.json_names {
+            JsonNames::Implicit => None,
+            JsonNames::CamelCase | JsonNames::Preserve => self
+                .json_name_option()
+                .is_none()
+                .then(|| self.json_name(w.json_names)),
+        };
+        let options = self
+            .options
+            .iter()
+            .copied()
+            .chain(json_name.as_deref().map(FieldOption::json_name))
+            .collect::<Vec<_>>();
+        if packed || !options.is_empty() {
+            write!(w, " [")?;
+            if packed {
+                write!(w, "packed = true")?;
+            }
+            for (i, option) in options.iter().enumerate() {
+                if packed || i > 0 {
+                    write!(w, ", ")?;
+                }
+                write!(w, "{} = ", option.name)?;
+                option.value.fmt(w)?;
+            }
+            write!(w, "]")?;
+        }
+        write!(w, ";")?;
         if !self.field_type.annotation.is_empty() {
             write!(w, " // {}", self.field_type.annotation)?;
         }
@@ -428,6 +1127,17 @@ fn fmt(&self, w: &mut PackageWriter<'_, '_>) -> io::Result<()> {
     }
 }
 
+impl OptionValue<'_> {
+    fn fmt(&self, w: &mut PackageWriter<'_, '_>) -> io::Result<()> {
+        match *self {
+            OptionValue::Bool(v) => write!(w, "{v}"),
+            OptionValue::Int(v) => write!(w, "{v}"),
+            OptionValue::String(v) => write!(w, "{v:?}"),
+            OptionValue::Identifier(v) => write!(w, "{v}"),
+        }
+    }
+}
+
 impl OneofDescriptor<'_> {
     fn fmt_nested_messages(&self, w: &mut PackageWriter<'_, '_>) -> io::Result<()> {
         for variant in self.variants {
@@ -453,9 +1163,9 @@ fn fmt(&self, w: &mut PackageWriter<'_, '_>) -> io::Result<()> {
                     field_type: FieldType::tuple(&[variant.field_type]),
                     ..*variant
                 }
-                .fmt(w)?;
+                .fmt(w, true)?;
             } else {
-                variant.fmt(w)?;
+                variant.fmt(w, true)?;
             }
         }
         w.unindent();
@@ -468,11 +1178,27 @@ fn fmt(&self, w: &mut PackageWriter<'_, '_>) -> io::Result<()> {
 #[cfg(test)]
 mod tests {
     use super::DescriptorWriter;
+    use super::FileLayout;
+    use super::IndentStyle;
+    use super::JsonNames;
+    use super::Syntax;
     use crate::protofile::message_description;
+    use crate::protofile::FieldDescriptor;
+    use crate::protofile::FieldOption;
+    use crate::protofile::FieldType;
+    use crate::protofile::MessageDescription;
+    use crate::protofile::MessageDescriptor;
+    use crate::protofile::MessageOption;
+    use crate::protofile::MethodDescriptor;
+    use crate::protofile::OneofDescriptor;
+    use crate::protofile::ServiceDescriptor;
+    use crate::protofile::TopLevelDescriptor;
     use crate::Protobuf;
     use std::cell::RefCell;
     use std::collections::HashMap;
     use std::io::Write;
+    use std::time::Duration;
+    use std::time::SystemTime;
 
     /// Comment on this guy.
     #[derive(Protobuf)]
@@ -535,6 +1261,28 @@ enum Bar {
         DoubleRepeat(Vec<Vec<u32>>),
     }
 
+    /// A color.
+    #[derive(Protobuf, Copy, Clone)]
+    #[mesh(package = "test", proto_enum)]
+    enum Color {
+        #[mesh(1)]
+        Red,
+        /// Doc comment
+        #[mesh(2)]
+        DarkGreen,
+    }
+
+    #[derive(Protobuf)]
+    #[mesh(package = "test")]
+    struct Palette {
+        #[mesh(1)]
+        primary: Color,
+        #[mesh(2)]
+        others: Vec<Color>,
+        #[mesh(3)]
+        maybe: Option<Color>,
+    }
+
     struct BorrowedWriter<T>(RefCell<T>);
 
     impl<T: Write> Write for &BorrowedWriter<T> {
@@ -547,13 +1295,27 @@ fn flush(&mut self) -> std::io::Result<()> {
         }
     }
 
+    /// Writes the files described by `writer` and checks that they match
+    /// `expected`.
+    #[track_caller]
+    fn check(writer: &DescriptorWriter<'_>, expected: &str) {
+        let out = BorrowedWriter(RefCell::new(Vec::<u8>::new()));
+        writer.write(|_name| Ok(&out)).unwrap();
+        let s = String::from_utf8(out.0.into_inner()).unwrap();
+        if s != expected {
+            for diff in diff::lines(expected, &s) {
+                match diff {
+                    diff::Result::Left(l) => println!("-{}", l),
+                    diff::Result::Both(l, _) => println!(" {}", l),
+                    diff::Result::Right(r) => println!("+{}", r),
+                }
+            }
+            panic!();
+        }
+    }
+
     #[test]
     fn test() {
-        let writer = BorrowedWriter(RefCell::new(Vec::<u8>::new()));
-        DescriptorWriter::new(&[message_description::<Foo>()])
-            .write(|_name| Ok(&writer))
-            .unwrap();
-        let s = String::from_utf8(writer.0.into_inner()).unwrap();
         let expected = r#"// Autogenerated, do not edit.
 
 syntax = "proto3";
@@ -632,15 +1394,811 @@ fn test() {
   WrappedArray wrapped_array = 13;
 }
 "#;
-        if s != expected {
-            for diff in diff::lines(expected, &s) {
-                match diff {
-                    diff::Result::Left(l) => println!("-{}", l),
-                    diff::Result::Both(l, _) => println!(" {}", l),
-                    diff::Result::Right(r) => println!("+{}", r),
-                }
-            }
-            panic!();
+        check(
+            &DescriptorWriter::new(&[message_description::<Foo>()]),
+            expected,
+        );
+    }
+
+    #[test]
+    fn proto2() {
+        let expected = r#"// Autogenerated, do not edit.
+
+syntax = "proto2";
+package test;
+
+import "google/protobuf/empty.proto";
+import "google/protobuf/wrappers.proto";
+
+message Bar {
+  message Other {
+    optional bool hi = 1;
+    optional uint32 hello = 2;
+  }
+
+  message Repeat {
+    repeated uint32 field1 = 1 [packed = true];
+  }
+
+  message DoubleRepeat {
+    message Field1 {
+      repeated uint32 field1 = 1 [packed = true];
+    }
+
+    repeated Field1 field1 = 1;
+  }
+
+  oneof variant {
+    .google.protobuf.Empty this = 1;
+    .google.protobuf.Empty this2 = 2;
+    uint32 that = 3;
+    Other other = 4;
+    Repeat repeat = 5;
+    DoubleRepeat double_repeat = 6;
+  }
+}
+
+// Comment on this guy.
+message Foo {
+  message Bar {
+    optional uint32 field1 = 1;
+    optional .google.protobuf.Empty field2 = 2;
+  }
+
+  message NestedRepeat {
+    repeated uint32 field1 = 1 [packed = true];
+  }
+
+  message VecMap {
+    optional uint32 key = 1;
+    repeated uint32 value = 2 [packed = true];
+  }
+
+  message WrappedArray {
+    repeated string field1 = 1;
+  }
+
+  // Doc comment
+  optional uint32 x = 1;
+  optional .google.protobuf.UInt32Value t = 2;
+  optional .google.protobuf.Empty t2 = 3;
+  optional Bar bar = 4;
+  // Another doc comment
+  // (multi-line)
+  repeated uint32 y = 5 [packed = true];
+  //
+  //        multi
+  //        line
+  //
+  optional .google.protobuf.Empty b = 6;
+  repeated .test.Foo repeated_self = 7;
+  optional .test.Bar e = 8;
+  repeated NestedRepeat nested_repeat = 9;
+  map<string, .google.protobuf.UInt32Value> proto_map = 10;
+  repeated VecMap vec_map = 11;
+  repeated uint32 bad_array = 12 [packed = true]; // packed repr only
+  optional WrappedArray wrapped_array = 13;
+}
+"#;
+        check(
+            DescriptorWriter::new(&[message_description::<Foo>()]).syntax(Syntax::Proto2),
+            expected,
+        );
+    }
+
+    #[test]
+    fn invalid_field_numbers() {
+        const UINT32: FieldType<'_> = FieldType::builtin("uint32");
+
+        #[track_caller]
+        fn check_err(tld: &'static TopLevelDescriptor<'static>, expected: &str) {
+            let err = DescriptorWriter::new(&[MessageDescription::Internal(tld)])
+                .write(|_name| Ok(std::io::sink()))
+                .unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+            assert_eq!(err.to_string(), expected);
+        }
+
+        static DUPLICATE: TopLevelDescriptor<'_> = TopLevelDescriptor::message(
+            "test",
+            &MessageDescriptor::new(
+                "Duplicate",
+                "",
+                &[
+                    FieldDescriptor::new("", UINT32, "a", 1),
+                    FieldDescriptor::new("", UINT32, "b", 2),
+                ],
+                &[OneofDescriptor::new(
+                    "variant",
+                    &[FieldDescriptor::new("", UINT32, "c", 2)],
+                )],
+                &[],
+            ),
+        );
+        check_err(
+            &DUPLICATE,
+            "message test.Duplicate has duplicate field number 2",
+        );
+
+        static RESERVED: TopLevelDescriptor<'_> = TopLevelDescriptor::message(
+            "test",
+            &MessageDescriptor::new(
+                "Outer",
+                "",
+                &[],
+                &[],
+                &[MessageDescriptor::new(
+                    "Reserved",
+                    "",
+                    &[FieldDescriptor::new("", UINT32, "a", 19000)],
+                    &[],
+                    &[],
+                )],
+            ),
+        );
+        check_err(
+            &RESERVED,
+            "message test.Outer.Reserved uses reserved field number 19000",
+        );
+    }
+
+    #[test]
+    fn reserved() {
+        static MESSAGE: TopLevelDescriptor<'_> = TopLevelDescriptor::message(
+            "test",
+            &MessageDescriptor::new(
+                "Evolved",
+                "",
+                &[
+                    FieldDescriptor::new("", FieldType::builtin("uint32"), "a", 1),
+                    FieldDescriptor::new("", FieldType::builtin("string"), "b", 4),
+                ],
+                &[],
+                &[],
+            )
+            .reserve(&[(3, 3), (5, 7)], &["old_name", "older_name"]),
+        );
+
+        let expected = r#"// Autogenerated, do not edit.
+
+syntax = "proto3";
+package test;
+
+message Evolved {
+  reserved 3, 5 to 7;
+  reserved "old_name", "older_name";
+
+  uint32 a = 1;
+  string b = 4;
+}
+"#;
+        check(
+            &DescriptorWriter::new(&[MessageDescription::Internal(&MESSAGE)]),
+            expected,
+        );
+    }
+
+    #[test]
+    fn proto_enum() {
+        let expected = r#"// Autogenerated, do not edit.
+
+syntax = "proto3";
+package test;
+
+// A color.
+enum Color {
+  // Decoded as COLOR_RED.
+  COLOR_UNSPECIFIED = 0;
+  COLOR_RED = 1;
+  // Doc comment
+  COLOR_DARK_GREEN = 2;
+}
+
+message Palette {
+  .test.Color primary = 1;
+  repeated .test.Color others = 2;
+  optional .test.Color maybe = 3;
+}
+"#;
+        check(
+            &DescriptorWriter::new(&[message_description::<Palette>()]),
+            expected,
+        );
+    }
+
+    #[derive(Protobuf)]
+    #[mesh(package = "test")]
+    struct Request {
+        #[mesh(1)]
+        id: u32,
+    }
+
+    #[derive(Protobuf)]
+    #[mesh(package = "test")]
+    struct Response {
+        #[mesh(1)]
+        value: String,
+    }
+
+    #[test]
+    fn service() {
+        const EMPTY: MessageDescription<'static> = MessageDescription::External {
+            name: "google.protobuf.Empty",
+            import_path: "google/protobuf/empty.proto",
+        };
+
+        static SERVICE: ServiceDescriptor<'_> = ServiceDescriptor::new(
+            "test",
+            "Widgets",
+            " Manages widgets.",
+            &[
+                MethodDescriptor::new(
+                    "Get",
+                    "",
+                    message_description::<Request>(),
+                    message_description::<Response>(),
+                ),
+                MethodDescriptor::new(
+                    "Watch",
+                    " Streams updates.",
+                    message_description::<Request>(),
+                    message_description::<Response>(),
+                )
+                .server_streaming(),
+                MethodDescriptor::new("Ping", "", EMPTY, EMPTY),
+            ],
+        );
+
+        let expected = r#"// Autogenerated, do not edit.
+
+syntax = "proto3";
+package test;
+
+import "google/protobuf/empty.proto";
+
+message Request {
+  uint32 id = 1;
+}
+
+message Response {
+  string value = 1;
+}
+
+// Manages widgets.
+service Widgets {
+  rpc Get (.test.Request) returns (.test.Response);
+  // Streams updates.
+  rpc Watch (.test.Request) returns (stream .test.Response);
+  rpc Ping (.google.protobuf.Empty) returns (.google.protobuf.Empty);
+}
+"#;
+        check(DescriptorWriter::new(&[]).services([&SERVICE]), expected);
+    }
+
+    #[derive(Protobuf)]
+    #[mesh(package = "test")]
+    struct Times {
+        #[mesh(1)]
+        created: SystemTime,
+        #[mesh(2)]
+        timeout: Duration,
+        #[mesh(3)]
+        history: Vec<SystemTime>,
+    }
+
+    #[test]
+    fn well_known_times() {
+        let expected = r#"// Autogenerated, do not edit.
+
+syntax = "proto3";
+package test;
+
+import "google/protobuf/duration.proto";
+import "google/protobuf/timestamp.proto";
+
+message Times {
+  .google.protobuf.Timestamp created = 1;
+  .google.protobuf.Duration timeout = 2;
+  repeated .google.protobuf.Timestamp history = 3;
+}
+"#;
+        check(
+            &DescriptorWriter::new(&[message_description::<Times>()]),
+            expected,
+        );
+    }
+
+    #[test]
+    fn indent() {
+        let expected = r#"// Autogenerated, do not edit.
+
+syntax = "proto3";
+package test;
+
+import "google/protobuf/empty.proto";
+
+message Bar {
+    message Other {
+        bool hi = 1;
+        uint32 hello = 2;
+    }
+
+    message Repeat {
+        repeated uint32 field1 = 1;
+    }
+
+    message DoubleRepeat {
+        message Field1 {
+            repeated uint32 field1 = 1;
         }
+
+        repeated Field1 field1 = 1;
+    }
+
+    oneof variant {
+        .google.protobuf.Empty this = 1;
+        .google.protobuf.Empty this2 = 2;
+        uint32 that = 3;
+        Other other = 4;
+        Repeat repeat = 5;
+        DoubleRepeat double_repeat = 6;
+    }
+}
+"#;
+        check(
+            DescriptorWriter::new(&[message_description::<Bar>()]).indent(IndentStyle::Spaces(4)),
+            expected,
+        );
+    }
+
+    #[test]
+    fn field_options() {
+        static MESSAGE: TopLevelDescriptor<'_> = TopLevelDescriptor::message(
+            "test",
+            &MessageDescriptor::new(
+                "Legacy",
+                "",
+                &[
+                    FieldDescriptor::new(
+                        "",
+                        FieldType::builtin("uint32").annotate("use b instead"),
+                        "a",
+                        1,
+                    )
+                    .options(&[FieldOption::deprecated()]),
+                    FieldDescriptor::new("", FieldType::builtin("string"), "b", 2)
+                        .options(&[FieldOption::json_name("x")]),
+                    FieldDescriptor::new("", FieldType::builtin("uint32").repeated(), "c", 3)
+                        .options(&[FieldOption::deprecated(), FieldOption::json_name("y")]),
+                ],
+                &[],
+                &[],
+            ),
+        );
+
+        let expected = r#"// Autogenerated, do not edit.
+
+syntax = "proto3";
+package test;
+
+message Legacy {
+  uint32 a = 1 [deprecated = true]; // use b instead
+  string b = 2 [json_name = "x"];
+  repeated uint32 c = 3 [deprecated = true, json_name = "y"];
+}
+"#;
+        check(
+            &DescriptorWriter::new(&[MessageDescription::Internal(&MESSAGE)]),
+            expected,
+        );
+
+        let expected = r#"// Autogenerated, do not edit.
+
+syntax = "proto2";
+package test;
+
+message Legacy {
+  optional uint32 a = 1 [deprecated = true]; // use b instead
+  optional string b = 2 [json_name = "x"];
+  repeated uint32 c = 3 [packed = true, deprecated = true, json_name = "y"];
+}
+"#;
+        check(
+            DescriptorWriter::new(&[MessageDescription::Internal(&MESSAGE)]).syntax(Syntax::Proto2),
+            expected,
+        );
+    }
+
+    #[test]
+    fn message_options() {
+        const UINT32: FieldType<'_> = FieldType::builtin("uint32");
+
+        static MESSAGE: TopLevelDescriptor<'_> = TopLevelDescriptor::message(
+            "test",
+            &MessageDescriptor::new(
+                "Legacy",
+                "",
+                &[
+                    FieldDescriptor::new("", UINT32, "a", 1),
+                    FieldDescriptor::new("", FieldType::tuple(&[UINT32, UINT32]), "pair", 2),
+                ],
+                &[],
+                &[],
+            )
+            .options(&[MessageOption::deprecated()]),
+        );
+
+        let expected = r#"// Autogenerated, do not edit.
+
+syntax = "proto3";
+package test;
+
+message Legacy {
+  option deprecated = true;
+
+  message Pair {
+    uint32 field1 = 1;
+    uint32 field2 = 2;
+  }
+
+  uint32 a = 1;
+  Pair pair = 2;
+}
+"#;
+        check(
+            DescriptorWriter::new(&[MessageDescription::Internal(&MESSAGE)]).verify(true),
+            expected,
+        );
+
+        let expected = r#"// Autogenerated, do not edit.
+
+syntax = "proto3";
+package test;
+
+message Legacy {
+  option deprecated = true;
+
+  message Pair {
+    // option (mesh.synthesized) = true;
+
+    uint32 field1 = 1;
+    uint32 field2 = 2;
+  }
+
+  uint32 a = 1;
+  Pair pair = 2;
+}
+"#;
+        check(
+            DescriptorWriter::new(&[MessageDescription::Internal(&MESSAGE)])
+                .mark_synthesized(true)
+                .verify(true),
+            expected,
+        );
+    }
+
+    #[test]
+    fn json_names() {
+        const UINT32: FieldType<'_> = FieldType::builtin("uint32");
+
+        static MESSAGE: TopLevelDescriptor<'_> = TopLevelDescriptor::message(
+            "test",
+            &MessageDescriptor::new(
+                "Names",
+                "",
+                &[
+                    FieldDescriptor::new("", UINT32, "page_size", 1),
+                    FieldDescriptor::new("", UINT32, "count", 2),
+                    FieldDescriptor::new("", UINT32, "next_token", 3)
+                        .options(&[FieldOption::json_name("next")]),
+                ],
+                &[],
+                &[],
+            ),
+        );
+
+        let expected = r#"// Autogenerated, do not edit.
+
+syntax = "proto3";
+package test;
+
+message Names {
+  uint32 page_size = 1 [json_name = "pageSize"];
+  uint32 count = 2 [json_name = "count"];
+  uint32 next_token = 3 [json_name = "next"];
+}
+"#;
+        check(
+            DescriptorWriter::new(&[MessageDescription::Internal(&MESSAGE)])
+                .json_names(JsonNames::CamelCase),
+            expected,
+        );
+
+        let expected = r#"// Autogenerated, do not edit.
+
+syntax = "proto3";
+package test;
+
+message Names {
+  uint32 page_size = 1 [json_name = "page_size"];
+  uint32 count = 2 [json_name = "count"];
+  uint32 next_token = 3 [json_name = "next"];
+}
+"#;
+        check(
+            DescriptorWriter::new(&[MessageDescription::Internal(&MESSAGE)])
+                .json_names(JsonNames::Preserve),
+            expected,
+        );
+
+        // `foo_bar` and `fooBar` are distinct proto fields, but both map to
+        // the JSON name `fooBar` unless snake_case names are preserved.
+        static COLLIDING: TopLevelDescriptor<'_> = TopLevelDescriptor::message(
+            "test",
+            &MessageDescriptor::new(
+                "Colliding",
+                "",
+                &[
+                    FieldDescriptor::new("", UINT32, "foo_bar", 1),
+                    FieldDescriptor::new("", UINT32, "fooBar", 2),
+                ],
+                &[],
+                &[],
+            ),
+        );
+        for json_names in [JsonNames::Implicit, JsonNames::CamelCase] {
+            let err = DescriptorWriter::new(&[MessageDescription::Internal(&COLLIDING)])
+                .json_names(json_names)
+                .write(|_name| Ok(std::io::sink()))
+                .unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+            assert_eq!(
+                err.to_string(),
+                "message test.Colliding has fields foo_bar and fooBar with the same JSON name fooBar"
+            );
+        }
+        DescriptorWriter::new(&[MessageDescription::Internal(&COLLIDING)])
+            .json_names(JsonNames::Preserve)
+            .write(|_name| Ok(std::io::sink()))
+            .unwrap();
+
+        static INVALID: TopLevelDescriptor<'_> = TopLevelDescriptor::message(
+            "test",
+            &MessageDescriptor::new(
+                "Invalid",
+                "",
+                &[FieldDescriptor::new("", UINT32, "r#type", 1)],
+                &[],
+                &[],
+            ),
+        );
+        let err = DescriptorWriter::new(&[MessageDescription::Internal(&INVALID)])
+            .write(|_name| Ok(std::io::sink()))
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        assert_eq!(
+            err.to_string(),
+            r#"message test.Invalid has invalid field name "r#type""#
+        );
+    }
+
+    #[derive(Protobuf)]
+    #[mesh(package = "test.other")]
+    struct Remote {
+        #[mesh(1)]
+        x: u32,
+    }
+
+    #[derive(Protobuf)]
+    #[mesh(package = "test.another")]
+    struct Distant {
+        #[mesh(1)]
+        y: u32,
+    }
+
+    #[derive(Protobuf)]
+    #[mesh(package = "test")]
+    struct Imports {
+        #[mesh(1)]
+        remote: Remote,
+        #[mesh(2)]
+        timeout: Duration,
+        #[mesh(3)]
+        distant: Distant,
+        #[mesh(4)]
+        empty: (),
+    }
+
+    #[test]
+    fn import_groups() {
+        let expected = r#"// Autogenerated, do not edit.
+
+syntax = "proto3";
+package test;
+
+import "google/protobuf/duration.proto";
+import "google/protobuf/empty.proto";
+
+import "test.another.proto";
+import "test.other.proto";
+
+message Imports {
+  .test.other.Remote remote = 1;
+  .google.protobuf.Duration timeout = 2;
+  .test.another.Distant distant = 3;
+  .google.protobuf.Empty empty = 4;
+}
+// Autogenerated, do not edit.
+
+syntax = "proto3";
+package test.another;
+
+message Distant {
+  uint32 y = 1;
+}
+// Autogenerated, do not edit.
+
+syntax = "proto3";
+package test.other;
+
+message Remote {
+  uint32 x = 1;
+}
+"#;
+        check(
+            &DescriptorWriter::new(&[message_description::<Imports>()]),
+            expected,
+        );
+    }
+
+    #[test]
+    fn nested_layout() {
+        let write = |layout| {
+            let mut files = Vec::new();
+            DescriptorWriter::new(&[message_description::<Imports>()])
+                .layout(layout)
+                .write(|name| {
+                    files.push(name.to_owned());
+                    Ok(std::io::sink())
+                })
+                .unwrap();
+            files
+        };
+
+        assert_eq!(
+            write(FileLayout::Flat),
+            ["test.proto", "test.another.proto", "test.other.proto"]
+        );
+        assert_eq!(
+            write(FileLayout::Nested),
+            ["test.proto", "test/another.proto", "test/other.proto"]
+        );
+
+        let expected = r#"// Autogenerated, do not edit.
+
+syntax = "proto3";
+package test;
+
+import "google/protobuf/duration.proto";
+import "google/protobuf/empty.proto";
+
+import "test/another.proto";
+import "test/other.proto";
+
+message Imports {
+  .test.other.Remote remote = 1;
+  .google.protobuf.Duration timeout = 2;
+  .test.another.Distant distant = 3;
+  .google.protobuf.Empty empty = 4;
+}
+// Autogenerated, do not edit.
+
+syntax = "proto3";
+package test.another;
+
+message Distant {
+  uint32 y = 1;
+}
+// Autogenerated, do not edit.
+
+syntax = "proto3";
+package test.other;
+
+message Remote {
+  uint32 x = 1;
+}
+"#;
+        check(
+            DescriptorWriter::new(&[message_description::<Imports>()]).layout(FileLayout::Nested),
+            expected,
+        );
+    }
+
+    #[test]
+    fn custom_file_name() {
+        let mut files = Vec::new();
+        let out = BorrowedWriter(RefCell::new(Vec::<u8>::new()));
+        DescriptorWriter::new(&[message_description::<Imports>()])
+            .file_name(|package| format!("gen/mesh_{package}.proto"))
+            .verify(true)
+            .write(|name| {
+                files.push(name.to_owned());
+                Ok(&out)
+            })
+            .unwrap();
+        assert_eq!(
+            files,
+            [
+                "gen/mesh_test.proto",
+                "gen/mesh_test.another.proto",
+                "gen/mesh_test.other.proto"
+            ]
+        );
+        let text = String::from_utf8(out.0.into_inner()).unwrap();
+        assert!(text.contains(
+            "import \"gen/mesh_test.another.proto\";\nimport \"gen/mesh_test.other.proto\";\n"
+        ));
+
+        let err = DescriptorWriter::new(&[message_description::<Imports>()])
+            .file_name(|_package| "mesh.proto".to_owned())
+            .write(|_name| Ok(std::io::sink()))
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        assert_eq!(
+            err.to_string(),
+            "packages test and test.another have the same file name mesh.proto"
+        );
+    }
+
+    #[test]
+    fn verify() {
+        let foo = [message_description::<Foo>()];
+        let imports = [message_description::<Imports>()];
+        for (roots, syntax, layout) in [
+            (&foo, Syntax::Proto3, FileLayout::Flat),
+            (&foo, Syntax::Proto2, FileLayout::Flat),
+            (&imports, Syntax::Proto3, FileLayout::Flat),
+            (&imports, Syntax::Proto3, FileLayout::Nested),
+        ] {
+            DescriptorWriter::new(roots)
+                .syntax(syntax)
+                .layout(layout)
+                .verify(true)
+                .write(|_| Ok(std::io::sink()))
+                .unwrap();
+        }
+
+        // A message name that is not an identifier is written as is.
+        // Verification catches the malformed output before anything is
+        // written.
+        const UINT32: FieldType<'_> = FieldType::builtin("uint32");
+        static BAD_NAME: TopLevelDescriptor<'_> = TopLevelDescriptor::message(
+            "test",
+            &MessageDescriptor::new(
+                "Bad Name",
+                "",
+                &[FieldDescriptor::new("", UINT32, "a", 1)],
+                &[],
+                &[],
+            ),
+        );
+        let bad = [MessageDescription::Internal(&BAD_NAME)];
+        DescriptorWriter::new(&bad)
+            .write(|_| Ok(std::io::sink()))
+            .unwrap();
+        let mut written = 0;
+        let err = DescriptorWriter::new(&bad)
+            .verify(true)
+            .write(|_| {
+                written += 1;
+                Ok(std::io::sink())
+            })
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(
+            err.to_string(),
+            "generated file test.proto is malformed at line 6: expected `{`, found `Name`"
+        );
+        assert_eq!(written, 0);
     }
 }
@@ -8,8 +8,7 @@
 use crate::protobuf::MessageReader;
 use crate::protobuf::MessageSizer;
 use crate::protobuf::MessageWriter;
-use crate::protofile::DescribeField;
-use crate::protofile::FieldType;
+use crate::protofile::DescribeMessage;
 use crate::protofile::MessageDescription;
 use crate::table::DescribeTable;
 use crate::table::TableEncoder;
@@ -25,10 +24,7 @@
 const NANOS_PER_SEC: u32 = 1_000_000_000;
 
 impl DescribeTable for Timestamp {
-    const DESCRIPTION: MessageDescription<'static> = MessageDescription::External {
-        name: "google.protobuf.Timestamp",
-        import_path: "google/protobuf/timestamp.proto",
-    };
+    const DESCRIPTION: MessageDescription<'static> = MessageDescription::TIMESTAMP;
 }
 
 /// A timestamp representing a point in UTC time with nanosecond resolution.
@@ -97,8 +93,8 @@ fn try_from(value: Timestamp) -> Result<Self, Self::Error> {
 /// Protobuf-compatible encoding for [`Duration`].
 pub struct DurationEncoding;
 
-impl DescribeField<Duration> for DurationEncoding {
-    const FIELD_TYPE: FieldType<'static> = FieldType::builtin("google.protobuf.Duration");
+impl DescribeMessage<Duration> for DurationEncoding {
+    const DESCRIPTION: MessageDescription<'static> = MessageDescription::DURATION;
 }
 
 impl<R> MessageEncode<Duration, R> for DurationEncoding {
@@ -132,6 +128,48 @@ fn read_message(
     }
 }
 
+/// Protobuf-compatible encoding for [`SystemTime`], as a
+/// `google.protobuf.Timestamp`.
+pub struct SystemTimeEncoding;
+
+impl DescribeMessage<SystemTime> for SystemTimeEncoding {
+    const DESCRIPTION: MessageDescription<'static> = MessageDescription::TIMESTAMP;
+}
+
+impl<R> MessageEncode<SystemTime, R> for SystemTimeEncoding {
+    fn write_message(item: SystemTime, writer: MessageWriter<'_, '_, R>) {
+        TableEncoder::write_message(Timestamp::from(item), writer);
+    }
+
+    fn compute_message_size(item: &mut SystemTime, sizer: MessageSizer<'_>) {
+        <TableEncoder as MessageEncode<_, R>>::compute_message_size(
+            &mut Timestamp::from(*item),
+            sizer,
+        );
+    }
+}
+
+impl<R> MessageDecode<'_, SystemTime, R> for SystemTimeEncoding {
+    fn read_message(
+        item: &mut InplaceOption<'_, SystemTime>,
+        reader: MessageReader<'_, '_, R>,
+    ) -> crate::Result<()> {
+        let message = item.take().map_or(
+            Timestamp {
+                seconds: 0,
+                nanos: 0,
+            },
+            Timestamp::from,
+        );
+        inplace_some!(message);
+        TableEncoder::read_message(&mut message, reader)?;
+        let time = SystemTime::try_from(message.take().unwrap())
+            .map_err(|_| DecodeError::TimestampRange)?;
+        item.set(time);
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::Timestamp;
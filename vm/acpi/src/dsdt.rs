@@ -11,6 +11,7 @@
 pub use objects::*;
 pub use ops::*;
 pub use resources::*;
+use std::ops::RangeInclusive;
 use x86defs::apic::APIC_BASE_ADDRESS;
 use zerocopy::AsBytes;
 use zerocopy::FromBytes;
@@ -115,6 +116,23 @@ fn append_to_vec(&self, byte_stream: &mut Vec<u8>) {
     }
 }
 
+/// A PCI host bridge and the resources it forwards to its buses.
+#[derive(Debug, Clone)]
+pub struct PciRootBus {
+    /// The bus numbers behind the bridge. The first is the root bus.
+    pub buses: RangeInclusive<u8>,
+    /// The IO port window, if any.
+    pub io: Option<RangeInclusive<u16>>,
+    /// The MMIO windows.
+    pub mmio: Vec<MemoryRange>,
+}
+
+/// The bus number of `\_SB.PCI0`, added by [`Dsdt::add_pci`].
+pub const PCI0_BUS: u8 = 0;
+
+/// The IO ports that `\_SB.PCI0` claims for PCI configuration access.
+pub const PCI0_CONFIG_PORTS: RangeInclusive<u16> = 0xcf8..=0xcff;
+
 pub struct PciRoutingTableEntry {
     pub address: u32,
     pub pin: u8,
@@ -354,14 +372,66 @@ pub fn add_pci(
         }
         pci0.add_object(&prt);
         let mut crs = CurrentResourceSettings::new();
-        crs.add_resource(&BusNumber::new(0, 1));
-        crs.add_resource(&IoPort::new(0xcf8, 0xcf8, 8));
+        crs.add_resource(&BusNumber::new(PCI0_BUS.into(), 1));
+        crs.add_resource(&IoPort::new(
+            *PCI0_CONFIG_PORTS.start(),
+            *PCI0_CONFIG_PORTS.start(),
+            PCI0_CONFIG_PORTS.len() as u8,
+        ));
         crs.add_resource(&QwordMemory::new(low.start(), low.end() - low.start()));
         crs.add_resource(&QwordMemory::new(high.start(), high.end() - high.start()));
         pci0.add_object(&crs);
         self.add_object(&pci0);
     }
 
+    /// Adds a PCI host bridge with the specified bus numbers and resource
+    /// windows.
+    ///
+    /// `uid` must be unique among the host bridges, and names the device. It
+    /// must not be 0, which is the implicit `_UID` of `\_SB.PCI0`.
+    ///
+    /// ```text
+    /// Device(\_SB.PC<uid>)
+    /// {
+    ///     Name(_HID, PNP0A03)
+    ///     Name(_UID, <uid>)
+    ///     Name(_BBN, <first bus>)
+    ///     Name(_CRS, ResourceTemplate()
+    ///     {
+    ///         WordBusNumber(...) // Bus numbers
+    ///         WordIO(...) // IO window
+    ///         QWordMemory(...) // MMIO windows
+    ///         ...
+    ///     })
+    /// }
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if the IO window covers the whole 64KB port space, whose length
+    /// does not fit in a WordIO descriptor.
+    pub fn add_pci_root_bus(&mut self, uid: u8, bus: &PciRootBus) {
+        let mut pci = Device::new(format!("\\_SB.PC{uid:02X}").as_bytes());
+        pci.add_object(&NamedObject::new(b"_HID", &EisaId(*b"PNP0A03")));
+        pci.add_object(&NamedInteger::new(b"_UID", uid.into()));
+        pci.add_object(&NamedInteger::new(b"_BBN", (*bus.buses.start()).into()));
+        let mut crs = CurrentResourceSettings::new();
+        crs.add_resource(&BusNumber::new(
+            (*bus.buses.start()).into(),
+            bus.buses.len() as u16,
+        ));
+        if let Some(io) = &bus.io {
+            let length = u32::from(*io.end()) - u32::from(*io.start()) + 1;
+            let length = u16::try_from(length).expect("io window covers the whole port space");
+            crs.add_resource(&WordIo::new(*io.start(), length));
+        }
+        for range in &bus.mmio {
+            crs.add_resource(&QwordMemory::new(range.start(), range.len()));
+        }
+        pci.add_object(&crs);
+        self.add_object(&pci);
+    }
+
     /// Add a VMBUS device to the DSDT.
     ///
     /// If `in_pci`, then enumerate the device under PCI0. Otherwise, enumerate
@@ -183,6 +183,36 @@ fn append_to_vec(&self, byte_stream: &mut Vec<u8>) {
     }
 }
 
+/// An ACPI IO port range decoded by a bridge.
+pub struct WordIo {
+    pub min_address: u16,
+    pub max_address: u16,
+    pub length: u16,
+}
+
+impl WordIo {
+    /// Constructs a new IO port range.
+    pub fn new(address: u16, length: u16) -> Self {
+        Self {
+            min_address: address,
+            max_address: address + (length - 1),
+            length,
+        }
+    }
+}
+
+impl ResourceObject for WordIo {
+    fn append_to_vec(&self, byte_stream: &mut Vec<u8>) {
+        byte_stream.extend_from_slice(&[0x88, 0x0d, 0, 1, 0x0c]);
+        byte_stream.push(3); // entire range
+        byte_stream.extend_from_slice(&(0u16).to_le_bytes()); // granularity
+        byte_stream.extend_from_slice(&self.min_address.to_le_bytes());
+        byte_stream.extend_from_slice(&self.max_address.to_le_bytes());
+        byte_stream.extend_from_slice(&(0u16).to_le_bytes()); // translation offset
+        byte_stream.extend_from_slice(&self.length.to_le_bytes());
+    }
+}
+
 pub struct Interrupt {
     pub is_wake_capable: bool,
     pub is_shared: bool,
@@ -345,6 +375,20 @@ fn verify_ioport_resource_object() {
         );
     }
 
+    #[test]
+    fn verify_word_io_resource_object() {
+        let mut crs = CurrentResourceSettings::new();
+        crs.add_resource(&WordIo::new(0x1000, 0x1000));
+        let bytes = crs.to_bytes();
+        verify_expected_bytes(
+            &bytes,
+            &[
+                0x08, b'_', b'C', b'R', b'S', 0x11, 21, 0x0a, 18, 0x88, 0x0d, 0x00, 0x01, 0x0c,
+                0x03, 0x00, 0x00, 0x00, 0x10, 0xff, 0x1f, 0x00, 0x00, 0x00, 0x10, 0x79, 0x00,
+            ],
+        );
+    }
+
     #[test]
     fn verify_interrupt_resource_object() {
         let mut crs = CurrentResourceSettings::new();
@@ -37,6 +37,9 @@ pub fn name(&self) -> &str {
 pub const GPE0_LINE_SET: LineSetId = LineSetId("gpe0");
 /// Line set for the BSP's local interrupts (LINT0/1) on x86.
 pub const BSP_LINT_LINE_SET: LineSetId = LineSetId("bsp_lint");
+/// Line set for PCI interrupts routed through the PIIX4 PCI to ISA bridge's
+/// PIRQ route control registers. Vectors 0-3 are PIRQA-PIRQD.
+pub const PIRQ_LINE_SET: LineSetId = LineSetId("pirq");
 
 impl CanResolveTo<ResolvedChipsetDevice> for ChipsetDeviceHandleKind {
     type Input<'a> = ResolveChipsetDeviceHandleParams<'a>;
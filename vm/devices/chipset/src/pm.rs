@@ -34,12 +34,16 @@
 use chipset_device::pio::ControlPortIoIntercept;
 use chipset_device::pio::PortIoIntercept;
 use chipset_device::pio::RegisterPortIoIntercept;
+use chipset_device::poll_device::PollDevice;
 use chipset_device::ChipsetDevice;
 use inspect::Inspect;
 use inspect::InspectMut;
 use open_enum::open_enum;
+use std::task::Context;
+use std::task::Poll;
 use vmcore::device_state::ChangeDeviceState;
 use vmcore::line_interrupt::LineInterrupt;
+use vmcore::vmtime::VmTime;
 use vmcore::vmtime::VmTimeAccess;
 
 open_enum! {
@@ -75,6 +79,7 @@ pub enum DynReg: u8 {
 const CONTROL_SUSPEND_ENABLE_MASK: u16 = 0x2000; // Enable the specified suspend type
 const CONTROL_SUSPEND_TYPE_MASK: u16 = 0x1C00; // Suspend type field
 const ENABLE_TIMER_OVERFLOW_MASK: u16 = 0x0001; // Timer overflow should interrupt
+const ENABLE_GLOBAL_MASK: u16 = 0x0020; // BIOS release of the global lock should interrupt
 const GLOBAL_CONTROL_BIOS_RLS_MASK: u32 = 0x00000002; // Generate SCI?
 const STATUS_DEVICE_MASK: u16 = 0x0010; // One device event flags is set
 const STATUS_GP_MASK: u16 = 0x0080; // One of the GP event flags is set
@@ -84,7 +89,82 @@ pub enum DynReg: u8 {
 /// Value that initiates a system reset when written to [`DynReg::RESET`].
 pub const RESET_VALUE: u8 = 0x01; // Reset the VM
 
+/// Frequency of the ACPI PM timer.
+const PM_TIMER_HZ: u128 = 3_579_545;
+
+/// Width of the ACPI PM timer counter.
+///
+/// This must match the `TMR_VAL_EXT` flag reported to the guest in the FADT.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Inspect)]
+pub enum PmTimerWidth {
+    /// 24-bit counter, as implemented by the PIIX4.
+    Bits24,
+    /// 32-bit counter (`TMR_VAL_EXT`).
+    Bits32,
+}
+
+impl PmTimerWidth {
+    fn bits(self) -> u32 {
+        match self {
+            PmTimerWidth::Bits24 => 24,
+            PmTimerWidth::Bits32 => 32,
+        }
+    }
+}
+
+/// The ACPI PM timer, derived from VM time.
+#[derive(Inspect)]
+struct PmTimer {
+    width: PmTimerWidth,
+    vmtime: VmTimeAccess,
+    /// The largest tick count reported to the guest, used to keep reads
+    /// monotonic if VM time is resynced backwards.
+    last_ticks: u64,
+}
+
+impl PmTimer {
+    fn new(width: PmTimerWidth, vmtime: VmTimeAccess) -> Self {
+        let mut this = Self {
+            width,
+            vmtime,
+            last_ticks: 0,
+        };
+        this.arm_overflow();
+        this
+    }
+
+    fn ticks(&self) -> u64 {
+        let now = (self.vmtime.now().as_100ns() as u128 * PM_TIMER_HZ / 10_000_000) as u64;
+        now.max(self.last_ticks)
+    }
+
+    /// Returns the current counter value.
+    fn read(&mut self) -> u32 {
+        self.last_ticks = self.ticks();
+        (self.last_ticks & ((1 << self.width.bits()) - 1)) as u32
+    }
+
+    /// Sets the VM time timeout to the next time the counter's most
+    /// significant bit changes, which is when the timer overflow status is
+    /// set.
+    fn arm_overflow(&mut self) {
+        let half_period = 1u64 << (self.width.bits() - 1);
+        let next = (self.ticks() / half_period + 1) * half_period;
+        // Round up so that the timeout never fires before the bit changes.
+        let next_100ns = (next as u128 * 10_000_000).div_ceil(PM_TIMER_HZ);
+        self.vmtime
+            .set_timeout(VmTime::from_100ns(next_100ns as u64));
+    }
+
+    /// Resets the timer after VM time has been reset or restored.
+    fn reset(&mut self) {
+        self.last_ticks = 0;
+        self.arm_overflow();
+    }
+}
+
 #[derive(Clone, Debug, Inspect)]
+#[inspect(extra = "PmState::inspect_acpi")]
 struct PmState {
     #[inspect(hex)]
     general_purpose_output: u32,
@@ -112,6 +192,11 @@ struct PmState {
     global_control: u32,
     #[inspect(hex)]
     device_control: u32,
+
+    /// The sleep type most recently requested by setting SLP_EN, for
+    /// diagnostics. This is not saved.
+    #[inspect(skip)]
+    last_sleep_type: Option<u16>,
 }
 
 impl PmState {
@@ -129,10 +214,45 @@ fn new() -> Self {
             global_enable: 0,
             global_control: 0,
             device_control: 0,
+            last_sleep_type: None,
         }
     }
 
-    fn read_dynamic(&mut self, vmtime: &VmTimeAccess, offset: u8) -> u32 {
+    /// Reports the ACPI fixed registers with their fields decoded.
+    fn inspect_acpi(&self, resp: &mut inspect::Response<'_>) {
+        resp.child("acpi", |req| {
+            req.respond()
+                .child("pm1a_evt", |req| {
+                    req.respond()
+                        .hex("status", self.status)
+                        .hex("enable", self.resume_enable)
+                        .field(
+                            "timer_overflow_enable",
+                            self.resume_enable & ENABLE_TIMER_OVERFLOW_MASK != 0,
+                        )
+                        .field(
+                            "global_enable",
+                            self.resume_enable & ENABLE_GLOBAL_MASK != 0,
+                        );
+                })
+                .child("pm1a_cnt", |req| {
+                    req.respond()
+                        .hex("value", self.control)
+                        .field("sci_enable", self.control & CONTROL_SCI_ENABLE_MASK != 0)
+                        .field(
+                            "sleep_type",
+                            (self.control & CONTROL_SUSPEND_TYPE_MASK) >> 10,
+                        )
+                        .field(
+                            "sleep_enable",
+                            self.control & CONTROL_SUSPEND_ENABLE_MASK != 0,
+                        );
+                })
+                .field("last_sleep_type", self.last_sleep_type);
+        });
+    }
+
+    fn read_dynamic(&mut self, pm_timer: &mut PmTimer, offset: u8) -> u32 {
         match DynReg(offset) {
             // 0x00 - two-byte value
             // Indicate that no events have triggered a sticky flag.
@@ -147,11 +267,7 @@ fn read_dynamic(&mut self, vmtime: &VmTimeAccess, offset: u8) -> u32 {
             // Hypervisor reference time is different from our reference time,
             // but that's ok because nothing else needs to match. This is faster
             // than us doing this work, but not always available.
-            DynReg::TIMER => {
-                let now = vmtime.now();
-                // Convert the 100ns-period VM time to the 3.579545MHz PM timer time.
-                (now.as_100ns() as u128 * 3_579_545 / 10_000_000) as u32
-            }
+            DynReg::TIMER => pm_timer.read(),
             // 0x0C - two-byte value
             DynReg::GEN_PURPOSE_STATUS => self.general_purpose_status.into(),
             // 0x0E - two-byte value
@@ -232,6 +348,7 @@ fn write_dynamic(&mut self, action: &mut PowerActionFn, offset: u8, value: u32,
                     //
                     // Any other values will be ignored.
                     let suspend_type = (value & CONTROL_SUSPEND_TYPE_MASK) >> 10;
+                    self.last_sleep_type = Some(suspend_type);
                     match suspend_type {
                         0 => (action)(PowerAction::PowerOff),
                         1 => (action)(PowerAction::Hibernate),
@@ -341,8 +458,8 @@ struct PowerManagementDeviceRt {
     pio_dynamic: Box<dyn ControlPortIoIntercept>,
     /// ACPI interrupt line
     acpi_interrupt: LineInterrupt,
-    /// VM time access for the PM timer.
-    vmtime: VmTimeAccess,
+    /// The PM timer.
+    pm_timer: PmTimer,
     /// Callback invoked whenever a power action is requested
     #[inspect(skip)]
     action: PowerActionFn,
@@ -398,12 +515,15 @@ impl PowerManagementDevice {
     /// - `action`: a callback invoked whenever the PM initiates a power event
     /// - `pio_control` and `pio_status`: define where in the port IO space the
     ///   control/status registers get mapped to.
+    /// - `pm_timer_width`: must match the width reported to the guest in the
+    ///   FADT
     /// - `enable_acpi_mode`: see the docs for [`EnableAcpiMode`]
     pub fn new(
         action: PowerActionFn,
         acpi_interrupt: LineInterrupt,
         register_pio: &mut dyn RegisterPortIoIntercept,
         vmtime: VmTimeAccess,
+        pm_timer_width: PmTimerWidth,
         enable_acpi_mode: Option<EnableAcpiMode>,
         pm_timer_assist: Option<Box<dyn PmTimerAssist>>,
     ) -> Self {
@@ -415,7 +535,7 @@ pub fn new(
                 pio_dynamic,
                 action,
                 acpi_interrupt,
-                vmtime,
+                pm_timer: PmTimer::new(pm_timer_width, vmtime),
                 pm_timer_assist,
             },
             state: PmState::new(),
@@ -511,6 +631,7 @@ async fn reset(&mut self) {
         self.rt.pio_dynamic.unmap();
         self.rt.acpi_interrupt.set_level(false);
         self.state = PmState::new();
+        self.rt.pm_timer.reset();
         if let Some(acpi_mode) = self.enable_acpi_mode {
             self.enable_acpi_mode(acpi_mode.default_pio_dynamic)
         }
@@ -522,6 +643,10 @@ fn supports_pio(&mut self) -> Option<&mut dyn PortIoIntercept> {
         Some(self)
     }
 
+    fn supports_poll_device(&mut self) -> Option<&mut dyn PollDevice> {
+        Some(self)
+    }
+
     fn supports_line_interrupt_target(&mut self) -> Option<&mut dyn LineInterruptTarget> {
         Some(self)
     }
@@ -568,7 +693,7 @@ fn io_read(&mut self, io_port: u16, data: &mut [u8]) -> IoResult {
             let value = if let Some(aligned_offset) = aligned_offset(offset) {
                 let value: u64 = self
                     .state
-                    .read_dynamic(&self.rt.vmtime, aligned_offset)
+                    .read_dynamic(&mut self.rt.pm_timer, aligned_offset)
                     .into();
                 value >> ((offset - aligned_offset) * 8)
             } else {
@@ -606,6 +731,16 @@ fn io_write(&mut self, io_port: u16, data: &[u8]) -> IoResult {
     }
 }
 
+impl PollDevice for PowerManagementDevice {
+    fn poll_device(&mut self, cx: &mut Context<'_>) {
+        while let Poll::Ready(_now) = self.rt.pm_timer.vmtime.poll_timeout(cx) {
+            self.state.status |= TIMER_OVERFLOW_MASK;
+            self.check_interrupt_assertion();
+            self.rt.pm_timer.arm_overflow();
+        }
+    }
+}
+
 /// Target for lines corresponding to bits in General Purpose Event Block 0.
 ///
 /// For a full general description of this register, see the ACPI Spec. See
@@ -679,6 +814,7 @@ fn save(&mut self) -> Result<Self::SavedState, SaveError> {
                 global_enable,
                 global_control,
                 device_control,
+                last_sleep_type: _,
             } = self.state;
 
             let saved_state = state::SavedState {
@@ -728,11 +864,167 @@ fn restore(&mut self, state: Self::SavedState) -> Result<(), RestoreError> {
                 global_enable,
                 global_control,
                 device_control,
+                last_sleep_type: None,
             };
 
+            self.rt.pm_timer.reset();
             self.check_interrupt_assertion();
 
             Ok(())
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chipset_device::pio::ExternallyManagedPortIoIntercepts;
+    use pal_async::DefaultPool;
+    use std::time::Duration;
+    use test_with_tracing::test;
+    use vmcore::line_interrupt::test_helpers::TestLineInterruptTarget;
+    use vmcore::vmtime::VmTimeKeeper;
+
+    struct TestVmTime {
+        pool: DefaultPool,
+        keeper: VmTimeKeeper,
+        access: Option<VmTimeAccess>,
+    }
+
+    impl TestVmTime {
+        fn new() -> Self {
+            let mut pool = DefaultPool::new();
+            let driver = pool.driver();
+            let mut keeper = VmTimeKeeper::new(&driver, VmTime::from_100ns(0));
+            let source = pool.run_until(keeper.builder().build(&driver)).unwrap();
+            let access = source.access("pm");
+            pool.run_until(keeper.start());
+            Self {
+                pool,
+                keeper,
+                access: Some(access),
+            }
+        }
+
+        /// Moves VM time to `time`, which may be in the past.
+        fn set(&mut self, time: Duration) {
+            let keeper = &mut self.keeper;
+            self.pool.run_until(async {
+                keeper.stop().await;
+                keeper
+                    .restore(vmcore::vmtime::SavedState::from_vmtime(VmTime::from_100ns(
+                        (time.as_nanos() / 100) as u64,
+                    )))
+                    .await;
+                keeper.start().await;
+            });
+        }
+    }
+
+    #[test]
+    fn pm_timer_monotonic() {
+        let mut vmtime = TestVmTime::new();
+        let mut timer = PmTimer::new(PmTimerWidth::Bits32, vmtime.access.take().unwrap());
+
+        vmtime.set(Duration::from_secs(1));
+        let first = timer.read();
+        vmtime.set(Duration::from_secs(2));
+        let second = timer.read();
+        assert!(second > first, "{second} <= {first}");
+        // One second of PM timer ticks, give or take VM time that elapsed
+        // while running the test.
+        assert!((3_579_545..3_579_545 * 2).contains(&(second - first)));
+
+        // Resyncing VM time backwards does not move the timer backwards.
+        vmtime.set(Duration::from_secs(1));
+        let third = timer.read();
+        assert!(third >= second, "{third} < {second}");
+    }
+
+    #[test]
+    fn pm_timer_24bit_wraps() {
+        let mut vmtime = TestVmTime::new();
+        let mut timer = PmTimer::new(PmTimerWidth::Bits24, vmtime.access.take().unwrap());
+
+        // 2^24 ticks is ~4.69 seconds, so five seconds in the counter has
+        // wrapped once.
+        vmtime.set(Duration::from_secs(5));
+        let wrapped = 5 * 3_579_545 - (1 << 24);
+        let value = timer.read();
+        assert!((wrapped..wrapped + 3_579_545).contains(&value), "{value}");
+    }
+
+    #[test]
+    fn pm_timer_overflow() {
+        let mut vmtime = TestVmTime::new();
+        let intcon = TestLineInterruptTarget::new_arc();
+        let mut pm = PowerManagementDevice::new(
+            Box::new(|_: PowerAction| {}),
+            LineInterrupt::new_with_target("acpi", intcon.clone(), 9),
+            &mut ExternallyManagedPortIoIntercepts,
+            vmtime.access.take().unwrap(),
+            PmTimerWidth::Bits24,
+            None,
+            None,
+        );
+        pm.state.resume_enable = ENABLE_TIMER_OVERFLOW_MASK;
+
+        let poll = |vmtime: &mut TestVmTime, pm: &mut PowerManagementDevice| {
+            vmtime.pool.run_until(std::future::poll_fn(|cx| {
+                pm.poll_device(cx);
+                Poll::Ready(())
+            }))
+        };
+
+        poll(&mut vmtime, &mut pm);
+        assert_eq!(pm.state.status & TIMER_OVERFLOW_MASK, 0);
+        assert!(!intcon.is_high(9));
+
+        // The most significant bit of the 24-bit counter changes every 2^23
+        // ticks, or ~2.34 seconds.
+        vmtime.set(Duration::from_millis(2500));
+        poll(&mut vmtime, &mut pm);
+        assert_eq!(pm.state.status & TIMER_OVERFLOW_MASK, TIMER_OVERFLOW_MASK);
+        assert!(intcon.is_high(9));
+    }
+
+    #[test]
+    fn inspect_sleep_state() {
+        let mut vmtime = TestVmTime::new();
+        let mut pm = PowerManagementDevice::new(
+            Box::new(|_: PowerAction| {}),
+            LineInterrupt::detached(),
+            &mut ExternallyManagedPortIoIntercepts,
+            vmtime.access.take().unwrap(),
+            PmTimerWidth::Bits24,
+            None,
+            None,
+        );
+
+        let inspect_acpi = |pm: &mut PowerManagementDevice| {
+            inspect::inspect("acpi", &mut *pm).results().to_string()
+        };
+        assert_eq!(
+            inspect_acpi(&mut pm),
+            "{pm1a_cnt: {sci_enable: false, sleep_enable: false, sleep_type: 0, value: 0x0}, pm1a_evt: {enable: 0x0, global_enable: false, status: 0x0, timer_overflow_enable: false}}"
+        );
+
+        // Enable SCI and the global lock interrupt, then request S4.
+        pm.state.write_dynamic(
+            &mut pm.rt.action,
+            DynReg::RESUME_ENABLE.0,
+            ENABLE_GLOBAL_MASK.into(),
+            0xffff,
+        );
+        pm.state.write_dynamic(
+            &mut pm.rt.action,
+            DynReg::CONTROL.0,
+            (CONTROL_SCI_ENABLE_MASK | CONTROL_SUSPEND_ENABLE_MASK | 1 << 10).into(),
+            0xffff,
+        );
+        assert_eq!(
+            inspect_acpi(&mut pm),
+            "{last_sleep_type: 1, pm1a_cnt: {sci_enable: true, sleep_enable: true, sleep_type: 1, value: 0x2401}, pm1a_evt: {enable: 0x20, global_enable: true, status: 0x0, timer_overflow_enable: false}}"
+        );
+    }
+}
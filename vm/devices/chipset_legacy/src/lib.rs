@@ -3,6 +3,10 @@
 
 //! Various "legacy" chipset devices that collectively implement the Hyper-V
 //! Generation 1 VM chipset.
+//!
+//! The PIIX4 IDE controller (PCI function 1 of the PIIX4) is not part of this
+//! crate: it is implemented by the `ide` crate, alongside the ATA/ATAPI drive
+//! emulation it dispatches to, and is configured via `ide_resources`.
 
 #![forbid(unsafe_code)]
 
@@ -203,6 +203,102 @@ fn restore(&mut self, state: Self::SavedState) -> Result<(), RestoreError> {
 mod tests {
     use super::*;
     use local_clock::MockLocalClock;
+    use local_clock::MockLocalClockAccessor;
+    use std::task::Poll;
+    use std::time::Duration;
+    use vmcore::line_interrupt::test_helpers::TestLineInterruptTarget;
+    use vmcore::vmtime::VmTime;
+    use vmcore::vmtime::VmTimeKeeper;
+
+    const IRQ: u32 = 8;
+
+    const SECOND: u8 = 0x00;
+    const SECOND_ALARM: u8 = 0x01;
+    const MINUTE: u8 = 0x02;
+    const MINUTE_ALARM: u8 = 0x03;
+    const HOUR: u8 = 0x04;
+    const HOUR_ALARM: u8 = 0x05;
+    const STATUS_A: u8 = 0x0A;
+    const STATUS_B: u8 = 0x0B;
+    const STATUS_C: u8 = 0x0C;
+
+    const STATUS_B_24H_BINARY: u8 = 0x06;
+    const STATUS_B_ALARM_ENABLE: u8 = 0x20;
+    const STATUS_B_PERIODIC_ENABLE: u8 = 0x40;
+    const STATUS_C_ALARM: u8 = 0x20;
+    const STATUS_C_PERIODIC: u8 = 0x40;
+    const STATUS_C_IRQ: u8 = 0x80;
+
+    /// An RTC wired to a test interrupt controller, with a running VM clock
+    /// that is only advanced explicitly.
+    struct TimedRtc {
+        pool: pal_async::DefaultPool,
+        vm_time_keeper: VmTimeKeeper,
+        vmtime: VmTime,
+        time: MockLocalClockAccessor,
+        intcon: std::sync::Arc<TestLineInterruptTarget>,
+        rtc: Piix4CmosRtc,
+    }
+
+    impl TimedRtc {
+        fn new() -> Self {
+            let mut pool = pal_async::DefaultPool::new();
+            let driver = pool.driver();
+            let vmtime = VmTime::from_100ns(0);
+            let mut vm_time_keeper = VmTimeKeeper::new(&driver, vmtime);
+            let vm_time_source = pool
+                .run_until(vm_time_keeper.builder().build(&driver))
+                .unwrap();
+
+            let time = MockLocalClock::new();
+            let time_access = time.accessor();
+            let intcon = TestLineInterruptTarget::new_arc();
+            let rtc = Piix4CmosRtc::new(
+                Box::new(time),
+                LineInterrupt::new_with_target("rtc", intcon.clone(), IRQ),
+                &vm_time_source,
+                None,
+                false,
+            );
+            pool.run_until(vm_time_keeper.start());
+
+            Self {
+                pool,
+                vm_time_keeper,
+                vmtime,
+                time: time_access,
+                intcon,
+                rtc,
+            }
+        }
+
+        /// Advances both the VM clock and the real time clock by `duration`,
+        /// then polls the RTC's timers.
+        fn advance(&mut self, duration: Duration) {
+            let Self {
+                pool,
+                vm_time_keeper,
+                vmtime,
+                time,
+                intcon: _,
+                rtc,
+            } = self;
+
+            *vmtime = vmtime.wrapping_add(duration);
+            time.tick(duration);
+            pool.run_until(async {
+                vm_time_keeper.stop().await;
+                vm_time_keeper
+                    .restore(vmcore::vmtime::SavedState::from_vmtime(*vmtime))
+                    .await;
+                vm_time_keeper.start().await;
+            });
+            pool.run_until(std::future::poll_fn(|cx| {
+                rtc.poll_device(cx);
+                Poll::Ready(())
+            }));
+        }
+    }
 
     fn new_test_rtc() -> (
         pal_async::DefaultPool,
@@ -321,6 +417,30 @@ fn test_writeable() {
         }
     }
 
+    #[test]
+    fn test_ext_save_restore() {
+        use vmcore::save_restore::SaveRestore;
+
+        let (_, _, mut rtc) = new_test_rtc();
+
+        // The extended bank must not alias the standard bank.
+        set_cmos_data(&mut rtc, 0x10, 0x5A);
+        set_ext_cmos_data(&mut rtc, 0x10, 0xA5);
+        assert_eq!(get_cmos_data(&mut rtc, 0x10), 0x5A);
+        assert_eq!(get_ext_cmos_data(&mut rtc, 0x10), 0xA5);
+
+        let state = rtc.save().unwrap();
+        let (_, _, mut rtc) = new_test_rtc();
+        rtc.restore(state).unwrap();
+
+        let mut temp = [0];
+        rtc.io_read(Piix4CmosRtcIoPort::EXTENDED_ADDRESS.0, &mut temp)
+            .unwrap();
+        assert_eq!(temp[0], 0x10);
+        assert_eq!(get_cmos_data(&mut rtc, 0x10), 0x5A);
+        assert_eq!(get_ext_cmos_data(&mut rtc, 0x10), 0xA5);
+    }
+
     #[test]
     fn test_writeable_ext() {
         let (_, _, mut rtc) = new_test_rtc();
@@ -341,4 +461,59 @@ fn test_writeable_ext() {
             assert_eq!(get_ext_cmos_data_shadow(&mut rtc, i), 0);
         }
     }
+
+    #[test]
+    fn test_alarm() {
+        let mut t = TimedRtc::new();
+        let rtc = &mut t.rtc;
+
+        set_cmos_data(rtc, STATUS_B, STATUS_B_24H_BINARY);
+        set_cmos_data(rtc, HOUR, 12);
+        set_cmos_data(rtc, MINUTE, 30);
+        set_cmos_data(rtc, SECOND, 10);
+
+        // Set an alarm one second ahead.
+        set_cmos_data(rtc, HOUR_ALARM, 12);
+        set_cmos_data(rtc, MINUTE_ALARM, 30);
+        set_cmos_data(rtc, SECOND_ALARM, 11);
+        get_cmos_data(rtc, STATUS_C);
+        set_cmos_data(rtc, STATUS_B, STATUS_B_24H_BINARY | STATUS_B_ALARM_ENABLE);
+
+        t.advance(Duration::from_millis(500));
+        assert!(!t.intcon.is_high(IRQ));
+
+        t.advance(Duration::from_millis(500));
+        assert!(t.intcon.is_high(IRQ));
+
+        // Reading register C reports the alarm, then clears it and the IRQ.
+        let rtc = &mut t.rtc;
+        let status_c = get_cmos_data(rtc, STATUS_C);
+        assert_eq!(status_c & STATUS_C_ALARM, STATUS_C_ALARM);
+        assert_eq!(status_c & STATUS_C_IRQ, STATUS_C_IRQ);
+        assert!(!t.intcon.is_high(IRQ));
+        assert_eq!(get_cmos_data(&mut t.rtc, STATUS_C), 0);
+    }
+
+    #[test]
+    fn test_periodic() {
+        let mut t = TimedRtc::new();
+        let rtc = &mut t.rtc;
+
+        // 0b0110 selects a 1024Hz periodic rate.
+        set_cmos_data(rtc, STATUS_A, 0x26);
+        get_cmos_data(rtc, STATUS_C);
+        set_cmos_data(
+            rtc,
+            STATUS_B,
+            STATUS_B_24H_BINARY | STATUS_B_PERIODIC_ENABLE,
+        );
+        assert!(!t.intcon.is_high(IRQ));
+
+        t.advance(Duration::from_millis(1));
+        assert!(t.intcon.is_high(IRQ));
+        let status_c = get_cmos_data(&mut t.rtc, STATUS_C);
+        assert_eq!(status_c & STATUS_C_PERIODIC, STATUS_C_PERIODIC);
+        assert_eq!(status_c & STATUS_C_IRQ, STATUS_C_IRQ);
+        assert!(!t.intcon.is_high(IRQ));
+    }
 }
@@ -3,6 +3,7 @@
 
 //! PIIX4 - PCI to ISA Bridge
 
+use chipset_device::interrupt::LineInterruptTarget;
 use chipset_device::io::IoError;
 use chipset_device::io::IoResult;
 use chipset_device::pci::PciConfigSpace;
@@ -18,6 +19,7 @@
 use pci_core::spec::hwid::ProgrammingInterface;
 use pci_core::spec::hwid::Subclass;
 use vmcore::device_state::ChangeDeviceState;
+use vmcore::line_interrupt::LineInterrupt;
 
 /// IO ports as specified by the PIIX4 data sheet
 mod io_ports {
@@ -26,9 +28,23 @@ mod io_ports {
     pub const MATH_COPROC1: u16 = 0xF1;
 }
 
+/// Number of PCI interrupt request lines (PIRQA-PIRQD).
+const NUM_PIRQS: usize = 4;
+
+/// ISA IRQs that PIRQs can be routed to. The remaining IRQs are reserved by
+/// the PIIX4.
+const ROUTABLE_ISA_IRQS: [u8; 11] = [3, 4, 5, 6, 7, 9, 10, 11, 12, 14, 15];
+
+/// Setting this bit in a PIRQ route control register disables routing.
+const PIRQ_ROUTE_DISABLED: u8 = 0x80;
+
 struct PciIsaBridgeRuntime {
     reset_evt: Box<dyn Fn() + Send + Sync>,
     set_a20_signal: Box<dyn FnMut(bool) + Send + Sync>,
+    /// Output lines for each ISA IRQ in [`ROUTABLE_ISA_IRQS`].
+    isa_irqs: Vec<LineInterrupt>,
+    /// Input levels of PIRQA-PIRQD.
+    pirq_levels: [bool; NUM_PIRQS],
 }
 
 /// PIIX4 (PCI device function 0) - PCI to ISA Bridge
@@ -74,9 +90,16 @@ fn new() -> Self {
 }
 
 impl PciIsaBridge {
+    /// Create a new PCI to ISA bridge.
+    ///
+    /// `new_isa_irq` is called to create the output line for each ISA IRQ
+    /// that PCI interrupts can be routed to. PCI interrupts are received via
+    /// the device's [`LineInterruptTarget`] implementation, where vectors 0-3
+    /// correspond to PIRQA-PIRQD.
     pub fn new(
         reset_evt: Box<dyn Fn() + Send + Sync>,
         set_a20_signal: Box<dyn FnMut(bool) + Send + Sync>,
+        mut new_isa_irq: impl FnMut(u8) -> LineInterrupt,
     ) -> Self {
         let cfg_space = ConfigSpaceType0Emulator::new(
             HardwareIds {
@@ -98,6 +121,11 @@ pub fn new(
             rt: PciIsaBridgeRuntime {
                 reset_evt,
                 set_a20_signal,
+                isa_irqs: ROUTABLE_ISA_IRQS
+                    .iter()
+                    .map(|&irq| new_isa_irq(irq))
+                    .collect(),
+                pirq_levels: [false; NUM_PIRQS],
             },
 
             cfg_space,
@@ -105,6 +133,26 @@ pub fn new(
         }
     }
 
+    /// Returns the ISA IRQ that `pirq` is routed to, if any.
+    fn pirq_route(&self, pirq: usize) -> Option<u8> {
+        let route = self.state.pci_irq_routing.to_le_bytes()[pirq];
+        if route & PIRQ_ROUTE_DISABLED != 0 {
+            return None;
+        }
+        let irq = route & 0xF;
+        ROUTABLE_ISA_IRQS.contains(&irq).then_some(irq)
+    }
+
+    /// Drives the ISA IRQ lines according to the current PIRQ levels and
+    /// routing.
+    fn update_isa_irqs(&self) {
+        for (&irq, line) in ROUTABLE_ISA_IRQS.iter().zip(&self.rt.isa_irqs) {
+            let high = (0..NUM_PIRQS)
+                .any(|pirq| self.rt.pirq_levels[pirq] && self.pirq_route(pirq) == Some(irq));
+            line.set_level(high);
+        }
+    }
+
     fn handle_math_coproc_read(&mut self, max_access_size: usize, data: &mut [u8]) {
         if data.len() > max_access_size {
             tracelimit::warn_ratelimited!(?max_access_size, len = ?data.len(), "unexpected MATH_COPROC read len");
@@ -169,6 +217,7 @@ async fn reset(&mut self) {
         // Assume the caller will reset the A20 state to its initial state.
         self.state = PciIsaBridgeState::new();
         self.cfg_space.reset();
+        self.update_isa_irqs();
     }
 }
 
@@ -180,6 +229,22 @@ fn supports_pio(&mut self) -> Option<&mut dyn PortIoIntercept> {
     fn supports_pci(&mut self) -> Option<&mut dyn PciConfigSpace> {
         Some(self)
     }
+
+    fn supports_line_interrupt_target(&mut self) -> Option<&mut dyn LineInterruptTarget> {
+        Some(self)
+    }
+}
+
+/// Target for PCI interrupt request lines PIRQA-PIRQD.
+impl LineInterruptTarget for PciIsaBridge {
+    fn set_irq(&mut self, vector: u32, high: bool) {
+        self.rt.pirq_levels[vector as usize] = high;
+        self.update_isa_irqs();
+    }
+
+    fn valid_lines(&self) -> &[std::ops::RangeInclusive<u32>] {
+        &[0..=(NUM_PIRQS as u32 - 1)]
+    }
 }
 
 impl PortIoIntercept for PciIsaBridge {
@@ -258,11 +323,17 @@ fn pci_cfg_write(&mut self, offset: u16, value: u32) -> IoResult {
         match ConfigSpace(offset) {
             _ if offset < 0x40 => return self.cfg_space.write_u32(offset, value),
             ConfigSpace::PIRQ => {
-                if self.state.pci_irq_routing != value {
-                    tracelimit::info_ratelimited!(new_pci_irq_routing = ?value, "custom PCI IRQ routing is not implemented!");
+                // Each byte routes one of PIRQA-PIRQD: bit 7 disables
+                // routing, and bits 3:0 select the ISA IRQ.
+                for (pirq, route) in value.to_le_bytes().into_iter().enumerate() {
+                    let irq = route & 0xF;
+                    if route & PIRQ_ROUTE_DISABLED == 0 && !ROUTABLE_ISA_IRQS.contains(&irq) {
+                        tracelimit::warn_ratelimited!(pirq, irq, "PIRQ routed to reserved IRQ");
+                    }
                 }
 
                 self.state.pci_irq_routing = value;
+                self.update_isa_irqs();
             }
             ConfigSpace::SER_IRQ => {
                 if !(value == 0x0000000D0 || value == 0x000000010) {
@@ -445,6 +516,7 @@ fn restore(&mut self, state: Self::SavedState) -> Result<(), RestoreError> {
 
             // sync a20 signal
             (self.rt.set_a20_signal)(self.state.a20_gate_enabled);
+            self.update_isa_irqs();
 
             self.cfg_space.restore(cfg_space)?;
 
@@ -452,3 +524,105 @@ fn restore(&mut self, state: Self::SavedState) -> Result<(), RestoreError> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parking_lot::Mutex;
+    use pci_core::PciInterruptPin;
+    use std::sync::Arc;
+    use vmcore::line_interrupt::test_helpers::TestLineInterruptTarget;
+    use vmcore::line_interrupt::LineSet;
+    use vmcore::line_interrupt::LineSetTarget;
+
+    struct BridgeTarget(Arc<Mutex<PciIsaBridge>>);
+
+    impl LineSetTarget for BridgeTarget {
+        fn set_irq(&self, vector: u32, high: bool) {
+            self.0.lock().set_irq(vector, high);
+        }
+    }
+
+    fn new_bridge(intcon: &Arc<TestLineInterruptTarget>) -> PciIsaBridge {
+        PciIsaBridge::new(Box::new(|| {}), Box::new(|_| {}), |irq| {
+            LineInterrupt::new_with_target("irq", intcon.clone(), irq.into())
+        })
+    }
+
+    #[test]
+    fn pirq_routing() {
+        let intcon = TestLineInterruptTarget::new_arc();
+        let mut bridge = new_bridge(&intcon);
+
+        // Route PIRQA to IRQ11 and PIRQB to IRQ10, leaving C and D disabled.
+        bridge
+            .pci_cfg_write(ConfigSpace::PIRQ.0, 0x8080_0A0B)
+            .unwrap();
+
+        bridge.set_irq(1, true);
+        assert!(intcon.is_high(10));
+        assert!(!intcon.is_high(11));
+
+        // Unrouted PIRQs are ignored.
+        bridge.set_irq(2, true);
+        assert!(!intcon.is_high(11));
+
+        // Disabling the route lowers the ISA IRQ.
+        bridge
+            .pci_cfg_write(ConfigSpace::PIRQ.0, 0x8080_800B)
+            .unwrap();
+        assert!(!intcon.is_high(10));
+
+        // Routing PIRQC to the same IRQ as PIRQA shares the line.
+        bridge
+            .pci_cfg_write(ConfigSpace::PIRQ.0, 0x800B_800B)
+            .unwrap();
+        assert!(intcon.is_high(11));
+        bridge.set_irq(2, false);
+        assert!(!intcon.is_high(11));
+    }
+
+    #[test]
+    fn pci_device_inta() {
+        let intcon = TestLineInterruptTarget::new_arc();
+        let bridge = Arc::new(Mutex::new(new_bridge(&intcon)));
+
+        // Wire the PIRQ lines to the bridge the same way the base chipset
+        // does, then give a PCI device's INTA# pin a line on PIRQA.
+        let pirqs = LineSet::new();
+        pirqs.add_target(0..=3, 0, "bridge", Arc::new(BridgeTarget(bridge.clone())));
+        let mut device = ConfigSpaceType0Emulator::new(
+            HardwareIds {
+                vendor_id: 0x1414,
+                device_id: 0x1,
+                revision_id: 0,
+                prog_if: ProgrammingInterface::NONE,
+                sub_class: Subclass::NONE,
+                base_class: ClassCode::UNCLASSIFIED,
+                type0_sub_vendor_id: 0,
+                type0_sub_system_id: 0,
+            },
+            Vec::new(),
+            DeviceBars::new(),
+        );
+        let inta =
+            device.set_interrupt_pin(PciInterruptPin::IntA, pirqs.new_line(0, "inta").unwrap());
+
+        // Default routing sends PIRQA to IRQ11.
+        inta.set_level(true);
+        assert!(intcon.is_high(11));
+        inta.set_level(false);
+        assert!(!intcon.is_high(11));
+
+        // Rerouting PIRQA follows the asserted line.
+        inta.set_level(true);
+        bridge
+            .lock()
+            .pci_cfg_write(ConfigSpace::PIRQ.0, 0x8080_800A)
+            .unwrap();
+        assert!(intcon.is_high(10));
+        assert!(!intcon.is_high(11));
+        inta.set_level(false);
+        assert!(!intcon.is_high(10));
+    }
+}
@@ -4,6 +4,7 @@
 //! PIIX4 - Power Management
 
 use chipset::pm::PmTimerAssist;
+use chipset::pm::PmTimerWidth;
 use chipset::pm::PowerAction;
 use chipset::pm::PowerActionFn;
 use chipset::pm::PowerManagementDevice;
@@ -14,6 +15,7 @@
 use chipset_device::pio::ControlPortIoIntercept;
 use chipset_device::pio::PortIoIntercept;
 use chipset_device::pio::RegisterPortIoIntercept;
+use chipset_device::poll_device::PollDevice;
 use chipset_device::ChipsetDevice;
 use inspect::Inspect;
 use inspect::InspectMut;
@@ -151,6 +153,7 @@ pub fn new(
                 interrupt,
                 register_pio,
                 vmtime,
+                PmTimerWidth::Bits24,
                 None, // manually configured
                 pm_timer_assist,
             ),
@@ -260,6 +263,10 @@ fn supports_pci(&mut self) -> Option<&mut dyn PciConfigSpace> {
         Some(self)
     }
 
+    fn supports_poll_device(&mut self) -> Option<&mut dyn PollDevice> {
+        Some(self)
+    }
+
     fn supports_line_interrupt_target(&mut self) -> Option<&mut dyn LineInterruptTarget> {
         Some(self)
     }
@@ -303,6 +310,12 @@ fn io_write(&mut self, io_port: u16, data: &[u8]) -> IoResult {
     }
 }
 
+impl PollDevice for Piix4Pm {
+    fn poll_device(&mut self, cx: &mut std::task::Context<'_>) {
+        self.inner.poll_device(cx)
+    }
+}
+
 /// Target for lines corresponding to bits in General Purpose Event Block 0.
 ///
 /// For a specific description of an implementation of this, see the PIIX4
@@ -7,27 +7,43 @@
 //! SIO Extended Function registers are shared with floppy disk controller
 //! registers. IO port reads/writes are forwarded to SIO config controller when
 //! the chipset is in config mode and are forwarded to the FDC otherwise.
+//!
+//! The SIO can optionally host the COM1 and COM2 16550 UARTs and the LPT1
+//! parallel port, in which case their IO port decode follows the base
+//! addresses programmed through the SIO config registers, and the UART
+//! interrupts follow the programmed IRQs.
 
 #![warn(missing_docs)]
 
 pub use self::maybe_floppy_disk_controller::MaybeStubFloppyDiskController;
 
+use self::parallel_port::ParallelPort;
+use self::parallel_port::PARALLEL_PORT_REGISTER_BANK_LEN;
 use self::super_io::SioController;
 use chipset_device::io::IoError;
 use chipset_device::io::IoResult;
+use chipset_device::pio::ControlPortIoIntercept;
 use chipset_device::pio::PortIoIntercept;
 use chipset_device::pio::RegisterPortIoIntercept;
 use chipset_device::poll_device::PollDevice;
 use chipset_device::ChipsetDevice;
 use floppy::DriveRibbon;
 use guestmem::GuestMemory;
+use inspect::Inspect;
 use inspect::InspectMut;
+use parking_lot::Mutex;
+use serial_16550::Serial16550;
+use serial_16550_resources::MmioOrIoPort;
+use serial_core::SerialIo;
+use std::sync::Arc;
 use std::task::Context;
 use thiserror::Error;
 use vmcore::device_state::ChangeDeviceState;
 use vmcore::isa_dma_channel::IsaDmaChannel;
 use vmcore::line_interrupt::LineInterrupt;
+use vmcore::line_interrupt::LineSetTarget;
 
+mod parallel_port;
 mod super_io;
 
 const PRI_FLOPPY_BASE_ADDR: u16 = 0x3F0;
@@ -38,6 +54,154 @@
 const PRI_EXT_FUNC_DATA_REG: u16 = 0x3F1;
 const SEC_EXT_FUNC_DATA_REG: u16 = 0x371;
 
+const UART_REGISTER_BANK_LEN: u16 = 8;
+
+/// The ISA IRQs that a hosted UART's interrupt can be routed to.
+pub const SERIAL_PORT_IRQS: [u8; 7] = [3, 4, 5, 7, 9, 10, 11];
+
+/// Forwards a hosted UART's interrupt to the ISA IRQ selected through the
+/// UART's logical device.
+///
+/// The UART's own line targets the router rather than a fixed IRQ, so that
+/// the guest can move the interrupt without the UART being involved.
+struct SerialIrqRouter {
+    state: Mutex<SerialIrqRouterState>,
+}
+
+struct SerialIrqRouterState {
+    /// Output lines for each IRQ in [`SERIAL_PORT_IRQS`].
+    lines: Vec<LineInterrupt>,
+    /// The selected IRQ, if any.
+    irq: Option<u8>,
+    /// The level of the UART's interrupt.
+    high: bool,
+}
+
+impl SerialIrqRouterState {
+    fn line(&self) -> Option<&LineInterrupt> {
+        let index = SERIAL_PORT_IRQS
+            .iter()
+            .position(|&irq| Some(irq) == self.irq)?;
+        Some(&self.lines[index])
+    }
+}
+
+impl SerialIrqRouter {
+    fn new(lines: Vec<LineInterrupt>) -> Self {
+        Self {
+            state: Mutex::new(SerialIrqRouterState {
+                lines,
+                irq: None,
+                high: false,
+            }),
+        }
+    }
+
+    /// Routes the interrupt to `irq`, or disconnects it if `irq` is `None`.
+    fn select(&self, irq: Option<u8>) {
+        let mut state = self.state.lock();
+        if irq == state.irq {
+            return;
+        }
+        if let Some(line) = state.line() {
+            line.set_level(false);
+        }
+        state.irq = irq;
+        match state.line() {
+            Some(line) => line.set_level(state.high),
+            None => {
+                if let Some(irq) = irq {
+                    tracelimit::warn_ratelimited!(irq, "serial port routed to unsupported irq");
+                }
+            }
+        }
+    }
+}
+
+impl LineSetTarget for SerialIrqRouter {
+    fn set_irq(&self, _vector: u32, high: bool) {
+        let mut state = self.state.lock();
+        state.high = high;
+        if let Some(line) = state.line() {
+            line.set_level(high);
+        }
+    }
+}
+
+impl Inspect for SerialIrqRouter {
+    fn inspect(&self, req: inspect::Request<'_>) {
+        let state = self.state.lock();
+        req.respond()
+            .field("irq", state.irq)
+            .field("high", state.high);
+    }
+}
+
+/// An IO port region whose base is programmed through the SIO config
+/// registers.
+#[derive(InspectMut)]
+struct RelocatableRegion {
+    #[inspect(skip)]
+    region: Box<dyn ControlPortIoIntercept>,
+    #[inspect(skip)]
+    len: u16,
+    #[inspect(with = "|x| x.map(inspect::AsHex)")]
+    mapped_base: Option<u16>,
+}
+
+impl RelocatableRegion {
+    fn new(register_pio: &mut dyn RegisterPortIoIntercept, name: &str, len: u16) -> Self {
+        Self {
+            region: register_pio.new_io_region(name, len),
+            len,
+            mapped_base: None,
+        }
+    }
+
+    /// Returns the offset of `io_port` within the region, if it is mapped.
+    fn offset_of(&self, io_port: u16) -> Option<u16> {
+        let base = self.mapped_base?;
+        (base..base + self.len)
+            .contains(&io_port)
+            .then(|| io_port - base)
+    }
+
+    /// Moves the region's IO port decode to `base`.
+    fn remap(&mut self, base: Option<u16>) {
+        if base == self.mapped_base {
+            return;
+        }
+        tracing::debug!(
+            region = self.region.region_name(),
+            old = ?self.mapped_base,
+            new = ?base,
+            "remapping sio region"
+        );
+        self.region.unmap();
+        if let Some(base) = base {
+            self.region.map(base);
+        }
+        self.mapped_base = base;
+    }
+}
+
+#[derive(InspectMut)]
+struct HostedSerialPort {
+    #[inspect(mut)]
+    uart: Serial16550,
+    #[inspect(flatten)]
+    region: RelocatableRegion,
+    irq: Arc<SerialIrqRouter>,
+}
+
+#[derive(InspectMut)]
+struct HostedParallelPort {
+    #[inspect(mut)]
+    lpt: ParallelPort,
+    #[inspect(flatten)]
+    region: RelocatableRegion,
+}
+
 /// Combo Floppy controller + SuperIO config controller, as specified by the
 /// Winbond W83977ATF SIO chipset.
 ///
@@ -56,6 +220,12 @@ pub struct Winbond83977FloppySioDevice<FDC: MaybeStubFloppyDiskController> {
     primary_fdc: FDC,
     #[inspect(mut)]
     secondary_fdc: FDC,
+    #[inspect(mut)]
+    com1: Option<HostedSerialPort>,
+    #[inspect(mut)]
+    com2: Option<HostedSerialPort>,
+    #[inspect(mut)]
+    lpt1: Option<HostedParallelPort>,
 }
 
 #[derive(Debug, Error)]
@@ -67,10 +237,22 @@ pub enum NewWinbond83977FloppySioDeviceError<FdcError> {
     BadPrimaryFdc(#[source] FdcError),
     #[error("failed to init secondary floppy controller")]
     BadSecondaryFdc(#[source] FdcError),
+    #[error("failed to init serial port")]
+    BadSerialPort(#[source] serial_16550::ConfigurationError),
 }
 
 impl<FDC: MaybeStubFloppyDiskController> Winbond83977FloppySioDevice<FDC> {
     /// Create a new `Winbond83977FloppySioDevice`
+    ///
+    /// `serial_ports` are the backends of the COM1 and COM2 UARTs to host, if
+    /// any. UARTs that are not hosted by the SIO must be emulated by
+    /// standalone devices at fixed addresses. `new_serial_irq` is called to
+    /// create a line for each IRQ in [`SERIAL_PORT_IRQS`] that a hosted UART
+    /// can be routed to.
+    ///
+    /// If `parallel_port` is provided, the SIO emulates a printer on LPT1 that
+    /// writes its output to the sink. Otherwise, the parallel port logical
+    /// device cannot be enabled.
     pub fn new(
         guest_memory: GuestMemory,
         interrupt: LineInterrupt,
@@ -79,13 +261,62 @@ pub fn new(
         secondary_disk_drive: DriveRibbon,
         primary_dma: Box<dyn IsaDmaChannel>,
         secondary_dma: Box<dyn IsaDmaChannel>,
+        serial_ports: [Option<Box<dyn SerialIo>>; 2],
+        mut new_serial_irq: impl FnMut(&str, u8) -> LineInterrupt,
+        parallel_port: Option<Box<dyn std::io::Write + Send>>,
     ) -> Result<Self, NewWinbond83977FloppySioDeviceError<FDC::NewError>> {
         let secondary_interrupt = interrupt
             .new_shared("floppy secondary")
             .map_err(NewWinbond83977FloppySioDeviceError::LineShare)?;
 
+        let sio = SioController::new(parallel_port.is_some());
+        let [com1, com2] = serial_ports;
+        let mut new_serial_port = |com: usize, io: Option<Box<dyn SerialIo>>| {
+            let Some(io) = io else {
+                return Ok(None);
+            };
+            let name = ["com1", "com2"][com];
+            let irq = Arc::new(SerialIrqRouter::new(
+                SERIAL_PORT_IRQS
+                    .iter()
+                    .map(|&irq| new_serial_irq(name, irq))
+                    .collect(),
+            ));
+            irq.select(sio.serial_port_irq(com));
+            let base = sio.serial_port_base(com);
+            // The UART only decodes the low bits of the address, so it is
+            // unaffected by later changes to the base address.
+            let uart = Serial16550::new(
+                name.into(),
+                MmioOrIoPort::IoPort(base.unwrap_or(0)),
+                1,
+                LineInterrupt::new_with_target(name, irq.clone(), 0),
+                io,
+                false,
+            )
+            .map_err(NewWinbond83977FloppySioDeviceError::BadSerialPort)?;
+            let mut region = RelocatableRegion::new(register_pio, name, UART_REGISTER_BANK_LEN);
+            region.remap(base);
+            Ok(Some(HostedSerialPort { uart, region, irq }))
+        };
+        let com1 = new_serial_port(0, com1)?;
+        let com2 = new_serial_port(1, com2)?;
+
+        let lpt1 = parallel_port.map(|sink| {
+            let mut region =
+                RelocatableRegion::new(register_pio, "lpt1", PARALLEL_PORT_REGISTER_BANK_LEN);
+            region.remap(sio.parallel_port_base());
+            HostedParallelPort {
+                lpt: ParallelPort::new(sink),
+                region,
+            }
+        });
+
         Ok(Self {
-            sio: SioController::default(),
+            sio,
+            com1,
+            com2,
+            lpt1,
             primary_fdc: FDC::new(
                 guest_memory.clone(),
                 interrupt,
@@ -108,6 +339,28 @@ pub fn new(
     }
 }
 
+impl<FDC: MaybeStubFloppyDiskController> Winbond83977FloppySioDevice<FDC> {
+    fn serial_ports_mut(&mut self) -> impl Iterator<Item = &mut HostedSerialPort> {
+        [self.com1.as_mut(), self.com2.as_mut()]
+            .into_iter()
+            .flatten()
+    }
+
+    /// Moves the decode and interrupts of the hosted ports to match the SIO
+    /// config.
+    fn update_ports(&mut self) {
+        for (com, port) in [&mut self.com1, &mut self.com2].into_iter().enumerate() {
+            if let Some(port) = port {
+                port.region.remap(self.sio.serial_port_base(com));
+                port.irq.select(self.sio.serial_port_irq(com));
+            }
+        }
+        if let Some(port) = &mut self.lpt1 {
+            port.region.remap(self.sio.parallel_port_base());
+        }
+    }
+}
+
 impl<FDC: MaybeStubFloppyDiskController> ChangeDeviceState for Winbond83977FloppySioDevice<FDC> {
     fn start(&mut self) {}
 
@@ -117,6 +370,13 @@ async fn reset(&mut self) {
         self.sio.reset().await;
         self.primary_fdc.reset().await;
         self.secondary_fdc.reset().await;
+        for port in self.serial_ports_mut() {
+            port.uart.reset().await;
+        }
+        if let Some(port) = &mut self.lpt1 {
+            port.lpt.reset().await;
+        }
+        self.update_ports();
     }
 }
 
@@ -134,6 +394,9 @@ impl<FDC: MaybeStubFloppyDiskController> PollDevice for Winbond83977FloppySioDev
     fn poll_device(&mut self, cx: &mut Context<'_>) {
         self.primary_fdc.poll_device(cx);
         self.secondary_fdc.poll_device(cx);
+        for port in self.serial_ports_mut() {
+            port.uart.poll_device(cx);
+        }
     }
 }
 
@@ -152,6 +415,19 @@ fn io_read(&mut self, io_port: u16, data: &mut [u8]) -> IoResult {
             }
         }
 
+        if let Some(port) = self
+            .serial_ports_mut()
+            .find(|port| port.region.offset_of(io_port).is_some())
+        {
+            return port.uart.io_read(io_port, data);
+        }
+
+        if let Some(port) = &mut self.lpt1 {
+            if let Some(offset) = port.region.offset_of(io_port) {
+                return port.lpt.read(offset, data);
+            }
+        }
+
         if self.primary_fdc.offset_of(io_port).is_some() {
             return self.primary_fdc.io_read(io_port, data);
         }
@@ -174,8 +450,24 @@ fn io_write(&mut self, io_port: u16, data: &[u8]) -> IoResult {
             PRI_EXT_FUNC_ENABLE_REG | SEC_EXT_FUNC_ENABLE_REG => {
                 self.sio.update_config_state(data[0]);
             }
-            PRI_EXT_FUNC_DATA_REG | SEC_EXT_FUNC_DATA_REG => self.sio.config_write(data[0]),
+            PRI_EXT_FUNC_DATA_REG | SEC_EXT_FUNC_DATA_REG => {
+                self.sio.config_write(data[0]);
+                self.update_ports();
+            }
             _ => {
+                if let Some(port) = self
+                    .serial_ports_mut()
+                    .find(|port| port.region.offset_of(io_port).is_some())
+                {
+                    return port.uart.io_write(io_port, data);
+                }
+
+                if let Some(port) = &mut self.lpt1 {
+                    if let Some(offset) = port.region.offset_of(io_port) {
+                        return port.lpt.write(offset, data);
+                    }
+                }
+
                 if self.primary_fdc.offset_of(io_port).is_some() {
                     return self.primary_fdc.io_write(io_port, data);
                 }
@@ -289,6 +581,7 @@ mod save_restore {
     use vmcore::save_restore::SaveRestore;
 
     mod state {
+        use super::parallel_port::ParallelPort;
         use super::super_io::SioController;
         use mesh::payload::Protobuf;
         use vmcore::save_restore::SaveRestore;
@@ -310,6 +603,12 @@ pub struct StubSavedState {
             pub floppy2: <floppy_pcat_stub::StubFloppyDiskController as SaveRestore>::SavedState,
             #[mesh(3)]
             pub sio: <SioController as SaveRestore>::SavedState,
+            #[mesh(4)]
+            pub com1: Option<<serial_16550::Serial16550 as SaveRestore>::SavedState>,
+            #[mesh(5)]
+            pub com2: Option<<serial_16550::Serial16550 as SaveRestore>::SavedState>,
+            #[mesh(6)]
+            pub lpt1: Option<<ParallelPort as SaveRestore>::SavedState>,
         }
 
         #[derive(Protobuf, SavedStateRoot)]
@@ -321,6 +620,12 @@ pub struct FullSavedState {
             pub floppy2: <floppy::FloppyDiskController as SaveRestore>::SavedState,
             #[mesh(3)]
             pub sio: <SioController as SaveRestore>::SavedState,
+            #[mesh(4)]
+            pub com1: Option<<serial_16550::Serial16550 as SaveRestore>::SavedState>,
+            #[mesh(5)]
+            pub com2: Option<<serial_16550::Serial16550 as SaveRestore>::SavedState>,
+            #[mesh(6)]
+            pub lpt1: Option<<ParallelPort as SaveRestore>::SavedState>,
         }
     }
 
@@ -334,6 +639,17 @@ fn save(&mut self) -> Result<Self::SavedState, SaveError> {
                         floppy1: self.primary_fdc.save()?,
                         floppy2: self.secondary_fdc.save()?,
                         sio: self.sio.save()?,
+                        com1: self
+                            .com1
+                            .as_mut()
+                            .map(|port| port.uart.save())
+                            .transpose()?,
+                        com2: self
+                            .com2
+                            .as_mut()
+                            .map(|port| port.uart.save())
+                            .transpose()?,
+                        lpt1: self.lpt1.as_mut().map(|port| port.lpt.save()).transpose()?,
                     };
                     Ok(saved_state)
                 }
@@ -343,11 +659,25 @@ fn restore(&mut self, state: Self::SavedState) -> Result<(), RestoreError> {
                         floppy1,
                         floppy2,
                         sio,
+                        com1,
+                        com2,
+                        lpt1,
                     } = state;
 
                     self.primary_fdc.restore(floppy1)?;
                     self.secondary_fdc.restore(floppy2)?;
                     self.sio.restore(sio)?;
+                    for (port, state) in [(&mut self.com1, com1), (&mut self.com2, com2)] {
+                        // Ports that were not hosted when the state was saved
+                        // keep their reset state.
+                        if let (Some(port), Some(state)) = (port, state) {
+                            port.uart.restore(state)?;
+                        }
+                    }
+                    if let (Some(port), Some(state)) = (&mut self.lpt1, lpt1) {
+                        port.lpt.restore(state)?;
+                    }
+                    self.update_ports();
                     Ok(())
                 }
             }
@@ -357,3 +687,164 @@ fn restore(&mut self, state: Self::SavedState) -> Result<(), RestoreError> {
     impl_save_restore!(StubSavedState, floppy_pcat_stub::StubFloppyDiskController);
     impl_save_restore!(FullSavedState, floppy::FloppyDiskController);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chipset_device::pio::ExternallyManagedPortIoIntercepts;
+    use floppy_pcat_stub::StubFloppyDiskController;
+    use serial_core::disconnected::Disconnected;
+    use std::sync::Arc;
+    use std::sync::Mutex;
+    use vmcore::line_interrupt::test_helpers::TestLineInterruptTarget;
+
+    fn new_device(
+        lpt_sink: Box<dyn std::io::Write + Send>,
+    ) -> Winbond83977FloppySioDevice<StubFloppyDiskController> {
+        new_device_with_irqs(lpt_sink, TestLineInterruptTarget::new_arc())
+    }
+
+    fn new_device_with_irqs(
+        lpt_sink: Box<dyn std::io::Write + Send>,
+        irqs: Arc<TestLineInterruptTarget>,
+    ) -> Winbond83977FloppySioDevice<StubFloppyDiskController> {
+        let serial_port = || Some(Box::new(Disconnected) as Box<dyn SerialIo>);
+        Winbond83977FloppySioDevice::new(
+            GuestMemory::empty(),
+            LineInterrupt::detached(),
+            &mut ExternallyManagedPortIoIntercepts,
+            DriveRibbon::None,
+            DriveRibbon::None,
+            Box::new(vmcore::isa_dma_channel::FloatingDmaChannel),
+            Box::new(vmcore::isa_dma_channel::FloatingDmaChannel),
+            [serial_port(), serial_port()],
+            |name, irq| LineInterrupt::new_with_target(name.to_owned(), irqs.clone(), irq.into()),
+            Some(lpt_sink),
+        )
+        .unwrap()
+    }
+
+    fn write(
+        dev: &mut Winbond83977FloppySioDevice<StubFloppyDiskController>,
+        port: u16,
+        value: u8,
+    ) {
+        assert!(matches!(dev.io_write(port, &[value]), IoResult::Ok));
+    }
+
+    fn config_write(
+        dev: &mut Winbond83977FloppySioDevice<StubFloppyDiskController>,
+        register: u8,
+        value: u8,
+    ) {
+        write(dev, PRI_EXT_FUNC_ENABLE_REG, register);
+        write(dev, PRI_EXT_FUNC_DATA_REG, value);
+    }
+
+    #[test]
+    fn relocate_com2() {
+        let mut dev = new_device(Box::new(std::io::sink()));
+        const SCRATCH: u16 = 7;
+
+        // Enter config mode, select COM2 and move it to 0x3E8.
+        write(&mut dev, PRI_EXT_FUNC_ENABLE_REG, 0x87);
+        write(&mut dev, PRI_EXT_FUNC_ENABLE_REG, 0x87);
+        config_write(&mut dev, 0x07, 3);
+        config_write(&mut dev, 0x60, 0x03);
+        config_write(&mut dev, 0x61, 0xE8);
+        write(&mut dev, PRI_EXT_FUNC_ENABLE_REG, 0xAA);
+
+        write(&mut dev, 0x3E8 + SCRATCH, 0x5A);
+        let mut data = [0];
+        assert!(matches!(
+            dev.io_read(0x3E8 + SCRATCH, &mut data),
+            IoResult::Ok
+        ));
+        assert_eq!(data[0], 0x5A);
+
+        assert!(matches!(
+            dev.io_read(0x2F8 + SCRATCH, &mut data),
+            IoResult::Err(IoError::InvalidRegister)
+        ));
+
+        // COM1 is unaffected.
+        write(&mut dev, 0x3F8 + SCRATCH, 0xA5);
+        assert!(matches!(
+            dev.io_read(0x3F8 + SCRATCH, &mut data),
+            IoResult::Ok
+        ));
+        assert_eq!(data[0], 0xA5);
+    }
+
+    #[test]
+    fn route_com2_irq() {
+        let irqs = TestLineInterruptTarget::new_arc();
+        let mut dev = new_device_with_irqs(Box::new(std::io::sink()), irqs.clone());
+        const IER: u16 = 0x2F8 + 1;
+        const MCR: u16 = 0x2F8 + 4;
+
+        // Raise a THR empty interrupt on COM2, which starts out on IRQ4.
+        write(&mut dev, IER, 0x02);
+        write(&mut dev, MCR, 0x08);
+        assert!(irqs.is_high(4));
+        assert!(!irqs.is_high(5));
+
+        // Move COM2 to IRQ5. The pending interrupt follows it.
+        write(&mut dev, PRI_EXT_FUNC_ENABLE_REG, 0x87);
+        write(&mut dev, PRI_EXT_FUNC_ENABLE_REG, 0x87);
+        config_write(&mut dev, 0x07, 3);
+        config_write(&mut dev, 0x70, 5);
+        assert!(!irqs.is_high(4));
+        assert!(irqs.is_high(5));
+
+        // Clearing the IRQ select disconnects the interrupt.
+        config_write(&mut dev, 0x70, 0);
+        write(&mut dev, PRI_EXT_FUNC_ENABLE_REG, 0xAA);
+        assert!(!irqs.is_high(5));
+    }
+
+    #[derive(Clone, Default)]
+    struct SharedSink(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for SharedSink {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn print_byte() {
+        let sink = SharedSink::default();
+        let mut dev = new_device(Box::new(sink.clone()));
+        const DATA: u16 = 0x378;
+        const STATUS: u16 = 0x379;
+        const CONTROL: u16 = 0x37A;
+
+        let mut status = [0];
+        assert!(matches!(dev.io_read(STATUS, &mut status), IoResult::Ok));
+        // Not busy, not acknowledging, online.
+        assert_eq!(status[0] & 0xD0, 0xD0);
+
+        // Latch a byte into the printer by pulsing STROBE.
+        write(&mut dev, DATA, b'A');
+        write(&mut dev, CONTROL, 0x0D);
+        write(&mut dev, CONTROL, 0x0C);
+        assert_eq!(sink.0.lock().unwrap().as_slice(), b"A");
+
+        // The printer acknowledges the byte, then returns to idle.
+        assert!(matches!(dev.io_read(STATUS, &mut status), IoResult::Ok));
+        assert_eq!(status[0] & 0xC0, 0x80);
+        assert!(matches!(dev.io_read(STATUS, &mut status), IoResult::Ok));
+        assert_eq!(status[0] & 0xC0, 0xC0);
+
+        // Holding STROBE asserted prints the byte only once.
+        write(&mut dev, CONTROL, 0x0D);
+        write(&mut dev, CONTROL, 0x0D);
+        assert_eq!(sink.0.lock().unwrap().as_slice(), b"AA");
+    }
+}
@@ -0,0 +1,219 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Emulation of the SIO's parallel port in IEEE-1284 SPP (compatibility)
+//! mode.
+//!
+//! Bytes are latched from the data register when the guest asserts STROBE,
+//! and are written straight through to a host sink. The emulated printer is
+//! never busy, and acknowledges each byte with a single ACK pulse, which is
+//! observed by the next read of the status register.
+//!
+//! The port's interrupt (IRQ7) is not emulated. Guests using the port poll
+//! the status register instead.
+
+use chipset_device::io::IoError;
+use chipset_device::io::IoResult;
+use inspect::InspectMut;
+use open_enum::open_enum;
+use std::io::Write;
+use vmcore::device_state::ChangeDeviceState;
+
+/// Number of IO ports decoded by the parallel port in SPP mode.
+pub const PARALLEL_PORT_REGISTER_BANK_LEN: u16 = 3;
+
+open_enum! {
+    enum RegisterOffset: u16 {
+        DATA    = 0,
+        STATUS  = 1,
+        CONTROL = 2,
+    }
+}
+
+mod status {
+    /// Reserved bits, which read as 1.
+    pub const RESERVED: u8 = 0x03;
+    /// Active low. No interrupt is pending.
+    pub const N_IRQ: u8 = 0x04;
+    /// Active low. No printer error.
+    pub const N_ERROR: u8 = 0x08;
+    /// The printer is online.
+    pub const SELECT: u8 = 0x10;
+    /// Active low. The printer is acknowledging a byte.
+    pub const N_ACK: u8 = 0x40;
+    /// Active low. The printer is busy.
+    pub const N_BUSY: u8 = 0x80;
+}
+
+mod control {
+    /// Latch the data register into the printer.
+    pub const STROBE: u8 = 0x01;
+    /// Active low. Initialize the printer.
+    pub const N_INIT: u8 = 0x04;
+    /// Bits that are backed by storage. The remaining bits read as 1.
+    pub const WRITABLE: u8 = 0x1F;
+}
+
+/// A parallel port in SPP mode, connected to a printer that writes everything
+/// it receives to a host sink.
+#[derive(InspectMut)]
+pub struct ParallelPort {
+    // Runtime glue
+    #[inspect(skip)]
+    sink: Box<dyn Write + Send>,
+
+    // Volatile state
+    #[inspect(hex)]
+    data: u8,
+    #[inspect(hex)]
+    control: u8,
+    ack_pending: bool,
+}
+
+impl ParallelPort {
+    /// Returns a new parallel port that writes printed bytes to `sink`.
+    ///
+    /// Use [`std::io::sink()`] to discard output.
+    pub fn new(sink: Box<dyn Write + Send>) -> Self {
+        Self {
+            sink,
+            data: 0,
+            control: control::N_INIT,
+            ack_pending: false,
+        }
+    }
+
+    fn read_status(&mut self) -> u8 {
+        let mut status =
+            status::RESERVED | status::N_IRQ | status::N_ERROR | status::SELECT | status::N_BUSY;
+        // The ACK pulse lasts for exactly one read.
+        if !std::mem::take(&mut self.ack_pending) {
+            status |= status::N_ACK;
+        }
+        status
+    }
+
+    fn write_control(&mut self, value: u8) {
+        let value = value & control::WRITABLE;
+        if value & control::N_INIT == 0 {
+            self.ack_pending = false;
+        }
+        let strobe_asserted = value & !self.control & control::STROBE != 0;
+        self.control = value;
+        if strobe_asserted {
+            self.print(self.data);
+        }
+    }
+
+    fn print(&mut self, byte: u8) {
+        if let Err(err) = self
+            .sink
+            .write_all(&[byte])
+            .and_then(|()| self.sink.flush())
+        {
+            tracelimit::warn_ratelimited!(
+                error = &err as &dyn std::error::Error,
+                "failed to write parallel port output"
+            );
+        }
+        self.ack_pending = true;
+    }
+
+    /// Reads the register at `offset` from the port base.
+    pub fn read(&mut self, offset: u16, data: &mut [u8]) -> IoResult {
+        if data.len() != 1 {
+            return IoResult::Err(IoError::InvalidAccessSize);
+        }
+
+        data[0] = match RegisterOffset(offset) {
+            RegisterOffset::DATA => self.data,
+            RegisterOffset::STATUS => self.read_status(),
+            RegisterOffset::CONTROL => self.control | !control::WRITABLE,
+            _ => return IoResult::Err(IoError::InvalidRegister),
+        };
+        IoResult::Ok
+    }
+
+    /// Writes the register at `offset` from the port base.
+    pub fn write(&mut self, offset: u16, data: &[u8]) -> IoResult {
+        if data.len() != 1 {
+            return IoResult::Err(IoError::InvalidAccessSize);
+        }
+
+        match RegisterOffset(offset) {
+            RegisterOffset::DATA => self.data = data[0],
+            // The status register is read-only.
+            RegisterOffset::STATUS => {}
+            RegisterOffset::CONTROL => self.write_control(data[0]),
+            _ => return IoResult::Err(IoError::InvalidRegister),
+        }
+        IoResult::Ok
+    }
+}
+
+impl ChangeDeviceState for ParallelPort {
+    fn start(&mut self) {}
+
+    async fn stop(&mut self) {}
+
+    async fn reset(&mut self) {
+        self.data = 0;
+        self.control = control::N_INIT;
+        self.ack_pending = false;
+    }
+}
+
+mod save_restore {
+    use super::*;
+    use vmcore::save_restore::RestoreError;
+    use vmcore::save_restore::SaveError;
+    use vmcore::save_restore::SaveRestore;
+
+    mod state {
+        use mesh::payload::Protobuf;
+        use vmcore::save_restore::SavedStateRoot;
+
+        #[derive(Protobuf, SavedStateRoot)]
+        #[mesh(package = "chipset.superio.lpt")]
+        pub struct SavedState {
+            #[mesh(1)]
+            pub data: u8,
+            #[mesh(2)]
+            pub control: u8,
+            #[mesh(3)]
+            pub ack_pending: bool,
+        }
+    }
+
+    impl SaveRestore for ParallelPort {
+        type SavedState = state::SavedState;
+
+        fn save(&mut self) -> Result<Self::SavedState, SaveError> {
+            let Self {
+                sink: _,
+                data,
+                control,
+                ack_pending,
+            } = *self;
+
+            Ok(state::SavedState {
+                data,
+                control,
+                ack_pending,
+            })
+        }
+
+        fn restore(&mut self, state: Self::SavedState) -> Result<(), RestoreError> {
+            let state::SavedState {
+                data,
+                control,
+                ack_pending,
+            } = state;
+
+            self.data = data;
+            self.control = control & control::WRITABLE;
+            self.ack_pending = ack_pending;
+            Ok(())
+        }
+    }
+}
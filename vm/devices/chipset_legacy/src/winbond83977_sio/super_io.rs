@@ -150,7 +150,10 @@ impl LogicalDeviceData {
     // least wrt these sorts of base chipset devices), we'll take the pragmatic
     // approach of hard-coding these values to "known good" values, and assume the
     // top-level VMM code hasn't decided to move things around.
-    fn default_data() -> [Self; NUM_SIO_DEVICES] {
+    //
+    // The one exception is the parallel port, which is only enabled when the
+    // SIO is hosting one.
+    fn default_data(has_parallel_port: bool) -> [Self; NUM_SIO_DEVICES] {
         let mut defaults: [Self; NUM_SIO_DEVICES] = [Self::default(); NUM_SIO_DEVICES];
 
         defaults[LogicalDeviceIndex::FLOPPY_CONTROLLER.0 as usize] = Self {
@@ -161,14 +164,24 @@ impl LogicalDeviceData {
             config_data: [0x0E, 0x00, 0xFF, 0x00, 0x00, 0x00, 0x00, 0x00],
         };
 
-        // This must exist for compatibility sake, even though we don't actually
-        // have an emulated parallel port.
-        defaults[LogicalDeviceIndex::PARALLEL_PORT.0 as usize] = Self {
-            enabled: false,
-            io_port_base: [0x0000; 2],
-            irq_vector: [0, 0],
-            dma_channel: [4, 0],
-            config_data: [0x3C, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+        // This must exist for compatibility sake, even if we don't have an
+        // emulated parallel port.
+        defaults[LogicalDeviceIndex::PARALLEL_PORT.0 as usize] = if has_parallel_port {
+            Self {
+                enabled: true,
+                io_port_base: [0x378, 0],
+                irq_vector: [7, 0],
+                dma_channel: [4, 0],
+                config_data: [0x3C, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+            }
+        } else {
+            Self {
+                enabled: false,
+                io_port_base: [0x0000; 2],
+                irq_vector: [0, 0],
+                dma_channel: [4, 0],
+                config_data: [0x3C, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+            }
         };
 
         defaults[LogicalDeviceIndex::COM1_PORT.0 as usize] = Self {
@@ -242,18 +255,24 @@ struct SioControllerState {
 
 #[derive(Debug, InspectMut)]
 pub struct SioController {
+    // Static configuration
+    has_parallel_port: bool,
+
     // Volatile state
     state: SioControllerState,
 }
 
-impl Default for SioController {
-    fn default() -> Self {
+impl SioController {
+    /// Returns a new SIO config controller. `has_parallel_port` indicates
+    /// whether the guest may enable the parallel port logical device.
+    pub fn new(has_parallel_port: bool) -> Self {
         Self {
+            has_parallel_port,
             state: SioControllerState {
                 config_idx_state: ConfigIdxState::default(),
                 config_idx: ConfigRegister::default(),
                 device_idx: LogicalDeviceIndex::default(),
-                device_data: LogicalDeviceData::default_data(),
+                device_data: LogicalDeviceData::default_data(has_parallel_port),
             },
         }
     }
@@ -442,8 +461,10 @@ pub fn config_write(&mut self, value: u8) {
         // Handle writes to device-specific registers.
         match self.state.config_idx {
             ConfigRegister::ENABLE_DEVICE => {
-                // Disallow enabling parallel port.
-                if self.state.device_idx != LogicalDeviceIndex::PARALLEL_PORT {
+                // Disallow enabling parallel port, unless one is emulated.
+                if self.state.device_idx != LogicalDeviceIndex::PARALLEL_PORT
+                    || self.has_parallel_port
+                {
                     dev.enabled = value & 0x1 == 1;
                 } else {
                     tracing::debug!("attempted to enable parallel port")
@@ -497,6 +518,37 @@ pub fn config_write(&mut self, value: u8) {
     }
 }
 
+impl SioController {
+    /// Returns the I/O base address currently decoded by serial port `com` (0
+    /// for COM1, 1 for COM2), or `None` if the port is disabled.
+    pub fn serial_port_base(&self, com: usize) -> Option<u16> {
+        let index = [LogicalDeviceIndex::COM1_PORT, LogicalDeviceIndex::COM2_PORT][com];
+        let dev = &self.state.device_data[index.0 as usize];
+        // The UART register bank is 8 bytes, so the low bits are ignored.
+        let base = dev.io_port_base[0] & !7;
+        (dev.enabled && base != 0).then_some(base)
+    }
+
+    /// Returns the IRQ currently selected for serial port `com`, or `None` if
+    /// the port is disabled or has no IRQ.
+    pub fn serial_port_irq(&self, com: usize) -> Option<u8> {
+        let index = [LogicalDeviceIndex::COM1_PORT, LogicalDeviceIndex::COM2_PORT][com];
+        let dev = &self.state.device_data[index.0 as usize];
+        // The high bits of the IRQ select register are reserved.
+        let irq = dev.irq_vector[0] & 0xf;
+        (dev.enabled && irq != 0).then_some(irq)
+    }
+
+    /// Returns the I/O base address currently decoded by the parallel port, or
+    /// `None` if the port is disabled.
+    pub fn parallel_port_base(&self) -> Option<u16> {
+        let dev = &self.state.device_data[LogicalDeviceIndex::PARALLEL_PORT.0 as usize];
+        // SPP registers are decoded from a 4-byte aligned base.
+        let base = dev.io_port_base[0] & !3;
+        (dev.enabled && base != 0).then_some(base)
+    }
+}
+
 impl ChangeDeviceState for SioController {
     fn start(&mut self) {}
 
@@ -506,7 +558,7 @@ async fn reset(&mut self) {
         self.state.config_idx_state = ConfigIdxState::default();
         self.state.config_idx = ConfigRegister::default();
         self.state.device_idx = LogicalDeviceIndex::default();
-        self.state.device_data = LogicalDeviceData::default_data();
+        self.state.device_data = LogicalDeviceData::default_data(self.has_parallel_port);
     }
 }
 
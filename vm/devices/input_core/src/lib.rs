@@ -25,6 +25,11 @@ pub enum InputData {
 }
 
 /// A mouse input event.
+///
+/// The mouse is an absolute pointing device, like a tablet, so that the guest
+/// cursor tracks the client's without capturing it. The coordinates range from
+/// 0 at the left/top edge of the screen to `0x7fff`, the largest HID absolute
+/// value, at the right/bottom edge.
 #[derive(Debug, Copy, Clone, MeshPayload)]
 pub struct MouseData {
     /// A bitmask of the buttons that are pressed.
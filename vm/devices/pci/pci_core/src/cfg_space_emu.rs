@@ -98,7 +98,7 @@ impl ConfigSpaceType0EmulatorState {
     fn new() -> Self {
         Self {
             latency_timer: 0,
-            command: cfg_space::Command::empty(),
+            command: cfg_space::Command::at_reset(),
             base_addresses: [0; 6],
             interrupt_line: 0,
         }
@@ -246,9 +246,9 @@ pub fn new(
             // least one page to avoid various problems in guest OSes.
             const MIN_BAR_SIZE: u64 = 4096;
             let len = std::cmp::max(len.next_power_of_two(), MIN_BAR_SIZE);
-            let mask64 = !(len - 1);
-            bar_masks[bar_index] = mask64 as u32 | cfg_space::BarEncodingBits::TYPE_64_BIT.bits();
-            bar_masks[bar_index + 1] = (mask64 >> 32) as u32;
+            bar_masks[bar_index] =
+                cfg_space::bar_mask_for_size(len) | cfg_space::BarEncodingBits::TYPE_64_BIT.bits();
+            bar_masks[bar_index + 1] = cfg_space::bar_high_mask_for_size(len);
             mapped_memory[bar_index] = Some(mapped);
         }
 
@@ -264,7 +264,7 @@ pub fn new(
             intx_interrupt: None,
 
             state: ConfigSpaceType0EmulatorState {
-                command: cfg_space::Command::empty(),
+                command: cfg_space::Command::at_reset(),
                 base_addresses: [0; 6],
                 interrupt_line: 0,
                 latency_timer: 0,
@@ -332,10 +332,7 @@ pub fn read_u32(&self, offset: u16, value: &mut u32) -> IoResult {
                 (self.hardware_ids.device_id as u32) << 16 | self.hardware_ids.vendor_id as u32
             }
             HeaderType00::STATUS_COMMAND => {
-                let mut status = cfg_space::Status::empty();
-                if !self.capabilities.is_empty() {
-                    status |= cfg_space::Status::CAPABILITIES_LIST;
-                }
+                let mut status = cfg_space::Status::at_reset(!self.capabilities.is_empty());
 
                 if let Some(intx_interrupt) = &self.intx_interrupt {
                     if intx_interrupt.interrupt_status.load(Ordering::SeqCst) {
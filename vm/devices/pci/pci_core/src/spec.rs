@@ -117,21 +117,32 @@ pub enum Subclass: u8 {
             NONE = 0x00,
 
             // Mass Storage Controller (Class code: 0x01)
+            // Other values: 0x00 - 0x05, 0x07, 0x09, 0x80
+            MASS_STORAGE_CONTROLLER_SATA = 0x06,
             MASS_STORAGE_CONTROLLER_NON_VOLATILE_MEMORY = 0x08,
 
             // Network Controller (Class code: 0x02)
             // Other values: 0x01 - 0x08, 0x80
             NETWORK_CONTROLLER_ETHERNET = 0x00,
 
+            // Display Controller (Class code: 0x03)
+            // Other values: 0x01, 0x02, 0x80
+            DISPLAY_CONTROLLER_VGA = 0x00,
+
             // Bridge (Class code: 0x06)
-            // Other values: 0x02 - 0x0A
+            // Other values: 0x02, 0x03, 0x05 - 0x0A
             BRIDGE_HOST = 0x00,
             BRIDGE_ISA = 0x01,
+            BRIDGE_PCI_TO_PCI = 0x04,
             BRIDGE_OTHER = 0x80,
 
             // Base System Peripheral (Class code: 0x08)
             // Other values: 0x00 - 0x06
             BASE_SYSTEM_PERIPHERAL_OTHER = 0x80,
+
+            // Serial Bus Controller (Class code: 0x0C)
+            // Other values: 0x00 - 0x02, 0x04 - 0x0A, 0x80
+            SERIAL_BUS_CONTROLLER_USB = 0x03,
         }
     }
 
@@ -159,12 +170,27 @@ pub enum ProgrammingInterface: u8{
 
             NONE = 0x00,
 
+            // Serial ATA Controller (Class code: 0x01, Subclass: 0x06)
+            // Other values: 0x00, 0x02
+            MASS_STORAGE_CONTROLLER_SATA_AHCI = 0x01,
+
             // Non-Volatile Memory Controller (Class code:0x01, Subclass: 0x08)
             // Other values: 0x01
             MASS_STORAGE_CONTROLLER_NON_VOLATILE_MEMORY_NVME = 0x02,
 
             // Ethernet Controller (Class code: 0x02, Subclass: 0x00)
             NETWORK_CONTROLLER_ETHERNET_GDMA = 0x01,
+
+            // VGA Compatible Controller (Class code: 0x03, Subclass: 0x00)
+            // Other values: 0x01
+            DISPLAY_CONTROLLER_VGA_COMPATIBLE = 0x00,
+
+            // USB Controller (Class code: 0x0C, Subclass: 0x03)
+            // Other values: 0x40, 0x80, 0xFE
+            SERIAL_BUS_CONTROLLER_USB_UHCI = 0x00,
+            SERIAL_BUS_CONTROLLER_USB_OHCI = 0x10,
+            SERIAL_BUS_CONTROLLER_USB_EHCI = 0x20,
+            SERIAL_BUS_CONTROLLER_USB_XHCI = 0x30,
         }
     }
 
@@ -236,6 +262,73 @@ pub enum HeaderType00: u16 {
 
     pub const HEADER_TYPE_00_SIZE: u16 = 0x40;
 
+    open_enum::open_enum! {
+        /// Offsets into the type 01h (PCI-to-PCI bridge) configuration space
+        /// header.
+        ///
+        /// Table pulled from <https://wiki.osdev.org/PCI>
+        ///
+        /// | Offset | Bits 31-24                       | Bits 23-16               | Bits 15-8                 | Bits 7-0                 |
+        /// |--------|----------------------------------|--------------------------|---------------------------|--------------------------|
+        /// | 0x0    | Device ID                        |                          | Vendor ID                 |                          |
+        /// | 0x4    | Status                           |                          | Command                   |                          |
+        /// | 0x8    | Class code                       |                          |                           | Revision ID              |
+        /// | 0xC    | BIST                             | Header type              | Latency Timer             | Cache Line Size          |
+        /// | 0x10   | Base address #0 (BAR0)           |                          |                           |                          |
+        /// | 0x14   | Base address #1 (BAR1)           |                          |                           |                          |
+        /// | 0x18   | Secondary Latency Timer          | Subordinate Bus Number   | Secondary Bus Number      | Primary Bus Number       |
+        /// | 0x1C   | Secondary Status                 |                          | I/O Limit                 | I/O Base                 |
+        /// | 0x20   | Memory Limit                     |                          | Memory Base               |                          |
+        /// | 0x24   | Prefetchable Memory Limit        |                          | Prefetchable Memory Base  |                          |
+        /// | 0x28   | Prefetchable Base Upper 32 Bits  |                          |                           |                          |
+        /// | 0x2C   | Prefetchable Limit Upper 32 Bits |                          |                           |                          |
+        /// | 0x30   | I/O Limit Upper 16 Bits          |                          | I/O Base Upper 16 Bits    |                          |
+        /// | 0x34   | Reserved                         |                          |                           | Capabilities Pointer     |
+        /// | 0x38   | Expansion ROM base address       |                          |                           |                          |
+        /// | 0x3C   | Bridge Control                   |                          | Interrupt PIN             | Interrupt Line           |
+        pub enum HeaderType01: u16 {
+            DEVICE_VENDOR            = 0x00,
+            STATUS_COMMAND           = 0x04,
+            CLASS_REVISION           = 0x08,
+            BIST_HEADER              = 0x0C,
+            BAR0                     = 0x10,
+            BAR1                     = 0x14,
+            LATENCY_BUS_NUMBERS      = 0x18,
+            SEC_STATUS_IO_RANGE      = 0x1C,
+            MEMORY_RANGE             = 0x20,
+            PREFETCH_RANGE           = 0x24,
+            PREFETCH_BASE_UPPER      = 0x28,
+            PREFETCH_LIMIT_UPPER     = 0x2C,
+            IO_RANGE_UPPER           = 0x30,
+            RESERVED_CAP_PTR         = 0x34,
+            EXPANSION_ROM_BASE       = 0x38,
+            BRIDGE_CONTROL_INTERRUPT = 0x3C,
+        }
+    }
+
+    pub const HEADER_TYPE_01_SIZE: u16 = 0x40;
+
+    bitflags::bitflags! {
+        /// Bridge Control Register (upper 16 bits of type 01h offset 0x3C)
+        #[derive(AsBytes, FromBytes, FromZeroes)]
+        #[repr(transparent)]
+        pub struct BridgeControl: u16 {
+            const PARITY_ERROR_RESPONSE     = 1 << 0;
+            const ENABLE_SERR               = 1 << 1;
+            const ISA_ENABLE                = 1 << 2;
+            const VGA_ENABLE                = 1 << 3;
+            const VGA_16_BIT_DECODE         = 1 << 4;
+            const MASTER_ABORT_MODE         = 1 << 5;
+            const SECONDARY_BUS_RESET       = 1 << 6;
+            const ENABLE_FAST_B2B           = 1 << 7;
+            const PRIMARY_DISCARD_TIMEOUT   = 1 << 8;
+            const SECONDARY_DISCARD_TIMEOUT = 1 << 9;
+            const DISCARD_TIMER_STATUS      = 1 << 10;
+            const DISCARD_TIMER_SERR_ENABLE = 1 << 11;
+            // rest of bits are reserved
+        }
+    }
+
     bitflags::bitflags! {
         /// BAR in-band encoding bits.
         ///
@@ -252,6 +345,128 @@ pub struct BarEncodingBits: u32 {
         }
     }
 
+    /// The low bits of a memory BAR that hold the in-band encoding bits.
+    const MEMORY_BAR_ENCODING_MASK: u32 = 0xf;
+    /// The low bits of an I/O BAR that hold the in-band encoding bits.
+    const IO_BAR_ENCODING_MASK: u32 = 0x3;
+
+    /// A decoded BAR.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub struct BarInfo {
+        /// The address bits of the BAR, with the encoding bits masked off.
+        pub address: u64,
+        /// The size of the BAR, as implied by the lowest set address bit.
+        ///
+        /// This is only meaningful when decoding the value read back after
+        /// writing all ones to the BAR. It is zero for an unimplemented BAR.
+        pub size: u64,
+        pub is_64bit: bool,
+        pub is_prefetchable: bool,
+        pub is_io: bool,
+    }
+
+    /// Decodes the value of a BAR.
+    ///
+    /// `high` is the value of the next BAR, which holds the upper 32 bits of
+    /// the address if `low` describes a 64-bit memory BAR. It is ignored
+    /// otherwise, and a missing `high` is treated as zero.
+    pub fn decode_bar(low: u32, high: Option<u32>) -> BarInfo {
+        if low & BarEncodingBits::USE_PIO.bits() != 0 {
+            let address = (low & !IO_BAR_ENCODING_MASK) as u64;
+            return BarInfo {
+                address,
+                size: implied_bar_size(address),
+                is_64bit: false,
+                is_prefetchable: false,
+                is_io: true,
+            };
+        }
+        // Bits 2:1 hold the memory BAR type.
+        let is_64bit = low & 0b110 == BarEncodingBits::TYPE_64_BIT.bits();
+        let mut address = (low & !MEMORY_BAR_ENCODING_MASK) as u64;
+        if is_64bit {
+            address |= (high.unwrap_or(0) as u64) << 32;
+        }
+        BarInfo {
+            address,
+            size: implied_bar_size(address),
+            is_64bit,
+            is_prefetchable: low & BarEncodingBits::PREFETCHABLE.bits() != 0,
+            is_io: false,
+        }
+    }
+
+    fn implied_bar_size(address: u64) -> u64 {
+        if address == 0 {
+            0
+        } else {
+            1 << address.trailing_zeros()
+        }
+    }
+
+    /// Returns the writable address bits of the low dword of a BAR of `size`
+    /// bytes.
+    ///
+    /// `size` must be a power of two.
+    pub fn bar_mask_for_size(size: u64) -> u32 {
+        assert!(size.is_power_of_two());
+        !(size - 1) as u32
+    }
+
+    bitflags::bitflags! {
+        /// Expansion ROM BAR encoding bits.
+        ///
+        /// Unlike regular BARs, the expansion ROM BAR has an enable bit, and
+        /// its address is always 32 bits with a 2KB granularity.
+        pub struct RomBarEncodingBits: u32 {
+            const ENABLE = 1 << 0;
+            // bits 10:1 are reserved
+            const ADDRESS_MASK = 0xffff_f800;
+        }
+    }
+
+    /// A decoded expansion ROM BAR.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub struct RomBarInfo {
+        /// The address bits of the BAR, with the other bits masked off.
+        pub address: u32,
+        /// The size of the ROM, as implied by the lowest set address bit.
+        ///
+        /// This is only meaningful when decoding the value read back after
+        /// writing all ones to the BAR. It is zero if there is no ROM.
+        pub size: u32,
+        pub enabled: bool,
+    }
+
+    /// Decodes the value of an expansion ROM BAR.
+    pub fn decode_rom_bar(value: u32) -> RomBarInfo {
+        let bits = RomBarEncodingBits::from_bits_truncate(value);
+        let address = (bits & RomBarEncodingBits::ADDRESS_MASK).bits();
+        RomBarInfo {
+            address,
+            size: implied_bar_size(address.into()) as u32,
+            enabled: bits.contains(RomBarEncodingBits::ENABLE),
+        }
+    }
+
+    /// Returns the writable bits of an expansion ROM BAR for a ROM of `size`
+    /// bytes, including the enable bit.
+    ///
+    /// `size` must be a power of two of at least 2KB.
+    pub fn rom_bar_mask_for_size(size: u32) -> u32 {
+        assert!(size.is_power_of_two() && size >= 0x800);
+        !(size - 1) | RomBarEncodingBits::ENABLE.bits()
+    }
+
+    /// Returns the writable address bits of the high dword of a 64-bit BAR of
+    /// `size` bytes.
+    ///
+    /// `size` must be a power of two.
+    pub fn bar_high_mask_for_size(size: u64) -> u32 {
+        assert!(size.is_power_of_two());
+        (!(size - 1) >> 32) as u32
+    }
+
     bitflags::bitflags! {
         /// Command Register
         #[derive(AsBytes, FromBytes, FromZeroes, Inspect)]
@@ -285,9 +500,9 @@ pub struct Status: u16 {
             // const RESERVED           = 1 << 6;
             const CAPABLE_FAST_B2B      = 1 << 7;
             const ERR_MASTER_PARITY     = 1 << 8;
-            const DEVSEL_FAST           = 0b00 << 10;
-            const DEVSEL_MED            = 0b01 << 10;
-            const DEVSEL_SLOW           = 0b10 << 10;
+            const DEVSEL_FAST           = 0b00 << 9;
+            const DEVSEL_MED            = 0b01 << 9;
+            const DEVSEL_SLOW           = 0b10 << 9;
             const ABORT_TARGET_SIGNALED = 1 << 11;
             const ABORT_TARGET_RECEIVED = 1 << 12;
             const ABORT_MASTER_RECEIVED = 1 << 13;
@@ -295,6 +510,71 @@ pub struct Status: u16 {
             const ERR_DETECTED_PARITY   = 1 << 15;
         }
     }
+
+    impl Command {
+        /// Returns the command register's value after reset.
+        ///
+        /// Every bit resets to 0, so the device decodes neither I/O nor memory
+        /// accesses, cannot master the bus, and has INTx enabled.
+        pub const fn at_reset() -> Self {
+            Self::empty()
+        }
+    }
+
+    impl Status {
+        /// Returns the status register's value after reset, for a device that
+        /// has a capability list if `capabilities_present`.
+        ///
+        /// The error and interrupt status bits reset to 0. DEVSEL timing is
+        /// reported as fast, since PCI Express devices hardwire it to 0.
+        pub const fn at_reset(capabilities_present: bool) -> Self {
+            let mut bits = Self::DEVSEL_FAST.bits();
+            if capabilities_present {
+                bits |= Self::CAPABILITIES_LIST.bits();
+            }
+            Self::from_bits_truncate(bits)
+        }
+
+        /// The error bits, which software clears by writing 1 to them. The
+        /// remaining bits are read-only.
+        pub const WRITE_1_TO_CLEAR: Self = Self::from_bits_truncate(
+            Self::ERR_MASTER_PARITY.bits()
+                | Self::ABORT_TARGET_SIGNALED.bits()
+                | Self::ABORT_TARGET_RECEIVED.bits()
+                | Self::ABORT_MASTER_RECEIVED.bits()
+                | Self::ERR_SIGNALED.bits()
+                | Self::ERR_DETECTED_PARITY.bits(),
+        );
+    }
+
+    /// Splits the dword at [`HeaderType00::STATUS_COMMAND`] into its command
+    /// and status registers, dropping reserved bits.
+    pub fn read_command_status(dword: u32) -> (Command, Status) {
+        (
+            Command::from_bits_truncate(dword as u16),
+            Status::from_bits_truncate((dword >> 16) as u16),
+        )
+    }
+
+    /// Returns the dword to write to [`HeaderType00::STATUS_COMMAND`] to set
+    /// the command register to `command`.
+    ///
+    /// The status half is zero, so that the write does not clear any of the
+    /// write-1-to-clear status bits.
+    pub fn write_command_status(command: Command) -> u32 {
+        command.bits().into()
+    }
+
+    /// Applies a guest write of `dword` to [`HeaderType00::STATUS_COMMAND`],
+    /// given the current status register.
+    ///
+    /// Returns the new command register, with reserved bits dropped, and the
+    /// new status register, with each write-1-to-clear bit that was written
+    /// as 1 cleared. Writes to read-only status bits are ignored.
+    pub fn apply_command_status_write(status: Status, dword: u32) -> (Command, Status) {
+        let (command, written) = read_command_status(dword);
+        (command, status - (written & Status::WRITE_1_TO_CLEAR))
+    }
 }
 
 /// Capabilities
@@ -308,11 +588,98 @@ pub mod caps {
         /// variants on an as-needed basis!
         pub enum CapabilityId: u8 {
             #![allow(missing_docs)] // self explanatory variants
+            MSI             = 0x05,
             VENDOR_SPECIFIC = 0x09,
+            PCI_EXPRESS     = 0x10,
             MSIX            = 0x11,
         }
     }
 
+    /// An iterator over the capability list in a function's configuration
+    /// space, yielding `(offset, id, next)` for each capability.
+    ///
+    /// Iteration starts at the Capabilities Pointer and stops at the end of
+    /// the list, or at the first malformed entry: one that points into the
+    /// configuration space header, or one that was already visited.
+    pub struct CapabilityWalker<F> {
+        read: F,
+        next: u8,
+        visited: [u64; 4],
+        malformed: bool,
+    }
+
+    impl<F: FnMut(u16) -> u8> CapabilityWalker<F> {
+        /// Returns a new walker that reads configuration space bytes with
+        /// `read`, which is called with the byte offset to read.
+        pub fn new(mut read: F) -> Self {
+            let next = read(super::cfg_space::HeaderType00::RESERVED_CAP_PTR.0);
+            Self {
+                read,
+                next,
+                visited: [0; 4],
+                malformed: false,
+            }
+        }
+
+        /// Returns true if iteration stopped at a malformed entry.
+        pub fn malformed(&self) -> bool {
+            self.malformed
+        }
+    }
+
+    impl<F: FnMut(u16) -> u8> Iterator for CapabilityWalker<F> {
+        type Item = (u8, CapabilityId, u8);
+
+        fn next(&mut self) -> Option<Self::Item> {
+            // The bottom two bits are reserved.
+            let offset = self.next & !3;
+            if offset == 0 {
+                return None;
+            }
+            let (word, bit) = (offset as usize / 64, offset % 64);
+            if offset < super::cfg_space::HEADER_TYPE_00_SIZE as u8
+                || self.visited[word] & (1 << bit) != 0
+            {
+                self.malformed = true;
+                self.next = 0;
+                return None;
+            }
+            self.visited[word] |= 1 << bit;
+            let id = CapabilityId((self.read)(offset.into()));
+            self.next = (self.read)(offset as u16 + 1);
+            Some((offset, id, self.next & !3))
+        }
+    }
+
+    /// Vendor-specific capability
+    ///
+    /// Sources: PCI 2.3 Spec - Appendix H
+    #[allow(missing_docs)] // primarily enums/structs with self-explanatory variants
+    pub mod vendor_specific {
+        open_enum::open_enum! {
+            /// Byte offsets into the Vendor-Specific Capability Header
+            ///
+            /// | Offset    | Bits 31-24  | Bits 23-16 | Bits 15-8    | Bits 7-0             |
+            /// |-----------|-------------|------------|--------------|----------------------|
+            /// | Cap + 0x0 | Vendor Data | Length     | Next Pointer | Capability ID (0x09) |
+            pub enum VendorSpecificCapabilityHeader: u16 {
+                CAP_ID   = 0x00,
+                NEXT_PTR = 0x01,
+                LENGTH   = 0x02,
+                DATA     = 0x03,
+            }
+        }
+
+        /// Returns the length of the vendor data of a capability whose Length
+        /// byte is `length`, or `None` if `length` is too short to cover the
+        /// header.
+        ///
+        /// The Length byte counts the whole capability, including the header.
+        pub fn data_len(length: u8) -> Option<u8> {
+            length.checked_sub(VendorSpecificCapabilityHeader::DATA.0 as u8)
+        }
+    }
+
     /// MSI-X
     #[allow(missing_docs)] // primarily enums/structs with self-explanatory variants
     pub mod msix {
@@ -343,4 +710,713 @@ pub enum MsixTableEntryIdx: u16 {
             }
         }
     }
+    /// PCI Express
+    ///
+    /// Sources: PCI Express Base Spec 4.0 - 7.5.3
+    #[allow(missing_docs)] // primarily enums/structs with self-explanatory variants
+    pub mod pcie {
+        open_enum::open_enum! {
+            /// Offsets into the PCI Express Capability Structure
+            ///
+            /// | Offset     | Bits 31-16               | Bits 15-8    | Bits 7-0             |
+            /// |------------|--------------------------|--------------|----------------------|
+            /// | Cap + 0x0  | PCI Express Capabilities | Next Pointer | Capability ID (0x10) |
+            /// | Cap + 0x4  | Device Capabilities      |              |                      |
+            /// | Cap + 0x8  | Device Status            | Device Control                      |
+            /// | Cap + 0xC  | Link Capabilities        |              |                      |
+            /// | Cap + 0x10 | Link Status              | Link Control                        |
+            /// | Cap + 0x14 | Slot Capabilities        |              |                      |
+            /// | Cap + 0x18 | Slot Status              | Slot Control                        |
+            /// | Cap + 0x1C | Root Capabilities        | Root Control                        |
+            /// | Cap + 0x20 | Root Status              |              |                      |
+            /// | Cap + 0x24 | Device Capabilities 2    |              |                      |
+            /// | Cap + 0x28 | Device Status 2          | Device Control 2                    |
+            /// | Cap + 0x2C | Link Capabilities 2      |              |                      |
+            /// | Cap + 0x30 | Link Status 2            | Link Control 2                      |
+            /// | Cap + 0x34 | Slot Capabilities 2      |              |                      |
+            /// | Cap + 0x38 | Slot Status 2            | Slot Control 2                      |
+            pub enum PciExpressCapabilityHeader: u16 {
+                PCIE_CAPS        = 0x00,
+                DEVICE_CAPS      = 0x04,
+                DEVICE_CTL_STS   = 0x08,
+                LINK_CAPS        = 0x0C,
+                LINK_CTL_STS     = 0x10,
+                SLOT_CAPS        = 0x14,
+                SLOT_CTL_STS     = 0x18,
+                ROOT_CTL_CAPS    = 0x1C,
+                ROOT_STS         = 0x20,
+                DEVICE_CAPS_2    = 0x24,
+                DEVICE_CTL_STS_2 = 0x28,
+                LINK_CAPS_2      = 0x2C,
+                LINK_CTL_STS_2   = 0x30,
+                SLOT_CAPS_2      = 0x34,
+                SLOT_CTL_STS_2   = 0x38,
+            }
+        }
+
+        /// The size of the PCI Express Capability Structure, including the
+        /// capability header.
+        pub const PCIE_CAPABILITY_SIZE: u16 = 0x3C;
+
+        open_enum::open_enum! {
+            /// Device/Port Type (bits 7:4 of the PCI Express Capabilities
+            /// register)
+            pub enum DevicePortType: u8 {
+                ENDPOINT                     = 0b0000,
+                LEGACY_ENDPOINT              = 0b0001,
+                ROOT_PORT                    = 0b0100,
+                UPSTREAM_SWITCH_PORT         = 0b0101,
+                DOWNSTREAM_SWITCH_PORT       = 0b0110,
+                PCIE_TO_PCI_BRIDGE           = 0b0111,
+                PCI_TO_PCIE_BRIDGE           = 0b1000,
+                ROOT_COMPLEX_ENDPOINT        = 0b1001,
+                ROOT_COMPLEX_EVENT_COLLECTOR = 0b1010,
+            }
+        }
+    }
+    /// MSI
+    ///
+    /// Sources: PCI 2.3 Spec - 6.8.1
+    #[allow(missing_docs)] // primarily enums/structs with self-explanatory variants
+    pub mod msi {
+        use bitfield_struct::bitfield;
+
+        open_enum::open_enum! {
+            /// Offsets into the MSI Capability Structure of a function that
+            /// only supports 32-bit message addresses.
+            ///
+            /// | Offset     | Bits 31-16      | Bits 15-8    | Bits 7-0             |
+            /// |------------|-----------------|--------------|----------------------|
+            /// | Cap + 0x0  | Message Control | Next Pointer | Capability ID (0x05) |
+            /// | Cap + 0x4  | Message Address |              |                      |
+            /// | Cap + 0x8  | Reserved        | Message Data                        |
+            /// | Cap + 0xC  | Mask Bits (optional)           |                      |
+            /// | Cap + 0x10 | Pending Bits (optional)        |                      |
+            pub enum MsiCapabilityHeader32: u16 {
+                CONTROL_CAPS = 0x00,
+                MSG_ADDR_LO  = 0x04,
+                MSG_DATA     = 0x08,
+                MASK_BITS    = 0x0C,
+                PENDING_BITS = 0x10,
+            }
+        }
+
+        open_enum::open_enum! {
+            /// Offsets into the MSI Capability Structure of a function that
+            /// supports 64-bit message addresses.
+            ///
+            /// | Offset     | Bits 31-16            | Bits 15-8    | Bits 7-0             |
+            /// |------------|-----------------------|--------------|----------------------|
+            /// | Cap + 0x0  | Message Control       | Next Pointer | Capability ID (0x05) |
+            /// | Cap + 0x4  | Message Address       |              |                      |
+            /// | Cap + 0x8  | Message Upper Address |              |                      |
+            /// | Cap + 0xC  | Reserved              | Message Data                        |
+            /// | Cap + 0x10 | Mask Bits (optional)  |              |                      |
+            /// | Cap + 0x14 | Pending Bits (optional)              |                      |
+            pub enum MsiCapabilityHeader64: u16 {
+                CONTROL_CAPS = 0x00,
+                MSG_ADDR_LO  = 0x04,
+                MSG_ADDR_HI  = 0x08,
+                MSG_DATA     = 0x0C,
+                MASK_BITS    = 0x10,
+                PENDING_BITS = 0x14,
+            }
+        }
+
+        /// Message Control Register
+        #[bitfield(u16)]
+        #[derive(PartialEq, Eq)]
+        pub struct MsiControl {
+            pub enable: bool,
+            /// log2 of the number of vectors requested by the function.
+            #[bits(3)]
+            pub multiple_message_capable: u8,
+            /// log2 of the number of vectors allocated by software.
+            #[bits(3)]
+            pub multiple_message_enable: u8,
+            pub capable_64bit: bool,
+            pub per_vector_masking: bool,
+            #[bits(7)]
+            _reserved: u16,
+        }
+
+        impl MsiControl {
+            /// The offset of the Message Upper Address register, if the
+            /// function supports 64-bit message addresses.
+            pub fn msg_addr_hi_offset(&self) -> Option<u16> {
+                self.capable_64bit()
+                    .then_some(MsiCapabilityHeader64::MSG_ADDR_HI.0)
+            }
+
+            /// The offset of the Message Data register.
+            pub fn msg_data_offset(&self) -> u16 {
+                if self.capable_64bit() {
+                    MsiCapabilityHeader64::MSG_DATA.0
+                } else {
+                    MsiCapabilityHeader32::MSG_DATA.0
+                }
+            }
+
+            /// The offset of the Mask Bits register, if the function supports
+            /// per-vector masking.
+            pub fn mask_bits_offset(&self) -> Option<u16> {
+                self.per_vector_masking().then(|| {
+                    if self.capable_64bit() {
+                        MsiCapabilityHeader64::MASK_BITS.0
+                    } else {
+                        MsiCapabilityHeader32::MASK_BITS.0
+                    }
+                })
+            }
+
+            /// The offset of the Pending Bits register, if the function
+            /// supports per-vector masking.
+            pub fn pending_bits_offset(&self) -> Option<u16> {
+                self.mask_bits_offset().map(|offset| offset + 4)
+            }
+
+            /// The length of the capability structure in bytes, including the
+            /// capability header, rounded up to a whole dword.
+            pub fn capability_len(&self) -> u16 {
+                match self.pending_bits_offset() {
+                    Some(offset) => offset + 4,
+                    None => self.msg_data_offset() + 4,
+                }
+            }
+        }
+    }
+}
+
+/// Extended Capabilities
+///
+/// Sources: PCI Express Base Spec 4.0 - 7.6 and 9.3.3
+pub mod ext_caps {
+    use bitfield_struct::bitfield;
+
+    /// The offset of the first extended capability in configuration space.
+    pub const EXTENDED_CAPABILITIES_START: u16 = 0x100;
+
+    /// The size of a PCI Express function's configuration space, including
+    /// the extended configuration space.
+    pub const EXTENDED_CONFIG_SPACE_SIZE: u16 = 0x1000;
+
+    open_enum::open_enum! {
+        /// Extended Capability IDs
+        ///
+        /// Sources: PCI Express Base Spec 4.0 - 7.6.2
+        ///
+        /// NOTE: this is a non-exhaustive list, so don't be afraid to add new
+        /// variants on an as-needed basis!
+        pub enum ExtendedCapabilityId: u16 {
+            #![allow(missing_docs)] // self explanatory variants
+            ADVANCED_ERROR_REPORTING = 0x0001,
+            DEVICE_SERIAL_NUMBER     = 0x0003,
+            VENDOR_SPECIFIC          = 0x000B,
+            ARI                      = 0x000E,
+            SRIOV                    = 0x0010,
+        }
+    }
+
+    /// Extended Capability Header
+    ///
+    /// | Bits 31-20  | Bits 19-16 | Bits 15-0     |
+    /// |-------------|------------|---------------|
+    /// | Next Offset | Version    | Capability ID |
+    #[bitfield(u32)]
+    #[derive(PartialEq, Eq)]
+    pub struct ExtendedCapabilityHeader {
+        /// The capability ID. See [`ExtendedCapabilityId`].
+        pub cap_id: u16,
+        /// The version of the capability structure.
+        #[bits(4)]
+        pub version: u8,
+        /// The offset of the next capability, or zero at the end of the list.
+        /// The bottom two bits are reserved.
+        #[bits(12)]
+        pub next: u16,
+    }
+
+    impl ExtendedCapabilityHeader {
+        /// The capability ID.
+        pub fn id(&self) -> ExtendedCapabilityId {
+            ExtendedCapabilityId(self.cap_id())
+        }
+    }
+
+    /// An iterator over the extended capability list in a PCI Express
+    /// function's configuration space, yielding `(offset, header)` for each
+    /// capability.
+    ///
+    /// Iteration starts at [`EXTENDED_CAPABILITIES_START`] and stops at the
+    /// end of the list, or at the first malformed entry: one that points
+    /// outside the extended configuration space, or one that was already
+    /// visited. An all-zero header at the start of the list indicates that
+    /// the function has no extended capabilities.
+    pub struct ExtendedCapabilityWalker<F> {
+        read: F,
+        next: u16,
+        visited: [u64; EXTENDED_CONFIG_SPACE_SIZE as usize / 4 / 64],
+        malformed: bool,
+    }
+
+    impl<F: FnMut(u16) -> u32> ExtendedCapabilityWalker<F> {
+        /// Returns a new walker that reads configuration space dwords with
+        /// `read`, which is called with the byte offset to read.
+        pub fn new(read: F) -> Self {
+            Self {
+                read,
+                next: EXTENDED_CAPABILITIES_START,
+                visited: [0; EXTENDED_CONFIG_SPACE_SIZE as usize / 4 / 64],
+                malformed: false,
+            }
+        }
+
+        /// Returns true if iteration stopped at a malformed entry.
+        pub fn malformed(&self) -> bool {
+            self.malformed
+        }
+    }
+
+    impl<F: FnMut(u16) -> u32> Iterator for ExtendedCapabilityWalker<F> {
+        type Item = (u16, ExtendedCapabilityHeader);
+
+        fn next(&mut self) -> Option<Self::Item> {
+            let offset = self.next & !3;
+            if offset == 0 {
+                return None;
+            }
+            let dword = offset as usize / 4;
+            let (word, bit) = (dword / 64, dword % 64);
+            if !(EXTENDED_CAPABILITIES_START..EXTENDED_CONFIG_SPACE_SIZE).contains(&offset)
+                || self.visited[word] & (1 << bit) != 0
+            {
+                self.malformed = true;
+                self.next = 0;
+                return None;
+            }
+            self.visited[word] |= 1 << bit;
+            let header = ExtendedCapabilityHeader::from((self.read)(offset));
+            if offset == EXTENDED_CAPABILITIES_START && u32::from(header) == 0 {
+                self.next = 0;
+                return None;
+            }
+            self.next = header.next();
+            Some((offset, header.with_next(header.next() & !3)))
+        }
+    }
+
+    /// Single Root I/O Virtualization
+    ///
+    /// Sources: PCI Express Base Spec 4.0 - 9.3.3
+    #[allow(missing_docs)] // primarily enums/structs with self-explanatory variants
+    pub mod sriov {
+        use bitfield_struct::bitfield;
+
+        open_enum::open_enum! {
+            /// Offsets into the SR-IOV Extended Capability Structure
+            ///
+            /// | Offset     | Bits 31-16                    | Bits 15-0                |
+            /// |------------|-------------------------------|--------------------------|
+            /// | Cap + 0x0  | Extended Capability Header    |                          |
+            /// | Cap + 0x4  | SR-IOV Capabilities           |                          |
+            /// | Cap + 0x8  | SR-IOV Status                 | SR-IOV Control           |
+            /// | Cap + 0xC  | TotalVFs                      | InitialVFs               |
+            /// | Cap + 0x10 | Function Dependency Link      | NumVFs                   |
+            /// | Cap + 0x14 | VF Stride                     | First VF Offset          |
+            /// | Cap + 0x18 | VF Device ID                  | Reserved                 |
+            /// | Cap + 0x1C | Supported Page Sizes          |                          |
+            /// | Cap + 0x20 | System Page Size              |                          |
+            /// | Cap + 0x24 | VF BAR0                       |                          |
+            /// | ...        | ...                           |                          |
+            /// | Cap + 0x38 | VF BAR5                       |                          |
+            /// | Cap + 0x3C | VF Migration State Array Offset                          |
+            pub enum SriovCapabilityHeader: u16 {
+                HEADER                   = 0x00,
+                CAPABILITIES             = 0x04,
+                CONTROL                  = 0x08,
+                STATUS                   = 0x0A,
+                INITIAL_VFS              = 0x0C,
+                TOTAL_VFS                = 0x0E,
+                NUM_VFS                  = 0x10,
+                FUNCTION_DEPENDENCY_LINK = 0x12,
+                FIRST_VF_OFFSET          = 0x14,
+                VF_STRIDE                = 0x16,
+                VF_DEVICE_ID             = 0x1A,
+                SUPPORTED_PAGE_SIZES     = 0x1C,
+                SYSTEM_PAGE_SIZE         = 0x20,
+                VF_BAR0                  = 0x24,
+                VF_MIGRATION_STATE       = 0x3C,
+            }
+        }
+
+        /// The size of the SR-IOV Extended Capability Structure, including
+        /// the capability header.
+        pub const SRIOV_CAPABILITY_SIZE: u16 = 0x40;
+
+        /// The number of VF BARs.
+        pub const VF_BAR_COUNT: u8 = 6;
+
+        /// Returns the offset of VF BAR `index`, or `None` if `index` is out
+        /// of range.
+        pub fn vf_bar_offset(index: u8) -> Option<u16> {
+            (index < VF_BAR_COUNT).then(|| SriovCapabilityHeader::VF_BAR0.0 + 4 * index as u16)
+        }
+
+        /// Returns the Routing ID of VF `vf_index` (counting from zero) of the
+        /// PF with Routing ID `pf_routing_id`, given the PF's First VF Offset
+        /// and VF Stride registers.
+        ///
+        /// Routing IDs wrap around modulo 2^16.
+        pub fn vf_routing_id(
+            pf_routing_id: u16,
+            first_vf_offset: u16,
+            vf_stride: u16,
+            vf_index: u16,
+        ) -> u16 {
+            pf_routing_id
+                .wrapping_add(first_vf_offset)
+                .wrapping_add(vf_stride.wrapping_mul(vf_index))
+        }
+
+        /// SR-IOV Control Register
+        #[bitfield(u16)]
+        #[derive(PartialEq, Eq)]
+        pub struct SriovControl {
+            pub vf_enable: bool,
+            pub vf_migration_enable: bool,
+            pub vf_migration_interrupt_enable: bool,
+            pub vf_mse: bool,
+            pub ari_capable_hierarchy: bool,
+            #[bits(11)]
+            _reserved: u16,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::caps::msi::MsiControl;
+    use super::caps::vendor_specific;
+    use super::caps::CapabilityId;
+    use super::caps::CapabilityWalker;
+    use super::cfg_space::apply_command_status_write;
+    use super::cfg_space::bar_high_mask_for_size;
+    use super::cfg_space::bar_mask_for_size;
+    use super::cfg_space::decode_bar;
+    use super::cfg_space::decode_rom_bar;
+    use super::cfg_space::read_command_status;
+    use super::cfg_space::rom_bar_mask_for_size;
+    use super::cfg_space::write_command_status;
+    use super::cfg_space::BarInfo;
+    use super::cfg_space::Command;
+    use super::cfg_space::RomBarInfo;
+    use super::cfg_space::Status;
+    use super::ext_caps::sriov;
+    use super::ext_caps::ExtendedCapabilityHeader;
+    use super::ext_caps::ExtendedCapabilityId;
+    use super::ext_caps::ExtendedCapabilityWalker;
+
+    #[test]
+    fn bar_64bit_prefetchable() {
+        // An 8GB BAR: write all ones, then read back the size mask.
+        let size = 8 << 30;
+        let low = bar_mask_for_size(size) | 0b1100;
+        let high = bar_high_mask_for_size(size);
+        assert_eq!((low, high), (0x0000_000c, 0xffff_fffe));
+        assert_eq!(
+            decode_bar(low, Some(high)),
+            BarInfo {
+                address: 0xffff_fffe_0000_0000,
+                size,
+                is_64bit: true,
+                is_prefetchable: true,
+                is_io: false,
+            }
+        );
+
+        // The same BAR, programmed to an address.
+        let info = decode_bar(0x0000_000c, Some(0x0000_0004));
+        assert_eq!(info.address, 0x4_0000_0000);
+        assert!(info.is_64bit && info.is_prefetchable && !info.is_io);
+    }
+
+    #[test]
+    fn bar_32bit_io() {
+        // A 256-byte I/O BAR whose upper 16 bits are hardwired to zero.
+        let low = (bar_mask_for_size(0x100) & 0xffff) | 0b01;
+        assert_eq!(
+            decode_bar(low, None),
+            BarInfo {
+                address: 0xff00,
+                size: 0x100,
+                is_64bit: false,
+                is_prefetchable: false,
+                is_io: true,
+            }
+        );
+
+        // An I/O BAR ignores the next BAR.
+        let info = decode_bar(0xc001, Some(0xffff_ffff));
+        assert_eq!(info.address, 0xc000);
+        assert!(info.is_io && !info.is_64bit);
+
+        // Unimplemented BAR.
+        assert_eq!(decode_bar(0, None).size, 0);
+    }
+
+    #[test]
+    fn rom_bar() {
+        // A 64KB ROM: write all ones, then read back the size mask.
+        let mask = rom_bar_mask_for_size(0x10000);
+        assert_eq!(mask, 0xffff_0001);
+        assert_eq!(
+            decode_rom_bar(mask),
+            RomBarInfo {
+                address: 0xffff_0000,
+                size: 0x10000,
+                enabled: true,
+            }
+        );
+
+        // The guest maps and enables the ROM. Reserved bits are ignored.
+        let value = (0xfebc_0000 & mask) | 0x7fe | 1;
+        assert_eq!(
+            decode_rom_bar(value & mask),
+            RomBarInfo {
+                address: 0xfebc_0000,
+                size: 0x40000,
+                enabled: true,
+            }
+        );
+        assert_eq!(decode_rom_bar(value).address, 0xfebc_0000);
+        assert!(!decode_rom_bar(0xfebc_0000).enabled);
+    }
+
+    #[test]
+    fn capability_walk() {
+        let mut cfg = [0u8; 256];
+        cfg[0x34] = 0x40;
+        cfg[0x40..0x42].copy_from_slice(&[CapabilityId::MSIX.0, 0x50]);
+        cfg[0x50..0x52].copy_from_slice(&[CapabilityId::VENDOR_SPECIFIC.0, 0x00]);
+        let mut walker = CapabilityWalker::new(|offset| cfg[offset as usize]);
+        assert_eq!(
+            walker.by_ref().collect::<Vec<_>>(),
+            [
+                (0x40, CapabilityId::MSIX, 0x50),
+                (0x50, CapabilityId::VENDOR_SPECIFIC, 0x00),
+            ]
+        );
+        assert!(!walker.malformed());
+
+        // Make the second entry point back at the first.
+        cfg[0x51] = 0x40;
+        let mut walker = CapabilityWalker::new(|offset| cfg[offset as usize]);
+        assert_eq!(walker.by_ref().count(), 2);
+        assert!(walker.malformed());
+
+        // Point into the header.
+        cfg[0x34] = 0x10;
+        let mut walker = CapabilityWalker::new(|offset| cfg[offset as usize]);
+        assert_eq!(walker.next(), None);
+        assert!(walker.malformed());
+    }
+
+    #[test]
+    fn vendor_specific_data_len() {
+        assert_eq!(vendor_specific::data_len(0), None);
+        assert_eq!(vendor_specific::data_len(2), None);
+        assert_eq!(vendor_specific::data_len(3), Some(0));
+        assert_eq!(vendor_specific::data_len(0x10), Some(0xd));
+        assert_eq!(vendor_specific::data_len(0xff), Some(0xfc));
+    }
+
+    #[test]
+    fn msi_offsets() {
+        let control = MsiControl::new();
+        assert_eq!(control.msg_addr_hi_offset(), None);
+        assert_eq!(control.msg_data_offset(), 0x8);
+        assert_eq!(control.mask_bits_offset(), None);
+        assert_eq!(control.pending_bits_offset(), None);
+        assert_eq!(control.capability_len(), 0xc);
+
+        let control = MsiControl::new().with_capable_64bit(true);
+        assert_eq!(control.msg_addr_hi_offset(), Some(0x8));
+        assert_eq!(control.msg_data_offset(), 0xc);
+        assert_eq!(control.mask_bits_offset(), None);
+        assert_eq!(control.capability_len(), 0x10);
+
+        let control = MsiControl::new().with_per_vector_masking(true);
+        assert_eq!(control.msg_data_offset(), 0x8);
+        assert_eq!(control.mask_bits_offset(), Some(0xc));
+        assert_eq!(control.pending_bits_offset(), Some(0x10));
+        assert_eq!(control.capability_len(), 0x14);
+
+        let control = MsiControl::new()
+            .with_capable_64bit(true)
+            .with_per_vector_masking(true);
+        assert_eq!(control.mask_bits_offset(), Some(0x10));
+        assert_eq!(control.pending_bits_offset(), Some(0x14));
+        assert_eq!(control.capability_len(), 0x18);
+    }
+
+    #[test]
+    fn msi_control_bits() {
+        let control = MsiControl::from(0x0181);
+        assert!(control.enable());
+        assert_eq!(control.multiple_message_capable(), 0);
+        assert!(control.capable_64bit());
+        assert!(control.per_vector_masking());
+
+        let control = MsiControl::from(0x0036);
+        assert!(!control.enable());
+        assert_eq!(control.multiple_message_capable(), 3);
+        assert_eq!(control.multiple_message_enable(), 3);
+        assert!(!control.capable_64bit());
+    }
+
+    #[test]
+    fn command_status_split() {
+        let (command, status) = read_command_status(0x0010_0486);
+        assert_eq!(
+            command,
+            Command::MMIO_ENABLED | Command::BUS_MASTER | Command::INTX_DISABLE
+        );
+        // Bit 7 of the command register is reserved.
+        assert_eq!(status, Status::CAPABILITIES_LIST);
+
+        assert_eq!(
+            write_command_status(Command::PIO_ENABLED | Command::INTX_DISABLE),
+            0x0000_0401
+        );
+    }
+
+    #[test]
+    fn command_status_at_reset() {
+        assert_eq!(Command::at_reset().bits(), 0);
+        assert_eq!(Status::at_reset(false).bits(), 0);
+        assert_eq!(Status::at_reset(true).bits(), 0x0010);
+        assert_eq!(
+            Status::at_reset(true) - Status::at_reset(false),
+            Status::CAPABILITIES_LIST
+        );
+
+        // DEVSEL timing occupies bits 9 and 10, clear of the error bits.
+        assert_eq!(Status::DEVSEL_MED.bits(), 0x0200);
+        assert_eq!(Status::DEVSEL_SLOW.bits(), 0x0400);
+        assert!(!Status::WRITE_1_TO_CLEAR.intersects(Status::DEVSEL_MED | Status::DEVSEL_SLOW));
+
+        // A reset device reads back with no error bits to clear.
+        let (command, status) = read_command_status((Status::at_reset(true).bits() as u32) << 16);
+        assert_eq!(command, Command::at_reset());
+        assert_eq!(status, Status::at_reset(true));
+        assert!(!status.intersects(Status::WRITE_1_TO_CLEAR));
+    }
+
+    #[test]
+    fn status_write_1_to_clear() {
+        let status =
+            Status::CAPABILITIES_LIST | Status::ABORT_MASTER_RECEIVED | Status::ERR_SIGNALED;
+
+        // Writing 0 to the status half leaves the error bits alone.
+        let (command, new_status) =
+            apply_command_status_write(status, write_command_status(Command::MMIO_ENABLED));
+        assert_eq!(command, Command::MMIO_ENABLED);
+        assert_eq!(new_status, status);
+
+        // Writing 1 clears only that error bit, and read-only bits cannot be
+        // changed.
+        let dword = ((Status::ABORT_MASTER_RECEIVED | Status::INTERRUPT_STATUS).bits() as u32)
+            << 16
+            | Command::MMIO_ENABLED.bits() as u32;
+        let (_, new_status) = apply_command_status_write(status, dword);
+        assert_eq!(new_status, Status::CAPABILITIES_LIST | Status::ERR_SIGNALED);
+
+        // Writing all ones clears every error bit.
+        let (_, new_status) = apply_command_status_write(status, !0);
+        assert_eq!(new_status, Status::CAPABILITIES_LIST);
+    }
+
+    #[test]
+    fn extended_capability_walk() {
+        let mut cfg = [0u32; 0x400];
+        let header = |id: ExtendedCapabilityId, next| {
+            u32::from(
+                ExtendedCapabilityHeader::new()
+                    .with_cap_id(id.0)
+                    .with_version(1)
+                    .with_next(next),
+            )
+        };
+        cfg[0x100 / 4] = header(ExtendedCapabilityId::ARI, 0x140);
+        cfg[0x140 / 4] = header(ExtendedCapabilityId::SRIOV, 0x000);
+        let walk = |cfg: &[u32]| {
+            let mut walker = ExtendedCapabilityWalker::new(|offset| cfg[offset as usize / 4]);
+            let caps = walker
+                .by_ref()
+                .map(|(offset, header)| (offset, header.id(), header.next()))
+                .collect::<Vec<_>>();
+            (caps, walker.malformed())
+        };
+        assert_eq!(
+            walk(&cfg),
+            (
+                vec![
+                    (0x100, ExtendedCapabilityId::ARI, 0x140),
+                    (0x140, ExtendedCapabilityId::SRIOV, 0),
+                ],
+                false
+            )
+        );
+        assert_eq!(ExtendedCapabilityHeader::from(cfg[0x40]).version(), 1);
+
+        // Make the second entry point back at the first.
+        cfg[0x140 / 4] = header(ExtendedCapabilityId::SRIOV, 0x100);
+        assert_eq!(walk(&cfg).0.len(), 2);
+        assert!(walk(&cfg).1);
+
+        // Point into the standard configuration space.
+        cfg[0x140 / 4] = header(ExtendedCapabilityId::SRIOV, 0x40);
+        assert_eq!(walk(&cfg).0.len(), 2);
+        assert!(walk(&cfg).1);
+
+        // No extended capabilities.
+        cfg[0x100 / 4] = 0;
+        assert_eq!(walk(&cfg), (vec![], false));
+    }
+
+    #[test]
+    fn sriov_offsets() {
+        use sriov::SriovCapabilityHeader;
+
+        assert_eq!(SriovCapabilityHeader::CONTROL.0, 0x08);
+        assert_eq!(SriovCapabilityHeader::TOTAL_VFS.0, 0x0e);
+        assert_eq!(SriovCapabilityHeader::NUM_VFS.0, 0x10);
+        assert_eq!(SriovCapabilityHeader::VF_STRIDE.0, 0x16);
+        assert_eq!(SriovCapabilityHeader::VF_DEVICE_ID.0, 0x1a);
+        assert_eq!(sriov::vf_bar_offset(0), Some(0x24));
+        assert_eq!(sriov::vf_bar_offset(5), Some(0x38));
+        assert_eq!(sriov::vf_bar_offset(6), None);
+        assert_eq!(
+            sriov::vf_bar_offset(5).unwrap() + 4,
+            SriovCapabilityHeader::VF_MIGRATION_STATE.0
+        );
+        assert_eq!(
+            SriovCapabilityHeader::VF_MIGRATION_STATE.0 + 4,
+            sriov::SRIOV_CAPABILITY_SIZE
+        );
+
+        // A PF at 01:00.0 with VFs starting at 01:00.4, two functions apart.
+        let pf = 0x0100;
+        assert_eq!(sriov::vf_routing_id(pf, 4, 2, 0), 0x0104);
+        assert_eq!(sriov::vf_routing_id(pf, 4, 2, 3), 0x010a);
+        // VFs can spill over onto subsequent buses.
+        assert_eq!(sriov::vf_routing_id(pf, 0x80, 1, 0x80), 0x0200);
+        assert_eq!(sriov::vf_routing_id(0xffff, 1, 1, 0), 0);
+
+        let control = sriov::SriovControl::from(0x0019);
+        assert!(control.vf_enable());
+        assert!(control.vf_mse());
+        assert!(control.ari_capable_hierarchy());
+        assert!(!control.vf_migration_enable());
+    }
 }
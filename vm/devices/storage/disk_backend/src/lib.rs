@@ -38,8 +38,16 @@ pub enum DiskError {
     IllegalBlock,
     #[error("invalid input")]
     InvalidInput,
+    #[error("io beyond end of disk: sector {sector}, len {len}, disk size {disk_size}")]
+    OutOfRange {
+        sector: u64,
+        len: u64,
+        disk_size: u64,
+    },
     #[error("io error")]
     Io(#[source] std::io::Error),
+    #[error("data integrity check failed at sector {sector}")]
+    IntegrityCheckFailed { sector: u64 },
     #[error("medium error")]
     MediumError(#[source] std::io::Error, MediumErrorDetails),
     #[error("failed to access guest memory")]
@@ -110,6 +118,11 @@ fn lba_status(&self) -> Option<&dyn GetLbaStatus> {
         None
     }
 
+    /// Optionally returns a trait object to resize the disk.
+    fn resize(&self) -> Option<&dyn Resize> {
+        None
+    }
+
     /// Optionally returns a trait object to issue persistent reservation
     /// requests.
     fn pr(&self) -> Option<&dyn pr::PersistentReservation> {
@@ -159,6 +172,10 @@ fn lba_status(&self) -> Option<&dyn GetLbaStatus> {
         self.as_ref().lba_status()
     }
 
+    fn resize(&self) -> Option<&dyn Resize> {
+        self.as_ref().resize()
+    }
+
     fn pr(&self) -> Option<&dyn pr::PersistentReservation> {
         self.as_ref().pr()
     }
@@ -205,6 +222,10 @@ fn lba_status(&self) -> Option<&dyn GetLbaStatus> {
         (*self).lba_status()
     }
 
+    fn resize(&self) -> Option<&dyn Resize> {
+        (*self).resize()
+    }
+
     fn pr(&self) -> Option<&dyn pr::PersistentReservation> {
         (*self).pr()
     }
@@ -383,3 +404,13 @@ fn unmap(
 
     fn optimal_unmap_sectors(&self) -> u32;
 }
+
+pub trait Resize: Sync {
+    /// Resizes the disk to `sector_count` sectors.
+    ///
+    /// On success, [`AsyncDisk::wait_resize`] must report the new size.
+    fn resize(
+        &self,
+        sector_count: u64,
+    ) -> StackFuture<'_, Result<(), DiskError>, { ASYNC_DISK_STACK_SIZE }>;
+}
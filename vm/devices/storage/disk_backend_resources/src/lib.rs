@@ -41,12 +41,42 @@ impl ResourceId<DiskHandleKind> for RamDiffDiskHandle {
 
 /// File-backed disk handle.
 #[derive(MeshPayload)]
-pub struct FileDiskHandle(pub std::fs::File);
+pub struct FileDiskHandle {
+    /// The backing file.
+    pub file: std::fs::File,
+    /// Whether to bypass the host page cache (`O_DIRECT`). Only supported on
+    /// Linux.
+    pub direct: bool,
+    /// The logical sector size. Defaults to 512 bytes.
+    pub sector_size: Option<u32>,
+    /// The physical sector size. Defaults to 4096 bytes, or the logical sector
+    /// size if that is larger.
+    pub physical_sector_size: Option<u32>,
+    /// The disk ID to report. Defaults to an ID derived from the identity of
+    /// the file, which changes if the file is moved or copied.
+    pub disk_id: Option<[u8; 16]>,
+}
 
 impl ResourceId<DiskHandleKind> for FileDiskHandle {
     const ID: &'static str = "file";
 }
 
+/// Copy-on-write overlay disk handle, layering a writable overlay file on top
+/// of a read-only base file.
+#[derive(MeshPayload)]
+pub struct FileOverlayDiskHandle {
+    /// The base file. This is never written to, so it can be shared between
+    /// multiple overlays.
+    pub base: std::fs::File,
+    /// The overlay file, which receives all writes. If it is empty, it is
+    /// initialized as an overlay with no written sectors.
+    pub overlay: std::fs::File,
+}
+
+impl ResourceId<DiskHandleKind> for FileOverlayDiskHandle {
+    const ID: &'static str = "file_overlay";
+}
+
 /// Disk handle for a disk that emulates persistent reservation support.
 #[derive(MeshPayload)]
 pub struct DiskWithReservationsHandle(pub Resource<DiskHandleKind>);
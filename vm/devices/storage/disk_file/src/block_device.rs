@@ -0,0 +1,94 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Geometry discovery for block devices.
+//!
+//! The file size reported for a block device is zero, so the capacity and
+//! sector sizes of a block device must be queried from the device itself.
+
+// UNSAFETY: Calling block device ioctls.
+#![cfg_attr(target_os = "linux", allow(unsafe_code))]
+
+use std::fs;
+use std::io;
+
+/// The geometry of a block device.
+#[derive(Debug, Copy, Clone)]
+pub struct Geometry {
+    /// The capacity of the device, in bytes.
+    pub size: u64,
+    /// The logical sector size of the device.
+    pub sector_size: u32,
+    /// The physical sector size of the device.
+    pub physical_sector_size: u32,
+}
+
+#[cfg(target_os = "linux")]
+mod ioctl {
+    use nix::ioctl_read;
+    use nix::ioctl_read_bad;
+    use nix::request_code_none;
+
+    const BLK_IOC_MAGIC: u8 = 0x12;
+    // #define BLKSSZGET  _IO(0x12,104)
+    ioctl_read_bad!(
+        blk_get_sector_size,
+        request_code_none!(BLK_IOC_MAGIC, 104),
+        u32
+    );
+    // #define BLKGETSIZE64 _IOR(0x12,114,size_t)
+    ioctl_read!(blk_get_size64, BLK_IOC_MAGIC, 114, u64);
+    // #define BLKPBSZGET _IO(0x12,123)
+    ioctl_read_bad!(
+        blk_get_physical_sector_size,
+        request_code_none!(BLK_IOC_MAGIC, 123),
+        u32
+    );
+}
+
+/// Returns the geometry of `file` if it is a block device, or `None` if it is
+/// some other kind of file.
+#[cfg(target_os = "linux")]
+pub fn geometry(file: &fs::File) -> io::Result<Option<Geometry>> {
+    use std::os::unix::prelude::*;
+
+    if !file.metadata()?.file_type().is_block_device() {
+        return Ok(None);
+    }
+    let fd = file.as_raw_fd();
+    let mut size = 0;
+    let mut sector_size = 0;
+    let mut physical_sector_size = 0;
+    // SAFETY: `fd` is a valid block device file descriptor for the duration
+    // of the calls, and each output points to a value of the type that the
+    // ioctl writes.
+    unsafe {
+        ioctl::blk_get_size64(fd, &mut size)?;
+        ioctl::blk_get_sector_size(fd, &mut sector_size)?;
+        ioctl::blk_get_physical_sector_size(fd, &mut physical_sector_size)?;
+    }
+    Ok(Some(Geometry {
+        size,
+        sector_size,
+        // Some devices do not report a physical sector size.
+        physical_sector_size: physical_sector_size.max(sector_size),
+    }))
+}
+
+/// Returns the geometry of `file` if it is a block device, or `None` if it is
+/// some other kind of file.
+///
+/// Block devices are only detected on Linux.
+#[cfg(not(target_os = "linux"))]
+pub fn geometry(_file: &fs::File) -> io::Result<Option<Geometry>> {
+    Ok(None)
+}
+
+/// Returns the size of `file`, querying the device capacity if it is a block
+/// device.
+pub fn file_size(file: &fs::File) -> io::Result<u64> {
+    match geometry(file)? {
+        Some(geometry) => Ok(geometry.size),
+        None => Ok(file.metadata()?.len()),
+    }
+}
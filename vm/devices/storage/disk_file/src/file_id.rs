@@ -0,0 +1,63 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Helpers for deriving a stable disk identifier from a file's identity.
+
+// UNSAFETY: Calling Win32 APIs to query the file index.
+#![cfg_attr(windows, allow(unsafe_code))]
+
+use guid::Guid;
+use sha2::Digest;
+use sha2::Sha256;
+use std::fs;
+use std::io;
+use zerocopy::AsBytes;
+
+/// Namespace hashed along with the file identity, so that the resulting IDs do
+/// not collide with IDs derived from the same values by other components.
+const NAMESPACE: Guid = Guid::from_static_str("5b7a4c1e-8f0d-4e39-9a6b-2c1f3d8e7a40");
+
+/// Returns a 16-byte identifier for `file`.
+///
+/// The identifier is derived from the volume and file index of the file, so it
+/// is stable for as long as the file stays in place, including across
+/// different opens of the same file.
+pub fn disk_id(file: &fs::File) -> io::Result<[u8; 16]> {
+    let (volume, index) = file_identity(file)?;
+    let hash = Sha256::new()
+        .chain_update(NAMESPACE.as_bytes())
+        .chain_update(volume.to_le_bytes())
+        .chain_update(index.to_le_bytes())
+        .finalize();
+    let mut id = [0; 16];
+    id.copy_from_slice(&hash[..16]);
+    Ok(id)
+}
+
+#[cfg(unix)]
+fn file_identity(file: &fs::File) -> io::Result<(u64, u64)> {
+    use std::os::unix::prelude::*;
+
+    let metadata = file.metadata()?;
+    Ok((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(windows)]
+fn file_identity(file: &fs::File) -> io::Result<(u64, u64)> {
+    use std::os::windows::prelude::*;
+    use windows_sys::Win32::Storage::FileSystem::GetFileInformationByHandle;
+    use windows_sys::Win32::Storage::FileSystem::BY_HANDLE_FILE_INFORMATION;
+
+    // SAFETY: BY_HANDLE_FILE_INFORMATION is a plain C struct, for which all
+    // zeroes is a valid value.
+    let mut info: BY_HANDLE_FILE_INFORMATION = unsafe { std::mem::zeroed() };
+    // SAFETY: the handle is owned by `file` and is valid for the duration of
+    // the call, and `info` is a valid output buffer.
+    if unsafe { GetFileInformationByHandle(file.as_raw_handle() as _, &mut info) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok((
+        info.dwVolumeSerialNumber.into(),
+        (u64::from(info.nFileIndexHigh) << 32) | u64::from(info.nFileIndexLow),
+    ))
+}
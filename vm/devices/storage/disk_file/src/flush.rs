@@ -0,0 +1,209 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Flush modes, and coalescing of concurrent flushes into a single sync.
+
+use blocking::unblock;
+use pal_async::driver::Driver;
+use pal_async::timer::PolledTimer;
+use parking_lot::Mutex;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How a file disk syncs its file to stable storage, for flushes and FUA
+/// writes.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum FlushMode {
+    /// Sync the file's data and all of its metadata, as with `fsync`.
+    #[default]
+    SyncAll,
+    /// Sync the file's data and only the metadata needed to read it back, as
+    /// with `fdatasync`. This is cheaper for fixed-size images, whose
+    /// metadata does not otherwise need to be durable.
+    ///
+    /// On platforms without a separate data sync, this is the same as
+    /// [`FlushMode::SyncAll`].
+    SyncData,
+}
+
+/// A file that can be synced to stable storage.
+pub trait SyncFile {
+    /// Syncs the file's data and metadata.
+    fn sync_all(&self) -> io::Result<()>;
+    /// Syncs the file's data.
+    fn sync_data(&self) -> io::Result<()>;
+}
+
+impl SyncFile for fs::File {
+    fn sync_all(&self) -> io::Result<()> {
+        fs::File::sync_all(self)
+    }
+
+    fn sync_data(&self) -> io::Result<()> {
+        fs::File::sync_data(self)
+    }
+}
+
+impl FlushMode {
+    /// Syncs `file` according to this mode.
+    pub(crate) fn sync(self, file: &impl SyncFile) -> io::Result<()> {
+        match self {
+            FlushMode::SyncAll => file.sync_all(),
+            FlushMode::SyncData => file.sync_data(),
+        }
+    }
+}
+
+/// Merges flushes that arrive within a window into a single sync.
+///
+/// Each sync is assigned a generation when it starts. A flush is satisfied by
+/// the first sync that starts after the flush was requested, whether or not
+/// the flush issued that sync itself.
+pub struct FlushCoalescer {
+    window: Duration,
+    driver: Box<dyn Driver>,
+    inner: Arc<Inner>,
+}
+
+impl fmt::Debug for FlushCoalescer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FlushCoalescer")
+            .field("window", &self.window)
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+#[derive(Debug)]
+struct Inner {
+    state: Mutex<State>,
+    event: event_listener::Event,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// The generation of the most recently started sync.
+    started: u64,
+    /// The generation of the most recently completed successful sync.
+    completed: u64,
+    /// Whether a sync is pending or in progress.
+    in_flight: bool,
+}
+
+/// Clears the pending sync if its flush is dropped while waiting out the
+/// window, so that a later flush can issue the sync instead.
+struct PendingSync(Option<Arc<Inner>>);
+
+impl Drop for PendingSync {
+    fn drop(&mut self) {
+        if let Some(inner) = self.0.take() {
+            inner.state.lock().in_flight = false;
+            inner.event.notify(usize::MAX);
+        }
+    }
+}
+
+impl FlushCoalescer {
+    /// Returns a new coalescer that waits `window` for more flushes to arrive
+    /// before issuing each sync, using timers from `driver`.
+    pub fn new(window: Duration, driver: impl Driver) -> Self {
+        Self {
+            window,
+            driver: Box::new(driver),
+            inner: Arc::new(Inner {
+                state: Mutex::new(State::default()),
+                event: Default::default(),
+            }),
+        }
+    }
+
+    /// Waits until `file` has been synced by a sync that started after this
+    /// call, issuing that sync with `mode` if one is not already pending.
+    ///
+    /// Returns whether this call issued the sync.
+    pub async fn flush(&self, file: &Arc<fs::File>, mode: FlushMode) -> io::Result<bool> {
+        // Any sync that has not yet been assigned a generation will start
+        // after this point.
+        let target = self.inner.state.lock().started + 1;
+        loop {
+            let listen = self.inner.event.listen();
+            {
+                let mut state = self.inner.state.lock();
+                if state.completed >= target {
+                    return Ok(false);
+                }
+                if !state.in_flight {
+                    state.in_flight = true;
+                    break;
+                }
+            }
+            listen.await;
+        }
+
+        let mut pending = PendingSync(Some(self.inner.clone()));
+        PolledTimer::new(&self.driver).sleep(self.window).await;
+
+        let file = file.clone();
+        let inner = pending.0.take().unwrap();
+        // Update the state from the blocking task so that it stays consistent
+        // even if this future is dropped.
+        unblock(move || {
+            let generation = {
+                let mut state = inner.state.lock();
+                state.started += 1;
+                state.started
+            };
+            let result = mode.sync(&*file);
+            {
+                let mut state = inner.state.lock();
+                state.in_flight = false;
+                if result.is_ok() {
+                    state.completed = generation;
+                }
+            }
+            inner.event.notify(usize::MAX);
+            result
+        })
+        .await?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[derive(Default)]
+    struct MockFile {
+        sync_all: Cell<u32>,
+        sync_data: Cell<u32>,
+    }
+
+    impl SyncFile for MockFile {
+        fn sync_all(&self) -> io::Result<()> {
+            self.sync_all.set(self.sync_all.get() + 1);
+            Ok(())
+        }
+
+        fn sync_data(&self) -> io::Result<()> {
+            self.sync_data.set(self.sync_data.get() + 1);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn flush_mode() {
+        let file = MockFile::default();
+        FlushMode::default().sync(&file).unwrap();
+        assert_eq!((file.sync_all.get(), file.sync_data.get()), (1, 0));
+
+        let file = MockFile::default();
+        FlushMode::SyncData.sync(&file).unwrap();
+        FlushMode::SyncData.sync(&file).unwrap();
+        assert_eq!((file.sync_all.get(), file.sync_data.get()), (0, 2));
+    }
+}
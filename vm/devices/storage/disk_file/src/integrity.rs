@@ -0,0 +1,349 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Per-sector checksums for detecting silent data corruption.
+//!
+//! The checksums are kept in a sidecar file, as a little-endian CRC32C for
+//! each physical sector of the disk. Checksumming physical rather than logical
+//! sectors means that a write of a single logical sector may only cover part
+//! of a checksummed range, in which case the rest of the range is read back to
+//! compute the new checksum.
+
+use crate::readwriteat::ReadWriteAt;
+use crate::FlushMode;
+use inspect::Inspect;
+use inspect_counters::SharedCounter;
+use parking_lot::Mutex;
+use std::fs;
+use std::io;
+use thiserror::Error;
+
+/// The size of each checksum in the sidecar file.
+const CHECKSUM_SIZE: u64 = 4;
+
+/// The most data to read at once when recomputing checksums.
+const UPDATE_CHUNK_SIZE: u64 = 0x100000;
+
+#[derive(Debug, Error)]
+pub enum IntegrityError {
+    #[error("io error")]
+    Io(#[from] io::Error),
+    /// The data at byte offset `offset` does not match its checksum.
+    #[error("checksum mismatch at offset {offset:#x}")]
+    Mismatch { offset: u64 },
+}
+
+/// Checksums for the contents of a file disk.
+///
+/// Each read is verified against the checksums, and each write updates them.
+/// Reads and writes are serialized so that a read never observes data that
+/// does not match its checksum because of a concurrent write. This mode is
+/// intended for testing and validation, so the cost of this is acceptable.
+#[derive(Debug, Inspect)]
+pub struct Integrity {
+    #[inspect(skip)]
+    sidecar: fs::File,
+    block_size: u32,
+    #[inspect(skip)]
+    lock: Mutex<()>,
+    /// The number of reads that failed verification.
+    failures: SharedCounter,
+}
+
+impl Integrity {
+    /// Returns checksums for a disk of `disk_size` bytes whose contents are in
+    /// `file`, stored in `sidecar` with one checksum per `block_size` bytes.
+    ///
+    /// If `sidecar` is empty, the checksums are computed from the current
+    /// contents of `file`. Otherwise, `sidecar` must already be the size
+    /// returned by [`Self::sidecar_size`].
+    pub fn new(
+        sidecar: fs::File,
+        block_size: u32,
+        file: &fs::File,
+        disk_size: u64,
+    ) -> io::Result<Self> {
+        assert!(block_size.is_power_of_two());
+        let this = Self {
+            sidecar,
+            block_size,
+            lock: Mutex::new(()),
+            failures: SharedCounter::new(),
+        };
+        if this.sidecar.metadata()?.len() == 0 {
+            this.sidecar.set_len(this.sidecar_size(disk_size))?;
+            this.update(file, 0, disk_size, disk_size)?;
+        }
+        Ok(this)
+    }
+
+    /// Returns the size of the sidecar file for a disk of `disk_size` bytes.
+    pub fn sidecar_size(&self, disk_size: u64) -> u64 {
+        disk_size.div_ceil(self.block_size.into()) * CHECKSUM_SIZE
+    }
+
+    /// Returns the current size of the sidecar file.
+    pub fn current_sidecar_size(&self) -> io::Result<u64> {
+        Ok(self.sidecar.metadata()?.len())
+    }
+
+    /// Returns the block-aligned range covering `len` bytes at `offset`,
+    /// clipped to the end of the disk.
+    fn aligned_range(&self, offset: u64, len: u64, disk_size: u64) -> (u64, u64) {
+        let block_size = u64::from(self.block_size);
+        let start = offset & !(block_size - 1);
+        let end = (offset + len).next_multiple_of(block_size).min(disk_size);
+        (start, end)
+    }
+
+    fn read_checksums(&self, start: u64, end: u64) -> io::Result<Vec<u32>> {
+        let block_size = u64::from(self.block_size);
+        let count = (end - start).div_ceil(block_size);
+        let mut bytes = vec![0; (count * CHECKSUM_SIZE) as usize];
+        read_full(
+            &self.sidecar,
+            &mut bytes,
+            start / block_size * CHECKSUM_SIZE,
+        )?;
+        Ok(bytes
+            .chunks_exact(CHECKSUM_SIZE as usize)
+            .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+            .collect())
+    }
+
+    fn write_checksums(&self, start: u64, data: &[u8]) -> io::Result<()> {
+        let bytes: Vec<u8> = data
+            .chunks(self.block_size as usize)
+            .flat_map(|block| crc32c(block).to_le_bytes())
+            .collect();
+        let offset = start / u64::from(self.block_size) * CHECKSUM_SIZE;
+        write_full(&self.sidecar, &bytes, offset)
+    }
+
+    /// Checks `data`, which starts at the block-aligned offset `start`,
+    /// against its checksums, returning the offset of the first mismatch at
+    /// or after `offset`.
+    fn verify(&self, start: u64, data: &[u8], offset: u64) -> Result<(), IntegrityError> {
+        let checksums = self.read_checksums(start, start + data.len() as u64)?;
+        for (i, (block, &checksum)) in data
+            .chunks(self.block_size as usize)
+            .zip(&checksums)
+            .enumerate()
+        {
+            if crc32c(block) != checksum {
+                self.failures.increment();
+                let block_offset = start + i as u64 * u64::from(self.block_size);
+                return Err(IntegrityError::Mismatch {
+                    offset: block_offset.max(offset),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads `buf.len()` bytes at `offset` from `file`, verifying them against
+    /// their checksums.
+    pub fn read(
+        &self,
+        file: &fs::File,
+        buf: &mut [u8],
+        offset: u64,
+        disk_size: u64,
+    ) -> Result<(), IntegrityError> {
+        if buf.is_empty() {
+            return Ok(());
+        }
+        let _guard = self.lock.lock();
+        let (start, end) = self.aligned_range(offset, buf.len() as u64, disk_size);
+        let mut data = vec![0; (end - start) as usize];
+        read_full(file, &mut data, start)?;
+        self.verify(start, &data, offset)?;
+        let head = (offset - start) as usize;
+        buf.copy_from_slice(&data[head..head + buf.len()]);
+        Ok(())
+    }
+
+    /// Writes `buf` at `offset` to `file`, updating the checksums.
+    ///
+    /// The existing data in any block that `buf` only partially covers is
+    /// verified before its checksum is replaced, so that corruption is not
+    /// hidden by a later write. If `fua` is set, both the data and the
+    /// checksums are synced to stable storage with that mode.
+    pub fn write(
+        &self,
+        file: &fs::File,
+        buf: &[u8],
+        offset: u64,
+        disk_size: u64,
+        fua: Option<FlushMode>,
+    ) -> Result<(), IntegrityError> {
+        if buf.is_empty() {
+            return Ok(());
+        }
+        let _guard = self.lock.lock();
+        let (start, end) = self.aligned_range(offset, buf.len() as u64, disk_size);
+        let mut data = vec![0; (end - start) as usize];
+        let head = (offset - start) as usize;
+        let tail = head + buf.len();
+        let block_size = self.block_size as usize;
+        let first_block = 0..block_size.min(data.len());
+        let last_block = (data.len() - 1) / block_size * block_size..data.len();
+        let mut partial = Vec::new();
+        if head != 0 {
+            partial.push(first_block);
+        }
+        if tail != data.len() && (head == 0 || last_block.start != 0) {
+            partial.push(last_block);
+        }
+        for block in partial {
+            let block_offset = start + block.start as u64;
+            let block = &mut data[block];
+            read_full(file, block, block_offset)?;
+            self.verify(block_offset, block, offset)?;
+        }
+        data[head..tail].copy_from_slice(buf);
+        write_full(file, buf, offset)?;
+        self.write_checksums(start, &data)?;
+        if let Some(mode) = fua {
+            mode.sync(file)?;
+            mode.sync(&self.sidecar)?;
+        }
+        Ok(())
+    }
+
+    /// Recomputes the checksums covering `len` bytes at `offset` from the
+    /// current contents of `file`, after the range has been modified other
+    /// than through [`Self::write`].
+    pub fn update(&self, file: &fs::File, offset: u64, len: u64, disk_size: u64) -> io::Result<()> {
+        let (mut start, end) = self.aligned_range(offset, len, disk_size);
+        let mut data = Vec::new();
+        while start < end {
+            let chunk_len = (end - start).min(UPDATE_CHUNK_SIZE);
+            data.resize(chunk_len as usize, 0);
+            read_full(file, &mut data, start)?;
+            self.write_checksums(start, &data)?;
+            start += chunk_len;
+        }
+        Ok(())
+    }
+
+    /// Runs `f` with reads and writes blocked.
+    pub fn locked<R>(&self, f: impl FnOnce() -> R) -> R {
+        let _guard = self.lock.lock();
+        f()
+    }
+
+    /// Updates the checksums after the disk is resized from `old_size` to
+    /// `new_size` bytes.
+    ///
+    /// This must be called from within [`Self::locked`].
+    pub fn resize(&self, file: &fs::File, old_size: u64, new_size: u64) -> io::Result<()> {
+        self.sidecar.set_len(self.sidecar_size(new_size))?;
+        // The block containing the old end of the disk may have changed size,
+        // and any new blocks need checksums.
+        if new_size > old_size {
+            self.update(file, old_size, new_size - old_size, new_size)?;
+        } else if new_size % u64::from(self.block_size) != 0 {
+            self.update(file, new_size - 1, 1, new_size)?;
+        }
+        Ok(())
+    }
+
+    /// Syncs the checksums to stable storage.
+    pub fn flush(&self) -> io::Result<()> {
+        self.sidecar.sync_all()
+    }
+}
+
+fn read_full(file: &fs::File, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+    while !buf.is_empty() {
+        let n = file.read_at(buf, offset)?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        buf = &mut buf[n..];
+        offset += n as u64;
+    }
+    Ok(())
+}
+
+fn write_full(file: &fs::File, mut buf: &[u8], mut offset: u64) -> io::Result<()> {
+    while !buf.is_empty() {
+        let n = file.write_at(buf, offset)?;
+        if n == 0 {
+            return Err(io::ErrorKind::WriteZero.into());
+        }
+        buf = &buf[n..];
+        offset += n as u64;
+    }
+    Ok(())
+}
+
+/// Computes the CRC32C (Castagnoli) checksum of `data`.
+fn crc32c(data: &[u8]) -> u32 {
+    const TABLE: [u32; 256] = {
+        let mut table = [0; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut crc = i as u32;
+            let mut bit = 0;
+            while bit < 8 {
+                crc = if crc & 1 != 0 {
+                    (crc >> 1) ^ 0x82f6_3b78
+                } else {
+                    crc >> 1
+                };
+                bit += 1;
+            }
+            table[i] = crc;
+            i += 1;
+        }
+        table
+    };
+    !data.iter().fold(!0, |crc, &b| {
+        TABLE[((crc ^ u32::from(b)) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc32c_check_value() {
+        assert_eq!(crc32c(b""), 0);
+        assert_eq!(crc32c(b"123456789"), 0xe306_9283);
+    }
+
+    #[test]
+    fn partial_block_write() {
+        let file = tempfile::tempfile().unwrap();
+        file.write_at(&[0x11; 0x4000], 0).unwrap();
+        let integrity = Integrity::new(tempfile::tempfile().unwrap(), 4096, &file, 0x4000).unwrap();
+        assert_eq!(integrity.current_sidecar_size().unwrap(), 16);
+
+        // Write one 512-byte sector in the middle of each of two blocks, and
+        // a range straddling a block boundary.
+        integrity
+            .write(&file, &[0x22; 0x200], 0x200, 0x4000, None)
+            .unwrap();
+        integrity
+            .write(&file, &[0x33; 0x400], 0x1e00, 0x4000, None)
+            .unwrap();
+
+        let mut buf = vec![0; 0x4000];
+        integrity.read(&file, &mut buf, 0, 0x4000).unwrap();
+        let mut expected = vec![0x11; 0x4000];
+        expected[0x200..0x400].fill(0x22);
+        expected[0x1e00..0x2200].fill(0x33);
+        assert!(buf == expected);
+
+        // Corrupt a byte outside the written ranges and check that a partial
+        // write to the same block fails rather than hiding the corruption.
+        file.write_at(&[0xff], 0x2800).unwrap();
+        let err = integrity
+            .write(&file, &[0x44; 0x200], 0x2000, 0x4000, None)
+            .unwrap_err();
+        assert!(matches!(err, IntegrityError::Mismatch { offset: 0x2000 }));
+    }
+}
@@ -1,26 +1,59 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT License.
 
-#![forbid(unsafe_code)]
+// Unsafe code is confined to the platform IO modules, which opt in with
+// `allow(unsafe_code)`. `forbid` would not let them.
+#![deny(unsafe_code)]
 
+mod block_device;
+mod file_id;
+mod flush;
+mod integrity;
+mod native;
+pub mod overlay;
+mod punch_hole;
+mod rate_limit;
+mod read_ahead;
 mod readwriteat;
+mod reflink;
+mod unbuffered;
+mod zero;
+
+pub use self::flush::FlushMode;
+pub use self::rate_limit::FlushLimit;
+pub use self::rate_limit::RateLimit;
+pub use self::read_ahead::ReadAheadConfig;
 
 use self::readwriteat::ReadWriteAt;
+use self::unbuffered::AlignedBuffer;
 use blocking::unblock;
 use disk_backend::resolve::ResolveDiskParameters;
 use disk_backend::resolve::ResolvedSimpleDisk;
 use disk_backend::AsyncDisk;
 use disk_backend::DiskError;
+use disk_backend::Resize;
 use disk_backend::SimpleDisk;
+use disk_backend::Unmap;
 use disk_backend::ASYNC_DISK_STACK_SIZE;
 use disk_backend_resources::FileDiskHandle;
 use guestmem::MemoryRead;
 use guestmem::MemoryWrite;
 use inspect::Inspect;
+use inspect_counters::SharedCounter;
+use parking_lot::RwLock;
+#[cfg(target_os = "linux")]
+use scsi_buffers::LockedIoBuffers;
 use scsi_buffers::RequestBuffers;
 use stackfuture::StackFuture;
 use std::fs;
+use std::future::Future;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
 use vm_resource::declare_static_resolver;
 use vm_resource::kind::DiskHandleKind;
 use vm_resource::ResolveResource;
@@ -30,26 +63,91 @@
 
 impl ResolveResource<DiskHandleKind, FileDiskHandle> for FileDiskResolver {
     type Output = ResolvedSimpleDisk;
-    type Error = std::io::Error;
+    type Error = OpenError;
 
     fn resolve(
         &self,
         rsrc: FileDiskHandle,
         input: ResolveDiskParameters<'_>,
     ) -> Result<Self::Output, Self::Error> {
-        Ok(FileDisk::open(rsrc.0, input.read_only)?.into())
+        let mut disk = if rsrc.sector_size.is_none() && rsrc.physical_sector_size.is_none() {
+            FileDisk::open(rsrc.file, input.read_only).map_err(OpenError::Io)?
+        } else {
+            let sector_size = rsrc.sector_size.unwrap_or(DEFAULT_SECTOR_SIZE);
+            let physical_sector_size = rsrc
+                .physical_sector_size
+                .unwrap_or(DEFAULT_PHYSICAL_SECTOR_SIZE.max(sector_size));
+            FileDisk::open_with_sector_sizes(
+                rsrc.file,
+                input.read_only,
+                sector_size,
+                physical_sector_size,
+            )?
+        };
+        if rsrc.direct {
+            disk.enable_direct().map_err(OpenError::Io)?;
+        }
+        if let Some(disk_id) = rsrc.disk_id {
+            disk.disk_id = Some(disk_id);
+        }
+        Ok(disk.into())
     }
 }
 
+/// An error opening a file disk.
+#[derive(Debug, Error)]
+pub enum OpenError {
+    #[error("file error")]
+    Io(#[source] std::io::Error),
+    #[error("invalid sector size {0}, must be a power of two no smaller than 512")]
+    InvalidSectorSize(u32),
+    #[error("physical sector size {physical} is smaller than sector size {logical}")]
+    InvalidPhysicalSectorSize { logical: u32, physical: u32 },
+    #[error("overlay file size {overlay_size} does not match base disk size {disk_size}")]
+    InvalidOverlaySize { overlay_size: u64, disk_size: u64 },
+    #[error("checksum file size {sidecar_size} does not match expected size {expected}")]
+    InvalidIntegritySize { sidecar_size: u64, expected: u64 },
+}
+
+const DEFAULT_SECTOR_SIZE: u32 = 512;
+const DEFAULT_PHYSICAL_SECTOR_SIZE: u32 = 4096;
+
 #[derive(Debug, Inspect)]
 pub struct FileDisk {
     file: Arc<fs::File>,
     metadata: Metadata,
+    #[inspect(rename = "disk_size", with = "|x| x.bytes.load(Ordering::Relaxed)")]
+    size: Arc<DiskSize>,
+    #[inspect(skip)]
+    resize_event: event_listener::Event,
     sector_shift: u32,
+    direct: bool,
+    disk_id: Option<[u8; 16]>,
+    /// Whether to check each IO against the current file size.
+    validate_size: bool,
+    /// Whether to deallocate the range of large all-zero writes rather than
+    /// writing the zeroes.
+    punch_zero_writes: bool,
+    /// The number of sync operations issued to the file, for flushes and FUA
+    /// writes.
+    syncs: SharedCounter,
+    #[inspect(debug)]
+    flush_mode: FlushMode,
+    #[inspect(skip)]
+    flush_coalescer: Option<flush::FlushCoalescer>,
+    read_ahead: Option<read_ahead::ReadAhead>,
+    #[inspect(with = "Option::is_some")]
+    native: Option<native::NativeIo>,
+    io_depth: Arc<IoDepth>,
+    integrity: Option<Arc<integrity::Integrity>>,
+    rate_limiter: Option<rate_limit::RateLimiter>,
 }
 
 #[derive(Debug, Inspect)]
 pub struct Metadata {
+    /// The initial size of the disk. The current size is reported separately,
+    /// since the disk may be resized.
+    #[inspect(skip)]
     pub disk_size: u64,
     pub sector_size: u32,
     pub physical_sector_size: u32,
@@ -57,14 +155,240 @@ pub struct Metadata {
 }
 
 impl FileDisk {
+    /// Opens the disk.
+    ///
+    /// The disk ID is derived from the identity of the file, so opening the
+    /// same file again produces the same ID.
+    ///
+    /// If `file` is a block device, the disk has the capacity and sector sizes
+    /// of the device.
     pub fn open(file: fs::File, read_only: bool) -> Result<Self, std::io::Error> {
+        Self::open_inner(file, read_only, None)
+    }
+
+    /// Opens the disk with the specified logical and physical sector sizes,
+    /// rather than those of the block device or the defaults.
+    ///
+    /// `sector_size` must be a power of two no smaller than 512, and
+    /// `physical_sector_size` must be at least `sector_size`.
+    pub fn open_with_sector_sizes(
+        file: fs::File,
+        read_only: bool,
+        sector_size: u32,
+        physical_sector_size: u32,
+    ) -> Result<Self, OpenError> {
+        if !sector_size.is_power_of_two() || sector_size < 512 {
+            return Err(OpenError::InvalidSectorSize(sector_size));
+        }
+        if physical_sector_size < sector_size {
+            return Err(OpenError::InvalidPhysicalSectorSize {
+                logical: sector_size,
+                physical: physical_sector_size,
+            });
+        }
+        Self::open_inner(file, read_only, Some((sector_size, physical_sector_size)))
+            .map_err(OpenError::Io)
+    }
+
+    /// Opens the disk with the given logical and physical sector sizes, or
+    /// those of the block device or the defaults if `None`.
+    fn open_inner(
+        file: fs::File,
+        read_only: bool,
+        sector_sizes: Option<(u32, u32)>,
+    ) -> Result<Self, std::io::Error> {
+        let geometry = block_device::geometry(&file)?;
+        let (sector_size, physical_sector_size) = sector_sizes.unwrap_or_else(|| {
+            geometry.map_or(
+                (DEFAULT_SECTOR_SIZE, DEFAULT_PHYSICAL_SECTOR_SIZE),
+                |geometry| (geometry.sector_size, geometry.physical_sector_size),
+            )
+        });
+        let disk_size = match geometry {
+            Some(geometry) => geometry.size,
+            None => file.metadata()?.len(),
+        };
         let metadata = Metadata {
-            disk_size: file.metadata()?.len(),
-            sector_size: 512,
-            physical_sector_size: 4096,
+            disk_size,
+            sector_size,
+            physical_sector_size,
             read_only,
         };
-        Ok(Self::with_metadata(file, metadata))
+        let disk_id = file_id::disk_id(&file)?;
+        let mut disk = Self::with_metadata(file, metadata);
+        disk.disk_id = Some(disk_id);
+        // Discarding a range of a block device may be slow or may not read
+        // back as zeroes, so only files are kept sparse by default.
+        disk.punch_zero_writes = geometry.is_none();
+        Ok(disk)
+    }
+
+    /// Opens the disk for unbuffered IO, bypassing the host page cache.
+    ///
+    /// IO is issued directly against guest memory when it is suitably aligned,
+    /// and through page-aligned bounce buffers otherwise. This is currently
+    /// only supported on Linux.
+    pub fn open_direct(file: fs::File, read_only: bool) -> Result<Self, std::io::Error> {
+        let mut disk = Self::open(file, read_only)?;
+        disk.enable_direct()?;
+        Ok(disk)
+    }
+
+    /// Opens the disk with flush coalescing enabled.
+    ///
+    /// Flushes that arrive within `flush_window` of each other are merged into
+    /// a single sync of the file, which reduces the load from guests that
+    /// issue many small flushes at the cost of up to `flush_window` of
+    /// additional flush latency. The window is timed with timers from
+    /// `driver`.
+    pub fn open_coalescing(
+        file: fs::File,
+        read_only: bool,
+        flush_window: Duration,
+        driver: impl pal_async::driver::Driver,
+    ) -> Result<Self, std::io::Error> {
+        let mut disk = Self::open(file, read_only)?;
+        disk.flush_coalescer = Some(flush::FlushCoalescer::new(flush_window, driver));
+        Ok(disk)
+    }
+
+    /// Opens the disk, syncing the file with `flush_mode` for flushes and FUA
+    /// writes.
+    ///
+    /// [`FlushMode::SyncData`] avoids syncing metadata that has not changed,
+    /// which makes flushes cheaper for fixed-size images.
+    pub fn open_with_flush_mode(
+        file: fs::File,
+        read_only: bool,
+        flush_mode: FlushMode,
+    ) -> Result<Self, std::io::Error> {
+        let mut disk = Self::open(file, read_only)?;
+        disk.flush_mode = flush_mode;
+        Ok(disk)
+    }
+
+    /// Opens the disk with a read-ahead cache.
+    ///
+    /// After a few sequential reads, reads that miss the cache are extended
+    /// by `config.window` bytes, and the extra data is cached so that the
+    /// reads that follow are served from memory.
+    pub fn open_read_ahead(
+        file: fs::File,
+        read_only: bool,
+        config: ReadAheadConfig,
+    ) -> Result<Self, std::io::Error> {
+        let mut disk = Self::open(file, read_only)?;
+        disk.read_ahead = Some(read_ahead::ReadAhead::new(
+            config,
+            disk.metadata.sector_size,
+        ));
+        Ok(disk)
+    }
+
+    /// Opens the disk with reads and writes issued through io_uring, so that
+    /// the number of IOs in flight is not bounded by the size of the blocking
+    /// thread pool.
+    ///
+    /// If `uring` does not support the necessary operations, this falls back
+    /// to the thread pool.
+    #[cfg(target_os = "linux")]
+    pub fn open_native(
+        file: fs::File,
+        read_only: bool,
+        uring: Arc<dyn pal_uring::Initiate>,
+    ) -> Result<Self, std::io::Error> {
+        let mut disk = Self::open(file, read_only)?;
+        disk.native = native::NativeIo::new(&disk.file, uring);
+        if disk.native.is_none() {
+            tracing::warn!("io_uring does not support file IO, falling back to thread pool");
+        }
+        Ok(disk)
+    }
+
+    /// Opens the disk with reads and writes issued as overlapped IO, so that
+    /// the number of IOs in flight is not bounded by the size of the blocking
+    /// thread pool.
+    ///
+    /// `file` must have been opened with `FILE_FLAG_OVERLAPPED`. It is only
+    /// used for overlapped reads and writes; the IO that still runs on the
+    /// thread pool, such as flushes, unmaps, and all-zero writes, goes through
+    /// a synchronous handle that is reopened from it. If `file` cannot be
+    /// associated with `driver`, all IO falls back to the thread pool.
+    #[cfg(windows)]
+    pub fn open_native(
+        file: fs::File,
+        read_only: bool,
+        driver: &(impl ?Sized + pal_async::driver::Driver),
+    ) -> Result<Self, std::io::Error> {
+        let sync_file = native::reopen_synchronous(&file, read_only)?;
+        let mut disk = Self::open(sync_file, read_only)?;
+        match native::NativeIo::new(file, &disk.file, driver) {
+            Ok(native) => disk.native = Some(native),
+            Err(err) => {
+                tracing::warn!(
+                    error = &err as &dyn std::error::Error,
+                    "failed to enable overlapped IO, falling back to thread pool"
+                );
+            }
+        }
+        Ok(disk)
+    }
+
+    /// Opens the disk with its IO rate limited to `limit`.
+    ///
+    /// Each IO waits for tokens from the limit's buckets before it is issued,
+    /// using timers from `driver`. The limit can be changed later with
+    /// [`Self::set_rate_limit`].
+    pub fn open_rate_limited(
+        file: fs::File,
+        read_only: bool,
+        limit: RateLimit,
+        driver: impl pal_async::driver::Driver,
+    ) -> Result<Self, std::io::Error> {
+        let mut disk = Self::open(file, read_only)?;
+        disk.rate_limiter = Some(rate_limit::RateLimiter::new(limit, driver));
+        Ok(disk)
+    }
+
+    /// Opens the disk with integrity checking, for detecting corruption in
+    /// tests.
+    ///
+    /// A CRC32C of each physical sector is kept in `sidecar`. Reads fail with
+    /// [`DiskError::IntegrityCheckFailed`] if the data does not match its
+    /// checksum, and writes update the checksums. If `sidecar` is empty, the
+    /// checksums are computed from the current contents of the disk.
+    ///
+    /// IO to the disk is serialized in this mode.
+    pub fn open_integrity(
+        file: fs::File,
+        read_only: bool,
+        sidecar: fs::File,
+    ) -> Result<Self, OpenError> {
+        let mut disk = Self::open(file, read_only).map_err(OpenError::Io)?;
+        let disk_size = disk.metadata.disk_size;
+        let integrity = integrity::Integrity::new(
+            sidecar,
+            disk.metadata.physical_sector_size,
+            &disk.file,
+            disk_size,
+        )
+        .map_err(OpenError::Io)?;
+        let sidecar_size = integrity.current_sidecar_size().map_err(OpenError::Io)?;
+        let expected = integrity.sidecar_size(disk_size);
+        if sidecar_size != expected {
+            return Err(OpenError::InvalidIntegritySize {
+                sidecar_size,
+                expected,
+            });
+        }
+        disk.integrity = Some(Arc::new(integrity));
+        Ok(disk)
+    }
+
+    fn enable_direct(&mut self) -> Result<(), std::io::Error> {
+        unbuffered::enable(&self.file)?;
+        self.direct = true;
+        Ok(())
     }
 
     /// Opens the disk using the specified metadata.
@@ -75,68 +399,698 @@ pub fn open(file: fs::File, read_only: bool) -> Result<Self, std::io::Error> {
     pub fn with_metadata(file: fs::File, metadata: Metadata) -> Self {
         assert!(metadata.sector_size.is_power_of_two());
         assert!(metadata.sector_size >= 512);
+        assert!(metadata.physical_sector_size >= metadata.sector_size);
         let sector_shift = metadata.sector_size.trailing_zeros();
         FileDisk {
             file: Arc::new(file),
+            size: Arc::new(DiskSize {
+                bytes: metadata.disk_size.into(),
+                lock: RwLock::new(()),
+            }),
+            resize_event: Default::default(),
             metadata,
             sector_shift,
+            direct: false,
+            disk_id: None,
+            validate_size: false,
+            punch_zero_writes: true,
+            syncs: SharedCounter::new(),
+            flush_mode: FlushMode::default(),
+            flush_coalescer: None,
+            read_ahead: None,
+            native: None,
+            io_depth: Default::default(),
+            integrity: None,
+            rate_limiter: None,
         }
     }
 
+    /// Sets whether to check each IO against a freshly queried file size,
+    /// logging a warning if the IO extends past the end of the file.
+    ///
+    /// This is a diagnostic for files that are truncated out from under a
+    /// running disk. It costs an extra syscall per IO, so it is off by default.
+    pub fn set_validate_size(&mut self, validate_size: bool) {
+        self.validate_size = validate_size;
+    }
+
+    /// Sets whether large all-zero writes deallocate the range instead of
+    /// writing it, as with [`Self::unmap`].
+    ///
+    /// This is on by default for files, to keep sparse files sparse, and off
+    /// for block devices.
+    pub fn set_punch_zero_writes(&mut self, punch_zero_writes: bool) {
+        self.punch_zero_writes = punch_zero_writes;
+    }
+
+    /// Replaces the disk's IO rate limit. IOs that are already waiting for
+    /// the previous limit are not affected.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the disk was not opened with [`Self::open_rate_limited`].
+    pub fn set_rate_limit(&self, limit: RateLimit) {
+        self.rate_limiter
+            .as_ref()
+            .expect("disk is not rate limited")
+            .set_limit(limit);
+    }
+
+    /// Returns the disk's IO rate limit.
+    pub fn rate_limit(&self) -> RateLimit {
+        self.rate_limiter
+            .as_ref()
+            .map_or(RateLimit::default(), |limiter| limiter.limit())
+    }
+
     pub fn into_inner(self) -> fs::File {
         Arc::try_unwrap(self.file).expect("no outstanding IOs")
     }
 }
 
 impl FileDisk {
+    /// Validates an IO of `len` bytes starting at `sector`, returning the byte
+    /// offset of the IO within the file.
+    ///
+    /// `len` must be a multiple of the sector size, and the IO must not extend
+    /// past the end of the disk.
+    fn check_io(&self, sector: u64, len: u64) -> Result<u64, DiskError> {
+        if len % self.metadata.sector_size as u64 != 0 {
+            return Err(DiskError::InvalidInput);
+        }
+        let disk_size = self.size.bytes.load(Ordering::Relaxed);
+        let end = sector
+            .checked_mul(self.metadata.sector_size.into())
+            .and_then(|offset| offset.checked_add(len));
+        match end {
+            Some(end) if end <= disk_size => Ok(end - len),
+            _ => Err(DiskError::OutOfRange {
+                sector,
+                len,
+                disk_size,
+            }),
+        }
+    }
+
+    /// Returns the memory to issue an IO against: the guest buffers
+    /// themselves if they can be locked, or a bounce buffer otherwise.
+    fn io_memory(&self, buffers: &RequestBuffers<'_>, for_write: bool) -> IoMemory {
+        #[cfg(target_os = "linux")]
+        if !self.direct || buffers.is_aligned(unbuffered::BUFFER_ALIGNMENT) {
+            if let Ok(locked) = buffers.lock(for_write) {
+                if locked.io_vecs().len() <= readwriteat::MAX_IO_VECS {
+                    return IoMemory::Locked(locked);
+                }
+            }
+        }
+        #[cfg(not(target_os = "linux"))]
+        let _ = for_write;
+        IoMemory::Bounce(AlignedBuffer::new(buffers.len()))
+    }
+
     pub async fn read(&self, buffers: &RequestBuffers<'_>, sector: u64) -> Result<(), DiskError> {
-        assert!(((sector << self.sector_shift) + buffers.len() as u64) <= self.metadata.disk_size);
-        let mut buffer = vec![0; buffers.len()];
+        let offset = self.check_io(sector, buffers.len() as u64)?;
+        let len = buffers.len();
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.io(len as u64).await;
+        }
+        if let Some(integrity) = &self.integrity {
+            return self.read_verified(integrity, buffers, offset).await;
+        }
+        if let Some(read_ahead) = &self.read_ahead {
+            let disk_size = self.size.bytes.load(Ordering::Relaxed);
+            match read_ahead.lookup(offset, len as u64, disk_size) {
+                read_ahead::Lookup::Hit(data) => {
+                    buffers.writer().write(&data)?;
+                    return Ok(());
+                }
+                read_ahead::Lookup::Miss {
+                    prefetch: Some(prefetch),
+                } => {
+                    if let Some(rate_limiter) = &self.rate_limiter {
+                        rate_limiter.prefetch(prefetch.len).await;
+                    }
+                    return self.read_and_prefetch(buffers, offset, prefetch).await;
+                }
+                read_ahead::Lookup::Miss { prefetch: None } => {}
+            }
+        }
+        let mut mem = self.io_memory(buffers, true);
+        let mem = if let Some(native) = &self.native {
+            if self.validate_size {
+                check_file_size(&self.file, offset, len as u64);
+            }
+            let _range = self
+                .size
+                .lock_range(offset, len as u64)
+                .map_err(DiskError::Io)?;
+            let _io = self.io_depth.enter();
+            let n = native.read(&mut mem, offset).await.map_err(DiskError::Io)?;
+            if n != len {
+                return Err(DiskError::Io(std::io::ErrorKind::UnexpectedEof.into()));
+            }
+            mem
+        } else {
+            let file = self.file.clone();
+            let size = self.size.clone();
+            let io_depth = self.io_depth.clone();
+            let validate_size = self.validate_size;
+            unblock(move || -> Result<_, std::io::Error> {
+                if validate_size {
+                    check_file_size(&file, offset, len as u64);
+                }
+                let _io = io_depth.enter();
+                let n = size.with_range(offset, len as u64, || match &mut mem {
+                    #[cfg(target_os = "linux")]
+                    IoMemory::Locked(locked) => file.readv_at(locked.io_vecs(), offset),
+                    IoMemory::Bounce(buffer) => file.read_at(buffer, offset),
+                })?;
+                if n != len {
+                    return Err(std::io::ErrorKind::UnexpectedEof.into());
+                }
+                Ok(mem)
+            })
+            .await
+            .map_err(DiskError::Io)?
+        };
+        match mem {
+            #[cfg(target_os = "linux")]
+            IoMemory::Locked(_) => {}
+            IoMemory::Bounce(buffer) => buffers.writer().write(&buffer)?,
+        }
+        Ok(())
+    }
+
+    /// Reads `buffers` from `offset`, extending the read to cover `prefetch`
+    /// and caching the extra data.
+    async fn read_and_prefetch(
+        &self,
+        buffers: &RequestBuffers<'_>,
+        offset: u64,
+        prefetch: read_ahead::Prefetch,
+    ) -> Result<(), DiskError> {
+        let len = buffers.len();
+        let total_len = len as u64 + prefetch.len;
         let file = self.file.clone();
-        let offset = sector << self.sector_shift;
+        let size = self.size.clone();
+        let io_depth = self.io_depth.clone();
+        let validate_size = self.validate_size;
         let buffer = unblock(move || -> Result<_, std::io::Error> {
-            file.read_at(&mut buffer, offset)?;
+            if validate_size {
+                check_file_size(&file, offset, total_len);
+            }
+            let _io = io_depth.enter();
+            let mut buffer = AlignedBuffer::new(total_len as usize);
+            let n = size.with_range(offset, total_len, || file.read_at(&mut buffer, offset))?;
+            if n != buffer.len() {
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
+            }
             Ok(buffer)
         })
         .await
         .map_err(DiskError::Io)?;
+        buffers.writer().write(&buffer[..len])?;
+        if let Some(read_ahead) = &self.read_ahead {
+            read_ahead.fill(prefetch, &buffer[len..]);
+        }
+        Ok(())
+    }
+
+    /// Reads `buffers` from `offset`, verifying the data against its
+    /// checksums.
+    async fn read_verified(
+        &self,
+        integrity: &Arc<integrity::Integrity>,
+        buffers: &RequestBuffers<'_>,
+        offset: u64,
+    ) -> Result<(), DiskError> {
+        let len = buffers.len();
+        let file = self.file.clone();
+        let size = self.size.clone();
+        let integrity = integrity.clone();
+        let io_depth = self.io_depth.clone();
+        let buffer = unblock(move || -> Result<_, integrity::IntegrityError> {
+            let _io = io_depth.enter();
+            let mut buffer = AlignedBuffer::new(len);
+            size.with_range(offset, len as u64, || {
+                integrity.read(
+                    &file,
+                    &mut buffer,
+                    offset,
+                    size.bytes.load(Ordering::Relaxed),
+                )
+            })?;
+            Ok(buffer)
+        })
+        .await
+        .map_err(|err| self.integrity_error(err))?;
         buffers.writer().write(&buffer)?;
         Ok(())
     }
 
+    /// Writes `buffers` at `offset`, updating the data's checksums.
+    async fn write_verified(
+        &self,
+        integrity: &Arc<integrity::Integrity>,
+        buffers: &RequestBuffers<'_>,
+        offset: u64,
+        fua: bool,
+    ) -> Result<(), DiskError> {
+        let mut buffer = AlignedBuffer::new(buffers.len());
+        buffers.reader().read(&mut buffer)?;
+        let file = self.file.clone();
+        let size = self.size.clone();
+        let integrity = integrity.clone();
+        let io_depth = self.io_depth.clone();
+        let flush_mode = self.flush_mode;
+        unblock(move || {
+            let _io = io_depth.enter();
+            size.with_range(offset, buffer.len() as u64, || {
+                integrity.write(
+                    &file,
+                    &buffer,
+                    offset,
+                    size.bytes.load(Ordering::Relaxed),
+                    fua.then_some(flush_mode),
+                )
+            })
+        })
+        .await
+        .map_err(|err| self.integrity_error(err))?;
+        if fua {
+            self.syncs.increment();
+        }
+        Ok(())
+    }
+
+    fn integrity_error(&self, err: integrity::IntegrityError) -> DiskError {
+        match err {
+            integrity::IntegrityError::Io(err) => DiskError::Io(err),
+            integrity::IntegrityError::Mismatch { offset } => {
+                tracing::error!(offset, "file disk integrity check failed");
+                DiskError::IntegrityCheckFailed {
+                    sector: offset >> self.sector_shift,
+                }
+            }
+        }
+    }
+
+    /// Writes `buffers` to the disk starting at `sector`.
+    ///
+    /// Large writes of all-zero data deallocate the range instead, as with
+    /// [`Self::unmap`], to keep sparse files sparse. See
+    /// [`Self::set_punch_zero_writes`].
+    ///
+    /// If `fua` is set, the data is synced to stable storage before this
+    /// returns, using the disk's [`FlushMode`]. This syncs rather than using
+    /// `sync_file_range`, since the latter does not flush the device's write
+    /// cache.
     pub async fn write(
         &self,
         buffers: &RequestBuffers<'_>,
         sector: u64,
-        _fua: bool,
+        fua: bool,
     ) -> Result<(), DiskError> {
-        assert!(((sector << self.sector_shift) + buffers.len() as u64) <= self.metadata.disk_size);
-        let mut buffer = vec![0; buffers.len()];
+        if self.metadata.read_only {
+            return Err(DiskError::ReadOnly);
+        }
+        let offset = self.check_io(sector, buffers.len() as u64)?;
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.io(buffers.len() as u64).await;
+        }
+        if let Some(integrity) = &self.integrity {
+            return self.write_verified(integrity, buffers, offset, fua).await;
+        }
+        let len = buffers.len();
+        let mut mem = self.io_memory(buffers, false);
+        match &mut mem {
+            #[cfg(target_os = "linux")]
+            IoMemory::Locked(_) => {}
+            IoMemory::Bounce(buffer) => buffers.reader().read(buffer)?,
+        }
+        let result = match &self.native {
+            Some(native) => self.write_native(native, mem, offset, len, fua).await,
+            None => self.write_blocking(mem, offset, len, fua).await,
+        };
+        // Invalidate even on failure, since part of the range may have been
+        // written.
+        if let Some(read_ahead) = &self.read_ahead {
+            read_ahead.invalidate(offset, len as u64);
+        }
+        result.map_err(DiskError::Io)?;
+        if fua {
+            self.syncs.increment();
+        }
+        Ok(())
+    }
+
+    /// Writes `mem` at `offset` from the blocking thread pool.
+    async fn write_blocking(
+        &self,
+        mem: IoMemory,
+        offset: u64,
+        len: usize,
+        fua: bool,
+    ) -> std::io::Result<()> {
         let file = self.file.clone();
-        buffers.reader().read(&mut buffer)?;
-        let offset = sector << self.sector_shift;
-        unblock(move || file.write_at(&buffer, offset))
-            .await
-            .map_err(DiskError::Io)?;
+        let size = self.size.clone();
+        let io_depth = self.io_depth.clone();
+        let validate_size = self.validate_size;
+        let punch_zero_writes = self.punch_zero_writes;
+        let flush_mode = self.flush_mode;
+        // Issue the write and the sync from a single blocking task to avoid a
+        // second round trip through the thread pool.
+        unblock(move || -> Result<_, std::io::Error> {
+            if validate_size {
+                check_file_size(&file, offset, len as u64);
+            }
+            let _io = io_depth.enter();
+            // Deallocate all-zero ranges rather than writing them, so that
+            // sparse files stay sparse.
+            let zero = punch_zero_writes && len >= zero::MIN_SCAN_LEN && mem.is_zero();
+            let n = size.with_range(offset, len as u64, || {
+                if zero {
+                    punch_hole::punch_hole(&file, offset, len as u64)?;
+                    return Ok(len);
+                }
+                match &mem {
+                    #[cfg(target_os = "linux")]
+                    IoMemory::Locked(locked) => file.writev_at(locked.io_vecs(), offset),
+                    IoMemory::Bounce(buffer) => file.write_at(buffer, offset),
+                }
+            })?;
+            if n != len {
+                return Err(std::io::ErrorKind::WriteZero.into());
+            }
+            if fua {
+                flush_mode.sync(&*file)?;
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    /// Writes `mem` at `offset` through native IO.
+    async fn write_native(
+        &self,
+        native: &native::NativeIo,
+        mut mem: IoMemory,
+        offset: u64,
+        len: usize,
+        fua: bool,
+    ) -> std::io::Result<()> {
+        // Punching a hole is a blocking call, so all-zero writes still go
+        // through the thread pool.
+        if self.punch_zero_writes && len >= zero::MIN_SCAN_LEN && mem.is_zero() {
+            return self.write_blocking(mem, offset, len, fua).await;
+        }
+        if self.validate_size {
+            check_file_size(&self.file, offset, len as u64);
+        }
+        let _range = self.size.lock_range(offset, len as u64)?;
+        let _io = self.io_depth.enter();
+        let n = native.write(&mut mem, offset, fua, self.flush_mode).await?;
+        if n != len {
+            return Err(std::io::ErrorKind::WriteZero.into());
+        }
         Ok(())
     }
 
-    pub async fn flush(&self) -> Result<(), DiskError> {
+    /// Deallocates `count` sectors starting at `sector`.
+    ///
+    /// On Linux this punches a hole in the file so that the host can reclaim
+    /// the backing space. Elsewhere, the range is overwritten with zeroes.
+    ///
+    /// If `block_level_only`, only the file system blocks that lie entirely
+    /// within the range are deallocated, and nothing is overwritten with
+    /// zeroes, so the partial blocks at either end keep their data, as does
+    /// the whole range where hole punching is not supported.
+    pub async fn unmap(
+        &self,
+        sector: u64,
+        count: u64,
+        block_level_only: bool,
+    ) -> Result<(), DiskError> {
+        if self.metadata.read_only {
+            return Err(DiskError::ReadOnly);
+        }
+        let len = count.saturating_mul(self.metadata.sector_size.into());
+        let offset = self.check_io(sector, len)?;
+        // Unmapped data is not transferred, so this does not take bandwidth.
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.io(0).await;
+        }
         let file = self.file.clone();
-        unblock(move || file.sync_all())
-            .await
-            .map_err(DiskError::Io)?;
+        let size = self.size.clone();
+        let integrity = self.integrity.clone();
+        let result = unblock(move || {
+            size.with_range(offset, len, || {
+                let punch = || {
+                    if block_level_only {
+                        punch_hole::punch_whole_blocks(&file, offset, len)
+                    } else {
+                        punch_hole::punch_hole(&file, offset, len)?;
+                        Ok(offset..offset + len)
+                    }
+                };
+                match &integrity {
+                    Some(integrity) => integrity.locked(|| {
+                        let range = punch()?;
+                        if range.is_empty() {
+                            return Ok(());
+                        }
+                        integrity.update(
+                            &file,
+                            range.start,
+                            range.end - range.start,
+                            size.bytes.load(Ordering::Relaxed),
+                        )
+                    }),
+                    None => punch().map(drop),
+                }
+            })
+        })
+        .await;
+        if let Some(read_ahead) = &self.read_ahead {
+            read_ahead.invalidate(offset, len);
+        }
+        result.map_err(DiskError::Io)?;
+        Ok(())
+    }
+
+    /// Resizes the disk to `new_size` bytes, truncating or extending the file.
+    ///
+    /// `new_size` must be a non-zero multiple of the sector size.
+    ///
+    /// Resizes are serialized with IO: each IO is performed entirely against
+    /// either the old or the new size, and an IO that no longer fits within
+    /// the disk after it is shrunk fails.
+    pub async fn resize(&self, new_size: u64) -> Result<(), DiskError> {
+        if self.metadata.read_only {
+            return Err(DiskError::ReadOnly);
+        }
+        if new_size == 0 || new_size % self.metadata.sector_size as u64 != 0 {
+            return Err(DiskError::InvalidInput);
+        }
+        let file = self.file.clone();
+        let size = self.size.clone();
+        let integrity = self.integrity.clone();
+        let result = unblock(move || -> Result<_, std::io::Error> {
+            let _guard = size.lock.write();
+            file.set_len(new_size)?;
+            let old_size = size.bytes.swap(new_size, Ordering::Relaxed);
+            if let Some(integrity) = &integrity {
+                integrity.locked(|| integrity.resize(&file, old_size, new_size))?;
+            }
+            Ok(())
+        })
+        .await;
+        if let Some(read_ahead) = &self.read_ahead {
+            read_ahead.invalidate_all();
+        }
+        result.map_err(DiskError::Io)?;
+        self.resize_event.notify(usize::MAX);
+        Ok(())
+    }
+
+    /// Creates a writable copy of the disk in a new file at `dest`, which
+    /// must not already exist, and opens it.
+    ///
+    /// The disk is flushed first. Where the file system supports it (e.g.
+    /// btrfs and XFS on Linux, APFS on macOS), the copy is a reflink that
+    /// shares storage with this disk until either is written, so it is
+    /// created almost instantly. Otherwise, the data is copied.
+    ///
+    /// The new disk has the same sector sizes as this one, but is otherwise
+    /// opened with the default options.
+    pub async fn clone_to(&self, dest: &Path) -> Result<FileDisk, DiskError> {
+        self.flush().await?;
+        let file = self.file.clone();
+        let size = self.size.clone();
+        let dest = dest.to_owned();
+        let clone = unblock(move || {
+            // Block resizes so that the copy is of a consistent size.
+            let _guard = size.lock.read();
+            reflink::clone_file(&file, &dest, size.bytes.load(Ordering::Relaxed))
+        })
+        .await
+        .map_err(DiskError::Io)?;
+        Self::open_inner(
+            clone,
+            false,
+            Some((
+                self.metadata.sector_size,
+                self.metadata.physical_sector_size,
+            )),
+        )
+        .map_err(DiskError::Io)
+    }
+
+    /// Flushes the file to stable storage.
+    ///
+    /// If flush coalescing is enabled, this may wait for a sync issued on
+    /// behalf of a concurrent flush instead of issuing its own.
+    ///
+    /// With integrity checking, the checksums are synced as well.
+    pub async fn flush(&self) -> Result<(), DiskError> {
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.flush().await;
+        }
+        if let Some(coalescer) = &self.flush_coalescer {
+            if coalescer
+                .flush(&self.file, self.flush_mode)
+                .await
+                .map_err(DiskError::Io)?
+            {
+                self.syncs.increment();
+            }
+        } else {
+            let file = self.file.clone();
+            let flush_mode = self.flush_mode;
+            unblock(move || flush_mode.sync(&*file))
+                .await
+                .map_err(DiskError::Io)?;
+            self.syncs.increment();
+        }
+        if let Some(integrity) = &self.integrity {
+            let integrity = integrity.clone();
+            unblock(move || integrity.flush())
+                .await
+                .map_err(DiskError::Io)?;
+        }
         Ok(())
     }
 }
 
+/// Warns if `len` bytes at `offset` extend past the current end of `file`,
+/// which indicates that the file was resized by something other than the
+/// disk.
+fn check_file_size(file: &fs::File, offset: u64, len: u64) {
+    match block_device::file_size(file) {
+        Ok(file_size) => {
+            if offset + len > file_size {
+                tracing::warn!(
+                    offset,
+                    len,
+                    file_size,
+                    "io beyond the end of the file, was it truncated?"
+                );
+            }
+        }
+        Err(err) => {
+            tracing::warn!(
+                error = &err as &dyn std::error::Error,
+                "failed to query file size"
+            );
+        }
+    }
+}
+
+/// The current size of the disk.
+#[derive(Debug)]
+struct DiskSize {
+    bytes: AtomicU64,
+    /// Held for read across each IO, and for write across each resize.
+    lock: RwLock<()>,
+}
+
+impl DiskSize {
+    /// Runs `f` with resizes blocked, failing if `len` bytes at `offset` are
+    /// no longer within the disk.
+    fn with_range<R, E: From<std::io::Error>>(
+        &self,
+        offset: u64,
+        len: u64,
+        f: impl FnOnce() -> Result<R, E>,
+    ) -> Result<R, E> {
+        let _guard = self.lock.read();
+        if offset + len > self.bytes.load(Ordering::Relaxed) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "io beyond the end of the resized disk",
+            )
+            .into());
+        }
+        f()
+    }
+}
+
+/// The number of reads and writes issued to the file that have not yet
+/// completed.
+#[derive(Debug, Default, Inspect)]
+struct IoDepth {
+    current: AtomicU64,
+    /// The most IOs that have been in flight at once.
+    peak: AtomicU64,
+}
+
+impl IoDepth {
+    /// Counts an IO as in flight until the returned guard is dropped.
+    fn enter(&self) -> IoDepthGuard<'_> {
+        let current = self.current.fetch_add(1, Ordering::Relaxed) + 1;
+        self.peak.fetch_max(current, Ordering::Relaxed);
+        IoDepthGuard(self)
+    }
+}
+
+struct IoDepthGuard<'a>(&'a IoDepth);
+
+impl Drop for IoDepthGuard<'_> {
+    fn drop(&mut self) {
+        self.0.current.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// The memory that an IO is issued against.
+enum IoMemory {
+    /// Guest memory, locked for the duration of the IO.
+    #[cfg(target_os = "linux")]
+    Locked(LockedIoBuffers),
+    /// A bounce buffer that is copied to or from guest memory.
+    Bounce(AlignedBuffer),
+}
+
+impl IoMemory {
+    /// Returns true if the memory contains only zeroes.
+    fn is_zero(&self) -> bool {
+        match self {
+            #[cfg(target_os = "linux")]
+            IoMemory::Locked(locked) => locked
+                .io_vecs()
+                .iter()
+                .all(|buffer| zero::is_zero_atomic(buffer)),
+            IoMemory::Bounce(buffer) => zero::is_zero(buffer),
+        }
+    }
+}
+
 impl SimpleDisk for FileDisk {
     fn disk_type(&self) -> &str {
         "file"
     }
 
     fn sector_count(&self) -> u64 {
-        self.metadata.disk_size >> self.sector_shift
+        self.size.bytes.load(Ordering::Relaxed) >> self.sector_shift
     }
 
     fn sector_size(&self) -> u32 {
@@ -148,7 +1102,7 @@ fn is_read_only(&self) -> bool {
     }
 
     fn disk_id(&self) -> Option<[u8; 16]> {
-        None
+        self.disk_id
     }
 
     fn physical_sector_size(&self) -> u32 {
@@ -156,7 +1110,15 @@ fn physical_sector_size(&self) -> u32 {
     }
 
     fn is_fua_respected(&self) -> bool {
-        false
+        true
+    }
+
+    fn unmap(&self) -> Option<&dyn Unmap> {
+        Some(self)
+    }
+
+    fn resize(&self) -> Option<&dyn Resize> {
+        Some(self)
     }
 }
 
@@ -181,4 +1143,583 @@ fn write_vectored<'a>(
     fn sync_cache(&self) -> StackFuture<'_, Result<(), DiskError>, { ASYNC_DISK_STACK_SIZE }> {
         StackFuture::from(self.flush())
     }
+
+    fn wait_resize<'a>(
+        &'a self,
+        sector_count: u64,
+    ) -> Pin<Box<dyn 'a + Send + Future<Output = u64>>> {
+        Box::pin(async move {
+            loop {
+                let listen = self.resize_event.listen();
+                let current = self.sector_count();
+                if current != sector_count {
+                    break current;
+                }
+                listen.await;
+            }
+        })
+    }
+}
+
+impl Unmap for FileDisk {
+    fn unmap(
+        &self,
+        sector_offset: u64,
+        sector_count: u64,
+        block_level_only: bool,
+    ) -> StackFuture<'_, Result<(), DiskError>, { ASYNC_DISK_STACK_SIZE }> {
+        StackFuture::from(async move {
+            self.unmap(sector_offset, sector_count, block_level_only)
+                .await
+        })
+    }
+
+    fn optimal_unmap_sectors(&self) -> u32 {
+        1
+    }
+}
+
+impl Resize for FileDisk {
+    fn resize(
+        &self,
+        sector_count: u64,
+    ) -> StackFuture<'_, Result<(), DiskError>, { ASYNC_DISK_STACK_SIZE }> {
+        StackFuture::from(async move {
+            let new_size = sector_count
+                .checked_mul(self.metadata.sector_size.into())
+                .ok_or(DiskError::InvalidInput)?;
+            self.resize(new_size).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FileDisk;
+    use super::OpenError;
+    use super::RateLimit;
+    use super::ReadAheadConfig;
+    use crate::readwriteat::ReadWriteAt;
+    use disk_backend::AsyncDisk;
+    use disk_backend::DiskError;
+    use disk_backend::SimpleDisk;
+    use guestmem::GuestMemory;
+    use pal_async::async_test;
+    use pal_async::DefaultDriver;
+    use scsi_buffers::OwnedRequestBuffers;
+    use std::num::NonZeroU64;
+    #[cfg(target_os = "linux")]
+    use std::sync::atomic::Ordering;
+    #[cfg(target_os = "linux")]
+    use std::sync::Arc;
+    use std::time::Duration;
+    use std::time::Instant;
+
+    #[async_test]
+    async fn fua_write() {
+        let file = tempfile::tempfile().unwrap();
+        file.set_len(0x10000).unwrap();
+        let disk = FileDisk::open(file.try_clone().unwrap(), false).unwrap();
+        assert!(disk.is_fua_respected());
+
+        let mem = GuestMemory::allocate(0x1000);
+        mem.write_at(0, &[0xa5; 0x1000]).unwrap();
+        let buffers = OwnedRequestBuffers::linear(0, 0x1000, false);
+
+        disk.write_vectored(&buffers.buffer(&mem), 8, false)
+            .await
+            .unwrap();
+        assert_eq!(disk.syncs.get(), 0);
+
+        disk.write_vectored(&buffers.buffer(&mem), 16, true)
+            .await
+            .unwrap();
+        assert_eq!(disk.syncs.get(), 1);
+
+        let mut data = vec![0; 0x1000];
+        file.read_at(&mut data, 16 * 512).unwrap();
+        assert!(data.iter().all(|&b| b == 0xa5));
+    }
+
+    #[async_test]
+    async fn coalesced_flushes(driver: DefaultDriver) {
+        let file = tempfile::tempfile().unwrap();
+        file.set_len(0x10000).unwrap();
+        let disk =
+            FileDisk::open_coalescing(file, false, Duration::from_millis(10), driver).unwrap();
+
+        futures::future::try_join_all((0..100).map(|_| disk.sync_cache()))
+            .await
+            .unwrap();
+        let syncs = disk.syncs.get();
+        assert!((1..10).contains(&syncs), "{syncs} syncs");
+
+        // A flush after the batch completes must issue a new sync.
+        disk.sync_cache().await.unwrap();
+        assert_eq!(disk.syncs.get(), syncs + 1);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[async_test]
+    async fn zero_write_deallocates() {
+        use std::os::unix::fs::MetadataExt;
+
+        let file = tempfile::tempfile().unwrap();
+        file.set_len(0x10000).unwrap();
+        let mut disk = FileDisk::open(file.try_clone().unwrap(), false).unwrap();
+
+        let mem = GuestMemory::allocate(0x10000);
+        mem.write_at(0, &[0xa5; 0x10000]).unwrap();
+        let buffers = OwnedRequestBuffers::linear(0, 0x10000, false);
+        disk.write_vectored(&buffers.buffer(&mem), 0, true)
+            .await
+            .unwrap();
+        let allocated = file.metadata().unwrap().blocks();
+        assert!(allocated > 0);
+
+        mem.fill_at(0, 0, 0x10000).unwrap();
+        disk.write_vectored(&buffers.buffer(&mem), 0, true)
+            .await
+            .unwrap();
+        let blocks = file.metadata().unwrap().blocks();
+        assert!(blocks < allocated, "{blocks} >= {allocated}");
+
+        let mut data = vec![0xff; 0x10000];
+        file.read_at(&mut data, 0).unwrap();
+        assert!(data.iter().all(|&b| b == 0));
+
+        // With hole punching disabled, the zeroes are written.
+        disk.set_punch_zero_writes(false);
+        disk.write_vectored(&buffers.buffer(&mem), 0, true)
+            .await
+            .unwrap();
+        let blocks = file.metadata().unwrap().blocks();
+        assert!(blocks >= allocated, "{blocks} < {allocated}");
+    }
+
+    #[async_test]
+    async fn read_ahead() {
+        let file = tempfile::tempfile().unwrap();
+        let data: Vec<u8> = (0..0x10000 / 512).flat_map(|i| [i as u8; 512]).collect();
+        file.write_at(&data, 0).unwrap();
+        let disk = FileDisk::open_read_ahead(
+            file,
+            false,
+            ReadAheadConfig {
+                window: 0x4000,
+                cap: 0x8000,
+            },
+        )
+        .unwrap();
+        let read_ahead = disk.read_ahead.as_ref().unwrap();
+
+        let mem = GuestMemory::allocate(0x1000);
+        let buffers = OwnedRequestBuffers::linear(0, 0x1000, true);
+        let read = |sector: u64| {
+            let disk = &disk;
+            let mem = &mem;
+            let buffers = &buffers;
+            async move {
+                disk.read_vectored(&buffers.buffer(mem), sector)
+                    .await
+                    .unwrap();
+                let mut buf = vec![0; 0x1000];
+                mem.read_at(0, &mut buf).unwrap();
+                buf
+            }
+        };
+
+        // The second sequential read prefetches the next 0x4000 bytes, which
+        // serve the following four reads.
+        for sector in (0..0x60).step_by(8) {
+            assert_eq!(read(sector).await, data[sector as usize * 512..][..0x1000]);
+        }
+        assert_eq!(read_ahead.hits(), 8);
+        assert_eq!(read_ahead.misses(), 4);
+
+        // A write invalidates the cached chunk that it overlaps.
+        mem.write_at(0, &[0xff; 0x1000]).unwrap();
+        let write_buffers = OwnedRequestBuffers::linear(0, 0x1000, false);
+        disk.write_vectored(&write_buffers.buffer(&mem), 0x68, false)
+            .await
+            .unwrap();
+        let misses = read_ahead.misses();
+        assert_eq!(read(0x68).await, [0xff; 0x1000]);
+        assert_eq!(read_ahead.misses(), misses + 1);
+
+        // Other chunks are unaffected.
+        let hits = read_ahead.hits();
+        assert_eq!(read(0x40).await, data[0x40 * 512..][..0x1000]);
+        assert_eq!(read_ahead.hits(), hits + 1);
+    }
+
+    #[async_test]
+    async fn out_of_range() {
+        let file = tempfile::tempfile().unwrap();
+        file.set_len(0x10000).unwrap();
+        let disk = FileDisk::open(file, false).unwrap();
+
+        let mem = GuestMemory::allocate(0x1000);
+        let buffers = OwnedRequestBuffers::linear(0, 0x1000, true);
+        let err = disk
+            .read_vectored(&buffers.buffer(&mem), 0x7f)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, DiskError::OutOfRange { sector: 0x7f, .. }));
+
+        let err = disk
+            .read_vectored(&buffers.buffer(&mem), u64::MAX)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, DiskError::OutOfRange { .. }));
+
+        let buffers = OwnedRequestBuffers::linear(0, 0x100, true);
+        let err = disk
+            .read_vectored(&buffers.buffer(&mem), 0)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, DiskError::InvalidInput));
+    }
+
+    #[test]
+    fn sector_sizes() {
+        let open = |sector_size, physical_sector_size| {
+            let file = tempfile::tempfile().unwrap();
+            file.set_len(0x10000).unwrap();
+            FileDisk::open_with_sector_sizes(file, false, sector_size, physical_sector_size)
+        };
+
+        let disk = open(4096, 4096).unwrap();
+        assert_eq!(disk.sector_size(), 4096);
+        assert_eq!(disk.physical_sector_size(), 4096);
+        assert_eq!(disk.sector_count(), 16);
+
+        assert!(matches!(
+            open(256, 4096),
+            Err(OpenError::InvalidSectorSize(256))
+        ));
+        assert!(matches!(
+            open(1000, 4096),
+            Err(OpenError::InvalidSectorSize(1000))
+        ));
+        assert!(matches!(
+            open(4096, 512),
+            Err(OpenError::InvalidPhysicalSectorSize { .. })
+        ));
+    }
+
+    /// Opens an attached loop device, if there is one, to check that the size
+    /// of a block device is discovered.
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn block_device() {
+        let Some(file) = (0..8).find_map(|i| {
+            let size = std::fs::read_to_string(format!("/sys/block/loop{i}/size")).ok()?;
+            if size.trim() == "0" {
+                return None;
+            }
+            std::fs::File::open(format!("/dev/loop{i}")).ok()
+        }) else {
+            tracing::info!("skipping, no attached loop device");
+            return;
+        };
+        let disk = FileDisk::open(file, true).unwrap();
+        assert!(disk.sector_count() > 0);
+        assert!(disk.sector_size() >= 512);
+        assert!(disk.physical_sector_size() >= disk.sector_size());
+        assert!(!disk.punch_zero_writes);
+    }
+
+    #[test]
+    fn disk_id() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        file.as_file().set_len(0x10000).unwrap();
+        let disk1 = FileDisk::open(file.reopen().unwrap(), false).unwrap();
+        let disk2 = FileDisk::open(file.reopen().unwrap(), true).unwrap();
+        assert!(disk1.disk_id().is_some());
+        assert_eq!(disk1.disk_id(), disk2.disk_id());
+
+        let other = FileDisk::open(tempfile::tempfile().unwrap(), false).unwrap();
+        assert_ne!(disk1.disk_id(), other.disk_id());
+    }
+
+    #[async_test]
+    async fn read_only() {
+        let file = tempfile::tempfile().unwrap();
+        file.set_len(0x10000).unwrap();
+        file.write_at(&[0x5a; 0x10000], 0).unwrap();
+        let disk = FileDisk::open(file.try_clone().unwrap(), true).unwrap();
+
+        let mem = GuestMemory::allocate(0x1000);
+        let buffers = OwnedRequestBuffers::linear(0, 0x1000, false);
+        let err = disk
+            .write_vectored(&buffers.buffer(&mem), 0, false)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, DiskError::ReadOnly));
+        let err = disk.unmap(0, 8, false).await.unwrap_err();
+        assert!(matches!(err, DiskError::ReadOnly));
+
+        let mut data = vec![0; 0x10000];
+        file.read_at(&mut data, 0).unwrap();
+        assert!(data.iter().all(|&b| b == 0x5a));
+    }
+
+    #[async_test]
+    async fn unmap_block_level_only() {
+        let file = tempfile::tempfile().unwrap();
+        #[cfg(unix)]
+        let block_size = {
+            use std::os::unix::prelude::*;
+            file.metadata().unwrap().blksize() as usize
+        };
+        #[cfg(not(unix))]
+        let block_size = 0x1000;
+        let len = block_size * 4;
+        file.write_at(&vec![0x5a; len], 0).unwrap();
+        let disk = FileDisk::open(file.try_clone().unwrap(), false).unwrap();
+        let sector_size = disk.sector_size() as usize;
+        let count = (len / sector_size - 2) as u64;
+
+        // Only the whole blocks in the middle may be deallocated, and the
+        // partial blocks at either end keep their data.
+        disk.unmap(1, count, true).await.unwrap();
+        let mut data = vec![0; len];
+        file.read_at(&mut data, 0).unwrap();
+        assert!(data[..block_size].iter().all(|&b| b == 0x5a));
+        assert!(data[len - block_size..].iter().all(|&b| b == 0x5a));
+
+        // Otherwise, the whole range reads back as zeroes.
+        disk.unmap(1, count, false).await.unwrap();
+        file.read_at(&mut data, 0).unwrap();
+        assert!(data[..sector_size].iter().all(|&b| b == 0x5a));
+        assert!(data[sector_size..len - sector_size].iter().all(|&b| b == 0));
+        assert!(data[len - sector_size..].iter().all(|&b| b == 0x5a));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[async_test]
+    async fn native_concurrency() {
+        const READS: usize = 256;
+
+        let pool = match pal_uring::IoUringPool::new("test", 64) {
+            Ok(pool) => pool,
+            Err(err) => {
+                tracing::info!(
+                    error = &err as &dyn std::error::Error,
+                    "skipping, io_uring unavailable"
+                );
+                return;
+            }
+        };
+        let initiator: Arc<dyn pal_uring::Initiate> = Arc::new(pool.client().initiator().clone());
+
+        let file = tempfile::tempfile().unwrap();
+        let data: Vec<u8> = (0..READS).flat_map(|i| [i as u8; 0x1000]).collect();
+        file.write_at(&data, 0).unwrap();
+        let disk = FileDisk::open_native(file, true, initiator).unwrap();
+        assert!(disk.native.is_some());
+
+        // Issue a page-sized read for each page of the file, all at once.
+        let mem = GuestMemory::allocate(data.len());
+        let mut reads = std::pin::pin!(futures::future::join_all((0..READS).map(|i| {
+            let disk = &disk;
+            let mem = &mem;
+            async move {
+                let buffers = OwnedRequestBuffers::new(&[i as u64]);
+                disk.read_vectored(&buffers.buffer(mem), i as u64 * 8)
+                    .await
+                    .unwrap();
+            }
+        })));
+
+        // No read can complete until the pool runs, so every read is in
+        // flight at once, which the blocking thread pool could not do.
+        assert!(futures::poll!(reads.as_mut()).is_pending());
+        assert_eq!(disk.io_depth.current.load(Ordering::Relaxed), READS as u64);
+
+        std::thread::spawn(|| pool.run());
+        reads.await;
+        assert_eq!(disk.io_depth.current.load(Ordering::Relaxed), 0);
+        assert_eq!(disk.io_depth.peak.load(Ordering::Relaxed), READS as u64);
+
+        let mut buf = vec![0; data.len()];
+        mem.read_at(0, &mut buf).unwrap();
+        assert!(buf == data);
+    }
+
+    #[async_test]
+    async fn integrity() {
+        let file = tempfile::tempfile().unwrap();
+        file.write_at(&[0x5a; 0x10000], 0).unwrap();
+        let sidecar = tempfile::tempfile().unwrap();
+        let disk = FileDisk::open_integrity(
+            file.try_clone().unwrap(),
+            false,
+            sidecar.try_clone().unwrap(),
+        )
+        .unwrap();
+        // One checksum per 4K physical sector.
+        assert_eq!(sidecar.metadata().unwrap().len(), 64);
+
+        // A single-sector write only covers part of a checksummed block.
+        let mem = GuestMemory::allocate(0x1000);
+        mem.write_at(0, &[0xa5; 0x200]).unwrap();
+        let buffers = OwnedRequestBuffers::linear(0, 0x200, false);
+        disk.write_vectored(&buffers.buffer(&mem), 0x11, false)
+            .await
+            .unwrap();
+        disk.sync_cache().await.unwrap();
+
+        let buffers = OwnedRequestBuffers::linear(0, 0x1000, true);
+        disk.read_vectored(&buffers.buffer(&mem), 0x10)
+            .await
+            .unwrap();
+        let mut data = vec![0; 0x1000];
+        mem.read_at(0, &mut data).unwrap();
+        assert!(data[..0x200].iter().all(|&b| b == 0x5a));
+        assert!(data[0x200..0x400].iter().all(|&b| b == 0xa5));
+        assert!(data[0x400..].iter().all(|&b| b == 0x5a));
+
+        // Flip a byte behind the disk's back.
+        file.write_at(&[0x5b], 0x2345).unwrap();
+        let err = disk
+            .read_vectored(&buffers.buffer(&mem), 0x10)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err, DiskError::IntegrityCheckFailed { sector: 0x10 }),
+            "{err:?}"
+        );
+
+        // Reads of other blocks still succeed.
+        disk.read_vectored(&buffers.buffer(&mem), 0x18)
+            .await
+            .unwrap();
+
+        // The checksums persist across reopening the disk.
+        drop(disk);
+        let disk = FileDisk::open_integrity(file, true, sidecar).unwrap();
+        let err = disk
+            .read_vectored(&buffers.buffer(&mem), 0x10)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, DiskError::IntegrityCheckFailed { .. }));
+    }
+
+    #[async_test]
+    async fn rate_limit(driver: DefaultDriver) {
+        const RATE: u64 = 0x100000;
+
+        let file = tempfile::tempfile().unwrap();
+        file.set_len(0x100000).unwrap();
+        let limit = RateLimit {
+            bytes_per_second: NonZeroU64::new(RATE),
+            ..Default::default()
+        };
+        let disk = FileDisk::open_rate_limited(file, false, limit, driver).unwrap();
+        assert_eq!(disk.rate_limit(), limit);
+
+        // The first second's worth of reads is served from the full bucket,
+        // and the rest must wait for tokens.
+        let mem = GuestMemory::allocate(0x10000);
+        let buffers = OwnedRequestBuffers::linear(0, 0x10000, true);
+        let start = Instant::now();
+        let mut bytes = 0;
+        for i in 0..24 {
+            disk.read_vectored(&buffers.buffer(&mem), (i % 16) * 0x80)
+                .await
+                .unwrap();
+            bytes += 0x10000;
+        }
+        let elapsed = start.elapsed();
+        assert!(
+            bytes <= RATE + (RATE as f64 * elapsed.as_secs_f64()) as u64,
+            "{bytes} bytes in {elapsed:?}"
+        );
+
+        // Flushes do not wait for bandwidth.
+        disk.sync_cache().await.unwrap();
+
+        // Removing the limit takes effect immediately.
+        disk.set_rate_limit(RateLimit::default());
+        let start = Instant::now();
+        for _ in 0..24 {
+            disk.read_vectored(&buffers.buffer(&mem), 0).await.unwrap();
+        }
+        assert!(start.elapsed() < elapsed);
+    }
+
+    #[async_test]
+    async fn resize() {
+        let file = tempfile::tempfile().unwrap();
+        file.set_len(0x10000).unwrap();
+        let disk = FileDisk::open(file.try_clone().unwrap(), false).unwrap();
+        assert_eq!(disk.sector_count(), 0x80);
+
+        disk.resize(0x20000).await.unwrap();
+        assert_eq!(disk.sector_count(), 0x100);
+        assert_eq!(file.metadata().unwrap().len(), 0x20000);
+        assert_eq!(disk.wait_resize(0x80).await, 0x100);
+
+        disk.resize(0x8000).await.unwrap();
+        assert_eq!(disk.sector_count(), 0x40);
+        assert_eq!(file.metadata().unwrap().len(), 0x8000);
+
+        let mem = GuestMemory::allocate(0x1000);
+        let buffers = OwnedRequestBuffers::linear(0, 0x1000, true);
+        let err = disk
+            .read_vectored(&buffers.buffer(&mem), 0x40)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, DiskError::OutOfRange { .. }));
+
+        assert!(matches!(
+            disk.resize(0x8001).await,
+            Err(DiskError::InvalidInput)
+        ));
+    }
+
+    #[async_test]
+    async fn clone_to() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = tempfile::tempfile().unwrap();
+        let data: Vec<u8> = (0..0x10000 / 512).flat_map(|i| [i as u8; 512]).collect();
+        file.write_at(&data, 0).unwrap();
+        let disk = FileDisk::open(file, false).unwrap();
+
+        // The clone is a reflink if the temporary directory's file system
+        // supports it, and a copy otherwise. Either way, it must behave as an
+        // independent copy of the disk.
+        let path = dir.path().join("clone.img");
+        let clone = disk.clone_to(&path).await.unwrap();
+        assert_eq!(clone.sector_count(), disk.sector_count());
+        assert!(!clone.is_read_only());
+        assert!(matches!(
+            disk.clone_to(&path).await,
+            Err(DiskError::Io(err)) if err.kind() == std::io::ErrorKind::AlreadyExists
+        ));
+
+        let mem = GuestMemory::allocate(0x10000);
+        let buffers = OwnedRequestBuffers::linear(0, 0x10000, true);
+        clone.read_vectored(&buffers.buffer(&mem), 0).await.unwrap();
+        let mut read = vec![0; 0x10000];
+        mem.read_at(0, &mut read).unwrap();
+        assert_eq!(read, data);
+
+        // Writes to the clone do not affect the source.
+        mem.fill_at(0, 0xff, 0x1000).unwrap();
+        let buffers = OwnedRequestBuffers::linear(0, 0x1000, false);
+        clone
+            .write_vectored(&buffers.buffer(&mem), 8, false)
+            .await
+            .unwrap();
+        let file = disk.into_inner();
+        let mut read = vec![0; 0x1000];
+        file.read_at(&mut read, 0x1000).unwrap();
+        assert_eq!(read, data[0x1000..0x2000]);
+        let clone = clone.into_inner();
+        clone.read_at(&mut read, 0x1000).unwrap();
+        assert!(read.iter().all(|&b| b == 0xff));
+    }
 }
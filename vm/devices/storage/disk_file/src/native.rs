@@ -0,0 +1,307 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Native asynchronous file IO.
+//!
+//! By default, each file disk IO runs on a thread from the blocking thread
+//! pool, so the number of IOs in flight is bounded by the size of the pool.
+//! With native IO, reads and writes are instead issued through io_uring on
+//! Linux and overlapped IO on Windows, and any number of them can be in flight
+//! at once.
+
+// UNSAFETY: Issuing io_uring IOs that reference buffers on the stack,
+// reopening Windows file handles, and releasing the disk size lock from a
+// thread other than the one that took it.
+#![allow(unsafe_code)]
+
+use crate::DiskSize;
+use crate::FlushMode;
+use crate::IoMemory;
+#[cfg(any(target_os = "linux", windows))]
+use std::fs;
+use std::io;
+use std::sync::atomic::Ordering;
+#[cfg(any(target_os = "linux", windows))]
+use std::sync::Arc;
+
+/// Issues IO to a file disk's file through the platform's native async IO
+/// interface.
+pub struct NativeIo {
+    #[cfg(target_os = "linux")]
+    uring: Arc<dyn pal_uring::Initiate>,
+    #[cfg(target_os = "linux")]
+    file: Arc<fs::File>,
+    #[cfg(windows)]
+    file: pal_async::windows::overlapped::OverlappedFile,
+    #[cfg(windows)]
+    sync_file: Arc<fs::File>,
+    #[cfg(not(any(target_os = "linux", windows)))]
+    void: std::convert::Infallible,
+}
+
+impl std::fmt::Debug for NativeIo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad("NativeIo")
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl NativeIo {
+    /// Returns a new object for issuing IO to `file` through `uring`, or
+    /// `None` if the ring does not support the necessary operations.
+    pub fn new(file: &Arc<fs::File>, uring: Arc<dyn pal_uring::Initiate>) -> Option<Self> {
+        use io_uring::opcode;
+
+        let initiator = uring.initiator();
+        let supported = [
+            opcode::Read::CODE,
+            opcode::Write::CODE,
+            opcode::Readv::CODE,
+            opcode::Writev::CODE,
+        ]
+        .into_iter()
+        .all(|code| initiator.probe(code));
+        supported.then(|| Self {
+            uring,
+            file: file.clone(),
+        })
+    }
+
+    /// Reads into `mem` from `offset`, returning the number of bytes read.
+    pub async fn read(&self, mem: &mut IoMemory, offset: u64) -> io::Result<usize> {
+        use io_uring::opcode;
+        use io_uring::types;
+        use std::os::unix::prelude::*;
+
+        let fd = types::Fd(self.file.as_raw_fd());
+        let sqe = match mem {
+            IoMemory::Locked(locked) => {
+                let io_vecs = locked.io_vecs();
+                opcode::Readv::new(fd, io_vecs.as_ptr().cast(), io_vecs.len() as u32)
+                    .offset(offset as _)
+                    .build()
+            }
+            IoMemory::Bounce(buffer) => {
+                opcode::Read::new(fd, buffer.as_mut_ptr(), buffer.len() as u32)
+                    .offset(offset as _)
+                    .build()
+            }
+        };
+        // SAFETY: the IO only references `mem`, which is borrowed until the
+        // IO completes, since the IO is awaited immediately.
+        let (r, ()) = unsafe { self.uring.initiator().issue_io((), |_| sqe) }.await;
+        Ok(r? as usize)
+    }
+
+    /// Writes `mem` at `offset`, returning the number of bytes written.
+    ///
+    /// If `fua` is set, the data is written through to stable storage as if
+    /// synced with `flush_mode`.
+    pub async fn write(
+        &self,
+        mem: &mut IoMemory,
+        offset: u64,
+        fua: bool,
+        flush_mode: FlushMode,
+    ) -> io::Result<usize> {
+        use io_uring::opcode;
+        use io_uring::types;
+        use std::os::unix::prelude::*;
+
+        // Not defined by libc for musl targets.
+        const RWF_DSYNC: types::RwFlags = 0x00000002;
+        const RWF_SYNC: types::RwFlags = 0x00000004;
+
+        let fd = types::Fd(self.file.as_raw_fd());
+        let flags = match (fua, flush_mode) {
+            (false, _) => 0,
+            (true, FlushMode::SyncAll) => RWF_SYNC,
+            (true, FlushMode::SyncData) => RWF_DSYNC,
+        };
+        let sqe = match &*mem {
+            IoMemory::Locked(locked) => {
+                let io_vecs = locked.io_vecs();
+                opcode::Writev::new(fd, io_vecs.as_ptr().cast(), io_vecs.len() as u32)
+                    .offset(offset as _)
+                    .rw_flags(flags)
+                    .build()
+            }
+            IoMemory::Bounce(buffer) => {
+                opcode::Write::new(fd, buffer.as_ptr(), buffer.len() as u32)
+                    .offset(offset as _)
+                    .rw_flags(flags)
+                    .build()
+            }
+        };
+        // SAFETY: the IO only references `mem`, which is borrowed until the
+        // IO completes, since the IO is awaited immediately.
+        let (r, ()) = unsafe { self.uring.initiator().issue_io((), |_| sqe) }.await;
+        Ok(r? as usize)
+    }
+}
+
+/// Reopens `file`, which may have been opened with `FILE_FLAG_OVERLAPPED`, as
+/// a handle for synchronous IO.
+///
+/// Synchronous reads and writes on an overlapped handle can return
+/// `STATUS_PENDING`, which std does not handle, so the disk's blocking IO
+/// paths must not use the overlapped handle.
+#[cfg(windows)]
+pub fn reopen_synchronous(file: &fs::File, read_only: bool) -> io::Result<fs::File> {
+    use std::os::windows::prelude::*;
+    use windows_sys::Win32::Foundation::INVALID_HANDLE_VALUE;
+    use windows_sys::Win32::Storage::FileSystem::ReOpenFile;
+    use windows_sys::Win32::Storage::FileSystem::FILE_GENERIC_READ;
+    use windows_sys::Win32::Storage::FileSystem::FILE_GENERIC_WRITE;
+    use windows_sys::Win32::Storage::FileSystem::FILE_SHARE_DELETE;
+    use windows_sys::Win32::Storage::FileSystem::FILE_SHARE_READ;
+    use windows_sys::Win32::Storage::FileSystem::FILE_SHARE_WRITE;
+
+    let access = if read_only {
+        FILE_GENERIC_READ
+    } else {
+        FILE_GENERIC_READ | FILE_GENERIC_WRITE
+    };
+    // SAFETY: the handle is owned by `file` and is valid for the duration of
+    // the call.
+    let handle = unsafe {
+        ReOpenFile(
+            file.as_raw_handle() as _,
+            access,
+            FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE,
+            0,
+        )
+    };
+    if handle == INVALID_HANDLE_VALUE {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: the handle was just opened and is owned by nothing else.
+    Ok(unsafe { fs::File::from_raw_handle(handle as _) })
+}
+
+#[cfg(windows)]
+impl NativeIo {
+    /// Returns a new object for issuing IO to `file` through `driver`.
+    ///
+    /// `file` must have been opened with `FILE_FLAG_OVERLAPPED`, and is only
+    /// used for overlapped IO. `sync_file` is a synchronous handle to the same
+    /// file, used to sync it for FUA writes.
+    pub fn new(
+        file: fs::File,
+        sync_file: &Arc<fs::File>,
+        driver: &(impl ?Sized + pal_async::driver::Driver),
+    ) -> io::Result<Self> {
+        Ok(Self {
+            file: pal_async::windows::overlapped::OverlappedFile::new(driver, file)?,
+            sync_file: sync_file.clone(),
+        })
+    }
+
+    /// Reads into `mem` from `offset`, returning the number of bytes read.
+    pub async fn read(&self, mem: &mut IoMemory, offset: u64) -> io::Result<usize> {
+        let IoMemory::Bounce(buffer) = mem;
+        let (r, read_buffer) = self
+            .file
+            .read_at(offset, std::mem::replace(buffer, AlignedBuffer::new(0)))
+            .await;
+        *buffer = read_buffer;
+        r
+    }
+
+    /// Writes `mem` at `offset`, returning the number of bytes written.
+    ///
+    /// If `fua` is set, the file is then synced with `flush_mode`.
+    pub async fn write(
+        &self,
+        mem: &mut IoMemory,
+        offset: u64,
+        fua: bool,
+        flush_mode: FlushMode,
+    ) -> io::Result<usize> {
+        let IoMemory::Bounce(buffer) = mem;
+        let (r, write_buffer) = self
+            .file
+            .write_at(offset, std::mem::replace(buffer, AlignedBuffer::new(0)))
+            .await;
+        *buffer = write_buffer;
+        let n = r?;
+        if fua {
+            let file = self.sync_file.clone();
+            blocking::unblock(move || flush_mode.sync(&*file)).await?;
+        }
+        Ok(n)
+    }
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+impl NativeIo {
+    pub async fn read(&self, _mem: &mut IoMemory, _offset: u64) -> io::Result<usize> {
+        match self.void {}
+    }
+
+    pub async fn write(
+        &self,
+        _mem: &mut IoMemory,
+        _offset: u64,
+        _fua: bool,
+        _flush_mode: FlushMode,
+    ) -> io::Result<usize> {
+        match self.void {}
+    }
+}
+
+#[cfg(windows)]
+use crate::unbuffered::AlignedBuffer;
+
+// SAFETY: the buffer's storage is on the heap, so the pointers remain stable
+// when the buffer is moved, and they are valid for `len` bytes.
+#[cfg(windows)]
+unsafe impl pal_async::windows::overlapped::IoBuf for AlignedBuffer {
+    fn as_ptr(&self) -> *const u8 {
+        <[u8]>::as_ptr(self)
+    }
+
+    fn len(&self) -> usize {
+        <[u8]>::len(self)
+    }
+}
+
+// SAFETY: as above.
+#[cfg(windows)]
+unsafe impl pal_async::windows::overlapped::IoBufMut for AlignedBuffer {
+    fn as_mut_ptr(&mut self) -> *mut u8 {
+        <[u8]>::as_mut_ptr(self)
+    }
+}
+
+impl DiskSize {
+    /// Blocks resizes until the returned guard is dropped, failing if `len`
+    /// bytes at `offset` are no longer within the disk.
+    ///
+    /// Unlike [`DiskSize::with_range`], the guard can be held across an
+    /// await. It does not wait behind a pending resize, so that an executor
+    /// thread never blocks on IOs that it must itself complete.
+    pub fn lock_range(&self, offset: u64, len: u64) -> io::Result<RangeGuard<'_>> {
+        std::mem::forget(self.lock.read_recursive());
+        let guard = RangeGuard(self);
+        if offset + len > self.bytes.load(Ordering::Relaxed) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "io beyond the end of the resized disk",
+            ));
+        }
+        Ok(guard)
+    }
+}
+
+/// A guard returned by [`DiskSize::lock_range`].
+pub struct RangeGuard<'a>(&'a DiskSize);
+
+impl Drop for RangeGuard<'_> {
+    fn drop(&mut self) {
+        // SAFETY: the read lock was taken and its guard forgotten in
+        // `lock_range`. Unlike the guard, parking_lot's lock itself does not
+        // require that it be released on the thread that took it.
+        unsafe { self.0.lock.force_unlock_read() };
+    }
+}
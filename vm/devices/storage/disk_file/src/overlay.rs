@@ -0,0 +1,375 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A copy-on-write disk that layers a writable overlay file on top of a
+//! read-only base file.
+//!
+//! The overlay file holds the disk's data at the same offsets as the base
+//! file, followed by an allocation bitmap with one bit per [`BLOCK_SIZE`]
+//! block. A set bit means the block has been written and must be read from
+//! the overlay; otherwise it is read from the base.
+
+use crate::file_id;
+use crate::readwriteat::ReadWriteAt;
+use crate::unbuffered::AlignedBuffer;
+use crate::FileDisk;
+use crate::Metadata;
+use crate::OpenError;
+use crate::DEFAULT_PHYSICAL_SECTOR_SIZE;
+use crate::DEFAULT_SECTOR_SIZE;
+use blocking::unblock;
+use disk_backend::resolve::ResolveDiskParameters;
+use disk_backend::resolve::ResolvedSimpleDisk;
+use disk_backend::AsyncDisk;
+use disk_backend::DiskError;
+use disk_backend::SimpleDisk;
+use disk_backend::ASYNC_DISK_STACK_SIZE;
+use disk_backend_resources::FileOverlayDiskHandle;
+use guestmem::MemoryRead;
+use inspect::Inspect;
+use parking_lot::RwLock;
+use scsi_buffers::RequestBuffers;
+use stackfuture::StackFuture;
+use std::fs;
+use vm_resource::declare_static_resolver;
+use vm_resource::kind::DiskHandleKind;
+use vm_resource::ResolveResource;
+
+pub struct FileOverlayDiskResolver;
+declare_static_resolver!(
+    FileOverlayDiskResolver,
+    (DiskHandleKind, FileOverlayDiskHandle)
+);
+
+impl ResolveResource<DiskHandleKind, FileOverlayDiskHandle> for FileOverlayDiskResolver {
+    type Output = ResolvedSimpleDisk;
+    type Error = OpenError;
+
+    fn resolve(
+        &self,
+        rsrc: FileOverlayDiskHandle,
+        input: ResolveDiskParameters<'_>,
+    ) -> Result<Self::Output, Self::Error> {
+        Ok(OverlayDisk::open(rsrc.base, rsrc.overlay, input.read_only)?.into())
+    }
+}
+
+/// The granularity at which sectors are copied from the base to the overlay.
+///
+/// This is part of the overlay file format, so it cannot change without
+/// invalidating existing overlays.
+const BLOCK_SIZE: u64 = 4096;
+const BLOCK_SHIFT: u32 = BLOCK_SIZE.trailing_zeros();
+
+/// A copy-on-write disk backed by a read-only base file and an overlay file
+/// that receives all writes.
+///
+/// Writes are only guaranteed to be durable, and the allocation bitmap
+/// consistent with the data, after a flush or FUA write completes.
+#[derive(Debug, Inspect)]
+pub struct OverlayDisk {
+    base: FileDisk,
+    overlay: FileDisk,
+    /// The offset of the allocation bitmap within the overlay file.
+    bitmap_offset: u64,
+    #[inspect(skip)]
+    bitmap: RwLock<Vec<u8>>,
+    /// Serializes writes that allocate blocks, so that read-modify-write of
+    /// partially written blocks and updates to the persisted bitmap do not
+    /// race with each other.
+    #[inspect(skip)]
+    alloc_lock: futures::lock::Mutex<()>,
+    disk_id: [u8; 16],
+}
+
+impl OverlayDisk {
+    /// Opens an overlay disk.
+    ///
+    /// If `overlay` is empty, it is initialized as an overlay of `base` with no
+    /// written sectors. Otherwise, it must be an overlay previously created
+    /// for a base of the same size.
+    pub fn open(base: fs::File, overlay: fs::File, read_only: bool) -> Result<Self, OpenError> {
+        let base = FileDisk::open(base, true).map_err(OpenError::Io)?;
+        let disk_size = base.metadata.disk_size;
+        let bitmap_len = disk_size.div_ceil(BLOCK_SIZE).div_ceil(8);
+        let overlay_len = overlay.metadata().map_err(OpenError::Io)?.len();
+        let mut bitmap = vec![0; bitmap_len as usize];
+        if overlay_len == 0 && !read_only {
+            overlay
+                .set_len(disk_size + bitmap_len)
+                .map_err(OpenError::Io)?;
+        } else if overlay_len == disk_size + bitmap_len {
+            let n = overlay
+                .read_at(&mut bitmap, disk_size)
+                .map_err(OpenError::Io)?;
+            if n != bitmap.len() {
+                return Err(OpenError::Io(std::io::ErrorKind::UnexpectedEof.into()));
+            }
+        } else {
+            return Err(OpenError::InvalidOverlaySize {
+                overlay_size: overlay_len,
+                disk_size,
+            });
+        }
+
+        // The overlay's identity distinguishes it from other overlays of the
+        // same base.
+        let disk_id = file_id::disk_id(&overlay).map_err(OpenError::Io)?;
+        let overlay = FileDisk::with_metadata(
+            overlay,
+            Metadata {
+                disk_size,
+                sector_size: DEFAULT_SECTOR_SIZE,
+                physical_sector_size: DEFAULT_PHYSICAL_SECTOR_SIZE,
+                read_only,
+            },
+        );
+        Ok(Self {
+            base,
+            overlay,
+            bitmap_offset: disk_size,
+            bitmap: RwLock::new(bitmap),
+            alloc_lock: Default::default(),
+            disk_id,
+        })
+    }
+
+    fn is_allocated(&self, block: u64) -> bool {
+        self.bitmap.read()[(block / 8) as usize] & (1 << (block % 8)) != 0
+    }
+
+    /// Reads from the overlay for blocks that have been written, and from the
+    /// base otherwise.
+    pub async fn read(&self, buffers: &RequestBuffers<'_>, sector: u64) -> Result<(), DiskError> {
+        let len = buffers.len() as u64;
+        let offset = self.overlay.check_io(sector, len)?;
+        let sector_shift = self.overlay.sector_shift;
+        let block_end = |pos: u64| (((offset + pos) | (BLOCK_SIZE - 1)) + 1 - offset).min(len);
+        let mut pos = 0;
+        while pos < len {
+            // Coalesce runs of blocks with the same allocation state into a
+            // single IO.
+            let allocated = self.is_allocated((offset + pos) >> BLOCK_SHIFT);
+            let mut end = block_end(pos);
+            while end < len && self.is_allocated((offset + end) >> BLOCK_SHIFT) == allocated {
+                end = block_end(end);
+            }
+            let disk = if allocated { &self.overlay } else { &self.base };
+            Box::pin(disk.read(
+                &buffers.subrange(pos as usize, (end - pos) as usize),
+                (offset + pos) >> sector_shift,
+            ))
+            .await?;
+            pos = end;
+        }
+        Ok(())
+    }
+
+    /// Writes to the overlay, copying any partially written blocks from the
+    /// base first.
+    pub async fn write(
+        &self,
+        buffers: &RequestBuffers<'_>,
+        sector: u64,
+        fua: bool,
+    ) -> Result<(), DiskError> {
+        if self.overlay.metadata.read_only {
+            return Err(DiskError::ReadOnly);
+        }
+        let len = buffers.len() as u64;
+        let offset = self.overlay.check_io(sector, len)?;
+        if len == 0 {
+            return Ok(());
+        }
+        let first_block = offset >> BLOCK_SHIFT;
+        let last_block = (offset + len - 1) >> BLOCK_SHIFT;
+
+        // Blocks are never deallocated, so once every block is allocated the
+        // write can go straight to the overlay.
+        if (first_block..=last_block).all(|block| self.is_allocated(block)) {
+            return Box::pin(self.overlay.write(buffers, sector, fua)).await;
+        }
+
+        let _alloc = self.alloc_lock.lock().await;
+
+        // Extend the write to cover any partially written blocks that are not
+        // yet allocated, filling in the rest of those blocks from the base.
+        let disk_size = self.overlay.metadata.disk_size;
+        let mut start = offset;
+        let mut end = offset + len;
+        if start % BLOCK_SIZE != 0 && !self.is_allocated(first_block) {
+            start = first_block << BLOCK_SHIFT;
+        }
+        if end % BLOCK_SIZE != 0 && !self.is_allocated(last_block) {
+            end = ((last_block + 1) << BLOCK_SHIFT).min(disk_size);
+        }
+        let mut buffer = AlignedBuffer::new((end - start) as usize);
+        let head = (offset - start) as usize;
+        let tail = head + len as usize;
+        buffers.reader().read(&mut buffer[head..tail])?;
+
+        // Write the data before marking it allocated, so that concurrent
+        // reads keep going to the base until the overlay is populated.
+        let base = self.base.file.clone();
+        let file = self.overlay.file.clone();
+        unblock(move || -> Result<_, std::io::Error> {
+            if head != 0 {
+                read_exact_at(&base, &mut buffer[..head], start)?;
+            }
+            if tail != buffer.len() {
+                read_exact_at(&base, &mut buffer[tail..], start + tail as u64)?;
+            }
+            let n = file.write_at(&buffer, start)?;
+            if n != buffer.len() {
+                return Err(std::io::ErrorKind::WriteZero.into());
+            }
+            Ok(())
+        })
+        .await
+        .map_err(DiskError::Io)?;
+
+        let (bitmap_start, bitmap_bytes) = {
+            let mut bitmap = self.bitmap.write();
+            for block in first_block..=last_block {
+                bitmap[(block / 8) as usize] |= 1 << (block % 8);
+            }
+            let range = (first_block / 8) as usize..=(last_block / 8) as usize;
+            (*range.start() as u64, bitmap[range].to_vec())
+        };
+        let file = self.overlay.file.clone();
+        let flush_mode = self.overlay.flush_mode;
+        let bitmap_offset = self.bitmap_offset + bitmap_start;
+        unblock(move || -> Result<_, std::io::Error> {
+            let n = file.write_at(&bitmap_bytes, bitmap_offset)?;
+            if n != bitmap_bytes.len() {
+                return Err(std::io::ErrorKind::WriteZero.into());
+            }
+            if fua {
+                flush_mode.sync(&*file)?;
+            }
+            Ok(())
+        })
+        .await
+        .map_err(DiskError::Io)?;
+        if fua {
+            self.overlay.syncs.increment();
+        }
+        Ok(())
+    }
+
+    /// Flushes the overlay. The base is never written, so it needs no flush.
+    pub async fn flush(&self) -> Result<(), DiskError> {
+        Box::pin(self.overlay.flush()).await
+    }
+}
+
+fn read_exact_at(file: &fs::File, buf: &mut [u8], offset: u64) -> std::io::Result<()> {
+    let n = file.read_at(buf, offset)?;
+    if n != buf.len() {
+        return Err(std::io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(())
+}
+
+impl SimpleDisk for OverlayDisk {
+    fn disk_type(&self) -> &str {
+        "file_overlay"
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.overlay.sector_count()
+    }
+
+    fn sector_size(&self) -> u32 {
+        self.overlay.sector_size()
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.overlay.is_read_only()
+    }
+
+    fn disk_id(&self) -> Option<[u8; 16]> {
+        Some(self.disk_id)
+    }
+
+    fn physical_sector_size(&self) -> u32 {
+        self.overlay.physical_sector_size()
+    }
+
+    fn is_fua_respected(&self) -> bool {
+        true
+    }
+}
+
+impl AsyncDisk for OverlayDisk {
+    fn read_vectored<'a>(
+        &'a self,
+        buffers: &'a RequestBuffers<'a>,
+        sector: u64,
+    ) -> StackFuture<'a, Result<(), DiskError>, { ASYNC_DISK_STACK_SIZE }> {
+        StackFuture::from(async move { self.read(buffers, sector).await })
+    }
+
+    fn write_vectored<'a>(
+        &'a self,
+        buffers: &'a RequestBuffers<'a>,
+        sector: u64,
+        fua: bool,
+    ) -> StackFuture<'a, Result<(), DiskError>, { ASYNC_DISK_STACK_SIZE }> {
+        StackFuture::from(async move { self.write(buffers, sector, fua).await })
+    }
+
+    fn sync_cache(&self) -> StackFuture<'_, Result<(), DiskError>, { ASYNC_DISK_STACK_SIZE }> {
+        StackFuture::from(self.flush())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::OverlayDisk;
+    use crate::readwriteat::ReadWriteAt;
+    use disk_backend::AsyncDisk;
+    use guestmem::GuestMemory;
+    use pal_async::async_test;
+    use scsi_buffers::OwnedRequestBuffers;
+
+    #[async_test]
+    async fn copy_on_write() {
+        let base = tempfile::tempfile().unwrap();
+        base.write_at(&[0x5a; 0x10000], 0).unwrap();
+        let overlay = tempfile::NamedTempFile::new().unwrap();
+        let disk =
+            OverlayDisk::open(base.try_clone().unwrap(), overlay.reopen().unwrap(), false).unwrap();
+
+        // Write a single sector in the middle of a block.
+        let mem = GuestMemory::allocate(0x1000);
+        mem.write_at(0, &[0xa5; 0x200]).unwrap();
+        disk.write_vectored(
+            &OwnedRequestBuffers::linear(0, 0x200, false).buffer(&mem),
+            9,
+            false,
+        )
+        .await
+        .unwrap();
+        drop(disk);
+
+        let mut data = vec![0; 0x10000];
+        base.read_at(&mut data, 0).unwrap();
+        assert!(data.iter().all(|&b| b == 0x5a));
+
+        // Reopen the overlay to check that the bitmap was persisted, and read
+        // across the allocated block and its unallocated neighbors.
+        let disk = OverlayDisk::open(base, overlay.reopen().unwrap(), true).unwrap();
+        disk.read_vectored(
+            &OwnedRequestBuffers::linear(0, 0x1000, true).buffer(&mem),
+            4,
+        )
+        .await
+        .unwrap();
+        let mut data = vec![0; 0x1000];
+        mem.read_at(0, &mut data).unwrap();
+        assert!(data[..0xa00].iter().all(|&b| b == 0x5a));
+        assert!(data[0xa00..0xc00].iter().all(|&b| b == 0xa5));
+        assert!(data[0xc00..].iter().all(|&b| b == 0x5a));
+    }
+}
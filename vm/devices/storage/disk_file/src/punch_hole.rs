@@ -0,0 +1,95 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Helpers for deallocating ranges of a file.
+
+use crate::readwriteat::ReadWriteAt;
+use crate::unbuffered::AlignedBuffer;
+use std::fs;
+use std::io::Result;
+use std::ops::Range;
+
+/// Deallocates the byte range `[offset, offset + len)` of `file` without
+/// changing the file size. Subsequent reads of the range return zeroes.
+///
+/// Where the platform or file system does not support hole punching, the
+/// range is overwritten with zeroes instead.
+pub fn punch_hole(file: &fs::File, offset: u64, len: u64) -> Result<()> {
+    if !fallocate_punch_hole(file, offset, len)? {
+        write_zeroes(file, offset, len)?;
+    }
+    Ok(())
+}
+
+/// Deallocates the file system blocks that lie entirely within the byte range
+/// `[offset, offset + len)` of `file`, leaving the partial blocks at either end
+/// untouched.
+///
+/// Returns the range that was deallocated. This is empty where the platform or
+/// file system does not support hole punching, since overwriting the range
+/// with zeroes would not deallocate anything.
+pub fn punch_whole_blocks(file: &fs::File, offset: u64, len: u64) -> Result<Range<u64>> {
+    let block_size = block_size(file)?;
+    let start = offset.next_multiple_of(block_size);
+    let end = (offset + len) / block_size * block_size;
+    if start >= end || !fallocate_punch_hole(file, start, end - start)? {
+        return Ok(start..start);
+    }
+    Ok(start..end)
+}
+
+/// Returns the file system block size of `file`.
+fn block_size(file: &fs::File) -> Result<u64> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::prelude::*;
+        Ok(file.metadata()?.blksize().max(1))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = file;
+        Ok(4096)
+    }
+}
+
+/// Punches a hole in `file`, returning `false` if hole punching is not
+/// supported.
+fn fallocate_punch_hole(file: &fs::File, offset: u64, len: u64) -> Result<bool> {
+    #[cfg(target_os = "linux")]
+    {
+        use nix::fcntl::FallocateFlags;
+        use std::os::unix::prelude::*;
+
+        match nix::fcntl::fallocate(
+            file.as_raw_fd(),
+            FallocateFlags::FALLOC_FL_PUNCH_HOLE | FallocateFlags::FALLOC_FL_KEEP_SIZE,
+            offset as i64,
+            len as i64,
+        ) {
+            Ok(()) => Ok(true),
+            Err(nix::errno::Errno::EOPNOTSUPP) => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (file, offset, len);
+        Ok(false)
+    }
+}
+
+/// Overwrites the byte range `[offset, offset + len)` of `file` with zeroes.
+fn write_zeroes(file: &fs::File, mut offset: u64, len: u64) -> Result<()> {
+    const CHUNK_SIZE: u64 = 0x10000;
+    let zeroes = AlignedBuffer::new(len.min(CHUNK_SIZE) as usize);
+    let end = offset + len;
+    while offset < end {
+        let this_len = (end - offset).min(CHUNK_SIZE) as usize;
+        let n = file.write_at(&zeroes[..this_len], offset)?;
+        if n == 0 {
+            return Err(std::io::ErrorKind::WriteZero.into());
+        }
+        offset += n as u64;
+    }
+    Ok(())
+}
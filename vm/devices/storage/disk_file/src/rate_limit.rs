@@ -0,0 +1,273 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Token bucket rate limiting of disk IO.
+//!
+//! Each bucket holds up to one second of tokens. An IO reserves its tokens
+//! when it is issued, driving the bucket into debt if there are not enough,
+//! and then waits until the debt has been repaid. IOs therefore wait in the
+//! order they were issued, and an IO larger than the bucket still makes
+//! progress rather than waiting forever for tokens that can never accumulate.
+
+use inspect::Inspect;
+use inspect_counters::SharedCounter;
+use pal_async::driver::Driver;
+use pal_async::timer::PolledTimer;
+use parking_lot::Mutex;
+use std::fmt;
+use std::num::NonZeroU64;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Limits on the rate of IO to a file disk.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct RateLimit {
+    /// The most reads, writes, and unmaps per second.
+    pub iops: Option<NonZeroU64>,
+    /// The most bytes read or written per second.
+    pub bytes_per_second: Option<NonZeroU64>,
+    /// How flushes are limited.
+    pub flushes: FlushLimit,
+}
+
+/// How flushes are limited by a [`RateLimit`].
+///
+/// Flushes never take tokens from the bandwidth bucket, so a flush can
+/// complete even while data IO is waiting for bandwidth.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum FlushLimit {
+    /// Each flush takes a token from the `iops` bucket shared with reads and
+    /// writes.
+    #[default]
+    Shared,
+    /// Flushes are limited to this many per second, independently of other
+    /// IO, or not at all if `None`.
+    Separate(Option<NonZeroU64>),
+}
+
+/// Enforces a [`RateLimit`] on a disk's IO.
+pub struct RateLimiter {
+    state: Mutex<State>,
+    /// The driver for the timers that IOs wait on for tokens.
+    driver: Box<dyn Driver>,
+    /// The number of IOs that waited for tokens.
+    throttled: SharedCounter,
+}
+
+impl fmt::Debug for RateLimiter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RateLimiter")
+            .field("state", &self.state)
+            .field("throttled", &self.throttled)
+            .finish()
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    limit: RateLimit,
+    iops: Option<Bucket>,
+    bytes: Option<Bucket>,
+    flushes: Option<Bucket>,
+}
+
+impl State {
+    fn new(limit: RateLimit, now: Instant) -> Self {
+        let flushes = match limit.flushes {
+            FlushLimit::Shared => None,
+            FlushLimit::Separate(rate) => rate,
+        };
+        Self {
+            limit,
+            iops: limit.iops.map(|rate| Bucket::new(rate, now)),
+            bytes: limit.bytes_per_second.map(|rate| Bucket::new(rate, now)),
+            flushes: flushes.map(|rate| Bucket::new(rate, now)),
+        }
+    }
+
+    /// Reserves the tokens for an IO of `len` bytes, returning how long to
+    /// wait before issuing it.
+    fn reserve_io(&mut self, len: u64, now: Instant) -> Duration {
+        let ops = self.iops.as_mut().map(|bucket| bucket.reserve(1, now));
+        let bytes = self.bytes.as_mut().map(|bucket| bucket.reserve(len, now));
+        ops.max(bytes).unwrap_or_default()
+    }
+
+    /// Reserves the bandwidth for `len` bytes read along with another IO,
+    /// returning how long to wait before issuing it.
+    fn reserve_bytes(&mut self, len: u64, now: Instant) -> Duration {
+        self.bytes
+            .as_mut()
+            .map_or(Duration::ZERO, |bucket| bucket.reserve(len, now))
+    }
+
+    /// Reserves the tokens for a flush, returning how long to wait before
+    /// issuing it.
+    fn reserve_flush(&mut self, now: Instant) -> Duration {
+        let bucket = match self.limit.flushes {
+            FlushLimit::Shared => self.iops.as_mut(),
+            FlushLimit::Separate(_) => self.flushes.as_mut(),
+        };
+        bucket.map_or(Duration::ZERO, |bucket| bucket.reserve(1, now))
+    }
+}
+
+/// A token bucket that refills at `rate` tokens per second, up to `rate`
+/// tokens.
+#[derive(Debug)]
+struct Bucket {
+    rate: f64,
+    /// The available tokens, or, if negative, the tokens reserved by waiting
+    /// IOs beyond those available.
+    tokens: f64,
+    last: Instant,
+}
+
+impl Bucket {
+    fn new(rate: NonZeroU64, now: Instant) -> Self {
+        let rate = rate.get() as f64;
+        Self {
+            rate,
+            tokens: rate,
+            last: now,
+        }
+    }
+
+    /// Takes `n` tokens, returning how long until the bucket is out of debt.
+    fn reserve(&mut self, n: u64, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.last = self.last.max(now);
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate) - n as f64;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+}
+
+impl RateLimiter {
+    /// Returns a new limiter that waits for tokens using timers from
+    /// `driver`.
+    pub fn new(limit: RateLimit, driver: impl Driver) -> Self {
+        Self {
+            state: Mutex::new(State::new(limit, Instant::now())),
+            driver: Box::new(driver),
+            throttled: SharedCounter::new(),
+        }
+    }
+
+    /// Returns the current limits.
+    pub fn limit(&self) -> RateLimit {
+        self.state.lock().limit
+    }
+
+    /// Replaces the limits. The buckets start full, and IOs that are already
+    /// waiting for tokens are not affected.
+    pub fn set_limit(&self, limit: RateLimit) {
+        *self.state.lock() = State::new(limit, Instant::now());
+    }
+
+    /// Waits until a read or write of `len` bytes may be issued.
+    pub async fn io(&self, len: u64) {
+        let delay = self.state.lock().reserve_io(len, Instant::now());
+        self.wait(delay).await
+    }
+
+    /// Waits until `len` more bytes may be read to extend a read that has
+    /// already been charged. No IOPS token is taken, since the extra data is
+    /// read by the same IO.
+    pub async fn prefetch(&self, len: u64) {
+        let delay = self.state.lock().reserve_bytes(len, Instant::now());
+        self.wait(delay).await
+    }
+
+    /// Waits until a flush may be issued.
+    pub async fn flush(&self) {
+        let delay = self.state.lock().reserve_flush(Instant::now());
+        self.wait(delay).await
+    }
+
+    async fn wait(&self, delay: Duration) {
+        if !delay.is_zero() {
+            self.throttled.increment();
+            PolledTimer::new(&self.driver).sleep(delay).await;
+        }
+    }
+}
+
+impl Inspect for RateLimiter {
+    fn inspect(&self, req: inspect::Request<'_>) {
+        let limit = self.limit();
+        let mut resp = req.respond();
+        resp.field("iops", limit.iops)
+            .field("bytes_per_second", limit.bytes_per_second)
+            .field("throttled", &self.throttled);
+        match limit.flushes {
+            FlushLimit::Shared => resp.field("flushes", "shared"),
+            FlushLimit::Separate(rate) => resp.field("flushes", rate),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rate(n: u64) -> Option<NonZeroU64> {
+        NonZeroU64::new(n)
+    }
+
+    #[test]
+    fn flush_while_bandwidth_exhausted() {
+        let now = Instant::now();
+        let mut state = State::new(
+            RateLimit {
+                iops: rate(10),
+                bytes_per_second: rate(1000),
+                flushes: FlushLimit::Shared,
+            },
+            now,
+        );
+
+        // An IO larger than the bucket waits for the debt to be repaid.
+        assert_eq!(state.reserve_io(3000, now), Duration::from_secs(2));
+        assert_eq!(
+            state.reserve_io(500, now + Duration::from_secs(1)),
+            Duration::from_millis(1500)
+        );
+
+        // A flush only needs an IOPS token.
+        assert_eq!(
+            state.reserve_flush(now + Duration::from_secs(1)),
+            Duration::ZERO
+        );
+
+        // A separate flush bucket is not drained by data IO.
+        let mut state = State::new(
+            RateLimit {
+                iops: rate(1),
+                bytes_per_second: None,
+                flushes: FlushLimit::Separate(rate(1)),
+            },
+            now,
+        );
+        assert_eq!(state.reserve_io(512, now), Duration::ZERO);
+        assert_eq!(state.reserve_io(512, now), Duration::from_secs(1));
+        assert_eq!(state.reserve_flush(now), Duration::ZERO);
+        assert_eq!(state.reserve_flush(now), Duration::from_secs(1));
+
+        // Prefetched data only takes bandwidth.
+        let mut state = State::new(
+            RateLimit {
+                iops: rate(1),
+                bytes_per_second: rate(1000),
+                flushes: FlushLimit::Shared,
+            },
+            now,
+        );
+        assert_eq!(state.reserve_io(500, now), Duration::ZERO);
+        assert_eq!(state.reserve_bytes(1500, now), Duration::from_secs(1));
+        assert_eq!(state.reserve_io(0, now), Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,231 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Read-ahead caching for sequential reads.
+
+use inspect::Inspect;
+use inspect_counters::SharedCounter;
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+/// The number of consecutive sequential reads after which reads are extended
+/// to prefetch the data that follows.
+const SEQUENTIAL_TRIGGER: u32 = 2;
+
+/// Configuration for a file disk's read-ahead cache.
+#[derive(Debug, Copy, Clone)]
+pub struct ReadAheadConfig {
+    /// The number of bytes to prefetch past the end of a sequential read.
+    pub window: u64,
+    /// The maximum number of bytes to cache. The least recently used
+    /// prefetched chunks are evicted first.
+    pub cap: u64,
+}
+
+/// A cache of data prefetched past the end of sequential reads.
+///
+/// Once [`SEQUENTIAL_TRIGGER`] reads in a row have each started where the
+/// previous one ended, the next read that misses the cache is extended by
+/// the configured window, and the extra data is cached to serve the reads
+/// that follow.
+#[derive(Debug, Inspect)]
+pub struct ReadAhead {
+    #[inspect(skip)]
+    config: ReadAheadConfig,
+    /// The alignment of prefetched ranges, so that they can be read with
+    /// unbuffered IO.
+    sector_size: u64,
+    #[inspect(rename = "cached_bytes", with = "|x| x.lock().cached_bytes")]
+    state: Mutex<State>,
+    hits: SharedCounter,
+    misses: SharedCounter,
+}
+
+#[derive(Debug)]
+struct State {
+    /// The end of the most recent read.
+    last_end: u64,
+    /// The number of reads in the current sequential run.
+    run: u32,
+    /// The cached chunks, least recently used first.
+    chunks: VecDeque<Chunk>,
+    cached_bytes: u64,
+    /// Incremented on each invalidation, so that prefetched data read before
+    /// an overlapping write is discarded rather than cached.
+    generation: u64,
+}
+
+#[derive(Debug)]
+struct Chunk {
+    offset: u64,
+    data: Arc<[u8]>,
+}
+
+impl Chunk {
+    fn end(&self) -> u64 {
+        self.offset + self.data.len() as u64
+    }
+}
+
+/// The result of looking up a read in the cache.
+pub enum Lookup {
+    /// The read was served from the cache.
+    Hit(Vec<u8>),
+    /// The read must be issued to the file. If `prefetch` is set, the read
+    /// should be extended to cover it, and the extra data passed to
+    /// [`ReadAhead::fill`].
+    Miss { prefetch: Option<Prefetch> },
+}
+
+/// A range to prefetch past the end of a read.
+pub struct Prefetch {
+    pub offset: u64,
+    pub len: u64,
+    generation: u64,
+}
+
+impl ReadAhead {
+    /// Returns a new cache for a disk with `sector_size` byte sectors.
+    pub fn new(config: ReadAheadConfig, sector_size: u32) -> Self {
+        Self {
+            config,
+            sector_size: sector_size.into(),
+            state: Mutex::new(State {
+                last_end: 0,
+                run: 0,
+                chunks: VecDeque::new(),
+                cached_bytes: 0,
+                generation: 0,
+            }),
+            hits: SharedCounter::new(),
+            misses: SharedCounter::new(),
+        }
+    }
+
+    /// Looks up a read of `len` bytes at `offset` in the cache, for a disk
+    /// that is currently `disk_size` bytes. `offset` and `len` must be sector
+    /// aligned; the returned prefetch range is too.
+    pub fn lookup(&self, offset: u64, len: u64, disk_size: u64) -> Lookup {
+        let mut state = self.state.lock();
+        let state = &mut *state;
+        if offset == state.last_end {
+            state.run = state.run.saturating_add(1);
+        } else {
+            state.run = 1;
+        }
+        state.last_end = offset + len;
+
+        if let Some(i) = state
+            .chunks
+            .iter()
+            .position(|chunk| chunk.offset <= offset && offset + len <= chunk.end())
+        {
+            let chunk = state.chunks.remove(i).unwrap();
+            let start = (offset - chunk.offset) as usize;
+            let data = chunk.data[start..start + len as usize].to_vec();
+            state.chunks.push_back(chunk);
+            self.hits.increment();
+            return Lookup::Hit(data);
+        }
+
+        self.misses.increment();
+        let end = offset + len;
+        let prefetch = (state.run >= SEQUENTIAL_TRIGGER && self.config.window <= self.config.cap)
+            .then(|| Prefetch {
+                offset: end,
+                len: self.config.window.min(disk_size.saturating_sub(end))
+                    & !(self.sector_size - 1),
+                generation: state.generation,
+            })
+            .filter(|prefetch| prefetch.len > 0);
+        Lookup::Miss { prefetch }
+    }
+
+    /// Caches the data read for `prefetch`, unless an overlapping
+    /// invalidation has happened since it was requested.
+    pub fn fill(&self, prefetch: Prefetch, data: &[u8]) {
+        assert_eq!(data.len() as u64, prefetch.len);
+        let mut state = self.state.lock();
+        if state.generation != prefetch.generation {
+            return;
+        }
+        // Replace any chunks that the new data overlaps.
+        state.remove_overlapping(prefetch.offset, prefetch.len);
+        state.chunks.push_back(Chunk {
+            offset: prefetch.offset,
+            data: data.into(),
+        });
+        state.cached_bytes += prefetch.len;
+        while state.cached_bytes > self.config.cap {
+            let chunk = state.chunks.pop_front().unwrap();
+            state.cached_bytes -= chunk.data.len() as u64;
+        }
+    }
+
+    /// Discards any cached data overlapping `len` bytes at `offset`. This
+    /// must be called after each write completes.
+    pub fn invalidate(&self, offset: u64, len: u64) {
+        let mut state = self.state.lock();
+        state.generation += 1;
+        state.remove_overlapping(offset, len);
+    }
+
+    /// Discards all cached data.
+    pub fn invalidate_all(&self) {
+        self.invalidate(0, u64::MAX);
+    }
+
+    #[cfg(test)]
+    pub fn hits(&self) -> u64 {
+        self.hits.get()
+    }
+
+    #[cfg(test)]
+    pub fn misses(&self) -> u64 {
+        self.misses.get()
+    }
+}
+
+impl State {
+    fn remove_overlapping(&mut self, offset: u64, len: u64) {
+        let end = offset.saturating_add(len);
+        let mut removed = 0;
+        self.chunks.retain(|chunk| {
+            let overlaps = chunk.offset < end && offset < chunk.end();
+            if overlaps {
+                removed += chunk.data.len() as u64;
+            }
+            !overlaps
+        });
+        self.cached_bytes -= removed;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefetch_sector_aligned() {
+        let read_ahead = ReadAhead::new(
+            ReadAheadConfig {
+                window: 0x1800,
+                cap: 0x10000,
+            },
+            4096,
+        );
+        let prefetch = |offset, disk_size| match read_ahead.lookup(offset, 0x1000, disk_size) {
+            Lookup::Miss { prefetch } => prefetch.map(|prefetch| prefetch.len),
+            Lookup::Hit(_) => panic!("unexpected hit"),
+        };
+
+        // The window is rounded down to whole sectors.
+        assert_eq!(prefetch(0, 0x100000), None);
+        assert_eq!(prefetch(0x1000, 0x100000), Some(0x1000));
+
+        // A window that is clamped to the end of the disk stays aligned, or
+        // is dropped if less than a sector remains.
+        assert_eq!(prefetch(0x2000, 0x3800), None);
+    }
+}
@@ -3,9 +3,18 @@
 
 //! Helpers for doing IO at a given offset.
 
+// UNSAFETY: Issuing vectored IO to locked guest memory buffers.
+#![cfg_attr(target_os = "linux", allow(unsafe_code))]
+
+#[cfg(target_os = "linux")]
+use scsi_buffers::IoBuffer;
 use std::fs;
 use std::io::Result;
 
+/// The maximum number of buffers that can be passed to a single vectored IO.
+#[cfg(target_os = "linux")]
+pub const MAX_IO_VECS: usize = libc::UIO_MAXIOV as usize;
+
 /// A unified extension trait for [`std::fs::File`] for reading/writing at a
 /// given offset.
 ///
@@ -15,6 +24,14 @@
 pub trait ReadWriteAt {
     fn write_at(&self, buf: &[u8], offset: u64) -> Result<usize>;
     fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize>;
+
+    /// Writes the contents of `bufs` at `offset` with a single vectored write.
+    #[cfg(target_os = "linux")]
+    fn writev_at(&self, bufs: &[IoBuffer<'_>], offset: u64) -> Result<usize>;
+
+    /// Reads into `bufs` from `offset` with a single vectored read.
+    #[cfg(target_os = "linux")]
+    fn readv_at(&self, bufs: &[IoBuffer<'_>], offset: u64) -> Result<usize>;
 }
 
 #[cfg(windows)]
@@ -36,4 +53,54 @@ fn write_at(&self, buf: &[u8], offset: u64) -> Result<usize> {
     fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
         std::os::unix::fs::FileExt::read_at(self, buf, offset)
     }
+
+    #[cfg(target_os = "linux")]
+    fn writev_at(&self, bufs: &[IoBuffer<'_>], offset: u64) -> Result<usize> {
+        use std::os::unix::prelude::*;
+
+        let count = bufs
+            .len()
+            .try_into()
+            .map_err(|_| std::io::ErrorKind::InvalidInput)?;
+        // SAFETY: IoBuffer is ABI compatible with iovec, and the buffers are
+        // guaranteed to be valid for the duration of the call.
+        let n = unsafe {
+            libc::pwritev(
+                self.as_raw_fd(),
+                bufs.as_ptr().cast::<libc::iovec>(),
+                count,
+                offset as libc::off_t,
+            )
+        };
+        if n < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(n as usize)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn readv_at(&self, bufs: &[IoBuffer<'_>], offset: u64) -> Result<usize> {
+        use std::os::unix::prelude::*;
+
+        let count = bufs
+            .len()
+            .try_into()
+            .map_err(|_| std::io::ErrorKind::InvalidInput)?;
+        // SAFETY: IoBuffer is ABI compatible with iovec, and the buffers are
+        // guaranteed to be valid for the duration of the call. The buffers
+        // are atomic, so it is fine for the kernel to write to them while
+        // they are shared.
+        let n = unsafe {
+            libc::preadv(
+                self.as_raw_fd(),
+                bufs.as_ptr().cast::<libc::iovec>(),
+                count,
+                offset as libc::off_t,
+            )
+        };
+        if n < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(n as usize)
+    }
 }
@@ -0,0 +1,109 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Copy-on-write cloning of files.
+//!
+//! A reflink creates a new file that shares the storage of the source file
+//! until either is written, so cloning a large image takes no time or space.
+//! Where the platform or file system does not support reflinks, the data is
+//! copied instead.
+
+// UNSAFETY: Calling the FICLONE ioctl and fclonefileat.
+#![cfg_attr(any(target_os = "linux", target_os = "macos"), allow(unsafe_code))]
+
+use crate::readwriteat::ReadWriteAt;
+use crate::unbuffered::AlignedBuffer;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+#[cfg(target_os = "linux")]
+mod ioctl {
+    // #define FICLONE _IOW(0x94, 9, int)
+    nix::ioctl_write_int!(ficlone, 0x94, 9);
+}
+
+/// Creates a new file at `dest` with the first `len` bytes of `src`, sharing
+/// storage with `src` where supported.
+///
+/// Fails if `dest` already exists.
+pub fn clone_file(src: &fs::File, dest: &Path, len: u64) -> io::Result<fs::File> {
+    #[cfg(target_os = "macos")]
+    {
+        use std::os::unix::prelude::*;
+
+        let c_dest = std::ffi::CString::new(dest.as_os_str().as_bytes())?;
+        // SAFETY: `src` is a valid file descriptor and `c_dest` is a valid
+        // NUL-terminated path for the duration of the call.
+        let r = unsafe { libc::fclonefileat(src.as_raw_fd(), libc::AT_FDCWD, c_dest.as_ptr(), 0) };
+        if r == 0 {
+            let file = fs::OpenOptions::new().read(true).write(true).open(dest)?;
+            // The clone has the whole file, which may be larger than the disk.
+            file.set_len(len)?;
+            return Ok(file);
+        }
+        let err = io::Error::last_os_error();
+        if !matches!(err.raw_os_error(), Some(libc::ENOTSUP | libc::EXDEV)) {
+            return Err(err);
+        }
+    }
+
+    let file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(dest)?;
+    let r = reflink_or_copy(src, &file, len);
+    if r.is_err() {
+        let _ = fs::remove_file(dest);
+    }
+    r?;
+    Ok(file)
+}
+
+fn reflink_or_copy(src: &fs::File, dest: &fs::File, len: u64) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::prelude::*;
+
+        // SAFETY: both file descriptors are valid for the duration of the
+        // call, and FICLONE takes the source file descriptor by value.
+        match unsafe { ioctl::ficlone(dest.as_raw_fd(), src.as_raw_fd() as _) } {
+            Ok(_) => return dest.set_len(len),
+            // The file system does not support reflinks, the files are on
+            // different file systems, or the source is not a regular file.
+            Err(
+                nix::errno::Errno::EOPNOTSUPP
+                | nix::errno::Errno::EXDEV
+                | nix::errno::Errno::EINVAL
+                | nix::errno::Errno::ENOTTY,
+            ) => {}
+            Err(err) => return Err(err.into()),
+        }
+    }
+    copy(src, dest, len)
+}
+
+/// Copies the first `len` bytes of `src` to `dest`.
+fn copy(src: &fs::File, dest: &fs::File, len: u64) -> io::Result<()> {
+    const CHUNK_SIZE: u64 = 0x100000;
+    let mut buf = AlignedBuffer::new(len.min(CHUNK_SIZE) as usize);
+    let mut offset = 0;
+    while offset < len {
+        let this_len = (len - offset).min(CHUNK_SIZE) as usize;
+        let n = src.read_at(&mut buf[..this_len], offset)?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let mut written = 0;
+        while written < n {
+            let m = dest.write_at(&buf[written..n], offset + written as u64)?;
+            if m == 0 {
+                return Err(io::ErrorKind::WriteZero.into());
+            }
+            written += m;
+        }
+        offset += n as u64;
+    }
+    dest.set_len(len)
+}
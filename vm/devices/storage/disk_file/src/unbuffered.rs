@@ -0,0 +1,72 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Helpers for unbuffered IO, which bypasses the host page cache.
+
+use std::fs;
+use std::ops::Deref;
+use std::ops::DerefMut;
+use zerocopy::AsBytes;
+use zerocopy::FromBytes;
+use zerocopy::FromZeroes;
+
+/// The alignment of buffers used for IO.
+///
+/// Unbuffered IO requires buffers aligned to the logical block size of the
+/// underlying device. The page size is a safe upper bound for that.
+pub const BUFFER_ALIGNMENT: usize = 4096;
+
+/// Switches `file` to unbuffered IO.
+pub fn enable(file: &fs::File) -> std::io::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        use nix::fcntl::FcntlArg;
+        use nix::fcntl::OFlag;
+        use std::os::unix::prelude::*;
+
+        let fd = file.as_raw_fd();
+        let flags = OFlag::from_bits_truncate(nix::fcntl::fcntl(fd, FcntlArg::F_GETFL)?);
+        nix::fcntl::fcntl(fd, FcntlArg::F_SETFL(flags | OFlag::O_DIRECT))?;
+        Ok(())
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = file;
+        Err(std::io::ErrorKind::Unsupported.into())
+    }
+}
+
+#[repr(C, align(4096))]
+#[derive(Clone, AsBytes, FromBytes, FromZeroes)]
+struct Block([u8; BUFFER_ALIGNMENT]);
+
+/// A zero-initialized byte buffer whose start is aligned to
+/// [`BUFFER_ALIGNMENT`].
+pub struct AlignedBuffer {
+    blocks: Vec<Block>,
+    len: usize,
+}
+
+impl AlignedBuffer {
+    /// Allocates a new buffer of `len` bytes.
+    pub fn new(len: usize) -> Self {
+        Self {
+            blocks: vec![Block::new_zeroed(); len.div_ceil(BUFFER_ALIGNMENT)],
+            len,
+        }
+    }
+}
+
+impl Deref for AlignedBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.blocks.as_bytes()[..self.len]
+    }
+}
+
+impl DerefMut for AlignedBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.blocks.as_bytes_mut()[..self.len]
+    }
+}
@@ -0,0 +1,87 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Helpers for detecting all-zero write buffers.
+
+// UNSAFETY: Scanning locked guest memory buffers a word at a time.
+#![cfg_attr(target_os = "linux", allow(unsafe_code))]
+
+#[cfg(target_os = "linux")]
+use std::sync::atomic::AtomicU64;
+#[cfg(target_os = "linux")]
+use std::sync::atomic::AtomicU8;
+#[cfg(target_os = "linux")]
+use std::sync::atomic::Ordering;
+
+/// The minimum write size worth scanning for zeroes. Below this, the cost of
+/// the scan outweighs the benefit of deallocating the range.
+pub const MIN_SCAN_LEN: usize = 4096;
+
+/// Returns true if every byte of `buf` is zero.
+pub fn is_zero(buf: &[u8]) -> bool {
+    let words = buf.chunks_exact(size_of::<u64>());
+    let tail = words.remainder();
+    words
+        .map(|word| u64::from_ne_bytes(word.try_into().unwrap()))
+        .all(|word| word == 0)
+        && tail.iter().all(|&b| b == 0)
+}
+
+/// Returns true if every byte of `buf` is zero.
+///
+/// The buffer may be concurrently modified (e.g. by the guest), in which case
+/// the result reflects some interleaving of those modifications.
+#[cfg(target_os = "linux")]
+pub fn is_zero_atomic(buf: &[AtomicU8]) -> bool {
+    let head_len = buf
+        .as_ptr()
+        .align_offset(align_of::<AtomicU64>())
+        .min(buf.len());
+    let (head, rest) = buf.split_at(head_len);
+    let word_count = rest.len() / size_of::<AtomicU64>();
+    let (middle, tail) = rest.split_at(word_count * size_of::<AtomicU64>());
+    // SAFETY: `middle` is aligned for `AtomicU64` and is exactly
+    // `word_count` words long. `AtomicU64` has the same in-memory
+    // representation as `u64`, and every bit pattern is valid for it.
+    let middle: &[AtomicU64] =
+        unsafe { std::slice::from_raw_parts(middle.as_ptr().cast(), word_count) };
+    middle.iter().all(|w| w.load(Ordering::Relaxed) == 0)
+        && head
+            .iter()
+            .chain(tail)
+            .all(|b| b.load(Ordering::Relaxed) == 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_zero() {
+        let mut buf = vec![0u8; 0x1003];
+        for start in 0..8 {
+            assert!(is_zero(&buf[start..]));
+        }
+        for i in [0, 7, 8, 0x800, 0x1002] {
+            buf[i] = 1;
+            assert!(!is_zero(&buf), "{i}");
+            buf[i] = 0;
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn detect_zero_atomic() {
+        let buf: Vec<AtomicU8> = (0..0x1003).map(|_| AtomicU8::new(0)).collect();
+        for start in 0..8 {
+            assert!(is_zero_atomic(&buf[start..]));
+        }
+        for i in [0, 7, 8, 0x800, 0x1002] {
+            buf[i].store(1, Ordering::Relaxed);
+            for start in 0..8.min(i + 1) {
+                assert!(!is_zero_atomic(&buf[start..]), "{i} {start}");
+            }
+            buf[i].store(0, Ordering::Relaxed);
+        }
+    }
+}
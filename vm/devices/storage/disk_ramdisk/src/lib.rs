@@ -2,6 +2,9 @@
 // Licensed under the MIT License.
 
 //! RAM-backed disk backend implementation.
+//!
+//! A RAM disk performs no IO to the host, which makes it a fast and
+//! deterministic stand-in for a file disk in tests, as well as a real ramdisk.
 
 #![forbid(unsafe_code)]
 #![warn(missing_docs)]
@@ -13,6 +16,7 @@
 use disk_backend::zerodisk::ZeroDisk;
 use disk_backend::AsyncDisk;
 use disk_backend::DiskError;
+use disk_backend::Resize;
 use disk_backend::SimpleDisk;
 use disk_backend::Unmap;
 use disk_backend::ASYNC_DISK_STACK_SIZE;
@@ -119,6 +123,24 @@ fn new_inner(
         })
     }
 
+    /// Validates an IO of `len` bytes starting at `sector`, returning the
+    /// number of sectors it covers.
+    fn check_io(&self, sector: u64, len: u64) -> Result<u64, DiskError> {
+        if len % SECTOR_SIZE as u64 != 0 {
+            return Err(DiskError::InvalidInput);
+        }
+        let count = len / SECTOR_SIZE as u64;
+        let sector_count = self.sector_count();
+        match sector.checked_add(count) {
+            Some(end) if end <= sector_count => Ok(count),
+            _ => Err(DiskError::OutOfRange {
+                sector,
+                len,
+                disk_size: sector_count * SECTOR_SIZE as u64,
+            }),
+        }
+    }
+
     fn resize(&self, new_sector_count: u64) -> anyhow::Result<()> {
         if new_sector_count == 0 {
             anyhow::bail!("invalid sector count");
@@ -166,6 +188,11 @@ fn is_fua_respected(&self) -> bool {
     fn unmap(&self) -> Option<&dyn Unmap> {
         self.lower_is_zero.then_some(self)
     }
+
+    fn resize(&self) -> Option<&dyn Resize> {
+        // A diff disk cannot grow past its lower disk.
+        self.lower_is_zero.then_some(self)
+    }
 }
 
 impl AsyncDisk for RamDisk {
@@ -175,7 +202,7 @@ fn read_vectored<'a>(
         sector: u64,
     ) -> StackFuture<'a, Result<(), DiskError>, { ASYNC_DISK_STACK_SIZE }> {
         StackFuture::from(async move {
-            let count = (buffers.len() / SECTOR_SIZE as usize) as u64;
+            let count = self.check_io(sector, buffers.len() as u64)?;
             tracing::trace!(sector, count, "read");
             // Always read the full lower and then overlay the changes.
             // Optimizations are possible, but some heuristics are necessary to
@@ -201,9 +228,10 @@ fn write_vectored<'a>(
         _fua: bool,
     ) -> StackFuture<'a, Result<(), DiskError>, { ASYNC_DISK_STACK_SIZE }> {
         StackFuture::from(async move {
-            assert!(!self.read_only);
-
-            let count = buffers.len() / SECTOR_SIZE as usize;
+            if self.read_only {
+                return Err(DiskError::ReadOnly);
+            }
+            let count = self.check_io(sector, buffers.len() as u64)? as usize;
             tracing::trace!(sector, count, "write");
 
             let mut data = self.data.write();
@@ -257,6 +285,13 @@ fn unmap(
         assert!(self.lower_is_zero);
         StackFuture::from(async move {
             tracing::trace!(sector_offset, sector_count, "unmap");
+            if self.read_only {
+                return Err(DiskError::ReadOnly);
+            }
+            let len = sector_count
+                .checked_mul(SECTOR_SIZE as u64)
+                .ok_or(DiskError::InvalidInput)?;
+            self.check_io(sector_offset, len)?;
             let mut data = self.data.write();
             // Sadly, there appears to be no way to remove a range of entries
             // from a btree map.
@@ -281,11 +316,27 @@ fn optimal_unmap_sectors(&self) -> u32 {
     }
 }
 
+impl Resize for RamDisk {
+    fn resize(
+        &self,
+        sector_count: u64,
+    ) -> StackFuture<'_, Result<(), DiskError>, { ASYNC_DISK_STACK_SIZE }> {
+        assert!(self.lower_is_zero);
+        StackFuture::from(async move {
+            if self.read_only {
+                return Err(DiskError::ReadOnly);
+            }
+            RamDisk::resize(self, sector_count).map_err(|_| DiskError::InvalidInput)
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::RamDisk;
     use super::SECTOR_SIZE;
     use crate::SimpleDisk;
+    use disk_backend::DiskError;
     use guestmem::GuestMemory;
     use pal_async::async_test;
     use scsi_buffers::OwnedRequestBuffers;
@@ -363,4 +414,79 @@ async fn diff() {
         check(&guest_mem, 11, 2, 1, 2);
         check(&guest_mem, 12, 3, 1, 0);
     }
+
+    #[async_test]
+    async fn read_write() {
+        let guest_mem = GuestMemory::allocate(0x10000);
+        let mut disk = RamDisk::new(0x10000, false).unwrap();
+        assert_eq!(disk.sector_count(), 0x10000 / SECTOR_U64);
+
+        // Unwritten sectors read as zero.
+        guest_mem.fill_at(0, 0xff, 0x1000).unwrap();
+        read(&guest_mem, &mut disk, 3, 8).await;
+        let mut buf = vec![0xffu8; 8 * SECTOR_USIZE];
+        guest_mem.read_at(0, &mut buf).unwrap();
+        assert!(buf.iter().all(|&b| b == 0));
+
+        write(&guest_mem, &mut disk, 3, 8, 1).await;
+        read(&guest_mem, &mut disk, 3, 8).await;
+        check(&guest_mem, 3, 0, 8, 1);
+
+        // The last sector is accessible, but nothing beyond it.
+        let last = disk.sector_count() - 1;
+        write(&guest_mem, &mut disk, last, 1, 2).await;
+        read(&guest_mem, &mut disk, last, 1).await;
+        check(&guest_mem, last, 0, 1, 2);
+        let r = disk
+            .read_vectored(
+                &OwnedRequestBuffers::linear(0, 2 * SECTOR_USIZE, true).buffer(&guest_mem),
+                last,
+            )
+            .await;
+        assert!(matches!(r, Err(DiskError::OutOfRange { .. })));
+    }
+
+    #[async_test]
+    async fn read_only() {
+        let guest_mem = GuestMemory::allocate(0x1000);
+        let disk = RamDisk::new(0x10000, true).unwrap();
+        let r = disk
+            .write_vectored(
+                &OwnedRequestBuffers::linear(0, SECTOR_USIZE, false).buffer(&guest_mem),
+                0,
+                false,
+            )
+            .await;
+        assert!(matches!(r, Err(DiskError::ReadOnly)));
+        let r = disk.unmap().unwrap().unmap(0, 1, false).await;
+        assert!(matches!(r, Err(DiskError::ReadOnly)));
+    }
+
+    #[async_test]
+    async fn unmap_and_resize() {
+        let guest_mem = GuestMemory::allocate(0x10000);
+        let mut disk = RamDisk::new(0x10000, false).unwrap();
+        write(&guest_mem, &mut disk, 0, 16, 1).await;
+        disk.unmap().unwrap().unmap(4, 4, false).await.unwrap();
+        read(&guest_mem, &mut disk, 0, 16).await;
+        check(&guest_mem, 0, 0, 4, 1);
+        check(&guest_mem, 8, 8, 8, 1);
+        let mut buf = vec![0xffu8; 4 * SECTOR_USIZE];
+        guest_mem.read_at(4 * SECTOR_U64, &mut buf).unwrap();
+        assert!(buf.iter().all(|&b| b == 0));
+
+        // Shrinking discards the data past the new end.
+        SimpleDisk::resize(&disk).unwrap().resize(8).await.unwrap();
+        assert_eq!(disk.sector_count(), 8);
+        SimpleDisk::resize(&disk).unwrap().resize(16).await.unwrap();
+        read(&guest_mem, &mut disk, 0, 16).await;
+        check(&guest_mem, 0, 0, 4, 1);
+        let mut buf = vec![0xffu8; 12 * SECTOR_USIZE];
+        guest_mem.read_at(4 * SECTOR_U64, &mut buf).unwrap();
+        assert!(buf.iter().all(|&b| b == 0));
+
+        // Diff disks cannot be resized.
+        let upper = RamDisk::diff(Arc::new(disk), false).unwrap();
+        assert!(SimpleDisk::resize(&upper).is_none());
+    }
 }
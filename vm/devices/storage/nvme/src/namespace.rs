@@ -231,9 +231,14 @@ fn map_disk_error(err: disk_backend::DiskError) -> NvmeError {
         disk_backend::DiskError::AbortDueToPreemptAndAbort => {
             NvmeError::new(spec::Status::COMMAND_ABORTED_DUE_TO_PREEMPT_AND_ABORT, err)
         }
-        disk_backend::DiskError::IllegalBlock => spec::Status::LBA_OUT_OF_RANGE.into(),
+        disk_backend::DiskError::IllegalBlock | disk_backend::DiskError::OutOfRange { .. } => {
+            spec::Status::LBA_OUT_OF_RANGE.into()
+        }
         disk_backend::DiskError::InvalidInput => spec::Status::INVALID_FIELD_IN_COMMAND.into(),
         disk_backend::DiskError::Io(err) => NvmeError::new(spec::Status::DATA_TRANSFER_ERROR, err),
+        disk_backend::DiskError::IntegrityCheckFailed { .. } => {
+            NvmeError::new(spec::Status::MEDIA_UNRECOVERED_READ_ERROR, err)
+        }
         disk_backend::DiskError::MediumError(_, details) => match details {
             disk_backend::MediumErrorDetails::ApplicationTagCheckFailed => {
                 spec::Status::MEDIA_END_TO_END_APPLICATION_TAG_CHECK_ERROR.into()
@@ -999,7 +999,7 @@ fn process_result(&self, result: Result<usize, ScsiError>, op: ScsiOp) -> ScsiRe
                                     0,
                                 )),
                             },
-                            DiskError::IllegalBlock => ScsiResult {
+                            DiskError::IllegalBlock | DiskError::OutOfRange { .. } => ScsiResult {
                                 scsi_status: ScsiStatus::CHECK_CONDITION,
                                 srb_status: SrbStatus::ERROR,
                                 tx: 0,
@@ -1019,6 +1019,16 @@ fn process_result(&self, result: Result<usize, ScsiError>, op: ScsiOp) -> ScsiRe
                                     0,
                                 )),
                             },
+                            DiskError::IntegrityCheckFailed { .. } => ScsiResult {
+                                scsi_status: ScsiStatus::CHECK_CONDITION,
+                                srb_status: SrbStatus::ERROR,
+                                tx: 0,
+                                sense_data: Some(scsi::SenseData::new(
+                                    SenseKey::MEDIUM_ERROR,
+                                    AdditionalSenseCode::UNRECOVERED_ERROR,
+                                    0,
+                                )),
+                            },
                             DiskError::MediumError(_, details) => {
                                 let (sense_code, qualifier) = match details {
                                     disk_backend::MediumErrorDetails::ApplicationTagCheckFailed => {
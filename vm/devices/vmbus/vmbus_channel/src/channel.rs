@@ -447,6 +447,8 @@ fn from(err: ChannelRestoreError) -> Self {
 struct Device {
     server_requests: Vec<mesh::Sender<ChannelServerRequest>>,
     open: Vec<bool>,
+    /// The ring buffer GPADL of each open channel.
+    ring_gpadl_ids: Vec<Option<GpadlId>>,
     subchannel_gpadls: Vec<BTreeSet<GpadlId>>,
     requests: SelectAll<TaggedStream<usize, mesh::Receiver<ChannelRequest>>>,
     subchannel_events: Vec<Notify>,
@@ -463,6 +465,7 @@ fn new(
         subchannel_enable_recv: mesh::Receiver<u16>,
     ) -> Self {
         let open: Vec<bool> = vec![false];
+        let ring_gpadl_ids: Vec<Option<GpadlId>> = vec![None];
         let subchannel_gpadls: Vec<BTreeSet<GpadlId>> = vec![];
         let mut requests: SelectAll<TaggedStream<usize, mesh::Receiver<ChannelRequest>>> =
             SelectAll::new();
@@ -470,6 +473,7 @@ fn new(
         Self {
             server_requests: vec![server_request_send],
             open,
+            ring_gpadl_ids,
             subchannel_gpadls,
             requests,
             subchannel_events,
@@ -602,6 +606,7 @@ async fn handle_open(
             true
         };
         self.open[channel_idx] = opened;
+        self.ring_gpadl_ids[channel_idx] = opened.then_some(open_request.open_data.ring_gpadl_id);
         opened
     }
 
@@ -626,10 +631,12 @@ async fn handle_close(&mut self, channel_idx: usize, channel: &mut dyn VmbusDevi
                 }
             }
             self.open.truncate(1);
+            self.ring_gpadl_ids.truncate(1);
             self.subchannel_gpadls.clear();
         }
         channel.close(channel_idx as u16).await;
         self.open[channel_idx] = false;
+        self.ring_gpadl_ids[channel_idx] = None;
         if channel_idx == 0 {
             // Drain any stale enable subchannel requests.
             while self.subchannel_enable_recv.try_recv().is_ok() {}
@@ -725,7 +732,32 @@ async fn handle_state_request(
                 .await;
             }
             StateRequest::Inspect(deferred) => {
-                deferred.inspect(&mut *channel);
+                deferred.respond(|resp| {
+                    let offer = channel.offer();
+                    resp.child("vmbus", |req| {
+                        req.respond()
+                            .field("interface_name", &offer.interface_name)
+                            .display("interface_id", &offer.interface_id)
+                            .display("instance_id", &offer.instance_id)
+                            .fields(
+                                "channels",
+                                self.open.iter().zip(&self.ring_gpadl_ids).enumerate().map(
+                                    |(channel_idx, (&open, &ring_gpadl_id))| {
+                                        (
+                                            channel_idx,
+                                            inspect::adhoc(move |req| {
+                                                req.respond().field("open", open).field(
+                                                    "ring_gpadl_id",
+                                                    ring_gpadl_id.map(|id| inspect::AsHex(id.0)),
+                                                );
+                                            }),
+                                        )
+                                    },
+                                ),
+                            );
+                    })
+                    .merge(&mut *channel);
+                });
             }
         }
     }
@@ -759,6 +791,7 @@ async fn enable_channels(
                     self.server_requests.push(server_request_send);
                     self.subchannel_gpadls.push(BTreeSet::new());
                     self.open.push(false);
+                    self.ring_gpadl_ids.push(None);
                 }
                 Err(err) => {
                     tracing::error!(
@@ -793,6 +826,10 @@ pub async fn restore(
                 .map_err(|err| ChannelRestoreError::RestoreError(err.into()))?;
 
             assert!(open == result.open_request.is_some());
+            self.ring_gpadl_ids[channel_idx] = result
+                .open_request
+                .as_ref()
+                .map(|open_request| open_request.open_data.ring_gpadl_id);
 
             for gpadl in result.gpadls {
                 let buf =
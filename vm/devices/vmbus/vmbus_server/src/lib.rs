@@ -77,6 +77,7 @@
 use vmbus_ring::gparange::MultiPagedRangeBuf;
 use vmcore::interrupt::Interrupt;
 use vmcore::save_restore::SavedStateRoot;
+use vmcore::synic::BackpressureBehavior;
 use vmcore::synic::EventPort;
 use vmcore::synic::GuestEventPort;
 use vmcore::synic::MessagePort;
@@ -371,7 +372,12 @@ pub fn build(self) -> anyhow::Result<VmbusServer> {
 
         let _message_port = self
             .synic
-            .add_message_port(connection_id, redirect_vtl, message_sender)
+            .add_message_port(
+                connection_id,
+                redirect_vtl,
+                message_sender,
+                BackpressureBehavior::Drop,
+            )
             .context("failed to create vmbus synic ports")?;
 
         // If this server is for VTL0, it is also responsible for the multiclient message port.
@@ -389,6 +395,7 @@ pub fn build(self) -> anyhow::Result<VmbusServer> {
                         MULTICLIENT_MESSAGE_CONNECTION_ID,
                         self.vtl,
                         multiclient_message_sender,
+                        BackpressureBehavior::Drop,
                     )
                     .context("failed to create vmbus synic ports")?,
             )
@@ -1615,6 +1622,7 @@ fn add_message_port(
             connection_id: u32,
             _minimum_vtl: Vtl,
             port: Arc<dyn MessagePort>,
+            _backpressure: BackpressureBehavior,
         ) -> Result<Box<dyn Sync + Send>, vmcore::synic::Error> {
             self.inner.lock().message_port = Some(port);
             Ok(Box::new(connection_id))
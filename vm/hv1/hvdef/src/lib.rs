@@ -1890,6 +1890,15 @@ pub enum $name: u32 {
                 Eom = 0x000A0014,
                 Sirbp = 0x000A0015,
 
+                Stimer0Config = 0x000B0000,
+                Stimer0Count = 0x000B0001,
+                Stimer1Config = 0x000B0002,
+                Stimer1Count = 0x000B0003,
+                Stimer2Config = 0x000B0004,
+                Stimer2Count = 0x000B0005,
+                Stimer3Config = 0x000B0006,
+                Stimer3Count = 0x000B0007,
+
                 VsmCodePageOffsets = 0x000D0002,
                 VsmVpStatus = 0x000D0003,
                 VsmPartitionStatus = 0x000D0004,
@@ -12,6 +12,8 @@
 use crate::signed_measurement::generate_vbs_measurement;
 use crate::vp_context_builder::snp::InjectionType;
 use crate::vp_context_builder::snp::SnpVpContextBuilder;
+use crate::vp_context_builder::snp::DEFAULT_TRAMPOLINE_PAGE;
+use crate::vp_context_builder::snp::DEFAULT_XCR0;
 use crate::vp_context_builder::tdx::TdxVpContextBuilder;
 use crate::vp_context_builder::vbs::VbsRegister;
 use crate::vp_context_builder::vbs::VbsVpContextBuilder;
@@ -198,6 +200,8 @@ fn init(
                         !with_paravisor,
                         shared_gpa_boundary,
                         injection_type,
+                        DEFAULT_XCR0,
+                        DEFAULT_TRAMPOLINE_PAGE,
                     )
                     .expect("must be valid"),
                 );
@@ -560,16 +564,18 @@ pub fn finalize(mut self, guest_svn: u32) -> anyhow::Result<IgvmOutput> {
             .take()
             .expect("should never be none")
             .finalize()
+            .context("failed to finalize vp context")?
             .into_iter()
         {
             match context {
                 VpContextState::Page(VpContextPageState {
+                    tag,
                     page_base,
                     page_count,
                     acceptance,
                     data,
                 }) => {
-                    self.import_pages(page_base, page_count, "vp-context-page", acceptance, &data)
+                    self.import_pages(page_base, page_count, tag, acceptance, &data)
                         .context("failed to import vp context page")?;
                 }
                 VpContextState::Directive(directive) => {
@@ -1258,6 +1264,40 @@ fn test_snp_measurement() {
         assert_eq!(ref_ld, snp_measurement.series[0].reference.snp_ld);
     }
 
+    #[test]
+    fn test_snp_trampoline_overlap() {
+        use igvm_defs::SnpPolicy;
+        let mut loader = IgvmLoader::<X86Register>::new(
+            false,
+            LoaderIsolationType::Snp {
+                shared_gpa_boundary_bits: Some(39),
+                policy: SnpPolicy::from((0x1 << 17) | (0x1 << 16) | (0x1f)),
+                injection_type: InjectionType::Restricted,
+            },
+        );
+        // The trampoline for UEFI without a paravisor is placed at page zero,
+        // which is already used by the image.
+        let data = vec![0, 5];
+        loader
+            .import_pages(
+                DEFAULT_TRAMPOLINE_PAGE,
+                1,
+                "data",
+                BootPageAcceptance::Exclusive,
+                &data,
+            )
+            .unwrap();
+        loader
+            .set_vp_context_page(Vtl::Vtl0, 0x10, BootPageAcceptance::VpContext)
+            .unwrap();
+        loader
+            .import_vp_register(Vtl::Vtl0, X86Register::Rip(0xfff0))
+            .unwrap();
+
+        let err = loader.finalize(1).unwrap_err();
+        assert!(format!("{err:#}").contains("snp-trampoline"), "{err:#}");
+    }
+
     #[test]
     fn test_tdx_measurement() {
         let ref_mrtd: [u8; 48] = [
@@ -13,6 +13,9 @@
 
 /// Holds memory state representing a VP context that should be imported.
 pub struct VpContextPageState {
+    /// The tag to report for these pages, such as when they overlap other
+    /// imported pages.
+    pub tag: &'static str,
     pub page_base: u64,
     pub page_count: u64,
     pub acceptance: BootPageAcceptance,
@@ -43,5 +46,7 @@ pub trait VpContextBuilder {
 
     /// Finalize all VP context data. Returns architecture specific data that should be either imported
     /// into guest memory space or added directly to the IGVM file.
-    fn finalize(self: Box<Self>) -> Vec<VpContextState>;
+    ///
+    /// Fails if the imported VP context is not valid.
+    fn finalize(self: Box<Self>) -> anyhow::Result<Vec<VpContextState>>;
 }
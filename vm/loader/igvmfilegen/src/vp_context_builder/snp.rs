@@ -8,16 +8,26 @@
 use crate::vp_context_builder::VpContextBuilder;
 use crate::vp_context_builder::VpContextPageState;
 use crate::vp_context_builder::VpContextState;
+use anyhow::Context;
 use hvdef::Vtl;
 use igvm_defs::PAGE_SIZE_4K;
 use loader::importer::BootPageAcceptance;
 use loader::importer::SegmentRegister;
 use loader::importer::TableRegister;
 use loader::importer::X86Register;
-use loader::paravisor::HCL_SECURE_VTL;
 use std::fmt::Debug;
 use x86defs::snp::SevSelector;
 use x86defs::snp::SevVmsa;
+use x86defs::xsave::X86X_XSAVE_USER_FEATURES;
+use x86defs::xsave::XFEATURE_AVX512;
+use x86defs::xsave::XFEATURE_SSE;
+use x86defs::xsave::XFEATURE_X87;
+use x86defs::xsave::XFEATURE_YMM;
+use x86defs::X64_CR0_PE;
+use x86defs::X64_CR0_PG;
+use x86defs::X64_CR4_PAE;
+use x86defs::X64_EFER_LMA;
+use x86defs::X64_EFER_LME;
 use x86defs::X64_EFER_SVME;
 use zerocopy::AsBytes;
 use zerocopy::FromZeroes;
@@ -63,14 +73,11 @@ fn set_vp_context_memory(&mut self, page_base: u64, acceptance: BootPageAcceptan
         }
     }
 
-    fn finalize(self) -> Vec<VpContextState> {
+    fn finalize(self) -> anyhow::Result<Vec<VpContextState>> {
         match self {
-            SnpVpContext::None => Vec::new(),
+            SnpVpContext::None => Ok(Vec::new()),
             SnpVpContext::Hardware(hardware_context) => hardware_context.finalize(),
-            SnpVpContext::Vbs(vbs_context) => match vbs_context.finalize() {
-                None => Vec::new(),
-                Some(state) => vec![state],
-            },
+            SnpVpContext::Vbs(vbs_context) => Ok(vbs_context.finalize()?.into_iter().collect()),
         }
     }
 }
@@ -84,12 +91,46 @@ pub enum InjectionType {
     Restricted,
 }
 
+/// The hardware reset value for XCR0, which only enables legacy x87 state.
+pub const DEFAULT_XCR0: u64 = XFEATURE_X87;
+
+/// The default page number for the trampoline that accepts the lower 1mb.
+pub const DEFAULT_TRAMPOLINE_PAGE: u64 = 0;
+
+/// The bits of DR7 that must be zero: bits 11, 12, 14 and 15, and the upper
+/// 32 bits.
+const DR7_RESERVED: u64 = 0xffff_ffff_0000_d800;
+
+/// Validates that `xcr0` is a feature mask that XSETBV would accept.
+fn validate_xcr0(xcr0: u64) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        xcr0 & !X86X_XSAVE_USER_FEATURES == 0,
+        "unsupported xcr0 features {:#x}",
+        xcr0 & !X86X_XSAVE_USER_FEATURES
+    );
+    anyhow::ensure!(xcr0 & XFEATURE_X87 != 0, "xcr0 must enable x87 state");
+    anyhow::ensure!(
+        xcr0 & XFEATURE_YMM == 0 || xcr0 & XFEATURE_SSE != 0,
+        "xcr0 AVX state requires SSE state"
+    );
+    anyhow::ensure!(
+        xcr0 & XFEATURE_AVX512 == 0
+            || (xcr0 & XFEATURE_AVX512 == XFEATURE_AVX512 && xcr0 & XFEATURE_YMM != 0),
+        "xcr0 AVX-512 state must be enabled together with AVX state"
+    );
+    Ok(())
+}
+
 /// A hardware SNP VP context, that is imported as a VMSA.
 #[derive(Debug)]
 struct SnpHardwareContext {
     /// If an assembly stub to accept the lower 1mb should be imported as page
     /// data.
     accept_lower_1mb: bool,
+    /// The page number to import the accept lower 1mb stub at.
+    trampoline_page: u64,
+    /// If the VMSA may start executing at RIP 0.
+    allow_zero_rip: bool,
     /// The acceptance to import this vp context as. This must be
     /// [`BootPageAcceptance::VpContext`].
     acceptance: Option<BootPageAcceptance>,
@@ -105,6 +146,8 @@ fn new(
         enlightened_uefi: bool,
         shared_gpa_boundary: u64,
         injection_type: InjectionType,
+        xcr0: u64,
+        trampoline_page: u64,
     ) -> Self {
         let mut vmsa: SevVmsa = FromZeroes::new_zeroed();
 
@@ -124,11 +167,11 @@ fn new(
             vmsa.sev_features.set_restrict_injection(true);
         } else {
             // Lower VTLs like VTL0 images (UEFI) are SevFeatureAlternateInjection,
-            // while VTL2 (HCL) is SevFeatureRestrictInjection
-            // Additionally, set the BTB isolation and Prevent Host IBS property for
-            // VTL2. VTL2 is responsible for setting this property on any additional
-            // VMSAs.
-            if vtl < HCL_SECURE_VTL {
+            // while higher VTLs (VTL1, or VTL2 for the HCL) are
+            // SevFeatureRestrictInjection. Additionally, set the BTB isolation
+            // and Prevent Host IBS property for higher VTLs, which are
+            // responsible for setting this property on any additional VMSAs.
+            if vtl == Vtl::Vtl0 {
                 vmsa.sev_features
                     .set_alternate_injection(injection_type == InjectionType::Restricted);
             } else {
@@ -142,12 +185,15 @@ fn new(
             }
         }
 
-        // Configure the hardware reset value for XFEM.  The HCL will execute XSETBV if it needs
-        // additional XSAVE support.
-        vmsa.xcr0 = 0x1; // Maps to LegacyX87 bit
+        // Configure the initial value for XFEM. By default this is the hardware
+        // reset value, and the HCL will execute XSETBV if it needs additional
+        // XSAVE support.
+        vmsa.xcr0 = xcr0;
 
         SnpHardwareContext {
             accept_lower_1mb: enlightened_uefi,
+            trampoline_page,
+            allow_zero_rip: false,
             acceptance: None,
             page_number: 0,
             vmsa,
@@ -172,9 +218,11 @@ fn import_register(&mut self, register: X86Register) {
             }
         };
 
+        // Lower VTLs imported in the VBS format accept any register. Only the
+        // VMSA rejects Idtr, Rsp and Rflags.
         match register {
             X86Register::Gdtr(reg) => self.vmsa.gdtr = create_vmsa_table_register(reg),
-            X86Register::Idtr(_) => panic!("Idtr not allowed for SNP"),
+            X86Register::Idtr(_) => panic!("Idtr not allowed for SNP VMSA"),
             X86Register::Ds(reg) => self.vmsa.ds = create_vmsa_segment_register(reg),
             X86Register::Es(reg) => self.vmsa.es = create_vmsa_segment_register(reg),
             X86Register::Fs(reg) => self.vmsa.fs = create_vmsa_segment_register(reg),
@@ -193,13 +241,19 @@ fn import_register(&mut self, register: X86Register) {
             X86Register::Rbp(reg) => self.vmsa.rbp = reg,
             X86Register::Rip(reg) => self.vmsa.rip = reg,
             X86Register::Rsi(reg) => self.vmsa.rsi = reg,
-            X86Register::Rsp(_) => panic!("rsp not allowed for SNP"),
+            X86Register::Rsp(_) => panic!("rsp not allowed for SNP VMSA"),
             X86Register::R8(reg) => self.vmsa.r8 = reg,
             X86Register::R9(reg) => self.vmsa.r9 = reg,
             X86Register::R10(reg) => self.vmsa.r10 = reg,
             X86Register::R11(reg) => self.vmsa.r11 = reg,
             X86Register::R12(reg) => self.vmsa.r12 = reg,
-            X86Register::Rflags(_) => panic!("rflags not allowed for SNP"),
+            X86Register::Rflags(_) => panic!("rflags not allowed for SNP VMSA"),
+            X86Register::Dr0(reg) => self.vmsa.dr0 = reg,
+            X86Register::Dr1(reg) => self.vmsa.dr1 = reg,
+            X86Register::Dr2(reg) => self.vmsa.dr2 = reg,
+            X86Register::Dr3(reg) => self.vmsa.dr3 = reg,
+            X86Register::Dr6(reg) => self.vmsa.dr6 = reg,
+            X86Register::Dr7(reg) => self.vmsa.dr7 = reg,
 
             X86Register::MtrrDefType(_)
             | X86Register::MtrrPhysBase0(_)
@@ -242,26 +296,78 @@ fn set_vp_context_memory(&mut self, page_base: u64, acceptance: BootPageAcceptan
         self.acceptance = Some(acceptance);
     }
 
-    fn finalize(mut self) -> Vec<VpContextState> {
+    /// Checks that the VMSA describes a processor state that the guest can
+    /// start in.
+    fn validate(&self) -> anyhow::Result<()> {
+        // The present bit of the compressed segment attributes.
+        const SEGMENT_PRESENT: u16 = 0x80;
+
+        let vmsa = &self.vmsa;
+        anyhow::ensure!(vmsa.efer & X64_EFER_SVME != 0, "EFER.SVME must be set");
+
+        let paging = vmsa.cr0 & X64_CR0_PG != 0;
+        anyhow::ensure!(
+            !paging || vmsa.cr0 & X64_CR0_PE != 0,
+            "CR0.PG requires CR0.PE, cr0 = {:#x}",
+            vmsa.cr0
+        );
+        anyhow::ensure!(
+            !paging || vmsa.cr3 != 0,
+            "CR3 must be set when paging is enabled"
+        );
+
+        let long_mode = paging && vmsa.efer & X64_EFER_LME != 0;
+        anyhow::ensure!(
+            long_mode == (vmsa.efer & X64_EFER_LMA != 0),
+            "EFER.LMA must be set exactly when EFER.LME and CR0.PG are set, efer = {:#x}, cr0 = {:#x}",
+            vmsa.efer,
+            vmsa.cr0
+        );
+        anyhow::ensure!(
+            !long_mode || vmsa.cr4 & X64_CR4_PAE != 0,
+            "long mode requires CR4.PAE, cr4 = {:#x}",
+            vmsa.cr4
+        );
+
+        if vmsa.cr0 & X64_CR0_PE != 0 {
+            anyhow::ensure!(vmsa.cs.attrib & SEGMENT_PRESENT != 0, "CS must be present");
+            anyhow::ensure!(vmsa.ss.attrib & SEGMENT_PRESENT != 0, "SS must be present");
+        }
+
+        anyhow::ensure!(
+            vmsa.dr7 & DR7_RESERVED == 0,
+            "DR7 reserved bits must be zero, dr7 = {:#x}",
+            vmsa.dr7
+        );
+
+        anyhow::ensure!(self.allow_zero_rip || vmsa.rip != 0, "RIP must be set");
+        Ok(())
+    }
+
+    fn finalize(mut self) -> anyhow::Result<Vec<VpContextState>> {
         let mut state = Vec::new();
 
         let acceptance = match self.acceptance {
-            None => return state,
+            None => return Ok(state),
             Some(acceptance) => acceptance,
         };
 
         assert_eq!(acceptance, BootPageAcceptance::VpContext);
 
+        self.validate().context("invalid snp vmsa")?;
+
         // If no paravisor is present, then generate a trampoline page to perform
         // validation of the low 1 MB of memory.  This is expected by UEFI and
         // normally performed by the HCL, but must be done in a trampoline if no
         // HCL is present.
         if self.accept_lower_1mb {
             let mut trampoline_page = vec![0u8; PAGE_SIZE_4K as usize];
+            let trampoline_gpa = self.trampoline_page * PAGE_SIZE_4K;
 
             // Since this page is discarded immediately after it executes, it can
-            // be placed anywhere in memory.  GPA page zero is a convenient unused
-            // location.
+            // be placed anywhere in memory. By default, GPA page zero is used as
+            // a convenient unused location. The page starts with the address to
+            // jump to once the lower 1mb has been accepted.
             trampoline_page[..8].copy_from_slice(self.vmsa.rip.as_bytes());
 
             // Place a breakpoint at the front of the page to force a triple fault
@@ -271,7 +377,7 @@ fn finalize(mut self) -> Vec<VpContextState> {
 
             // Set RIP to the trampoline page.
             let mut byte_offset = break_offset + 1;
-            self.vmsa.rip = byte_offset as u64;
+            self.vmsa.rip = trampoline_gpa + byte_offset as u64;
 
             let copy_instr =
                 |trampoline_page: &mut Vec<u8>, byte_offset, instruction: &[u8]| -> usize {
@@ -280,12 +386,17 @@ fn finalize(mut self) -> Vec<VpContextState> {
                     byte_offset + instruction.len()
                 };
 
-            // mov esi, 01000h
-            byte_offset = copy_instr(
-                &mut trampoline_page,
-                byte_offset,
-                &[0xBE, 0x00, 0x10, 0x00, 0x00],
-            );
+            // The trampoline page is imported as already accepted, so it must
+            // not be validated again. When it is at page zero, start at the
+            // next page. Otherwise start at page zero and skip the trampoline
+            // page if it is within the lower 1mb.
+            let start_gpa: u32 = if trampoline_gpa == 0 { 0x1000 } else { 0 };
+            let skip_gpa =
+                (trampoline_gpa != 0 && trampoline_gpa < 0x100000).then_some(trampoline_gpa as u32);
+
+            // mov esi, start_gpa
+            byte_offset = copy_instr(&mut trampoline_page, byte_offset, &[0xBE]);
+            byte_offset = copy_instr(&mut trampoline_page, byte_offset, start_gpa.as_bytes());
 
             // mov ebx, 0100000h
             byte_offset = copy_instr(
@@ -307,6 +418,17 @@ fn finalize(mut self) -> Vec<VpContextState> {
             // L1:
             let jump_offset = byte_offset;
 
+            let skip_offset = skip_gpa.map(|skip_gpa| {
+                // cmp esi, skip_gpa
+                byte_offset = copy_instr(&mut trampoline_page, byte_offset, &[0x81, 0xFE]);
+                byte_offset = copy_instr(&mut trampoline_page, byte_offset, skip_gpa.as_bytes());
+
+                // je L2
+                byte_offset = copy_instr(&mut trampoline_page, byte_offset, &[0x74]);
+                byte_offset += 1;
+                byte_offset
+            });
+
             // mov eax, esi
             byte_offset = copy_instr(&mut trampoline_page, byte_offset, &[0x8B, 0xC6]);
 
@@ -326,6 +448,12 @@ fn finalize(mut self) -> Vec<VpContextState> {
             byte_offset += 1;
             trampoline_page[byte_offset - 1] = (break_offset as u8).wrapping_sub(byte_offset as u8);
 
+            // L2:
+            if let Some(skip_offset) = skip_offset {
+                trampoline_page[skip_offset - 1] =
+                    (byte_offset as u8).wrapping_sub(skip_offset as u8);
+            }
+
             // add esi, 01000h
             byte_offset = copy_instr(
                 &mut trampoline_page,
@@ -341,14 +469,18 @@ fn finalize(mut self) -> Vec<VpContextState> {
             byte_offset += 1;
             trampoline_page[byte_offset - 1] = (jump_offset as u8).wrapping_sub(byte_offset as u8);
 
-            // jmp [0]
+            // jmp [rip - (offset of the start of the page)]
+            //
+            // This is RIP-relative, so it reads the target address from the
+            // start of the trampoline page wherever the page is placed.
             byte_offset = copy_instr(&mut trampoline_page, byte_offset, &[0xFF, 0x25]);
             let relative_offset: u32 = 0u32.wrapping_sub(byte_offset as u32 + 4);
             trampoline_page[byte_offset..byte_offset + 4]
                 .copy_from_slice(relative_offset.as_bytes());
 
             state.push(VpContextState::Page(VpContextPageState {
-                page_base: 0,
+                tag: "snp-trampoline",
+                page_base: self.trampoline_page,
                 page_count: 1,
                 acceptance: BootPageAcceptance::Exclusive,
                 data: trampoline_page,
@@ -356,13 +488,14 @@ fn finalize(mut self) -> Vec<VpContextState> {
         }
 
         state.push(VpContextState::Page(VpContextPageState {
+            tag: "snp-vmsa",
             page_base: self.page_number,
             page_count: 1,
             acceptance,
             data: self.vmsa.as_bytes().to_vec(),
         }));
 
-        state
+        Ok(state)
     }
 }
 
@@ -384,6 +517,15 @@ impl SnpVpContextBuilder {
     /// `injection_type` specifies the injection type for the highest enabled
     /// VMPL.
     ///
+    /// `xcr0` specifies the initial XSAVE feature mask for the highest enabled
+    /// VMPL. Use [`DEFAULT_XCR0`] for the hardware reset value.
+    ///
+    /// `trampoline_page` specifies the page number to import the trampoline
+    /// code at, if `enlightened_uefi` is set. It must be below 4GB and must not
+    /// be used by anything else in the image; the loader fails to import it if
+    /// it overlaps pages that were already imported. Use
+    /// [`DEFAULT_TRAMPOLINE_PAGE`] to place it at page zero.
+    ///
     /// Only the highest VTL will have a VMSA generated, with lower VTLs being
     /// imported with the VBS format as page data.
     pub fn new(
@@ -391,7 +533,16 @@ pub fn new(
         enlightened_uefi: bool,
         shared_gpa_boundary: u64,
         injection_type: InjectionType,
+        xcr0: u64,
+        trampoline_page: u64,
     ) -> anyhow::Result<Self> {
+        validate_xcr0(xcr0)?;
+        // The trampoline code uses 32-bit addresses.
+        anyhow::ensure!(
+            trampoline_page < (1 << 32) / PAGE_SIZE_4K,
+            "trampoline page {trampoline_page:#x} must be below 4GB"
+        );
+
         let mut contexts = [SnpVpContext::None, SnpVpContext::None, SnpVpContext::None];
 
         match max_vtl {
@@ -401,9 +552,22 @@ pub fn new(
                     enlightened_uefi,
                     shared_gpa_boundary,
                     injection_type,
+                    xcr0,
+                    trampoline_page,
+                ))
+            }
+            Vtl::Vtl1 => {
+                // Treat VTL0 as the VBS format.
+                contexts[0] = SnpVpContext::Vbs(VbsVpContext::new(0));
+                contexts[1] = SnpVpContext::Hardware(SnpHardwareContext::new(
+                    Vtl::Vtl1,
+                    enlightened_uefi,
+                    shared_gpa_boundary,
+                    injection_type,
+                    xcr0,
+                    trampoline_page,
                 ))
             }
-            Vtl::Vtl1 => anyhow::bail!("VTL1 import state not supported for SNP"),
             Vtl::Vtl2 => {
                 // Treat VTL0 as the VBS format.
                 contexts[0] = SnpVpContext::Vbs(VbsVpContext::new(0));
@@ -412,12 +576,25 @@ pub fn new(
                     enlightened_uefi,
                     shared_gpa_boundary,
                     injection_type,
+                    xcr0,
+                    trampoline_page,
                 ))
             }
         }
 
         Ok(Self { contexts })
     }
+
+    /// Allows the VMSA to start executing at RIP 0, for images whose entry
+    /// point is at the bottom of the code segment. By default a zero RIP is
+    /// rejected as a missing register.
+    pub fn allow_zero_rip(&mut self, allow: bool) {
+        for context in &mut self.contexts {
+            if let SnpVpContext::Hardware(context) = context {
+                context.allow_zero_rip = allow;
+            }
+        }
+    }
 }
 
 impl VpContextBuilder for SnpVpContextBuilder {
@@ -435,13 +612,251 @@ fn set_vp_context_memory(&mut self, vtl: Vtl, page_base: u64, acceptance: BootPa
         self.contexts[vtl as usize].set_vp_context_memory(page_base, acceptance);
     }
 
-    fn finalize(self: Box<Self>) -> Vec<VpContextState> {
+    fn finalize(self: Box<Self>) -> anyhow::Result<Vec<VpContextState>> {
         let mut state = Vec::new();
 
         for context in self.contexts {
-            state.extend(context.finalize())
+            state.extend(context.finalize()?)
         }
 
-        state
+        Ok(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use igvm::IgvmDirectiveHeader;
+    use x86defs::SegmentAttributes;
+    use x86defs::X64_DEFAULT_CODE_SEGMENT_ATTRIBUTES;
+    use x86defs::X64_DEFAULT_DATA_SEGMENT_ATTRIBUTES;
+    use x86defs::X64_EMPTY_DR7;
+    use zerocopy::FromBytes;
+
+    #[test]
+    fn vtl1_vmsa() {
+        let mut builder = Box::new(
+            SnpVpContextBuilder::new(
+                Vtl::Vtl1,
+                false,
+                0,
+                InjectionType::Restricted,
+                DEFAULT_XCR0,
+                0,
+            )
+            .unwrap(),
+        );
+        builder.set_vp_context_memory(Vtl::Vtl1, 0x10, BootPageAcceptance::VpContext);
+        builder.import_vp_register(Vtl::Vtl1, X86Register::Rip(0x1234));
+
+        let state = builder.finalize().unwrap();
+        let [VpContextState::Page(page)] = state.as_slice() else {
+            panic!("expected a single vmsa page");
+        };
+        assert_eq!(page.page_base, 0x10);
+        assert_eq!(page.acceptance, BootPageAcceptance::VpContext);
+
+        let vmsa = SevVmsa::read_from_prefix(page.data.as_slice()).unwrap();
+        assert_eq!(vmsa.rip, 0x1234);
+        assert!(vmsa.sev_features.restrict_injection());
+        assert!(vmsa.sev_features.snp_btb_isolation());
+        assert!(!vmsa.sev_features.vtom());
+    }
+
+    #[test]
+    fn xcr0() {
+        let xcr0 = XFEATURE_X87 | XFEATURE_SSE | XFEATURE_YMM;
+        let mut builder = Box::new(
+            SnpVpContextBuilder::new(Vtl::Vtl0, false, 0, InjectionType::Normal, xcr0, 0).unwrap(),
+        );
+        builder.set_vp_context_memory(Vtl::Vtl0, 0x10, BootPageAcceptance::VpContext);
+        builder.import_vp_register(Vtl::Vtl0, X86Register::Rip(0x1234));
+
+        let state = builder.finalize().unwrap();
+        let [VpContextState::Page(page)] = state.as_slice() else {
+            panic!("expected a single vmsa page");
+        };
+        let vmsa = SevVmsa::read_from_prefix(page.data.as_slice()).unwrap();
+        assert_eq!(vmsa.xcr0, xcr0);
+
+        // AVX state cannot be enabled without SSE state.
+        SnpVpContextBuilder::new(
+            Vtl::Vtl0,
+            false,
+            0,
+            InjectionType::Normal,
+            XFEATURE_X87 | XFEATURE_YMM,
+            0,
+        )
+        .unwrap_err();
+    }
+
+    #[test]
+    fn vbs_lower_vtl_rsp() {
+        let mut builder = Box::new(
+            SnpVpContextBuilder::new(
+                Vtl::Vtl2,
+                false,
+                0,
+                InjectionType::Restricted,
+                DEFAULT_XCR0,
+                0,
+            )
+            .unwrap(),
+        );
+        builder.import_vp_register(Vtl::Vtl0, X86Register::Rsp(0x8000));
+        builder.import_vp_register(Vtl::Vtl0, X86Register::Rflags(0x2));
+
+        let state = builder.finalize().unwrap();
+        let [VpContextState::Directive(IgvmDirectiveHeader::X64VbsVpContext { registers, .. })] =
+            state.as_slice()
+        else {
+            panic!("expected a single vbs vp context");
+        };
+        let registers = registers
+            .iter()
+            .map(|&reg| X86Register::from(reg))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            registers,
+            [X86Register::Rsp(0x8000), X86Register::Rflags(0x2)]
+        );
+    }
+
+    #[test]
+    fn trampoline_base() {
+        const TRAMPOLINE_PAGE: u64 = 0x20;
+        const TRAMPOLINE_GPA: u64 = TRAMPOLINE_PAGE * PAGE_SIZE_4K;
+
+        let mut builder = Box::new(
+            SnpVpContextBuilder::new(
+                Vtl::Vtl0,
+                true,
+                0,
+                InjectionType::Restricted,
+                DEFAULT_XCR0,
+                TRAMPOLINE_PAGE,
+            )
+            .unwrap(),
+        );
+        builder.set_vp_context_memory(Vtl::Vtl0, 0x10, BootPageAcceptance::VpContext);
+        builder.import_vp_register(Vtl::Vtl0, X86Register::Rip(0xfff0));
+
+        let state = builder.finalize().unwrap();
+        let [VpContextState::Page(trampoline), VpContextState::Page(vmsa)] = state.as_slice()
+        else {
+            panic!("expected a trampoline page and a vmsa page");
+        };
+        assert_eq!(trampoline.page_base, TRAMPOLINE_PAGE);
+        assert_eq!(trampoline.acceptance, BootPageAcceptance::Exclusive);
+        let data = trampoline.data.as_slice();
+        assert_eq!(u64::read_from_prefix(data).unwrap(), 0xfff0);
+
+        // The VP starts in the trampoline code.
+        let vmsa = SevVmsa::read_from_prefix(vmsa.data.as_slice()).unwrap();
+        assert_eq!(vmsa.rip, TRAMPOLINE_GPA + 9);
+
+        // The trampoline page itself is skipped when validating.
+        let skip = (TRAMPOLINE_GPA as u32).to_le_bytes();
+        assert!(data
+            .windows(6)
+            .any(|w| w[..2] == [0x81, 0xFE] && w[2..] == skip));
+
+        // The final jump reads its target from the start of the page.
+        let jmp = data
+            .windows(2)
+            .position(|w| w == [0xFF, 0x25])
+            .expect("should have a jump");
+        let next_rip = jmp + 6;
+        let disp = i32::read_from_prefix(&data[jmp + 2..]).unwrap();
+        assert_eq!(
+            (TRAMPOLINE_GPA + next_rip as u64).wrapping_add_signed(disp.into()),
+            TRAMPOLINE_GPA
+        );
+    }
+
+    /// Returns a VTL0 builder with `registers` imported on top of a valid long
+    /// mode state.
+    fn long_mode_builder(registers: &[X86Register]) -> Box<SnpVpContextBuilder> {
+        let segment = |selector, attributes: SegmentAttributes| SegmentRegister {
+            base: 0,
+            limit: 0xffffffff,
+            selector,
+            attributes: attributes.as_bits(),
+        };
+
+        let mut builder = Box::new(
+            SnpVpContextBuilder::new(
+                Vtl::Vtl0,
+                false,
+                0,
+                InjectionType::Normal,
+                DEFAULT_XCR0,
+                DEFAULT_TRAMPOLINE_PAGE,
+            )
+            .unwrap(),
+        );
+        builder.set_vp_context_memory(Vtl::Vtl0, 0x10, BootPageAcceptance::VpContext);
+        for register in [
+            X86Register::Cr0(X64_CR0_PE | X64_CR0_PG),
+            X86Register::Cr3(0x1000),
+            X86Register::Cr4(X64_CR4_PAE),
+            X86Register::Efer(X64_EFER_LME | X64_EFER_LMA),
+            X86Register::Cs(segment(0x8, X64_DEFAULT_CODE_SEGMENT_ATTRIBUTES)),
+            X86Register::Ss(segment(0x10, X64_DEFAULT_DATA_SEGMENT_ATTRIBUTES)),
+            X86Register::Rip(0x1234),
+        ]
+        .iter()
+        .chain(registers)
+        {
+            builder.import_vp_register(Vtl::Vtl0, *register);
+        }
+        builder
+    }
+
+    /// Finalizes a VTL0 VMSA with `registers` imported on top of a valid long
+    /// mode state.
+    fn finalize_long_mode(registers: &[X86Register]) -> anyhow::Result<Vec<VpContextState>> {
+        long_mode_builder(registers).finalize()
+    }
+
+    #[test]
+    fn validate_vmsa() {
+        finalize_long_mode(&[]).unwrap();
+
+        // Paging without a page table.
+        finalize_long_mode(&[X86Register::Cr3(0)]).unwrap_err();
+
+        // Long mode enabled by EFER.LME but not reported as active.
+        finalize_long_mode(&[X86Register::Efer(X64_EFER_LME)]).unwrap_err();
+
+        // RIP 0 is rejected unless explicitly allowed.
+        finalize_long_mode(&[X86Register::Rip(0)]).unwrap_err();
+        let mut builder = long_mode_builder(&[X86Register::Rip(0)]);
+        builder.allow_zero_rip(true);
+        let state = builder.finalize().unwrap();
+        let [VpContextState::Page(page)] = state.as_slice() else {
+            panic!("expected a single vmsa page");
+        };
+        assert_eq!(
+            SevVmsa::read_from_prefix(page.data.as_slice()).unwrap().rip,
+            0
+        );
+    }
+
+    #[test]
+    fn debug_registers() {
+        // An execution breakpoint on DR0, enabled by DR7.L0.
+        let dr7 = X64_EMPTY_DR7 | 1;
+        let state = finalize_long_mode(&[X86Register::Dr0(0x5000), X86Register::Dr7(dr7)]).unwrap();
+        let [VpContextState::Page(page)] = state.as_slice() else {
+            panic!("expected a single vmsa page");
+        };
+        let vmsa = SevVmsa::read_from_prefix(page.data.as_slice()).unwrap();
+        assert_eq!(vmsa.dr0, 0x5000);
+        assert_eq!(vmsa.dr7, dr7);
+
+        // Bit 12 of DR7 is reserved.
+        finalize_long_mode(&[X86Register::Dr7(dr7 | 0x1000)]).unwrap_err();
     }
 }
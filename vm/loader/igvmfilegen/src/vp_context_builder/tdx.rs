@@ -133,6 +133,12 @@ fn import_register(&mut self, register: X86Register) {
             X86Register::R11(r11) => self.trampoline_context.r11 = r11,
             X86Register::R12(_) => panic!("r12 not allowed for tdx"),
             X86Register::Rflags(_) => panic!("rflags not allowed for tdx"),
+            X86Register::Dr0(_)
+            | X86Register::Dr1(_)
+            | X86Register::Dr2(_)
+            | X86Register::Dr3(_)
+            | X86Register::Dr6(_)
+            | X86Register::Dr7(_) => panic!("debug registers not allowed for tdx"),
 
             X86Register::MtrrDefType(_)
             | X86Register::MtrrPhysBase0(_)
@@ -506,6 +512,7 @@ fn finalize(mut self) -> VpContextState {
 
         // Add this data to the architectural reset page.
         VpContextState::Page(VpContextPageState {
+            tag: "tdx-reset-page",
             page_base: 0xFFFFF,
             page_count: 1,
             acceptance: loader::importer::BootPageAcceptance::Exclusive,
@@ -552,10 +559,10 @@ fn set_vp_context_memory(
         }
     }
 
-    fn finalize(self) -> Option<VpContextState> {
+    fn finalize(self) -> anyhow::Result<Option<VpContextState>> {
         match self {
-            TdxVpContext::None => None,
-            TdxVpContext::Hardware(hardware_context) => Some(hardware_context.finalize()),
+            TdxVpContext::None => Ok(None),
+            TdxVpContext::Hardware(hardware_context) => Ok(Some(hardware_context.finalize())),
             TdxVpContext::Vbs(vbs_context) => vbs_context.finalize(),
         }
     }
@@ -606,15 +613,15 @@ fn vp_context_page(&self, vtl: Vtl) -> anyhow::Result<u64> {
         self.contexts[vtl as usize].vp_context_page()
     }
 
-    fn finalize(self: Box<Self>) -> Vec<VpContextState> {
+    fn finalize(self: Box<Self>) -> anyhow::Result<Vec<VpContextState>> {
         let mut contexts = Vec::new();
 
         for context in self.contexts {
-            if let Some(v) = context.finalize() {
+            if let Some(v) = context.finalize()? {
                 contexts.push(v);
             }
         }
 
-        contexts
+        Ok(contexts)
     }
 }
@@ -23,33 +23,34 @@
 /// [`IgvmDirectiveHeader`] types.
 pub trait VbsRegister: Sized {
     /// Convert the list of registers into the corresponding
-    /// [`IgvmDirectiveHeader`] for this architecture.
-    fn into_igvm_header(vtl: Vtl, list: Vec<Self>) -> IgvmDirectiveHeader;
+    /// [`IgvmDirectiveHeader`] for this architecture, failing if a register
+    /// cannot be described in a VBS VP context.
+    fn into_igvm_header(vtl: Vtl, list: Vec<Self>) -> anyhow::Result<IgvmDirectiveHeader>;
 }
 
 impl VbsRegister for X86Register {
-    fn into_igvm_header(vtl: Vtl, list: Vec<Self>) -> IgvmDirectiveHeader {
-        IgvmDirectiveHeader::X64VbsVpContext {
+    fn into_igvm_header(vtl: Vtl, list: Vec<Self>) -> anyhow::Result<IgvmDirectiveHeader> {
+        Ok(IgvmDirectiveHeader::X64VbsVpContext {
             registers: list
                 .into_iter()
-                .map(|reg| reg.into())
-                .collect::<Vec<igvm::registers::X86Register>>(),
+                .map(|reg| reg.try_into())
+                .collect::<Result<Vec<igvm::registers::X86Register>, _>>()?,
             vtl: (vtl as u8).try_into().expect("vtl should be valid"),
             compatibility_mask: DEFAULT_COMPATIBILITY_MASK,
-        }
+        })
     }
 }
 
 impl VbsRegister for Aarch64Register {
-    fn into_igvm_header(vtl: Vtl, list: Vec<Self>) -> IgvmDirectiveHeader {
-        IgvmDirectiveHeader::AArch64VbsVpContext {
+    fn into_igvm_header(vtl: Vtl, list: Vec<Self>) -> anyhow::Result<IgvmDirectiveHeader> {
+        Ok(IgvmDirectiveHeader::AArch64VbsVpContext {
             registers: list
                 .into_iter()
                 .map(|reg| reg.into())
                 .collect::<Vec<igvm::registers::AArch64Register>>(),
             vtl: (vtl as u8).try_into().expect("vtl should be valid"),
             compatibility_mask: DEFAULT_COMPATIBILITY_MASK,
-        }
+        })
     }
 }
 
@@ -106,19 +107,19 @@ pub fn set_vp_context_memory(&mut self, page_base: u64, acceptance: BootPageAcce
         self.acceptance = Some(acceptance);
     }
 
-    pub fn finalize(self) -> Option<VpContextState> {
+    pub fn finalize(self) -> anyhow::Result<Option<VpContextState>> {
         if self.registers.is_empty() {
-            None
+            Ok(None)
         } else {
             let header = R::into_igvm_header(
                 self.vtl.try_into().expect("vtl should be valid"),
                 self.registers,
-            );
+            )?;
 
-            match self.acceptance {
+            let state = match self.acceptance {
                 None => {
                     // Serialize as a VP context IGVM header.
-                    Some(VpContextState::Directive(header))
+                    VpContextState::Directive(header)
                 }
                 Some(acceptance) => {
                     // Serialize the same binary format as an IGVM header, but instead to be deposited as page data.
@@ -132,14 +133,16 @@ pub fn finalize(self) -> Option<VpContextState> {
 
                     assert!(file_data.len() <= PAGE_SIZE_4K as usize);
 
-                    Some(VpContextState::Page(VpContextPageState {
+                    VpContextState::Page(VpContextPageState {
+                        tag: "vbs-vp-context",
                         page_base: self.page_number,
                         page_count: 1,
                         acceptance,
                         data: file_data,
-                    }))
+                    })
                 }
-            }
+            };
+            Ok(Some(state))
         }
     }
 }
@@ -182,18 +185,18 @@ fn set_vp_context_memory(&mut self, vtl: Vtl, page_base: u64, acceptance: BootPa
         self.contexts[vtl as usize].set_vp_context_memory(page_base, acceptance);
     }
 
-    fn finalize(self: Box<Self>) -> Vec<VpContextState> {
+    fn finalize(self: Box<Self>) -> anyhow::Result<Vec<VpContextState>> {
         // TODO: Importing VTL1 state not currently supported.
         assert!(self.contexts[1].registers.is_empty());
 
         let mut state = Vec::new();
 
         for context in self.contexts {
-            if let Some(v) = context.finalize() {
+            if let Some(v) = context.finalize()? {
                 state.push(v);
             }
         }
 
-        state
+        Ok(state)
     }
 }
@@ -10,6 +10,7 @@
 pub use X86Register as Register;
 
 use hvdef::Vtl;
+use thiserror::Error;
 
 /// The page acceptance used for importing pages into the initial launch context
 /// of the guest.
@@ -143,6 +144,12 @@ pub enum X86Register {
     R11(u64),
     R12(u64),
     Rflags(u64),
+    Dr0(u64),
+    Dr1(u64),
+    Dr2(u64),
+    Dr3(u64),
+    Dr6(u64),
+    Dr7(u64),
     MtrrDefType(u64),
     MtrrPhysBase0(u64),
     MtrrPhysMask0(u64),
@@ -212,10 +219,17 @@ fn from(value: igvm::registers::X86Register) -> Self {
     }
 }
 
-impl From<X86Register> for igvm::registers::X86Register {
-    fn from(value: X86Register) -> Self {
+/// Error returned when a register cannot be described in a VBS VP context.
+#[derive(Debug, Error)]
+#[error("register {0:x?} is not supported in a vbs vp context")]
+pub struct UnsupportedVbsRegister(pub X86Register);
+
+impl TryFrom<X86Register> for igvm::registers::X86Register {
+    type Error = UnsupportedVbsRegister;
+
+    fn try_from(value: X86Register) -> Result<Self, Self::Error> {
         use igvm::registers::X86Register as igvm_reg;
-        match value {
+        let reg = match value {
             X86Register::Gdtr(v) => igvm_reg::Gdtr(v.into()),
             X86Register::Idtr(v) => igvm_reg::Idtr(v.into()),
             X86Register::Ds(v) => igvm_reg::Ds(v.into()),
@@ -240,6 +254,12 @@ fn from(value: X86Register) -> Self {
             X86Register::R11(v) => igvm_reg::R11(v),
             X86Register::R12(v) => igvm_reg::R12(v),
             X86Register::Rflags(v) => igvm_reg::Rflags(v),
+            X86Register::Dr0(_)
+            | X86Register::Dr1(_)
+            | X86Register::Dr2(_)
+            | X86Register::Dr3(_)
+            | X86Register::Dr6(_)
+            | X86Register::Dr7(_) => return Err(UnsupportedVbsRegister(value)),
             X86Register::MtrrDefType(v) => igvm_reg::MtrrDefType(v),
             X86Register::MtrrPhysBase0(v) => igvm_reg::MtrrPhysBase0(v),
             X86Register::MtrrPhysMask0(v) => igvm_reg::MtrrPhysMask0(v),
@@ -257,7 +277,8 @@ fn from(value: X86Register) -> Self {
             X86Register::MtrrFix4kE8000(v) => igvm_reg::MtrrFix4kE8000(v),
             X86Register::MtrrFix4kF0000(v) => igvm_reg::MtrrFix4kF0000(v),
             X86Register::MtrrFix4kF8000(v) => igvm_reg::MtrrFix4kF8000(v),
-        }
+        };
+        Ok(reg)
     }
 }
 
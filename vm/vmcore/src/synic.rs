@@ -18,6 +18,17 @@ pub trait MessagePort: Send + Sync {
     fn handle_message(&self, msg: &[u8], trusted: bool) -> bool;
 }
 
+/// How the guest is told that a message port could not accept a message.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BackpressureBehavior {
+    /// Fail the post with `HV_STATUS_INSUFFICIENT_BUFFERS`. The message is
+    /// dropped.
+    Drop,
+    /// Fail the post with `HV_STATUS_TIMEOUT`, which asks the guest to retry
+    /// the post later.
+    Retry,
+}
+
 pub trait EventPort: Send + Sync {
     fn handle_event(&self, flag: u16);
     fn os_event(&self) -> Option<&pal_event::Event> {
@@ -37,11 +48,15 @@ pub enum Error {
 pub trait SynicPortAccess: Send + Sync {
     /// Adds a host message port, which gets notified when the guest calls
     /// `HvPostMessage`.
+    ///
+    /// `backpressure` determines how the post fails when `port` cannot accept
+    /// the message.
     fn add_message_port(
         &self,
         connection_id: u32,
         minimum_vtl: Vtl,
         port: Arc<dyn MessagePort>,
+        backpressure: BackpressureBehavior,
     ) -> Result<Box<dyn Sync + Send>, Error>;
 
     /// Adds a host event port, which gets notified when the guest calls
@@ -294,6 +294,9 @@ fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
 #[derive(Clone)]
 pub struct ResourceResolver {
     resolvers: Arc<Vec<(ResolverKey, Arc<dyn Any + Send + Sync>)>>,
+    /// Resolvers that take precedence over both the static and the dynamic
+    /// resolvers.
+    overrides: Arc<Vec<(ResolverKey, Arc<dyn Any + Send + Sync>)>>,
 }
 
 impl Inspect for ResourceResolver {
@@ -315,6 +318,11 @@ fn inspect(&self, req: inspect::Request<'_>) {
                 req.respond();
             });
         }
+        for (key, _) in &*self.overrides {
+            resp.child(&format!("{}/{}", key.kind, key.id), |req| {
+                req.respond().field("override", true);
+            });
+        }
     }
 }
 
@@ -524,6 +532,7 @@ pub fn new() -> Self {
 
         Self {
             resolvers: Arc::new(Vec::new()),
+            overrides: Arc::new(Vec::new()),
         }
     }
 
@@ -577,10 +586,79 @@ pub fn add_async_resolver<K, O, T, R>(&mut self, resolver: R)
         Arc::make_mut(&mut self.resolvers).push((key, Arc::new(resolver)));
     }
 
+    /// Overrides the resolver for a resource type, replacing any static,
+    /// dynamic, or previously overridden resolver for it.
+    ///
+    /// This is intended for tests and embedders that need to substitute a
+    /// resolver, such as a mock disk resolver, while keeping the rest. Since
+    /// the resolver list is shared between clones, override a clone to get a
+    /// new resolver without affecting the original:
+    ///
+    /// ```ignore
+    /// let mut resolver = resolver.clone();
+    /// resolver.override_resolver::<DiskHandleKind, _, FileDiskHandle, _>(MockDiskResolver);
+    /// ```
+    pub fn override_resolver<K, O, T, R>(&mut self, resolver: R)
+    where
+        K: CanResolveTo<O>,
+        O: 'static,
+        T: ResourceId<K> + MeshPayload,
+        R: 'static + ResolveResource<K, T, Output = O>,
+    {
+        let resolver = TypedResolver::<T, _> {
+            resolver,
+            _phantom: PhantomData,
+        };
+        self.push_override::<K, O, T>(UntypedResolver(Box::new(resolver)));
+    }
+
+    /// Overrides the resolver for a resource type with an async resolver.
+    ///
+    /// See [`Self::override_resolver`].
+    pub fn override_async_resolver<K, O, T, R>(&mut self, resolver: R)
+    where
+        K: CanResolveTo<O>,
+        O: 'static,
+        T: ResourceId<K> + MeshPayload,
+        R: 'static + AsyncResolveResource<K, T, Output = O>,
+    {
+        let resolver = TypedAsyncResolver::<T, _> {
+            resolver,
+            _phantom: PhantomData,
+        };
+        self.push_override::<K, O, T>(UntypedResolver(Box::new(resolver)));
+    }
+
+    fn push_override<K, O, T>(&mut self, resolver: UntypedResolver<K, O>)
+    where
+        K: CanResolveTo<O>,
+        O: 'static,
+        T: ResourceId<K>,
+    {
+        let key = ResolverKey {
+            kind: K::NAME,
+            id: T::ID,
+        };
+        let overrides = Arc::make_mut(&mut self.overrides);
+        overrides.retain(|(k, _)| *k != key);
+        overrides.push((key, Arc::new(resolver)));
+    }
+
     fn find_resolver<K: CanResolveTo<O>, O: 'static>(
         &self,
         id: &str,
     ) -> Option<&dyn DynResolveResource<K, O>> {
+        for (key, resolver) in &*self.overrides {
+            if key.kind == K::NAME && key.id == id {
+                return Some(
+                    resolver
+                        .downcast_ref::<UntypedResolver<K, O>>()
+                        .unwrap()
+                        .0
+                        .as_ref(),
+                );
+            }
+        }
         for private::StaticResolver { key, resolver } in private::STATIC_RESOLVERS
             .iter()
             .copied()
@@ -731,4 +809,38 @@ async fn test_resources() {
 
         assert_eq!(resolver.resolve(x, ()).await.unwrap().result, "10");
     }
+
+    struct MockResolver;
+
+    impl ResolveResource<TestHandleKind, TestHandle> for MockResolver {
+        type Output = TestConcreteObject;
+        type Error = Infallible;
+
+        fn resolve(&self, resource: TestHandle, _: ()) -> Result<TestConcreteObject, Self::Error> {
+            Ok(TestConcreteObject {
+                result: format!("mock {}", resource.valuex2),
+            })
+        }
+    }
+
+    #[async_test]
+    async fn override_resolver() {
+        let resolver = ResourceResolver::new();
+        let mut overridden = resolver.clone();
+        overridden.override_resolver::<TestHandleKind, _, TestHandle, _>(MockResolver);
+
+        let handle = || Resource::new(TestHandle { valuex2: 10 });
+        assert_eq!(
+            overridden.resolve(handle(), ()).await.unwrap().result,
+            "mock 10"
+        );
+        // The original resolver still uses the static resolver.
+        assert_eq!(resolver.resolve(handle(), ()).await.unwrap().result, "10");
+        // Other resource types still resolve through the static resolver.
+        let x = overridden
+            .resolve(Resource::new(TestConfig { value: 3 }), ())
+            .await
+            .unwrap();
+        assert_eq!(overridden.resolve(x, ()).await.unwrap().result, "mock 6");
+    }
 }
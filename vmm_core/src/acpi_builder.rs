@@ -14,7 +14,10 @@
 use chipset::ioapic;
 use chipset::psp;
 use inspect::Inspect;
+use memory_range::MemoryRange;
 use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+use thiserror::Error;
 use vm_topology::memory::MemoryLayout;
 use vm_topology::processor::aarch64::Aarch64Topology;
 use vm_topology::processor::x86::X86Topology;
@@ -22,6 +25,7 @@
 use vm_topology::processor::ProcessorTopology;
 use x86defs::apic::APIC_BASE_ADDRESS;
 use zerocopy::AsBytes;
+use zerocopy::FromBytes;
 
 /// Binary ACPI tables constructed by [`AcpiTablesBuilder`].
 pub struct BuiltAcpiTables {
@@ -56,6 +60,46 @@ pub struct AcpiTablesBuilder<'a, T: AcpiTopology> {
     pub pm_base: u16,
     /// ACPI IRQ number
     pub acpi_irq: u32,
+    /// Additional caller-provided SSDTs, referenced from the XSDT in order.
+    ///
+    /// Use [`Self::add_ssdt`] to add a table so that its header is validated.
+    pub ssdts: Vec<Vec<u8>>,
+    /// PCI host bridges to describe in the DSDT, in addition to any added by
+    /// the caller's closure.
+    ///
+    /// Use [`Self::add_pci_root_bus`] to add a bridge so that its resources
+    /// are validated against the others and against `\_SB.PCI0`.
+    pub pci_root_buses: Vec<dsdt::PciRootBus>,
+}
+
+/// Error returned by [`AcpiTablesBuilder::add_ssdt`].
+#[derive(Debug, Error)]
+pub enum SsdtError {
+    #[error("ssdt is too small to contain a table header")]
+    TooSmall,
+    #[error("invalid ssdt signature {0:?}")]
+    InvalidSignature([u8; 4]),
+    #[error("ssdt header length {header} does not match table length {actual}")]
+    LengthMismatch { header: u32, actual: usize },
+    #[error("invalid ssdt checksum")]
+    InvalidChecksum,
+}
+
+/// Error returned by [`AcpiTablesBuilder::add_pci_root_bus`].
+#[derive(Debug, Error)]
+pub enum PciRootBusError {
+    #[error("pci root bus has an empty bus range or window")]
+    Empty,
+    #[error("io window {0:#x?} covers the whole io port space")]
+    IoTooLarge(RangeInclusive<u16>),
+    #[error("bus numbers {0:?} overlap those of PCI0 or another pci root bus")]
+    BusOverlap(RangeInclusive<u8>),
+    #[error("io window {0:#x?} overlaps the io ports of PCI0 or another pci root bus")]
+    IoOverlap(RangeInclusive<u16>),
+    #[error("mmio window {0} overlaps the mmio gaps of PCI0 or another pci root bus")]
+    MmioOverlap(MemoryRange),
+    #[error("mmio window {0} overlaps guest ram or the vtl2 memory range")]
+    RamOverlap(MemoryRange),
 }
 
 pub const OEM_INFO: acpi::builder::OemInfo = acpi::builder::OemInfo {
@@ -157,6 +201,104 @@ fn extend_madt(topology: &ProcessorTopology<Self>, madt: &mut Vec<u8>) {
 }
 
 impl<T: AcpiTopology> AcpiTablesBuilder<'_, T> {
+    /// Adds a caller-provided SSDT, to be emitted after the built-in tables.
+    ///
+    /// SSDTs are referenced from the XSDT in the order they were added.
+    pub fn add_ssdt(&mut self, bytes: Vec<u8>) -> Result<(), SsdtError> {
+        let header = acpi_spec::Header::read_from_prefix(&bytes).ok_or(SsdtError::TooSmall)?;
+        if header.signature != *b"SSDT" {
+            return Err(SsdtError::InvalidSignature(header.signature));
+        }
+        let length = header.length.get();
+        if length as usize != bytes.len() {
+            return Err(SsdtError::LengthMismatch {
+                header: length,
+                actual: bytes.len(),
+            });
+        }
+        if bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) != 0 {
+            return Err(SsdtError::InvalidChecksum);
+        }
+        self.ssdts.push(bytes);
+        Ok(())
+    }
+
+    /// Adds a PCI host bridge, to be described in the DSDT built by
+    /// [`Self::build_acpi_tables`].
+    ///
+    /// The bridge's bus numbers and windows must not overlap those of any
+    /// bridge already added, or those of `\_SB.PCI0`, which owns bus 0, the
+    /// configuration ports, and the MMIO gaps of the memory layout. Its MMIO
+    /// windows must also stay clear of RAM and of the VTL2 range. The
+    /// bridges are named `\_SB.PC01` and up, with matching `_UID`s, to stay
+    /// clear of `\_SB.PCI0`'s implicit `_UID` of 0.
+    pub fn add_pci_root_bus(&mut self, bus: dsdt::PciRootBus) -> Result<(), PciRootBusError> {
+        fn overlaps<T: PartialOrd>(a: &RangeInclusive<T>, b: &RangeInclusive<T>) -> bool {
+            a.start() <= b.end() && b.start() <= a.end()
+        }
+
+        if bus.buses.is_empty()
+            || bus.io.as_ref().is_some_and(|io| io.is_empty())
+            || bus.mmio.iter().any(|range| range.is_empty())
+        {
+            return Err(PciRootBusError::Empty);
+        }
+        if let Some(io) = bus.io.as_ref().filter(|io| io.len() > u16::MAX.into()) {
+            return Err(PciRootBusError::IoTooLarge(io.clone()));
+        }
+        if bus.buses.contains(&dsdt::PCI0_BUS) {
+            return Err(PciRootBusError::BusOverlap(bus.buses));
+        }
+        if let Some(io) = bus
+            .io
+            .as_ref()
+            .filter(|io| overlaps(io, &dsdt::PCI0_CONFIG_PORTS))
+        {
+            return Err(PciRootBusError::IoOverlap(io.clone()));
+        }
+        if let Some(range) = bus
+            .mmio
+            .iter()
+            .find(|range| self.mem_layout.mmio().iter().any(|gap| gap.overlaps(range)))
+        {
+            return Err(PciRootBusError::MmioOverlap(*range));
+        }
+        if let Some(range) = bus.mmio.iter().find(|range| {
+            self.mem_layout
+                .ram()
+                .iter()
+                .map(|ram| &ram.range)
+                .chain(&self.mem_layout.vtl2_range())
+                .any(|ram| ram.overlaps(range))
+        }) {
+            return Err(PciRootBusError::RamOverlap(*range));
+        }
+        for (i, range) in bus.mmio.iter().enumerate() {
+            if bus.mmio[..i].iter().any(|other| other.overlaps(range)) {
+                return Err(PciRootBusError::MmioOverlap(*range));
+            }
+        }
+        for other in &self.pci_root_buses {
+            if overlaps(&bus.buses, &other.buses) {
+                return Err(PciRootBusError::BusOverlap(bus.buses));
+            }
+            if let (Some(io), Some(other_io)) = (&bus.io, &other.io) {
+                if overlaps(io, other_io) {
+                    return Err(PciRootBusError::IoOverlap(io.clone()));
+                }
+            }
+            if let Some(range) = bus
+                .mmio
+                .iter()
+                .find(|range| other.mmio.iter().any(|other| other.overlaps(range)))
+            {
+                return Err(PciRootBusError::MmioOverlap(*range));
+            }
+        }
+        self.pci_root_buses.push(bus);
+        Ok(())
+    }
+
     fn with_srat<F, R>(&self, f: F) -> R
     where
         F: FnOnce(&acpi::builder::Table<'_>) -> R,
@@ -396,6 +538,10 @@ pub fn build_acpi_tables<F>(&self, gpa: u64, add_devices_to_dsdt: F) -> BuiltAcp
         ));
         // Add any chipset devices.
         add_devices_to_dsdt(self.mem_layout, &mut dsdt_data);
+        for (i, bus) in self.pci_root_buses.iter().enumerate() {
+            // PCI0 has the implicit _UID 0.
+            dsdt_data.add_pci_root_bus(i as u8 + 1, bus);
+        }
         // Add processor devices:
         // Device(P###) { Name(_HID, "ACPI0007") Name(_UID, #) Method(_STA, 0) { Return(0xF) } }
         for proc_index in 1..self.processor_topology.vp_count() + 1 {
@@ -541,6 +687,9 @@ fn build_acpi_tables_inner(&self, gpa: u64, dsdt: &[u8]) -> BuiltAcpiTables {
         if self.cache_topology.is_some() {
             self.with_pptt(|t| b.append(t));
         }
+        for ssdt in &self.ssdts {
+            b.append_raw(ssdt);
+        }
 
         let (rdsp, tables) = b.build();
 
@@ -607,6 +756,8 @@ fn new_builder<'a>(
             with_psp: false,
             pm_base: 1234,
             acpi_irq: 2,
+            ssdts: Vec::new(),
+            pci_root_buses: Vec::new(),
         }
     }
 
@@ -650,4 +801,198 @@ fn test_basic_madt_cpu() {
             apic_ids.iter().map(|e| Some(*e)).collect::<Vec<_>>()
         );
     }
+
+    fn new_ssdt(oem_tableid: &[u8; 8], body: &[u8]) -> Vec<u8> {
+        let mut ssdt = acpi_spec::Header {
+            signature: *b"SSDT",
+            length: ((size_of::<acpi_spec::Header>() + body.len()) as u32).into(),
+            revision: 2,
+            checksum: 0,
+            oem_id: OEM_INFO.oem_id,
+            oem_tableid: *oem_tableid,
+            oem_revision: 0u32.into(),
+            creator_id: 0u32.into(),
+            creator_revision: 0u32.into(),
+        }
+        .as_bytes()
+        .to_vec();
+        ssdt.extend_from_slice(body);
+        ssdt[9] = 0u8.wrapping_sub(ssdt.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)));
+        ssdt
+    }
+
+    fn table_sum(table: &[u8]) -> u8 {
+        table.iter().fold(0, |sum, &b| sum.wrapping_add(b))
+    }
+
+    #[test]
+    fn test_ssdt() {
+        const GPA: u64 = 0x10000;
+
+        let mem = new_mem();
+        let topology = TopologyBuilder::new_x86().build(1).unwrap();
+        let mut builder = new_builder(&mem, &topology);
+
+        // Scope(\_SB) {}
+        let ssdt = new_ssdt(b"CUSTOM01", &[0x10, 0x06, b'\\', b'_', b'S', b'B', b'_']);
+        builder.add_ssdt(ssdt.clone()).unwrap();
+        let ssdt2 = new_ssdt(b"CUSTOM02", &[]);
+        builder.add_ssdt(ssdt2.clone()).unwrap();
+
+        let mut bad = ssdt.clone();
+        bad[10] ^= 1;
+        assert!(matches!(
+            builder.add_ssdt(bad),
+            Err(SsdtError::InvalidChecksum)
+        ));
+        let mut bad = ssdt.clone();
+        bad.push(0);
+        assert!(matches!(
+            builder.add_ssdt(bad),
+            Err(SsdtError::LengthMismatch { .. })
+        ));
+        let mut bad = ssdt.clone();
+        bad[..4].copy_from_slice(b"DSDT");
+        assert!(matches!(
+            builder.add_ssdt(bad),
+            Err(SsdtError::InvalidSignature(_))
+        ));
+        assert!(matches!(
+            builder.add_ssdt(vec![0; 8]),
+            Err(SsdtError::TooSmall)
+        ));
+
+        let tables = builder.build_acpi_tables(GPA, |_, _| {});
+        let table_at = |addr: u64| {
+            let offset = (addr - GPA - 0x1000) as usize;
+            let header = acpi_spec::Header::read_from_prefix(&tables.tables[offset..]).unwrap();
+            &tables.tables[offset..offset + header.length.get() as usize]
+        };
+
+        let rsdp = acpi_spec::Rsdp::read_from_prefix(&tables.rdsp).unwrap();
+        let xsdt = table_at(rsdp.xsdt);
+        assert_eq!(&xsdt[..4], b"XSDT");
+        assert_eq!(table_sum(xsdt), 0);
+
+        let entries = xsdt[size_of::<acpi_spec::Header>()..]
+            .chunks_exact(8)
+            .map(|addr| table_at(u64::from_ne_bytes(addr.try_into().unwrap())))
+            .collect::<Vec<_>>();
+        for table in &entries {
+            assert_eq!(table_sum(table), 0);
+        }
+
+        // The SSDTs come last, in the order they were added.
+        let [.., first, second] = entries.as_slice() else {
+            panic!("missing ssdts");
+        };
+        assert_eq!(*first, ssdt.as_slice());
+        assert_eq!(*second, ssdt2.as_slice());
+    }
+
+    #[test]
+    fn test_pci_root_buses() {
+        use acpi::dsdt::ResourceObject;
+
+        let mem = new_mem();
+        let topology = TopologyBuilder::new_x86().build(1).unwrap();
+        let mut builder = new_builder(&mem, &topology);
+
+        let bus = |buses, io, mmio: &[MemoryRange]| dsdt::PciRootBus {
+            buses,
+            io,
+            mmio: mmio.to_vec(),
+        };
+        let window = |n: u64| MemoryRange::new(n * TB..(n + 1) * TB);
+        builder
+            .add_pci_root_bus(bus(1..=0x7f, Some(0x1000..=0x1fff), &[window(4)]))
+            .unwrap();
+        builder
+            .add_pci_root_bus(bus(
+                0x80..=0xfe,
+                Some(0x2000..=0x2fff),
+                &[window(5), window(6)],
+            ))
+            .unwrap();
+
+        assert!(matches!(
+            builder.add_pci_root_bus(bus(0x7f..=0x80, None, &[])),
+            Err(PciRootBusError::BusOverlap(_))
+        ));
+        assert!(matches!(
+            builder.add_pci_root_bus(bus(0xff..=0xff, Some(0x2fff..=0x3000), &[])),
+            Err(PciRootBusError::IoOverlap(_))
+        ));
+        assert!(matches!(
+            builder.add_pci_root_bus(bus(
+                0xff..=0xff,
+                None,
+                &[MemoryRange::new(4 * TB + 0x1000..4 * TB + 0x2000)]
+            )),
+            Err(PciRootBusError::MmioOverlap(_))
+        ));
+        assert!(matches!(
+            builder.add_pci_root_bus(bus(0xff..=0xff, None, &[window(7), window(7)])),
+            Err(PciRootBusError::MmioOverlap(_))
+        ));
+        assert!(matches!(
+            builder.add_pci_root_bus(bus(0xff..=0xff, None, &[MemoryRange::EMPTY])),
+            Err(PciRootBusError::Empty)
+        ));
+
+        // PCI0's bus, configuration ports, and MMIO gaps are reserved.
+        assert!(matches!(
+            builder.add_pci_root_bus(bus(0..=0, None, &[])),
+            Err(PciRootBusError::BusOverlap(_))
+        ));
+        assert!(matches!(
+            builder.add_pci_root_bus(bus(0xff..=0xff, Some(0xc00..=0xcff), &[])),
+            Err(PciRootBusError::IoOverlap(_))
+        ));
+        assert!(matches!(
+            builder.add_pci_root_bus(bus(0xff..=0xff, None, &[MMIO[1]])),
+            Err(PciRootBusError::MmioOverlap(_))
+        ));
+        assert!(matches!(
+            builder.add_pci_root_bus(bus(0xff..=0xff, Some(0..=0xffff), &[])),
+            Err(PciRootBusError::IoTooLarge(_))
+        ));
+
+        // So are RAM and the VTL2 range.
+        assert!(matches!(
+            builder.add_pci_root_bus(bus(0xff..=0xff, None, &[MemoryRange::new(4 * GB..5 * GB)])),
+            Err(PciRootBusError::RamOverlap(_))
+        ));
+        let vtl2 = MemoryRange::new(2 * TB..2 * TB + GB);
+        let vtl2_mem = MemoryLayout::new(42, TB, &MMIO, Some(vtl2)).unwrap();
+        let mut vtl2_builder = new_builder(&vtl2_mem, &topology);
+        assert!(matches!(
+            vtl2_builder.add_pci_root_bus(bus(1..=1, None, &[vtl2])),
+            Err(PciRootBusError::RamOverlap(_))
+        ));
+        assert_eq!(builder.pci_root_buses.len(), 2);
+
+        let tables = builder.build_acpi_tables(0x10000, |_, _| {});
+        let header = acpi_spec::Header::read_from_prefix(&tables.tables).unwrap();
+        assert_eq!(&header.signature, b"DSDT");
+        let dsdt = &tables.tables[..header.length.get() as usize];
+        assert_eq!(table_sum(dsdt), 0);
+
+        // Each bridge's resources are described contiguously in its _CRS.
+        let contains = |needle: &[u8]| dsdt.windows(needle.len()).any(|w| w == needle);
+        let mut crs1 = dsdt::BusNumber::new(1, 0x7f).to_bytes();
+        crs1.extend(dsdt::WordIo::new(0x1000, 0x1000).to_bytes());
+        crs1.extend(dsdt::QwordMemory::new(4 * TB, TB).to_bytes());
+        crs1.extend([0x79, 0]);
+        let mut crs2 = dsdt::BusNumber::new(0x80, 0x7f).to_bytes();
+        crs2.extend(dsdt::WordIo::new(0x2000, 0x1000).to_bytes());
+        crs2.extend(dsdt::QwordMemory::new(5 * TB, TB).to_bytes());
+        crs2.extend(dsdt::QwordMemory::new(6 * TB, TB).to_bytes());
+        crs2.extend([0x79, 0]);
+        assert!(!contains(b"PC00"));
+        assert!(contains(b"PC01"));
+        assert!(contains(b"PC02"));
+        assert!(contains(&crs1));
+        assert!(contains(&crs2));
+    }
 }
@@ -5,6 +5,16 @@
 
 pub mod topology;
 
+use hvdef::HvEnlightenmentInformation;
+use hvdef::HvFeatures;
+use hvdef::HvHardwareFeatures;
+use hvdef::HV_CPUID_FUNCTION_HV_INTERFACE;
+use hvdef::HV_CPUID_FUNCTION_HV_VENDOR_AND_MAX_FUNCTION;
+use hvdef::HV_CPUID_FUNCTION_MS_HV_ENLIGHTENMENT_INFORMATION;
+use hvdef::HV_CPUID_FUNCTION_MS_HV_FEATURES;
+use hvdef::HV_CPUID_FUNCTION_MS_HV_HARDWARE_FEATURES;
+use hvdef::HV_CPUID_FUNCTION_MS_HV_IMPLEMENTATION_LIMITS;
+use hvdef::HV_CPUID_FUNCTION_MS_HV_VERSION;
 use hvdef::VIRTUALIZATION_STACK_CPUID_INTERFACE;
 use hvdef::VIRTUALIZATION_STACK_CPUID_PROPERTIES;
 use hvdef::VIRTUALIZATION_STACK_CPUID_VENDOR;
@@ -12,6 +22,13 @@
 use hvdef::VS1_PARTITION_PROPERTIES_EAX_IS_PORTABLE;
 use virt::CpuidLeaf;
 use x86defs::cpuid::CpuidFunction;
+use x86defs::cpuid::ExtendedFeatureSubleaf0Ebx;
+use x86defs::cpuid::ExtendedFeatureSubleaf0Ecx;
+use x86defs::cpuid::ExtendedFeatureSubleaf0Edx;
+use x86defs::cpuid::ExtendedVersionAndFeaturesEcx;
+use x86defs::cpuid::ExtendedVersionAndFeaturesEdx;
+use x86defs::cpuid::VersionAndFeaturesEcx;
+use x86defs::cpuid::VersionAndFeaturesEdx;
 
 /// A function used to query the cpuid result for a given input value (`eax`,
 /// `ecx`).
@@ -63,3 +80,285 @@ pub fn hyperv_cpuid_leaves(extended_ioapic_rte: bool) -> impl Iterator<Item = Cp
     ]
     .into_iter()
 }
+
+/// Builds the Hyper-V synthetic CPUID leaves, starting at
+/// [`HV_CPUID_FUNCTION_HV_VENDOR_AND_MAX_FUNCTION`].
+///
+/// The resulting leaves can be combined with other leaves, such as those from
+/// [`hyperv_cpuid_leaves`], to present a tailored set of enlightenments to the
+/// guest.
+#[derive(Debug, Clone)]
+pub struct HyperVCpuidBuilder {
+    vendor: [u8; 12],
+    interface: [u8; 4],
+    features: HvFeatures,
+    enlightenments: HvEnlightenmentInformation,
+    max_virtual_processors: u32,
+    max_logical_processors: u32,
+    hardware_features: Option<HvHardwareFeatures>,
+}
+
+impl HyperVCpuidBuilder {
+    /// Returns a builder for the standard Hyper-V vendor and interface
+    /// signatures, reporting `features` and `enlightenments`.
+    pub fn new(features: HvFeatures, enlightenments: HvEnlightenmentInformation) -> Self {
+        Self {
+            vendor: *b"Microsoft Hv",
+            interface: *b"Hv#1",
+            features,
+            enlightenments,
+            max_virtual_processors: 0,
+            max_logical_processors: 0,
+            hardware_features: None,
+        }
+    }
+
+    /// Sets the hypervisor vendor signature.
+    pub fn vendor(mut self, vendor: [u8; 12]) -> Self {
+        self.vendor = vendor;
+        self
+    }
+
+    /// Sets the hypervisor interface signature.
+    pub fn interface(mut self, interface: [u8; 4]) -> Self {
+        self.interface = interface;
+        self
+    }
+
+    /// Sets the maximum number of virtual and logical processors reported in
+    /// the implementation limits leaf.
+    pub fn implementation_limits(
+        mut self,
+        max_virtual_processors: u32,
+        max_logical_processors: u32,
+    ) -> Self {
+        self.max_virtual_processors = max_virtual_processors;
+        self.max_logical_processors = max_logical_processors;
+        self
+    }
+
+    /// Reports `hardware_features` in the hardware features leaf.
+    pub fn hardware_features(mut self, hardware_features: HvHardwareFeatures) -> Self {
+        self.hardware_features = Some(hardware_features);
+        self
+    }
+
+    /// Returns the synthetic CPUID leaves.
+    pub fn build(&self) -> Vec<CpuidLeaf> {
+        let signature = |bytes: &[u8]| u32::from_le_bytes(bytes.try_into().unwrap());
+        let split_u128 = |x: u128| -> [u32; 4] {
+            let bytes = x.to_le_bytes();
+            [
+                signature(&bytes[0..4]),
+                signature(&bytes[4..8]),
+                signature(&bytes[8..12]),
+                signature(&bytes[12..16]),
+            ]
+        };
+
+        let max_function = if self.hardware_features.is_some() {
+            HV_CPUID_FUNCTION_MS_HV_HARDWARE_FEATURES
+        } else {
+            HV_CPUID_FUNCTION_MS_HV_IMPLEMENTATION_LIMITS
+        };
+
+        let mut leaves = vec![
+            CpuidLeaf::new(
+                HV_CPUID_FUNCTION_HV_VENDOR_AND_MAX_FUNCTION,
+                [
+                    max_function,
+                    signature(&self.vendor[0..4]),
+                    signature(&self.vendor[4..8]),
+                    signature(&self.vendor[8..12]),
+                ],
+            ),
+            CpuidLeaf::new(
+                HV_CPUID_FUNCTION_HV_INTERFACE,
+                [signature(&self.interface), 0, 0, 0],
+            ),
+            CpuidLeaf::new(HV_CPUID_FUNCTION_MS_HV_VERSION, [0, 0, 0, 0]),
+            CpuidLeaf::new(
+                HV_CPUID_FUNCTION_MS_HV_FEATURES,
+                split_u128(self.features.into()),
+            ),
+            CpuidLeaf::new(
+                HV_CPUID_FUNCTION_MS_HV_ENLIGHTENMENT_INFORMATION,
+                split_u128(self.enlightenments.into()),
+            ),
+            CpuidLeaf::new(
+                HV_CPUID_FUNCTION_MS_HV_IMPLEMENTATION_LIMITS,
+                [
+                    self.max_virtual_processors,
+                    self.max_logical_processors,
+                    0,
+                    0,
+                ],
+            ),
+        ];
+
+        if let Some(hardware_features) = self.hardware_features {
+            leaves.push(CpuidLeaf::new(
+                HV_CPUID_FUNCTION_MS_HV_HARDWARE_FEATURES,
+                split_u128(hardware_features.into()),
+            ));
+        }
+
+        leaves
+    }
+}
+
+/// A policy of processor features to hide from the guest.
+///
+/// This is used to present the same features to a VM on every host in a
+/// migration pool, by masking off the features that are not available on all
+/// of them. Each set bit in the policy clears the corresponding feature bit in
+/// the guest's CPUID results.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct FeatureMask {
+    /// Features to hide in leaf 01h, ecx.
+    pub version_and_features_ecx: VersionAndFeaturesEcx,
+    /// Features to hide in leaf 01h, edx.
+    pub version_and_features_edx: VersionAndFeaturesEdx,
+    /// Features to hide in leaf 07h, subleaf 0, ebx.
+    pub extended_features_ebx: ExtendedFeatureSubleaf0Ebx,
+    /// Features to hide in leaf 07h, subleaf 0, ecx.
+    pub extended_features_ecx: ExtendedFeatureSubleaf0Ecx,
+    /// Features to hide in leaf 07h, subleaf 0, edx.
+    pub extended_features_edx: ExtendedFeatureSubleaf0Edx,
+    /// Features to hide in leaf 8000_0001h, ecx.
+    pub extended_version_and_features_ecx: ExtendedVersionAndFeaturesEcx,
+    /// Features to hide in leaf 8000_0001h, edx.
+    pub extended_version_and_features_edx: ExtendedVersionAndFeaturesEdx,
+}
+
+impl FeatureMask {
+    /// Returns partial leaves that clear the masked feature bits.
+    ///
+    /// Since later leaves override earlier ones, these must be added after
+    /// all other leaves have been composed. Leaf 07h is masked for subleaf 0
+    /// only, so any other leaf 07h results must also specify their subleaf.
+    pub fn leaves(&self) -> impl Iterator<Item = CpuidLeaf> {
+        let mask = |function: u32, [ebx, ecx, edx]: [u32; 3]| {
+            CpuidLeaf::new(function, [0; 4]).masked([0, ebx, ecx, edx])
+        };
+        [
+            mask(
+                CpuidFunction::VersionAndFeatures.0,
+                [
+                    0,
+                    self.version_and_features_ecx.into(),
+                    self.version_and_features_edx.into(),
+                ],
+            ),
+            mask(
+                CpuidFunction::ExtendedFeatures.0,
+                [
+                    self.extended_features_ebx.into(),
+                    self.extended_features_ecx.into(),
+                    self.extended_features_edx.into(),
+                ],
+            )
+            .indexed(0),
+            mask(
+                CpuidFunction::ExtendedVersionAndFeatures.0,
+                [
+                    0,
+                    self.extended_version_and_features_ecx.into(),
+                    self.extended_version_and_features_edx.into(),
+                ],
+            ),
+        ]
+        .into_iter()
+        .filter(|leaf| leaf.mask != [0; 4])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hvdef::HvPartitionPrivilege;
+    use virt::CpuidLeafSet;
+
+    #[test]
+    fn hyperv_builder() {
+        let features = HvFeatures::new()
+            .with_privileges(
+                HvPartitionPrivilege::new()
+                    .with_access_hypercall_msrs(true)
+                    .with_access_vp_index(true)
+                    .into(),
+            )
+            .with_frequency_regs_available(true);
+        let leaves = CpuidLeafSet::new(
+            HyperVCpuidBuilder::new(features, HvEnlightenmentInformation::new())
+                .vendor(*b"Contoso Hv  ")
+                .build(),
+        );
+
+        let [max_function, ebx, ecx, edx] =
+            leaves.result(HV_CPUID_FUNCTION_HV_VENDOR_AND_MAX_FUNCTION, 0, &[0; 4]);
+        assert_eq!(max_function, HV_CPUID_FUNCTION_MS_HV_IMPLEMENTATION_LIMITS);
+        assert_eq!(
+            [ebx, ecx, edx].map(u32::to_le_bytes).concat(),
+            b"Contoso Hv  "
+        );
+
+        let result = leaves.result(HV_CPUID_FUNCTION_MS_HV_FEATURES, 0, &[0; 4]);
+        let result = HvFeatures::from(
+            u128::from(result[0])
+                | u128::from(result[1]) << 32
+                | u128::from(result[2]) << 64
+                | u128::from(result[3]) << 96,
+        );
+        assert_eq!(u128::from(result), u128::from(features));
+        assert!(result.frequency_regs_available());
+        assert!(HvPartitionPrivilege::from(result.privileges()).access_vp_index());
+    }
+
+    #[test]
+    fn feature_mask() {
+        let function = CpuidFunction::ExtendedFeatures.0;
+        let ebx = ExtendedFeatureSubleaf0Ebx::new()
+            .with_avx2(true)
+            .with_bmi2(true)
+            .with_smep(true);
+
+        // Compose leaf 7 from a VMM-provided result and the host's defaults,
+        // then hide AVX2.
+        let mut leaves = vec![CpuidLeaf::new(function, [0, ebx.into(), 0, 0])
+            .indexed(0)
+            .masked([0, ebx.with_smep(false).into(), 0, 0])];
+        leaves.extend(hyperv_cpuid_leaves(false));
+        leaves.extend(
+            FeatureMask {
+                extended_features_ebx: ExtendedFeatureSubleaf0Ebx::new().with_avx2(true),
+                ..Default::default()
+            }
+            .leaves(),
+        );
+        let leaves = CpuidLeafSet::new(leaves);
+
+        let host = [
+            0,
+            ExtendedFeatureSubleaf0Ebx::new()
+                .with_smep(true)
+                .with_avx2(true)
+                .into(),
+            0x1234,
+            0x5678,
+        ];
+        let result = leaves.result(function, 0, &host);
+        let result_ebx = ExtendedFeatureSubleaf0Ebx::from(result[1]);
+        assert!(!result_ebx.avx2());
+        assert!(result_ebx.bmi2());
+        assert!(result_ebx.smep());
+        assert_eq!(result[2..], [0x1234, 0x5678]);
+
+        // Other leaves are unaffected.
+        let result = leaves.result(CpuidFunction::VersionAndFeatures.0, 0, &[0; 4]);
+        assert_eq!(result, [0, 0, 1 << 31, 0]);
+
+        // An empty policy adds no leaves.
+        assert_eq!(FeatureMask::default().leaves().count(), 0);
+    }
+}
@@ -3,11 +3,11 @@
 
 //! Functions for resolving and building devices.
 
-use anyhow::Context as _;
 use guestmem::GuestMemory;
 use pci_core::msi::MsiInterruptSet;
 use pci_core::msi::MsiInterruptTarget;
 use std::sync::Arc;
+use thiserror::Error;
 use vm_resource::kind::PciDeviceHandleKind;
 use vm_resource::Resource;
 use vm_resource::ResourceResolver;
@@ -15,8 +15,37 @@
 use vmbus_server::VmbusServerControl;
 use vmcore::vm_task::VmTaskDriverSource;
 use vmcore::vpci_msi::VpciInterruptMapper;
+use vmotherboard::AddDeviceError;
 use vmotherboard::ChipsetBuilder;
 
+/// Error returned by [`build_vpci_device`].
+#[derive(Debug, Error)]
+pub enum DeviceBuildError {
+    /// The PCI device resource could not be resolved into a device.
+    #[error("failed to resolve pci device resource")]
+    ResolveResource(#[source] AddDeviceError),
+    /// A device with the same VPCI instance ID has already been added.
+    #[error("vpci instance id {0} is already in use")]
+    AddressConflict(Guid, #[source] AddDeviceError),
+    /// The interrupt target for the device could not be allocated, e.g.
+    /// because the hypervisor is out of virtual device or interrupt resources.
+    #[error("failed to create virtual device")]
+    InterruptTarget(#[source] anyhow::Error),
+    /// The VPCI bus hosting the device could not be created.
+    #[error("failed to create vpci bus")]
+    CreateBus(#[source] AddDeviceError),
+}
+
+impl DeviceBuildError {
+    fn add_device(instance_id: Guid, err: AddDeviceError, f: fn(AddDeviceError) -> Self) -> Self {
+        if err.is_name_in_use() {
+            Self::AddressConflict(instance_id, err)
+        } else {
+            f(err)
+        }
+    }
+}
+
 /// Resolves a PCI device resource, builds the corresponding device, and builds
 /// a VPCI bus to host it.
 pub async fn build_vpci_device(
@@ -33,7 +62,7 @@ pub async fn build_vpci_device(
         Arc<dyn MsiInterruptTarget>,
         Arc<dyn VpciInterruptMapper>,
     )>,
-) -> anyhow::Result<()> {
+) -> Result<(), DeviceBuildError> {
     let device_name = format!("{}:vpci-{instance_id}", resource.id());
 
     let mut msi_set = MsiInterruptSet::new();
@@ -57,21 +86,24 @@ pub async fn build_vpci_device(
                     .await
                     .map(|r| r.0)
             })
-            .await?
+            .await
+            .map_err(|err| {
+                DeviceBuildError::add_device(instance_id, err, DeviceBuildError::ResolveResource)
+            })?
     };
 
+    let device_id = (instance_id.data2 as u64) << 16 | (instance_id.data3 as u64 & 0xfff8);
+    let (msi_controller, interrupt_mapper) =
+        new_virtual_device(device_id).map_err(DeviceBuildError::InterruptTarget)?;
+
+    msi_set.connect(msi_controller.as_ref());
+
     {
-        let device_id = (instance_id.data2 as u64) << 16 | (instance_id.data3 as u64 & 0xfff8);
         let vpci_bus_name = format!("vpci:{instance_id}");
         let mut builder = chipset_builder.arc_mutex_device(vpci_bus_name);
         let mut register_mmio = builder.services().register_mmio();
         builder
             .try_add_async(|_services| async {
-                let (msi_controller, interrupt_mapper) =
-                    new_virtual_device(device_id).context("failed to create virtual device")?;
-
-                msi_set.connect(msi_controller.as_ref());
-
                 let bus = vpci::bus::VpciBus::new(
                     driver_source,
                     instance_id,
@@ -84,7 +116,10 @@ pub async fn build_vpci_device(
 
                 anyhow::Ok(bus)
             })
-            .await?;
+            .await
+            .map_err(|err| {
+                DeviceBuildError::add_device(instance_id, err, DeviceBuildError::CreateBus)
+            })?;
     }
 
     Ok(())
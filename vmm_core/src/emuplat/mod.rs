@@ -4,3 +4,4 @@
 pub mod gic;
 pub mod hcl_compat_uefi_nvram_storage;
 pub mod ioapic;
+pub mod wall_clock;
@@ -0,0 +1,121 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Pluggable wall-clock sources for the guest's real time clocks.
+
+use inspect::Inspect;
+use local_clock::InspectableLocalClock;
+use local_clock::LocalClock;
+use local_clock::LocalClockDelta;
+use local_clock::LocalClockTime;
+use local_clock::SystemTimeClock;
+
+/// A source of wall-clock time for the real time clocks presented to the guest
+/// (e.g. the CMOS RTC and the UEFI time services).
+///
+/// Each guest-visible clock gets its own [`LocalClock`] instance, so that the
+/// guest setting the time on one clock does not affect the others.
+pub trait WallClockSource: Send + Sync {
+    /// Returns a new clock, initialized to the source's current time.
+    fn new_clock(&self) -> Box<dyn InspectableLocalClock>;
+}
+
+/// A wall-clock source that reports the host's time.
+#[derive(Debug, Default)]
+pub struct HostWallClock;
+
+impl WallClockSource for HostWallClock {
+    fn new_clock(&self) -> Box<dyn InspectableLocalClock> {
+        Box::new(SystemTimeClock::new())
+    }
+}
+
+/// A wall-clock source that reports the host's time, skewed by a fixed delta.
+#[derive(Debug)]
+pub struct OffsetWallClock {
+    offset: LocalClockDelta,
+}
+
+impl OffsetWallClock {
+    /// Returns a new source that is `offset` ahead of the host's time (or
+    /// behind it, if `offset` is negative).
+    pub fn new(offset: LocalClockDelta) -> Self {
+        Self { offset }
+    }
+}
+
+impl WallClockSource for OffsetWallClock {
+    fn new_clock(&self) -> Box<dyn InspectableLocalClock> {
+        let mut clock = SystemTimeClock::new();
+        let now = clock.get_time();
+        clock.set_time(now + self.offset);
+        Box::new(clock)
+    }
+}
+
+/// A wall-clock source whose clocks are frozen at a fixed time, for
+/// reproducible guest behavior.
+#[derive(Debug)]
+pub struct FrozenWallClock {
+    time: LocalClockTime,
+}
+
+impl FrozenWallClock {
+    /// Returns a new source whose clocks always start at `time`.
+    pub fn new(time: LocalClockTime) -> Self {
+        Self { time }
+    }
+}
+
+impl WallClockSource for FrozenWallClock {
+    fn new_clock(&self) -> Box<dyn InspectableLocalClock> {
+        Box::new(FrozenClock { time: self.time })
+    }
+}
+
+/// A clock that does not advance on its own. The guest can still set it.
+#[derive(Debug, Inspect)]
+struct FrozenClock {
+    time: LocalClockTime,
+}
+
+impl LocalClock for FrozenClock {
+    fn get_time(&mut self) -> LocalClockTime {
+        self.time
+    }
+
+    fn set_time(&mut self, new_time: LocalClockTime) {
+        self.time = new_time;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frozen_clock() {
+        let time = LocalClockTime::from_millis_since_unix_epoch(1_700_000_000_000);
+        let source = FrozenWallClock::new(time);
+
+        let mut clock = source.new_clock();
+        assert_eq!(clock.get_time(), time);
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        assert_eq!(clock.get_time(), time);
+
+        // Setting one clock does not affect clocks from the same source.
+        let later = time + LocalClockDelta::from_millis(1000);
+        clock.set_time(later);
+        assert_eq!(clock.get_time(), later);
+        assert_eq!(source.new_clock().get_time(), time);
+    }
+
+    #[test]
+    fn offset_clock() {
+        let offset = LocalClockDelta::from_millis(-86_400_000);
+        let host = HostWallClock.new_clock().get_time();
+        let skewed = OffsetWallClock::new(offset).new_clock().get_time();
+        let delta = (skewed - host - offset).as_millis();
+        assert!(delta.abs() < 60_000, "{delta}");
+    }
+}
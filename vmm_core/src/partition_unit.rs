@@ -3,9 +3,12 @@
 
 //! State unit for managing the VM partition and associated virtual processors.
 
+mod affinity;
 mod debug;
 mod vp_set;
 
+pub use affinity::AffinityError;
+pub use affinity::VpAffinity;
 pub use vp_set::block_on_vp;
 pub use vp_set::Halt;
 pub use vp_set::RequestYield;
@@ -70,6 +73,7 @@ struct PartitionUnitRunner {
     partition: Box<dyn VmPartition>,
     vp_set: VpSet,
     started: bool,
+    paused: bool,
     needs_reset: bool,
     halt_reason: Option<HaltReason>,
     halt_request_recv: Receiver<InternalHaltReason>,
@@ -90,6 +94,7 @@ fn inspect_mut(&mut self, req: inspect::Request<'_>) {
                 self.halt_reason.as_ref().map_or("running", |_| "halted"),
             )
             .merge(&self.halt_reason)
+            .field("paused", self.paused)
             .merge(&self.vp_set)
             .field_mut_with("clear_halt", |clear| {
                 // Clear halt if "true" is specified.
@@ -114,6 +119,8 @@ fn inspect_mut(&mut self, req: inspect::Request<'_>) {
 
 enum PartitionRequest {
     ClearHalt(Rpc<(), bool>), // TODO: remove this, and use DebugRequest::Resume
+    PauseQuiesced(Rpc<(), ()>),
+    Resume(Rpc<(), ()>),
     SetInitialRegs(Rpc<(Vtl, Arc<InitialRegs>), Result<(), InitialRegError>>),
     SetInitialPageVisibility(
         Rpc<Vec<(MemoryRange, PageVisibility)>, Result<(), InitialVisibilityError>>,
@@ -131,6 +138,10 @@ pub struct PartitionUnitParams<'a> {
     /// other reason).
     pub client_notify_send: mesh::Sender<HaltReason>,
     pub debugger_rpc: Option<Receiver<vmm_core_defs::debug_rpc::DebugRequest>>,
+    /// The host CPU affinity for each VP's thread, indexed by VP index. VPs
+    /// past the end of the list, or with `None`, run with the affinity of the
+    /// thread that runs them.
+    pub vp_affinity: Vec<Option<VpAffinity>>,
 }
 
 /// The halt reason receiver to pass to put in [`PartitionUnitParams`].
@@ -150,6 +161,8 @@ pub enum Error {
     NameInUse(NameInUse),
     #[error("missing guest memory required for gdb support")]
     MissingGuestMemory,
+    #[error("invalid vp affinity")]
+    VpAffinity(#[source] AffinityError),
 }
 
 /// Error returned by [`PartitionUnit::set_initial_regs()`].
@@ -172,7 +185,9 @@ impl PartitionUnit {
     /// Creates a new VM partition state unit.
     ///
     /// The caller is responsible for launching a thread for each VP and running
-    /// the VP using the returned [`VpRunner`]s.
+    /// the VP using the returned [`VpRunner`]s. Each VP's affinity from
+    /// [`PartitionUnitParams::vp_affinity`] is applied to the thread that runs
+    /// it; see [`VpAffinity`] for details.
     pub fn new(
         spawner: impl Spawn,
         builder: UnitBuilder<'_>,
@@ -184,11 +199,15 @@ pub fn new(
             return Err(Error::DebuggingNotSupported);
         }
 
+        affinity::validate(&params.vp_affinity, params.processor_topology.vp_count())
+            .map_err(Error::VpAffinity)?;
+
+        let mut vp_affinity = params.vp_affinity.into_iter();
         let mut vp_set = VpSet::new(params.vtl_guest_memory.map(|m| m.cloned()), params.halt_vps);
         let vps = params
             .processor_topology
             .vps_arch()
-            .map(|vp| vp_set.add(vp))
+            .map(|vp| vp_set.add(vp, vp_affinity.next().flatten()))
             .collect();
 
         let (req_send, req_recv) = mesh::channel();
@@ -197,6 +216,7 @@ pub fn new(
             partition: Box::new(partition),
             vp_set,
             started: false,
+            paused: false,
             needs_reset: false,
             halt_reason: None,
             halt_request_recv: params.halt_request_recv.0,
@@ -245,6 +265,30 @@ pub async fn clear_halt(&mut self) -> bool {
             .unwrap()
     }
 
+    /// Pauses the VPs without stopping the partition unit.
+    ///
+    /// The returned future completes only after every VP has acknowledged the
+    /// stop request and returned from its run loop, so the caller can safely
+    /// inspect or manipulate VP state. The VPs stay paused, even across state
+    /// unit restarts and halt clears, until [`Self::resume()`] is called.
+    pub async fn pause_quiesced(&mut self) {
+        self.req_send
+            .call(PartitionRequest::PauseQuiesced, ())
+            .await
+            .unwrap()
+    }
+
+    /// Resumes VPs paused by [`Self::pause_quiesced()`].
+    ///
+    /// The VPs only start running again if the partition unit is started and
+    /// not halted.
+    pub async fn resume(&mut self) {
+        self.req_send
+            .call(PartitionRequest::Resume, ())
+            .await
+            .unwrap()
+    }
+
     /// Sets the register state for the VPs for initial boot.
     ///
     /// If the VM has been run before and has not been reset since it last ran,
@@ -325,6 +369,10 @@ enum Event {
                 }
                 Event::Request(request) => match request {
                     PartitionRequest::ClearHalt(rpc) => rpc.handle_sync(|()| self.clear_halt()),
+                    PartitionRequest::PauseQuiesced(rpc) => {
+                        rpc.handle(|()| self.pause_quiesced()).await
+                    }
+                    PartitionRequest::Resume(rpc) => rpc.handle_sync(|()| self.resume()),
                     PartitionRequest::SetInitialRegs(rpc) => {
                         rpc.handle(|(vtl, state)| self.set_initial_regs(vtl, state))
                             .await
@@ -409,6 +457,17 @@ fn clear_halt(&mut self) -> bool {
         }
     }
 
+    /// Stops the VPs and keeps them stopped until [`Self::resume()`].
+    async fn pause_quiesced(&mut self) {
+        self.paused = true;
+        self.vp_set.stop().await;
+    }
+
+    fn resume(&mut self) {
+        self.paused = false;
+        self.try_start();
+    }
+
     async fn set_initial_regs(
         &mut self,
         vtl: Vtl,
@@ -446,7 +505,7 @@ async fn set_initial_page_visibility(
     }
 
     fn try_start(&mut self) {
-        if self.started && self.halt_reason.is_none() {
+        if self.started && self.halt_reason.is_none() && !self.paused {
             self.vp_set.start();
         }
     }
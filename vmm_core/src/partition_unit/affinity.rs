@@ -0,0 +1,194 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Host CPU affinity for VP threads.
+
+use std::collections::BTreeSet;
+use std::io;
+use thiserror::Error;
+use virt::VpIndex;
+
+/// The set of host CPUs that a VP's thread may run on.
+///
+/// The affinity is applied to the calling thread the first time
+/// [`VpRunner::run`] is called, and it is not reapplied on later calls, so the
+/// runner should be run on the same thread for its whole life.
+///
+/// The affinity does not stop the host scheduler from migrating the VP thread
+/// between the CPUs in the set. To prevent migration entirely, pin the VP to a
+/// single CPU. If every CPU in the set later goes offline, the host kernel
+/// resets the thread's affinity to all CPUs, and the VP keeps running unpinned
+/// until its thread is restarted.
+///
+/// Affinity is currently only supported on Linux.
+///
+/// [`VpRunner::run`]: super::VpRunner::run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VpAffinity {
+    cpus: BTreeSet<u32>,
+}
+
+impl VpAffinity {
+    /// Returns an affinity allowing the VP thread to run on any of `cpus`.
+    pub fn new(cpus: impl IntoIterator<Item = u32>) -> Self {
+        Self {
+            cpus: cpus.into_iter().collect(),
+        }
+    }
+
+    /// Returns an affinity pinning the VP thread to `cpu`.
+    pub fn pinned(cpu: u32) -> Self {
+        Self::new([cpu])
+    }
+
+    /// Returns the host CPUs in the set, in ascending order.
+    pub fn cpus(&self) -> impl '_ + Iterator<Item = u32> {
+        self.cpus.iter().copied()
+    }
+}
+
+/// Error returned when a VP affinity is not valid.
+#[derive(Debug, Error)]
+pub enum AffinityError {
+    #[error("vp affinity is not supported on this platform")]
+    Unsupported,
+    #[error("affinity specified for vp {0}, which does not exist")]
+    UnknownVp(u32),
+    #[error("affinity for vp {0} has no cpus")]
+    Empty(u32),
+    #[error("affinity for vp {vp} includes cpu {cpu}, which is not online")]
+    InvalidCpu { vp: u32, cpu: u32 },
+    #[error("failed to query online cpus")]
+    OnlineCpus(#[source] io::Error),
+}
+
+/// Checks that `affinity[i]` is empty or refers only to online host CPUs, for
+/// each VP `i` in `0..vp_count`.
+pub(crate) fn validate(
+    affinity: &[Option<VpAffinity>],
+    vp_count: u32,
+) -> Result<(), AffinityError> {
+    if affinity.iter().all(Option::is_none) {
+        return Ok(());
+    }
+    let online = sys::online_cpus()?;
+    for (vp, affinity) in affinity.iter().enumerate() {
+        let vp = vp as u32;
+        let Some(affinity) = affinity else {
+            continue;
+        };
+        if vp >= vp_count {
+            return Err(AffinityError::UnknownVp(vp));
+        }
+        if affinity.cpus.is_empty() {
+            return Err(AffinityError::Empty(vp));
+        }
+        if let Some(cpu) = affinity.cpus().find(|cpu| !online.contains(cpu)) {
+            return Err(AffinityError::InvalidCpu { vp, cpu });
+        }
+    }
+    Ok(())
+}
+
+/// Restricts the current thread to the CPUs in `affinity`.
+pub(crate) fn apply(vp: VpIndex, affinity: &VpAffinity) {
+    if let Err(err) = sys::set_current_thread_affinity(affinity) {
+        tracing::warn!(
+            vp = vp.index(),
+            error = &err as &dyn std::error::Error,
+            "failed to set vp thread affinity"
+        );
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use super::AffinityError;
+    use super::VpAffinity;
+    use pal::unix::affinity::max_procs;
+    use pal::unix::affinity::CpuSet;
+    use std::collections::BTreeSet;
+    use std::io;
+
+    /// Returns the set of online host CPUs.
+    pub fn online_cpus() -> Result<BTreeSet<u32>, AffinityError> {
+        let list = std::fs::read_to_string("/sys/devices/system/cpu/online")
+            .map_err(AffinityError::OnlineCpus)?;
+        let mut set = CpuSet::new();
+        set.set_mask_list(&list).map_err(|err| {
+            AffinityError::OnlineCpus(io::Error::new(io::ErrorKind::InvalidData, err))
+        })?;
+        Ok((0..max_procs()).filter(|&cpu| set.is_set(cpu)).collect())
+    }
+
+    pub fn set_current_thread_affinity(affinity: &VpAffinity) -> io::Result<()> {
+        let mut set = CpuSet::new();
+        for cpu in affinity.cpus() {
+            if cpu >= max_procs() {
+                return Err(io::ErrorKind::InvalidInput.into());
+            }
+            set.set(cpu);
+        }
+        pal::unix::affinity::set_current_thread_affinity(&set)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod sys {
+    use super::AffinityError;
+    use super::VpAffinity;
+    use std::collections::BTreeSet;
+    use std::io;
+
+    pub fn online_cpus() -> Result<BTreeSet<u32>, AffinityError> {
+        Err(AffinityError::Unsupported)
+    }
+
+    pub fn set_current_thread_affinity(_affinity: &VpAffinity) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use pal::unix::affinity::get_current_thread_affinity;
+    use pal::unix::affinity::max_procs;
+    use pal::unix::affinity::CpuSet;
+
+    fn current_cpus() -> Vec<u32> {
+        let mut set = CpuSet::new();
+        get_current_thread_affinity(&mut set).unwrap();
+        (0..max_procs()).filter(|&cpu| set.is_set(cpu)).collect()
+    }
+
+    #[test]
+    fn pin_and_read_back() {
+        std::thread::spawn(|| {
+            let cpu = current_cpus()[0];
+            let affinity = VpAffinity::pinned(cpu);
+            validate(&[Some(affinity.clone())], 1).unwrap();
+            sys::set_current_thread_affinity(&affinity).unwrap();
+            assert_eq!(current_cpus(), [cpu]);
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn invalid_affinity() {
+        assert!(matches!(
+            validate(&[Some(VpAffinity::new([]))], 1),
+            Err(AffinityError::Empty(0))
+        ));
+        assert!(matches!(
+            validate(&[None, Some(VpAffinity::pinned(max_procs()))], 2),
+            Err(AffinityError::InvalidCpu { vp: 1, cpu }) if cpu == max_procs()
+        ));
+        assert!(matches!(
+            validate(&[None, Some(VpAffinity::pinned(0))], 1),
+            Err(AffinityError::UnknownVp(1))
+        ));
+        validate(&[None, None], 1).unwrap();
+    }
+}
@@ -3,6 +3,8 @@
 
 //! Virtual processor state management.
 
+use super::affinity;
+use super::affinity::VpAffinity;
 use super::HaltReason;
 use super::HaltReasonReceiver;
 use super::InternalHaltReason;
@@ -625,8 +627,9 @@ impl VpSet {
         }
     }
 
-    /// Adds a VP and returns its runner.
-    pub fn add(&mut self, vp: TargetVpInfo) -> VpRunner {
+    /// Adds a VP and returns its runner, which applies `affinity` to the
+    /// thread that runs it.
+    pub fn add(&mut self, vp: TargetVpInfo, affinity: Option<VpAffinity>) -> VpRunner {
         assert!(!self.started);
         let (send, recv) = mesh::channel();
         let (done_send, done_recv) = mesh::oneshot();
@@ -641,6 +644,7 @@ pub fn add(&mut self, vp: TargetVpInfo) -> VpRunner {
             _done: done_send,
             cancel_recv,
             cancel_send: Arc::new(cancel_send),
+            affinity,
             inner: RunnerInner {
                 vp: vp.as_ref().vp_index,
                 inner: self.inner.clone(),
@@ -913,6 +917,8 @@ pub struct VpRunner {
     cancel_send: Arc<mesh::Sender<()>>,
     cancel_recv: mesh::Receiver<()>,
     _done: mesh::OneshotSender<()>,
+    /// The affinity to apply on the first call to `run`.
+    affinity: Option<VpAffinity>,
     inner: RunnerInner,
 }
 
@@ -952,6 +958,9 @@ impl VpRunner {
     /// be reissued, with the same or different `vp` object, to continue running
     /// the VP.
     ///
+    /// The first call applies the VP's host CPU affinity, if any, to the
+    /// calling thread.
+    ///
     /// Do not reissue this call if it returns `Ok`. Do not drop this future
     /// without awaiting it to completion.
     pub async fn run(
@@ -960,6 +969,9 @@ pub async fn run(
         io: &impl CpuIo,
     ) -> Result<(), RunCancelled> {
         let vp_index = self.inner.vp;
+        if let Some(affinity) = self.affinity.take() {
+            affinity::apply(vp_index, &affinity);
+        }
         self.run_inner(&mut BoundVp { vp, io, vp_index }).await
     }
 
@@ -1364,3 +1376,128 @@ pub fn block_on_vp<F: Future>(partition: Arc<dyn RequestYield>, vp: VpIndex, fut
         })
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pal_async::async_test;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+    use std::time::Duration;
+    use vm_topology::processor::VpInfo;
+
+    /// A VP that runs until it is asked to stop, taking a while to wind down.
+    struct TestVp {
+        running: Arc<AtomicUsize>,
+        started: mesh::Sender<()>,
+    }
+
+    impl ProtobufSaveRestore for TestVp {
+        fn save(&mut self) -> Result<SavedStateBlob, SaveError> {
+            Err(SaveError::NotSupported)
+        }
+
+        fn restore(&mut self, _state: SavedStateBlob) -> Result<(), RestoreError> {
+            Err(RestoreError::SavedStateNotSupported)
+        }
+    }
+
+    #[async_trait(?Send)]
+    impl ControlVp for TestVp {
+        async fn run_vp(
+            &mut self,
+            _vtl_guest_memory: &[Option<GuestMemory>; NUM_VTLS],
+            mut stop: StopVp<'_>,
+        ) -> Result<StopReason, HaltReason> {
+            self.running.fetch_add(1, Ordering::SeqCst);
+            self.started.send(());
+            let err = stop
+                .until_stop(std::future::pending::<()>())
+                .await
+                .unwrap_err();
+            // Give a stop that does not wait for acknowledgement a chance to
+            // observe the VP still running.
+            std::thread::sleep(Duration::from_millis(50));
+            self.running.fetch_sub(1, Ordering::SeqCst);
+            Ok(StopReason::OnRequest(err))
+        }
+
+        fn inspect_vp(
+            &mut self,
+            _gm: &[Option<GuestMemory>; NUM_VTLS],
+            _req: inspect::Request<'_>,
+        ) {
+        }
+
+        fn set_initial_regs(
+            &mut self,
+            _vtl: Vtl,
+            _state: &InitialRegs,
+            _to_set: RegistersToSet,
+        ) -> Result<(), RegisterSetError> {
+            Ok(())
+        }
+
+        #[cfg(all(feature = "gdb", guest_arch = "x86_64"))]
+        fn debug(&mut self) -> &mut dyn DebugVp {
+            unimplemented!()
+        }
+    }
+
+    fn vp_info(index: u32) -> TargetVpInfo {
+        let base = VpInfo {
+            vp_index: VpIndex::new(index),
+            vnode: 0,
+        };
+        #[cfg(guest_arch = "x86_64")]
+        let info = TargetVpInfo {
+            base,
+            apic_id: index,
+        };
+        #[cfg(guest_arch = "aarch64")]
+        let info = TargetVpInfo {
+            base,
+            mpidr: aarch64defs::MpidrEl1::new().with_aff0(index as u8),
+            gicr: 0,
+        };
+        info
+    }
+
+    #[async_test]
+    async fn stop_waits_for_all_vps() {
+        const VP_COUNT: u32 = 4;
+
+        let (halt, _halt_recv) = Halt::new();
+        let mut vp_set = VpSet::new([None, None, None], Arc::new(halt));
+        let running = Arc::new(AtomicUsize::new(0));
+        let (started_send, mut started_recv) = mesh::channel();
+        let threads = (0..VP_COUNT)
+            .map(|index| {
+                let mut runner = vp_set.add(vp_info(index), None);
+                let mut vp = TestVp {
+                    running: running.clone(),
+                    started: started_send.clone(),
+                };
+                std::thread::spawn(move || {
+                    futures::executor::block_on(runner.run_inner(&mut vp)).unwrap()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for _ in 0..2 {
+            vp_set.start();
+            for _ in 0..VP_COUNT {
+                started_recv.next().await.unwrap();
+            }
+            assert_eq!(running.load(Ordering::SeqCst), VP_COUNT as usize);
+
+            vp_set.stop().await;
+            assert_eq!(running.load(Ordering::SeqCst), 0);
+        }
+
+        vp_set.teardown().await;
+        for thread in threads {
+            thread.join().unwrap();
+        }
+    }
+}
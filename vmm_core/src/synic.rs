@@ -4,6 +4,8 @@
 use hvdef::HvError;
 use hvdef::HvResult;
 use hvdef::Vtl;
+use inspect::Inspect;
+use inspect_counters::SharedCounter;
 use parking_lot::Mutex;
 use std::collections::hash_map;
 use std::collections::HashMap;
@@ -13,6 +15,7 @@
 use virt::Synic;
 use virt::VpIndex;
 use vmcore::monitor::MonitorId;
+use vmcore::synic::BackpressureBehavior;
 use vmcore::synic::EventPort;
 use vmcore::synic::MessagePort;
 use vmcore::synic::SynicMonitorAccess;
@@ -21,18 +24,45 @@
 pub struct SynicPorts {
     partition: Arc<dyn Synic>,
     ports: Arc<PortMap>,
+    stats: PortStats,
 }
 
-type PortMap = Mutex<HashMap<u32, Port>>;
+#[derive(Debug, Default, Inspect)]
+struct PortStats {
+    messages_delivered: SharedCounter,
+    messages_rejected: SharedCounter,
+    events_delivered: SharedCounter,
+    events_rejected: SharedCounter,
+}
+
+/// The ports for each connection ID, sorted by descending minimum VTL.
+type PortMap = Mutex<HashMap<u32, Vec<Port>>>;
 
 impl SynicPorts {
     pub fn new(partition: Arc<dyn Synic>) -> Self {
         Self {
             partition,
             ports: Default::default(),
+            stats: Default::default(),
         }
     }
 
+    /// Returns the port for `connection_id` with the highest minimum VTL that
+    /// still permits access from `vtl`.
+    fn port(&self, vtl: Vtl, connection_id: u32) -> HvResult<PortType> {
+        let ports = self.ports.lock();
+        let ports = ports
+            .get(&connection_id)
+            .ok_or(HvError::InvalidConnectionId)?;
+        // The ports are sorted by descending minimum VTL, so the first match
+        // is the most privileged one.
+        ports
+            .iter()
+            .find(|port| vtl >= port.minimum_vtl)
+            .map(|port| port.port_type.clone())
+            .ok_or(HvError::OperationDenied)
+    }
+
     pub fn on_post_message(
         &self,
         vtl: Vtl,
@@ -40,43 +70,129 @@ pub fn on_post_message(
         secure: bool,
         message: &[u8],
     ) -> HvResult<()> {
-        let port = self.ports.lock().get(&connection_id).cloned();
-        if let Some(Port {
-            port_type: PortType::Message(port),
-            minimum_vtl,
-        }) = port
-        {
-            if vtl < minimum_vtl {
-                Err(HvError::OperationDenied)
-            } else if port.handle_message(message, secure) {
-                Ok(())
-            } else {
-                // TODO: VMBus sometimes (in Azure?) returns HV_STATUS_TIMEOUT
-                //       here instead to force the guest to retry. Should we do
-                //       the same? Perhaps only for Linux VMs?
-                Err(HvError::InsufficientBuffers)
-            }
+        let result = self.post_message_to_port(vtl, connection_id, secure, message);
+        if result.is_ok() {
+            self.stats.messages_delivered.increment();
         } else {
-            Err(HvError::InvalidConnectionId)
+            self.stats.messages_rejected.increment();
+        }
+        result
+    }
+
+    fn post_message_to_port(
+        &self,
+        vtl: Vtl,
+        connection_id: u32,
+        secure: bool,
+        message: &[u8],
+    ) -> HvResult<()> {
+        let PortType::Message(port, backpressure) = self.port(vtl, connection_id)? else {
+            return Err(HvError::InvalidConnectionId);
+        };
+        if port.handle_message(message, secure) {
+            Ok(())
+        } else {
+            match backpressure {
+                BackpressureBehavior::Drop => Err(HvError::InsufficientBuffers),
+                BackpressureBehavior::Retry => Err(HvError::Timeout),
+            }
         }
     }
 
     pub fn on_signal_event(&self, vtl: Vtl, connection_id: u32, flag_number: u16) -> HvResult<()> {
-        let port = self.ports.lock().get(&connection_id).cloned();
-        if let Some(Port {
-            port_type: PortType::Event(port),
+        let result = self.signal_event_port(vtl, connection_id, flag_number);
+        if result.is_ok() {
+            self.stats.events_delivered.increment();
+        } else {
+            self.stats.events_rejected.increment();
+        }
+        result
+    }
+
+    fn signal_event_port(&self, vtl: Vtl, connection_id: u32, flag_number: u16) -> HvResult<()> {
+        let PortType::Event(port) = self.port(vtl, connection_id)? else {
+            return Err(HvError::InvalidConnectionId);
+        };
+        port.handle_event(flag_number);
+        Ok(())
+    }
+
+    /// Adds `port` for `connection_id`.
+    ///
+    /// Multiple ports of the same type can share a connection ID as long as
+    /// they have different minimum VTLs.
+    fn add_port(
+        &self,
+        connection_id: u32,
+        port: Port,
+        inner_handle: Option<Box<dyn Sync + Send>>,
+    ) -> Result<Box<dyn Sync + Send>, vmcore::synic::Error> {
+        let mut ports = self.ports.lock();
+        let ports = ports.entry(connection_id).or_default();
+        if ports.iter().any(|existing| {
+            existing.minimum_vtl == port.minimum_vtl
+                || std::mem::discriminant(&existing.port_type)
+                    != std::mem::discriminant(&port.port_type)
+        }) {
+            return Err(vmcore::synic::Error::ConnectionIdInUse(connection_id));
+        }
+        let minimum_vtl = port.minimum_vtl;
+        let index = ports.partition_point(|existing| existing.minimum_vtl > minimum_vtl);
+        ports.insert(index, port);
+        Ok(Box::new(PortHandle {
+            ports: Arc::downgrade(&self.ports),
+            connection_id,
             minimum_vtl,
-        }) = port
-        {
-            if vtl < minimum_vtl {
-                Err(HvError::OperationDenied)
-            } else {
-                port.handle_event(flag_number);
-                Ok(())
+            _inner_handle: inner_handle,
+        }))
+    }
+
+    /// Returns the connection IDs of ports that appear to have been leaked,
+    /// logging each of them.
+    ///
+    /// A port is registered with its own reference to the port object, and
+    /// its owner normally keeps another. If the registration holds the only
+    /// remaining reference, then the owner has most likely been torn down
+    /// without dropping the port's handle, leaving a zombie port behind. Ports
+    /// whose owners intentionally keep no reference are reported as well, so
+    /// this is only a diagnostic; the ports are not removed.
+    pub fn prune_stale(&self) -> Vec<u32> {
+        let ports = self.ports.lock();
+        let mut stale = Vec::new();
+        for (&connection_id, ports) in ports.iter() {
+            for port in ports {
+                let strong_count = match &port.port_type {
+                    PortType::Message(port, _) => Arc::strong_count(port),
+                    PortType::Event(port) => Arc::strong_count(port),
+                };
+                if strong_count == 1 {
+                    tracing::warn!(
+                        connection_id,
+                        minimum_vtl = port.minimum_vtl as u8,
+                        port_type = ?port.port_type,
+                        "synic port has no owner, its handle may have been leaked"
+                    );
+                    stale.push(connection_id);
+                }
             }
-        } else {
-            Err(HvError::InvalidConnectionId)
         }
+        stale.sort();
+        stale.dedup();
+        stale
+    }
+}
+
+impl Inspect for SynicPorts {
+    fn inspect(&self, req: inspect::Request<'_>) {
+        let ports = self.ports.lock();
+        req.respond().merge(&self.stats).field(
+            "ports",
+            inspect::iter_by_key(
+                ports
+                    .iter()
+                    .map(|(connection_id, ports)| (connection_id, inspect::iter_by_index(ports))),
+            ),
+        );
     }
 }
 
@@ -86,23 +202,16 @@ fn add_message_port(
         connection_id: u32,
         minimum_vtl: Vtl,
         port: Arc<dyn MessagePort>,
+        backpressure: BackpressureBehavior,
     ) -> Result<Box<dyn Sync + Send>, vmcore::synic::Error> {
-        match self.ports.lock().entry(connection_id) {
-            hash_map::Entry::Occupied(_) => {
-                return Err(vmcore::synic::Error::ConnectionIdInUse(connection_id))
-            }
-            hash_map::Entry::Vacant(e) => {
-                e.insert(Port {
-                    port_type: PortType::Message(port),
-                    minimum_vtl,
-                });
-            }
-        }
-        Ok(Box::new(PortHandle {
-            ports: Arc::downgrade(&self.ports),
+        self.add_port(
             connection_id,
-            _inner_handle: None,
-        }))
+            Port {
+                port_type: PortType::Message(port, backpressure),
+                minimum_vtl,
+            },
+            None,
+        )
     }
 
     fn add_event_port(
@@ -119,23 +228,14 @@ fn add_event_port(
             None
         };
 
-        match self.ports.lock().entry(connection_id) {
-            hash_map::Entry::Occupied(_) => {
-                return Err(vmcore::synic::Error::ConnectionIdInUse(connection_id))
-            }
-            hash_map::Entry::Vacant(e) => {
-                e.insert(Port {
-                    port_type: PortType::Event(port),
-                    minimum_vtl,
-                });
-            }
-        }
-
-        Ok(Box::new(PortHandle {
-            ports: Arc::downgrade(&self.ports),
+        self.add_port(
             connection_id,
-            _inner_handle: inner_handle,
-        }))
+            Port {
+                port_type: PortType::Event(port),
+                minimum_vtl,
+            },
+            inner_handle,
+        )
     }
 
     fn post_message(&self, vtl: Vtl, vp: u32, sint: u8, typ: u32, payload: &[u8]) {
@@ -172,38 +272,298 @@ fn set_monitor_page(&self, gpa: Option<u64>) -> anyhow::Result<()> {
     }
 }
 
+/// A port to register as part of a [`SynicPortGroup`].
+pub enum SynicPortGroupEntry {
+    /// A message port, as in [`SynicPortAccess::add_message_port`].
+    Message {
+        connection_id: u32,
+        minimum_vtl: Vtl,
+        port: Arc<dyn MessagePort>,
+        backpressure: BackpressureBehavior,
+    },
+    /// An event port, as in [`SynicPortAccess::add_event_port`].
+    Event {
+        connection_id: u32,
+        minimum_vtl: Vtl,
+        port: Arc<dyn EventPort>,
+    },
+}
+
+/// A set of synic ports that are registered together and removed together
+/// when the group is dropped.
+#[must_use]
+pub struct SynicPortGroup {
+    _handles: Vec<Box<dyn Sync + Send>>,
+}
+
+impl SynicPortGroup {
+    /// Registers each of `ports` with `synic`.
+    ///
+    /// If any port fails to register, the ports registered before it are
+    /// removed again and the error is returned.
+    pub fn new(
+        synic: &dyn SynicPortAccess,
+        ports: impl IntoIterator<Item = SynicPortGroupEntry>,
+    ) -> Result<Self, vmcore::synic::Error> {
+        let handles = ports
+            .into_iter()
+            .map(|entry| match entry {
+                SynicPortGroupEntry::Message {
+                    connection_id,
+                    minimum_vtl,
+                    port,
+                    backpressure,
+                } => synic.add_message_port(connection_id, minimum_vtl, port, backpressure),
+                SynicPortGroupEntry::Event {
+                    connection_id,
+                    minimum_vtl,
+                    port,
+                } => synic.add_event_port(connection_id, minimum_vtl, port),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { _handles: handles })
+    }
+}
+
 struct PortHandle {
     ports: Weak<PortMap>,
     connection_id: u32,
+    minimum_vtl: Vtl,
     _inner_handle: Option<Box<dyn Sync + Send>>,
 }
 
 impl Drop for PortHandle {
     fn drop(&mut self) {
         if let Some(ports) = self.ports.upgrade() {
-            let entry = ports.lock().remove(&self.connection_id);
-            entry.expect("port was previously added");
+            let mut ports = ports.lock();
+            let hash_map::Entry::Occupied(mut entry) = ports.entry(self.connection_id) else {
+                panic!("port was previously added");
+            };
+            let index = entry
+                .get()
+                .iter()
+                .position(|port| port.minimum_vtl == self.minimum_vtl)
+                .expect("port was previously added");
+            entry.get_mut().remove(index);
+            if entry.get().is_empty() {
+                entry.remove();
+            }
         }
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Inspect)]
 struct Port {
+    #[inspect(rename = "type")]
     port_type: PortType,
+    #[inspect(with = "|&vtl| vtl as u8")]
     minimum_vtl: Vtl,
 }
 
 #[derive(Clone)]
 enum PortType {
-    Message(Arc<dyn MessagePort>),
+    Message(Arc<dyn MessagePort>, BackpressureBehavior),
     Event(Arc<dyn EventPort>),
 }
 
+impl Inspect for PortType {
+    fn inspect(&self, req: inspect::Request<'_>) {
+        match self {
+            Self::Message(..) => req.value("message".into()),
+            Self::Event(_) => req.value("event".into()),
+        }
+    }
+}
+
 impl Debug for PortType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.pad(match self {
-            Self::Message(_) => "Port::Message",
+            Self::Message(..) => "Port::Message",
             Self::Event(_) => "Port::Event",
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+
+    struct TestSynic;
+
+    impl Synic for TestSynic {
+        fn post_message(&self, _vtl: Vtl, _vp: VpIndex, _sint: u8, _typ: u32, _payload: &[u8]) {}
+
+        fn new_guest_event_port(&self) -> Box<dyn vmcore::synic::GuestEventPort> {
+            unimplemented!()
+        }
+
+        fn prefer_os_events(&self) -> bool {
+            false
+        }
+    }
+
+    #[derive(Default)]
+    struct TestMessagePort {
+        messages: AtomicUsize,
+        full: AtomicBool,
+    }
+
+    impl MessagePort for TestMessagePort {
+        fn handle_message(&self, _msg: &[u8], _trusted: bool) -> bool {
+            if self.full.load(Ordering::Relaxed) {
+                return false;
+            }
+            self.messages.fetch_add(1, Ordering::Relaxed);
+            true
+        }
+    }
+
+    fn new_ports() -> SynicPorts {
+        SynicPorts::new(Arc::new(TestSynic))
+    }
+
+    #[test]
+    fn shared_connection_id() {
+        let ports = new_ports();
+        let vtl0_port = Arc::new(TestMessagePort::default());
+        let vtl1_port = Arc::new(TestMessagePort::default());
+        let _vtl0_handle = ports
+            .add_message_port(1, Vtl::Vtl0, vtl0_port.clone(), BackpressureBehavior::Drop)
+            .unwrap();
+        let vtl1_handle = ports
+            .add_message_port(1, Vtl::Vtl1, vtl1_port.clone(), BackpressureBehavior::Drop)
+            .unwrap();
+
+        // Each VTL reaches the most privileged port it is allowed to use.
+        ports.on_post_message(Vtl::Vtl0, 1, false, &[]).unwrap();
+        ports.on_post_message(Vtl::Vtl1, 1, false, &[]).unwrap();
+        ports.on_post_message(Vtl::Vtl2, 1, false, &[]).unwrap();
+        assert_eq!(vtl0_port.messages.load(Ordering::Relaxed), 1);
+        assert_eq!(vtl1_port.messages.load(Ordering::Relaxed), 2);
+
+        // The same minimum VTL cannot be registered twice.
+        assert!(matches!(
+            ports.add_message_port(1, Vtl::Vtl1, vtl1_port.clone(), BackpressureBehavior::Drop),
+            Err(vmcore::synic::Error::ConnectionIdInUse(1))
+        ));
+
+        // Dropping the VTL1 port falls back to the VTL0 port.
+        drop(vtl1_handle);
+        ports.on_post_message(Vtl::Vtl1, 1, false, &[]).unwrap();
+        assert_eq!(vtl0_port.messages.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn minimum_vtl_denied() {
+        let ports = new_ports();
+        let _handle = ports
+            .add_message_port(
+                1,
+                Vtl::Vtl1,
+                Arc::new(TestMessagePort::default()),
+                BackpressureBehavior::Drop,
+            )
+            .unwrap();
+        assert_eq!(
+            ports.on_post_message(Vtl::Vtl0, 1, false, &[]),
+            Err(HvError::OperationDenied)
+        );
+        assert_eq!(
+            ports.on_post_message(Vtl::Vtl0, 2, false, &[]),
+            Err(HvError::InvalidConnectionId)
+        );
+    }
+
+    #[test]
+    fn backpressure() {
+        let ports = new_ports();
+        let port = Arc::new(TestMessagePort::default());
+        port.full.store(true, Ordering::Relaxed);
+        let _drop_handle = ports
+            .add_message_port(1, Vtl::Vtl0, port.clone(), BackpressureBehavior::Drop)
+            .unwrap();
+        let _retry_handle = ports
+            .add_message_port(2, Vtl::Vtl0, port.clone(), BackpressureBehavior::Retry)
+            .unwrap();
+
+        assert_eq!(
+            ports.on_post_message(Vtl::Vtl0, 1, false, &[]),
+            Err(HvError::InsufficientBuffers)
+        );
+        assert_eq!(
+            ports.on_post_message(Vtl::Vtl0, 2, false, &[]),
+            Err(HvError::Timeout)
+        );
+
+        // Once the port drains, the retried post succeeds.
+        port.full.store(false, Ordering::Relaxed);
+        ports.on_post_message(Vtl::Vtl0, 2, false, &[]).unwrap();
+        assert_eq!(port.messages.load(Ordering::Relaxed), 1);
+        assert_eq!(ports.stats.messages_delivered.get(), 1);
+        assert_eq!(ports.stats.messages_rejected.get(), 2);
+    }
+
+    #[test]
+    fn port_group_rollback() {
+        let ports = new_ports();
+        let port = Arc::new(TestMessagePort::default());
+        let _existing = ports
+            .add_message_port(2, Vtl::Vtl0, port.clone(), BackpressureBehavior::Drop)
+            .unwrap();
+
+        let entries = |connection_ids: &[u32]| {
+            connection_ids
+                .iter()
+                .map(|&connection_id| SynicPortGroupEntry::Message {
+                    connection_id,
+                    minimum_vtl: Vtl::Vtl0,
+                    port: port.clone(),
+                    backpressure: BackpressureBehavior::Drop,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // The second ID collides, so the first must be rolled back.
+        assert!(matches!(
+            SynicPortGroup::new(&ports, entries(&[1, 2])),
+            Err(vmcore::synic::Error::ConnectionIdInUse(2))
+        ));
+        assert_eq!(
+            ports.on_post_message(Vtl::Vtl0, 1, false, &[]),
+            Err(HvError::InvalidConnectionId)
+        );
+
+        // Dropping a successful group removes all of its ports.
+        let group = SynicPortGroup::new(&ports, entries(&[1, 3])).unwrap();
+        ports.on_post_message(Vtl::Vtl0, 1, false, &[]).unwrap();
+        ports.on_post_message(Vtl::Vtl0, 3, false, &[]).unwrap();
+        drop(group);
+        assert!(ports.ports.lock().keys().eq([&2]));
+    }
+
+    #[test]
+    fn prune_stale() {
+        let ports = new_ports();
+        let owned = Arc::new(TestMessagePort::default());
+        let _owned_handle = ports
+            .add_message_port(1, Vtl::Vtl0, owned.clone(), BackpressureBehavior::Drop)
+            .unwrap();
+        let leaked = Arc::new(TestMessagePort::default());
+        let leaked_handle = ports
+            .add_message_port(2, Vtl::Vtl0, leaked.clone(), BackpressureBehavior::Drop)
+            .unwrap();
+        assert!(ports.prune_stale().is_empty());
+
+        // The owner goes away without dropping the port's handle.
+        drop(leaked);
+        assert_eq!(ports.prune_stale(), [2]);
+
+        // The port is only reported, not removed.
+        ports.on_post_message(Vtl::Vtl0, 2, false, &[]).unwrap();
+        drop(leaked_handle);
+        assert!(ports.prune_stale().is_empty());
+    }
+}
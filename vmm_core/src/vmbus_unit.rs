@@ -245,3 +245,152 @@ pub async fn offer_vmbus_device_handle_unit(
         })?;
     Ok(unit)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use guestmem::GuestMemory;
+    use guid::Guid;
+    use inspect::InspectMut;
+    use mesh::rpc::RpcSend;
+    use pal_async::async_test;
+    use pal_async::DefaultDriver;
+    use parking_lot::Mutex;
+    use vmbus_channel::bus::ChannelRequest;
+    use vmbus_channel::bus::OfferInput;
+    use vmbus_channel::bus::OfferParams;
+    use vmbus_channel::bus::OfferResources;
+    use vmbus_channel::bus::OpenData;
+    use vmbus_channel::bus::OpenRequest;
+    use vmbus_channel::bus::ParentBus;
+    use vmbus_channel::channel::ChannelOpenError;
+    use vmbus_channel::channel::DeviceResources;
+    use vmbus_channel::channel::SaveRestoreVmbusDevice;
+    use vmbus_core::protocol::GpadlId;
+    use vmbus_core::protocol::UserDefinedData;
+    use vmcore::interrupt::Interrupt;
+    use zerocopy::FromZeroes;
+
+    const INSTANCE_ID: Guid = Guid::from_static_str("0b3b2f5a-3ba1-4f5d-9f53-1f9ab8c4b2a1");
+
+    #[derive(Clone, Default)]
+    struct TestBus {
+        offer: Arc<Mutex<Option<OfferInput>>>,
+    }
+
+    #[async_trait]
+    impl ParentBus for TestBus {
+        async fn add_child(&self, request: OfferInput) -> anyhow::Result<OfferResources> {
+            *self.offer.lock() = Some(request);
+            Ok(OfferResources {
+                guest_mem: GuestMemory::empty(),
+            })
+        }
+
+        fn clone_bus(&self) -> Box<dyn ParentBus> {
+            Box::new(self.clone())
+        }
+
+        fn use_event(&self) -> bool {
+            false
+        }
+    }
+
+    struct TestDevice;
+
+    impl InspectMut for TestDevice {
+        fn inspect_mut(&mut self, req: inspect::Request<'_>) {
+            req.respond().field("device", true);
+        }
+    }
+
+    #[async_trait]
+    impl VmbusDevice for TestDevice {
+        fn offer(&self) -> OfferParams {
+            OfferParams {
+                interface_name: "test".to_owned(),
+                instance_id: INSTANCE_ID,
+                ..Default::default()
+            }
+        }
+
+        fn install(&mut self, _resources: DeviceResources) {}
+
+        async fn open(
+            &mut self,
+            _channel_idx: u16,
+            _open_request: &OpenRequest,
+        ) -> Result<(), ChannelOpenError> {
+            Ok(())
+        }
+
+        async fn close(&mut self, _channel_idx: u16) {}
+
+        async fn retarget_vp(&mut self, _channel_idx: u16, _target_vp: u32) {}
+
+        fn start(&mut self) {}
+
+        async fn stop(&mut self) {}
+
+        fn supports_save_restore(&mut self) -> Option<&mut dyn SaveRestoreVmbusDevice> {
+            None
+        }
+    }
+
+    async fn inspect_path(unit: &ChannelUnit<TestDevice>, path: &str) -> String {
+        let mut inspection = inspect::inspect(path, unit);
+        inspection.resolve().await;
+        inspection.results().to_string()
+    }
+
+    #[async_test]
+    async fn inspect_channel_state(driver: DefaultDriver) {
+        let bus = TestBus::default();
+        let unit = ChannelUnit(offer_channel(&driver, &bus, TestDevice).await.unwrap());
+        let request_send = bus.offer.lock().take().unwrap().request_send;
+
+        assert_eq!(inspect_path(&unit, "device").await, "true");
+        assert_eq!(
+            inspect_path(&unit, "vmbus/interface_name").await,
+            r#""test""#
+        );
+        assert_eq!(
+            inspect_path(&unit, "vmbus/instance_id").await,
+            r#""0b3b2f5a-3ba1-4f5d-9f53-1f9ab8c4b2a1""#
+        );
+        assert_eq!(
+            inspect_path(&unit, "vmbus/channels").await,
+            "{0: {open: false}}"
+        );
+
+        let opened = request_send
+            .call(
+                ChannelRequest::Open,
+                OpenRequest {
+                    open_data: OpenData {
+                        target_vp: 0,
+                        ring_offset: 1,
+                        ring_gpadl_id: GpadlId(0x42),
+                        event_flag: 0,
+                        connection_id: 0,
+                        user_data: UserDefinedData::new_zeroed(),
+                    },
+                    interrupt: Interrupt::null(),
+                },
+            )
+            .await
+            .unwrap();
+        assert!(opened);
+        assert_eq!(
+            inspect_path(&unit, "vmbus/channels").await,
+            "{0: {open: true, ring_gpadl_id: 0x42}}"
+        );
+
+        request_send.call(ChannelRequest::Close, ()).await.unwrap();
+        assert_eq!(
+            inspect_path(&unit, "vmbus/channels").await,
+            "{0: {open: false}}"
+        );
+    }
+}
@@ -1,12 +1,14 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT License.
 
-//! [`StateUnit`] support for [`VmTimeKeeper`].
+//! [`StateUnit`] support for [`VmTimeKeeper`], plus guest TSC scaling.
 
+use inspect::Inspect;
 use inspect::InspectMut;
 use mesh::Receiver;
 use state_unit::StateRequest;
 use state_unit::StateUnit;
+use thiserror::Error;
 use vmcore::save_restore::RestoreError;
 use vmcore::save_restore::SaveError;
 use vmcore::save_restore::SavedStateBlob;
@@ -45,3 +47,135 @@ async fn restore(&mut self, state: SavedStateBlob) -> Result<(), RestoreError> {
 pub async fn run_vmtime(keeper: &mut VmTimeKeeper, recv: Receiver<StateRequest>) {
     state_unit::run_unit(KeeperUnit(keeper), recv).await;
 }
+
+/// Error returned by [`GuestTscScale::set_guest_frequency()`].
+#[derive(Debug, Error)]
+#[error("guest tsc frequency {guest_frequency} cannot be derived from host tsc frequency {host_frequency}")]
+pub struct InvalidTscFrequency {
+    host_frequency: u64,
+    guest_frequency: u64,
+}
+
+/// Converts host TSC values to guest TSC values, so that the guest can observe
+/// a TSC frequency that differs from the host's (e.g. after migrating between
+/// hosts with different TSC rates).
+///
+/// The guest TSC is computed as `host_tsc * multiplier + offset`, where the
+/// multiplier is a 32.32 fixed-point ratio of the guest and host frequencies.
+#[derive(Debug, Clone, Inspect)]
+pub struct GuestTscScale {
+    host_frequency: u64,
+    guest_frequency: u64,
+    #[inspect(hex)]
+    multiplier: u64,
+    #[inspect(hex)]
+    offset: u64,
+}
+
+impl GuestTscScale {
+    const FRACTION_BITS: u32 = 32;
+
+    /// Returns a new scale for a host TSC running at `host_frequency` Hz,
+    /// initially presenting the host frequency to the guest unchanged.
+    pub fn new(host_frequency: u64) -> Self {
+        assert_ne!(host_frequency, 0);
+        Self {
+            host_frequency,
+            guest_frequency: host_frequency,
+            multiplier: 1 << Self::FRACTION_BITS,
+            offset: 0,
+        }
+    }
+
+    /// The host TSC frequency, in Hz.
+    pub fn host_frequency(&self) -> u64 {
+        self.host_frequency
+    }
+
+    /// The TSC frequency presented to the guest, in Hz.
+    pub fn guest_frequency(&self) -> u64 {
+        self.guest_frequency
+    }
+
+    /// Returns the guest TSC value corresponding to the host TSC value
+    /// `host_tsc`.
+    pub fn guest_tsc(&self, host_tsc: u64) -> u64 {
+        self.scale(host_tsc).wrapping_add(self.offset)
+    }
+
+    /// Changes the TSC frequency presented to the guest, recomputing the
+    /// multiplier.
+    ///
+    /// `host_tsc` is the current host TSC value. The guest TSC value at
+    /// `host_tsc` is preserved across the change, so the guest TSC never goes
+    /// backwards; it just advances at the new rate from then on.
+    pub fn set_guest_frequency(
+        &mut self,
+        guest_frequency: u64,
+        host_tsc: u64,
+    ) -> Result<(), InvalidTscFrequency> {
+        let multiplier =
+            ((guest_frequency as u128) << Self::FRACTION_BITS) / self.host_frequency as u128;
+        let multiplier =
+            u64::try_from(multiplier)
+                .ok()
+                .filter(|&m| m != 0)
+                .ok_or(InvalidTscFrequency {
+                    host_frequency: self.host_frequency,
+                    guest_frequency,
+                })?;
+
+        let guest_tsc = self.guest_tsc(host_tsc);
+        self.guest_frequency = guest_frequency;
+        self.multiplier = multiplier;
+        self.offset = guest_tsc.wrapping_sub(self.scale(host_tsc));
+        Ok(())
+    }
+
+    fn scale(&self, host_tsc: u64) -> u64 {
+        ((host_tsc as u128 * self.multiplier as u128) >> Self::FRACTION_BITS) as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GuestTscScale;
+
+    const HOST_FREQUENCY: u64 = 1_000_000_000;
+
+    #[test]
+    fn scale_2x() {
+        let mut scale = GuestTscScale::new(HOST_FREQUENCY);
+        assert_eq!(scale.guest_tsc(12345), 12345);
+
+        scale
+            .set_guest_frequency(HOST_FREQUENCY * 2, 1_000_000)
+            .unwrap();
+        assert_eq!(scale.guest_frequency(), HOST_FREQUENCY * 2);
+
+        let first = scale.guest_tsc(2_000_000);
+        let second = scale.guest_tsc(2_500_000);
+        assert_eq!(second - first, 1_000_000);
+    }
+
+    #[test]
+    fn monotonic_across_change() {
+        let mut scale = GuestTscScale::new(HOST_FREQUENCY);
+        scale
+            .set_guest_frequency(HOST_FREQUENCY * 3, 1_000_000)
+            .unwrap();
+
+        // Slow the guest TSC back down; it must not jump backwards.
+        let host_tsc = 5_000_000;
+        let before = scale.guest_tsc(host_tsc);
+        scale
+            .set_guest_frequency(HOST_FREQUENCY / 2, host_tsc)
+            .unwrap();
+        assert_eq!(scale.guest_tsc(host_tsc), before);
+        assert_eq!(scale.guest_tsc(host_tsc + 1000), before + 500);
+
+        assert!(scale.set_guest_frequency(0, host_tsc).is_err());
+        assert!(scale.set_guest_frequency(u64::MAX, host_tsc).is_err());
+        assert_eq!(scale.guest_tsc(host_tsc), before);
+    }
+}
@@ -9,17 +9,22 @@
 pub use x86_initial_regs as initial_regs;
 
 use loader::importer::Aarch64Register;
+use loader::importer::UnsupportedVbsRegister;
 use loader::importer::X86Register;
+use std::convert::Infallible;
 use std::sync::Arc;
 use vm_topology::processor::aarch64::Aarch64VpInfo;
 use vm_topology::processor::x86::X86VpInfo;
 
 /// Converts a list of loader registers to the VM initial register state.
+///
+/// Like the VBS VP context, the initial register state cannot hold debug
+/// registers, so these are rejected.
 pub fn x86_initial_regs(
     init: &[X86Register],
     caps: &virt::x86::X86PartitionCapabilities,
     bsp_id: &X86VpInfo,
-) -> Arc<virt::x86::X86InitialRegs> {
+) -> Result<Arc<virt::x86::X86InitialRegs>, UnsupportedVbsRegister> {
     let mut regs = Arc::new(virt::x86::X86InitialRegs::at_reset(caps, bsp_id));
 
     let state = Arc::get_mut(&mut regs).unwrap();
@@ -49,6 +54,12 @@ pub fn x86_initial_regs(
             X86Register::R11(v) => state.registers.r11 = v,
             X86Register::R12(v) => state.registers.r12 = v,
             X86Register::Rflags(v) => state.registers.rflags = v,
+            X86Register::Dr0(_)
+            | X86Register::Dr1(_)
+            | X86Register::Dr2(_)
+            | X86Register::Dr3(_)
+            | X86Register::Dr6(_)
+            | X86Register::Dr7(_) => return Err(UnsupportedVbsRegister(reg)),
             X86Register::MtrrDefType(v) => state.cc.msr_mtrr_def_type = v,
             X86Register::MtrrPhysBase0(v) => state.cc.variable[0] = v,
             X86Register::MtrrPhysMask0(v) => state.cc.variable[1] = v,
@@ -69,7 +80,7 @@ pub fn x86_initial_regs(
         }
     }
 
-    regs
+    Ok(regs)
 }
 
 fn into_virt_x86_tr(tr: loader::importer::TableRegister) -> virt::x86::TableRegister {
@@ -93,7 +104,7 @@ pub fn aarch64_initial_regs(
     init: &[Aarch64Register],
     caps: &virt::aarch64::Aarch64PartitionCapabilities,
     bsp_id: &Aarch64VpInfo,
-) -> Arc<virt::aarch64::Aarch64InitialRegs> {
+) -> Result<Arc<virt::aarch64::Aarch64InitialRegs>, Infallible> {
     let mut regs = Arc::new(virt::aarch64::Aarch64InitialRegs::at_reset(caps, bsp_id));
 
     let state = Arc::get_mut(&mut regs).unwrap();
@@ -112,5 +123,5 @@ pub fn aarch64_initial_regs(
         }
     }
 
-    regs
+    Ok(regs)
 }
@@ -16,6 +16,7 @@
 use chipset_device_resources::BSP_LINT_LINE_SET;
 use chipset_device_resources::GPE0_LINE_SET;
 use chipset_device_resources::IRQ_LINE_SET;
+use chipset_device_resources::PIRQ_LINE_SET;
 use closeable_mutex::CloseableMutex;
 use firmware_uefi::UefiCommandSet;
 use framebuffer::Framebuffer;
@@ -326,11 +327,14 @@ pub async fn build(
             builder
                 .arc_mutex_device("piix4-pci-isa-bridge")
                 .on_pci_bus(attached_to)
-                .add(|_| {
-                    chipset_legacy::piix4_pci_isa_bridge::PciIsaBridge::new(
+                .add(|services| {
+                    let bridge = chipset_legacy::piix4_pci_isa_bridge::PciIsaBridge::new(
                         reset.clone(),
                         set_a20_signal,
-                    )
+                        |irq| services.new_line(IRQ_LINE_SET, &format!("pirq{irq}"), irq.into()),
+                    );
+                    services.add_line_target(PIRQ_LINE_SET, 0..=3, 0);
+                    bridge
                 })?;
         }
 
@@ -385,6 +389,8 @@ pub async fn build(
         if let Some(options::dev::WinbondSuperIoAndFloppyFullDeps {
             primary_disk_drive,
             secondary_disk_drive,
+            serial_ports,
+            parallel_port,
         }) = deps_winbond_super_io_and_floppy_full
         {
             if let Some(dma) = &dma {
@@ -405,6 +411,11 @@ pub async fn build(
                         secondary_disk_drive,
                         primary_dma,
                         secondary_dma,
+                        serial_ports,
+                        |name, irq| {
+                            services.new_line(IRQ_LINE_SET, &format!("{name}-irq{irq}"), irq.into())
+                        },
+                        parallel_port,
                     )
                 })?;
             } else {
@@ -413,8 +424,10 @@ pub async fn build(
         }
 
         #[cfg(feature = "dev_winbond_super_io_and_floppy_stub")]
-        if let Some(options::dev::WinbondSuperIoAndFloppyStubDeps) =
-            deps_winbond_super_io_and_floppy_stub
+        if let Some(options::dev::WinbondSuperIoAndFloppyStubDeps {
+            serial_ports,
+            parallel_port,
+        }) = deps_winbond_super_io_and_floppy_stub
         {
             if let Some(dma) = &dma {
                 // IRQ and DMA channel assignment MUST match the values reported
@@ -434,6 +447,11 @@ pub async fn build(
                         floppy::DriveRibbon::None,
                         primary_dma,
                         secondary_dma,
+                        serial_ports,
+                        |name, irq| {
+                            services.new_line(IRQ_LINE_SET, &format!("{name}-irq{irq}"), irq.into())
+                        },
+                        parallel_port,
                     )
                 })?;
             } else {
@@ -538,6 +556,8 @@ pub async fn build(
                     services.new_line(IRQ_LINE_SET, "gpe0", acpi_irq),
                     &mut services.register_pio(),
                     services.register_vmtime().access("pm"),
+                    // Matches the `TMR_VAL_EXT` flag in the generated FADT.
+                    pm::PmTimerWidth::Bits32,
                     Some(pm::EnableAcpiMode {
                         default_pio_dynamic: pio_dynamic_reg_base,
                     }),
@@ -1185,7 +1205,16 @@ pub struct GenericIsaFloppyDeps {
             /// IRQ and DMA channel assignment MUST match the values reported by
             /// the PCAT BIOS ACPI tables, and the Super IO emulator, and cannot
             /// be tweaked by top-level VMM code.
-            pub struct WinbondSuperIoAndFloppyStubDeps;
+            pub struct WinbondSuperIoAndFloppyStubDeps {
+                /// Backends for the COM1 and COM2 UARTs to host in the Super
+                /// I/O, whose base addresses and IRQs the guest can program.
+                /// Ports that are not hosted must be added as standalone
+                /// devices instead.
+                pub serial_ports: [Option<Box<dyn serial_core::SerialIo>>; 2],
+                /// Sink for the printer emulated on LPT1, if the Super I/O should
+                /// have a parallel port.
+                pub parallel_port: Option<Box<dyn std::io::Write + Send>>,
+            }
         }
 
         feature_gated! {
@@ -1201,6 +1230,14 @@ pub struct WinbondSuperIoAndFloppyFullDeps {
                 pub primary_disk_drive: floppy::DriveRibbon,
                 /// Floppy Drive attached to the secondary controller
                 pub secondary_disk_drive: floppy::DriveRibbon,
+                /// Backends for the COM1 and COM2 UARTs to host in the Super
+                /// I/O, whose base addresses and IRQs the guest can program.
+                /// Ports that are not hosted must be added as standalone
+                /// devices instead.
+                pub serial_ports: [Option<Box<dyn serial_core::SerialIo>>; 2],
+                /// Sink for the printer emulated on LPT1, if the Super I/O should
+                /// have a parallel port.
+                pub parallel_port: Option<Box<dyn std::io::Write + Send>>,
             }
         }
 
@@ -5,6 +5,7 @@
 //! [`Chipset`](crate::Chipset).
 
 use super::services::ArcMutexChipsetServices;
+use super::services::FinalizeError;
 use crate::BusIdPci;
 use crate::VmmChipsetDevice;
 use arc_cyclic_builder::ArcCyclicBuilder;
@@ -47,6 +48,22 @@ pub struct AddDeviceError {
     inner: AddDeviceErrorKind,
 }
 
+impl AddDeviceError {
+    /// Returns true if the device could not be added because another device
+    /// with the same name has already been added to the chipset.
+    pub fn is_name_in_use(&self) -> bool {
+        match &self.inner {
+            AddDeviceErrorKind::Finalize(err) => {
+                matches!(
+                    err.downcast_ref::<FinalizeError>(),
+                    Some(FinalizeError::NameInUse(_))
+                )
+            }
+            AddDeviceErrorKind::DeviceError(_) | AddDeviceErrorKind::NoPciBusAddress => false,
+        }
+    }
+}
+
 /// Additional trait implemented by Arc + CloseableMutex [`ChipsetServices`] that gives
 /// the services an opportunity to perform any chipset-specific wiring of the
 /// constructed `Arc<CloseableMutex<T: ChipsetDevice>>`.
@@ -268,3 +285,27 @@ pub fn services(&mut self) -> &mut ArcMutexChipsetServices<'a, 'b> {
         &mut self.services
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::AddDeviceErrorKind;
+    use super::FinalizeError;
+    use state_unit::StateUnits;
+
+    #[test]
+    fn name_in_use() {
+        let units = StateUnits::new();
+        let _unit = units.add("dev").build(mesh::channel().0).unwrap();
+        let err = units.add("dev").build(mesh::channel().0).unwrap_err();
+
+        let err = AddDeviceErrorKind::Finalize(FinalizeError::NameInUse(err).into())
+            .with_dev_name("dev".into());
+        assert!(err.is_name_in_use());
+
+        let err = AddDeviceErrorKind::Finalize(FinalizeError::NoInterruptTarget.into())
+            .with_dev_name("dev".into());
+        assert!(!err.is_name_in_use());
+        let err = AddDeviceErrorKind::NoPciBusAddress.with_dev_name("dev".into());
+        assert!(!err.is_name_in_use());
+    }
+}
@@ -23,6 +23,7 @@
 
 // API wart: future changes should avoid exposing the `ChipsetBuilder`, and move
 // _all_ device instantiation into `vmotherboard` itself.
+pub use self::chipset::backing::arc_mutex::device::AddDeviceError;
 pub use self::chipset::backing::arc_mutex::device::ArcMutexChipsetDeviceBuilder;
 pub use self::chipset::ChipsetBuilder;
 